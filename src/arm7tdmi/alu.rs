@@ -251,7 +251,7 @@ impl Core {
 
     fn alu_sub_flags(a: i32, b: i32, carry: &mut bool, overflow: &mut bool) -> i32 {
         let res = a.wrapping_sub(b);
-        *carry = b <= a;
+        *carry = (b as u32) <= (a as u32);
         let (_, would_overflow) = a.overflowing_sub(b);
         *overflow = would_overflow;
         res
@@ -1,9 +1,11 @@
 use std::fmt;
 
-use super::{AluOpCode, ArmCond, ArmFormat, ArmHalfwordTransferType, ArmInstruction};
+use super::{AluOpCode, ArmCond, ArmFormat, ArmHalfwordTransferType, ArmInstruction, CycleCost};
 use crate::arm7tdmi::{
-    psr::RegPSR, reg_string, Addr, BarrelShiftOpCode, BarrelShifterValue, ShiftedRegister, REG_PC,
+    psr::RegPSR, Addr, BarrelShiftOpCode, BarrelShifterValue, ShiftedRegister, SymbolResolver,
+    REG_PC,
 };
+use crate::disass::{DisasmStyle, Styled, StyledDisplay};
 
 impl fmt::Display for ArmCond {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -83,32 +85,77 @@ fn is_shift(shift: &ShiftedRegister) -> bool {
 }
 
 impl ArmInstruction {
-    fn make_shifted_reg_string(&self, reg: usize, shift: ShiftedRegister) -> String {
-        let reg = reg_string(reg).to_string();
+    fn make_shifted_reg_string(
+        &self,
+        reg: usize,
+        shift: ShiftedRegister,
+        style: DisasmStyle,
+    ) -> String {
+        let reg = style.reg(reg).to_string();
         if !is_shift(&shift) {
             return reg;
         }
 
         match shift {
-            ShiftedRegister::ByAmount(imm, typ) => format!("{}, {} #{}", reg, typ, imm),
-            ShiftedRegister::ByRegister(rs, typ) => format!("{}, {} {}", reg, typ, reg_string(rs)),
+            ShiftedRegister::ByAmount(imm, typ) => format!("{}, {} #{}", reg, style.of(typ), imm),
+            ShiftedRegister::ByRegister(rs, typ) => {
+                format!("{}, {} {}", reg, style.of(typ), style.reg(rs))
+            }
         }
     }
 
-    fn fmt_bx(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "bx\t{Rn}", Rn = reg_string(self.rn()))
+    fn fmt_bx(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
+        write!(
+            f,
+            "{mnem}\t{Rn}",
+            mnem = style.of("bx"),
+            Rn = style.reg(self.rn())
+        )
+    }
+
+    fn branch_target(&self) -> Addr {
+        8 + self.pc.wrapping_add(self.branch_offset() as Addr)
     }
 
-    fn fmt_branch(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn branch_mnemonic(&self, style: DisasmStyle) -> Styled<String> {
+        style.of(format!(
+            "b{link}{cond}",
+            link = if self.link_flag() { "l" } else { "" },
+            cond = self.cond
+        ))
+    }
+
+    fn fmt_branch(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
         write!(
             f,
-            "b{link}{cond}\t{ofs:#x}",
-            link = if self.link_flag() { "l" } else { "" },
-            cond = self.cond,
-            ofs = 8 + self.pc.wrapping_add(self.branch_offset() as Addr)
+            "{mnem}\t{ofs:#x}",
+            mnem = self.branch_mnemonic(style),
+            ofs = self.branch_target()
         )
     }
 
+    /// Like `Display`, but branch targets are named by `resolver` when it has a symbol at or
+    /// below them: `bl game_init <0x80000c8>` or `b game_init+0x10 <0x80000d8>`
+    pub fn disassemble_with_symbols(&self, resolver: &SymbolResolver) -> String {
+        if self.fmt != ArmFormat::B_BL {
+            return self.to_string();
+        }
+        let target = self.branch_target();
+        match resolver.nearest_symbol(target) {
+            Some((name, addr)) => format!(
+                "{mnem}\t{name}{offset} <{target:#x}>",
+                mnem = self.branch_mnemonic(DisasmStyle::Lowercase),
+                name = name,
+                offset = match target - addr {
+                    0 => String::new(),
+                    offset => format!("+{:#x}", offset),
+                },
+                target = target
+            ),
+            None => self.to_string(),
+        }
+    }
+
     fn set_cond_mark(&self) -> &str {
         if self.set_cond_flag() {
             "s"
@@ -117,7 +164,11 @@ impl ArmInstruction {
         }
     }
 
-    fn fmt_operand2(&self, f: &mut fmt::Formatter) -> Result<Option<u32>, fmt::Error> {
+    fn fmt_operand2(
+        &self,
+        f: &mut fmt::Formatter,
+        style: DisasmStyle,
+    ) -> Result<Option<u32>, fmt::Error> {
         let operand2 = self.operand2().unwrap();
         match operand2 {
             BarrelShifterValue::RotatedImmediate(_, _) => {
@@ -130,14 +181,14 @@ impl ArmInstruction {
                 shift,
                 added: _,
             } => {
-                write!(f, "{}", self.make_shifted_reg_string(reg, shift))?;
+                write!(f, "{}", self.make_shifted_reg_string(reg, shift, style))?;
                 Ok(None)
             }
             _ => panic!("invalid operand2"),
         }
     }
 
-    fn fmt_data_processing(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_data_processing(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
         use AluOpCode::*;
 
         let opcode = self.opcode().unwrap();
@@ -145,31 +196,40 @@ impl ArmInstruction {
         match opcode {
             MOV | MVN => write!(
                 f,
-                "{opcode}{S}{cond}\t{Rd}, ",
-                opcode = opcode,
-                cond = self.cond,
-                S = self.set_cond_mark(),
-                Rd = reg_string(self.rd())
+                "{mnem}\t{Rd}, ",
+                mnem = style.of(format_args!(
+                    "{opcode}{S}{cond}",
+                    opcode = opcode,
+                    cond = self.cond,
+                    S = self.set_cond_mark()
+                )),
+                Rd = style.reg(self.rd())
             ),
             CMP | CMN | TEQ | TST => write!(
                 f,
-                "{opcode}{cond}\t{Rn}, ",
-                opcode = opcode,
-                cond = self.cond,
-                Rn = reg_string(self.rn())
+                "{mnem}\t{Rn}, ",
+                mnem = style.of(format_args!(
+                    "{opcode}{cond}",
+                    opcode = opcode,
+                    cond = self.cond
+                )),
+                Rn = style.reg(self.rn())
             ),
             _ => write!(
                 f,
-                "{opcode}{S}{cond}\t{Rd}, {Rn}, ",
-                opcode = opcode,
-                cond = self.cond,
-                S = self.set_cond_mark(),
-                Rd = reg_string(self.rd()),
-                Rn = reg_string(self.rn())
+                "{mnem}\t{Rd}, {Rn}, ",
+                mnem = style.of(format_args!(
+                    "{opcode}{S}{cond}",
+                    opcode = opcode,
+                    cond = self.cond,
+                    S = self.set_cond_mark()
+                )),
+                Rd = style.reg(self.rd()),
+                Rn = style.reg(self.rn())
             ),
         }?;
 
-        self.fmt_operand2(f).unwrap();
+        self.fmt_operand2(f, style).unwrap();
         Ok(())
     }
 
@@ -181,19 +241,25 @@ impl ArmInstruction {
         }
     }
 
-    fn fmt_rn_offset(&self, f: &mut fmt::Formatter, offset: BarrelShifterValue) -> fmt::Result {
-        write!(f, "[{Rn}", Rn = reg_string(self.rn()))?;
+    fn fmt_rn_offset(
+        &self,
+        f: &mut fmt::Formatter,
+        offset: BarrelShifterValue,
+        style: DisasmStyle,
+    ) -> fmt::Result {
+        write!(f, "[{Rn}", Rn = style.reg(self.rn()))?;
         let (ofs_string, comment) = match offset {
             BarrelShifterValue::ImmediateValue(value) => {
-                let value_for_commnet = if self.rn() == REG_PC {
-                    value + (self.pc as i32) + 8 // account for pipelining
+                // the sign of the immediate comes from the U bit
+                let comment = if self.rn() == REG_PC {
+                    let addr = (self.pc as i32) + 8 + value; // account for pipelining
+                    format!("\t; {:#x}", addr)
+                } else if value < 0 {
+                    format!("\t; -{:#x}", -value)
                 } else {
-                    value
+                    format!("\t; {:#x}", value)
                 };
-                (
-                    format!("#{}", value),
-                    Some(format!("\t; {:#x}", value_for_commnet)),
-                )
+                (format!("#{}", value), Some(comment))
             }
             BarrelShifterValue::ShiftedRegister {
                 reg,
@@ -203,7 +269,7 @@ impl ArmInstruction {
                 format!(
                     "{}{}",
                     if added { "" } else { "-" },
-                    self.make_shifted_reg_string(reg, shift)
+                    self.make_shifted_reg_string(reg, shift, style)
                 ),
                 None,
             ),
@@ -223,42 +289,44 @@ impl ArmInstruction {
         }
     }
 
-    fn fmt_ldr_str(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_ldr_str(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
         write!(
             f,
-            "{mnem}{B}{T}{cond}\t{Rd}, ",
-            mnem = if self.load_flag() { "ldr" } else { "str" },
-            B = if self.transfer_size() == 1 { "b" } else { "" },
-            cond = self.cond,
-            T = if !self.pre_index_flag() && self.write_back_flag() {
-                "t"
-            } else {
-                ""
-            },
-            Rd = reg_string(self.rd()),
+            "{mnem}\t{Rd}, ",
+            mnem = style.of(format_args!(
+                "{mnem}{B}{T}{cond}",
+                mnem = if self.load_flag() { "ldr" } else { "str" },
+                B = if self.transfer_size() == 1 { "b" } else { "" },
+                cond = self.cond,
+                T = if self.force_user_mode_flag() { "t" } else { "" },
+            )),
+            Rd = style.reg(self.rd()),
         )?;
 
-        self.fmt_rn_offset(f, self.ldr_str_offset())
+        self.fmt_rn_offset(f, self.ldr_str_offset(), style)
     }
 
-    fn fmt_ldm_stm(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_ldm_stm(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
         write!(
             f,
-            "{mnem}{inc_dec}{pre_post}{cond}\t{Rn}{auto_inc}, {{",
-            mnem = if self.load_flag() { "ldm" } else { "stm" },
-            inc_dec = if self.add_offset_flag() { 'i' } else { 'd' },
-            pre_post = if self.pre_index_flag() { 'b' } else { 'a' },
-            cond = self.cond,
-            Rn = reg_string(self.rn()),
+            "{mnem}\t{Rn}{auto_inc}, {{",
+            mnem = style.of(format_args!(
+                "{mnem}{inc_dec}{pre_post}{cond}",
+                mnem = if self.load_flag() { "ldm" } else { "stm" },
+                inc_dec = if self.add_offset_flag() { 'i' } else { 'd' },
+                pre_post = if self.pre_index_flag() { 'b' } else { 'a' },
+                cond = self.cond,
+            )),
+            Rn = style.reg(self.rn()),
             auto_inc = if self.write_back_flag() { "!" } else { "" }
         )?;
 
         let mut register_list = self.register_list().into_iter();
         if let Some(reg) = register_list.next() {
-            write!(f, "{}", reg_string(reg))?;
+            write!(f, "{}", style.reg(reg))?;
         }
         for reg in register_list {
-            write!(f, ", {}", reg_string(reg))?;
+            write!(f, ", {}", style.reg(reg))?;
         }
         write!(
             f,
@@ -272,35 +340,35 @@ impl ArmInstruction {
     }
 
     /// MRS - transfer PSR contents to a register
-    fn fmt_mrs(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_mrs(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
         write!(
             f,
-            "mrs{cond}\t{Rd}, {psr}",
-            cond = self.cond,
-            Rd = reg_string(self.rd()),
+            "{mnem}\t{Rd}, {psr}",
+            mnem = style.of(format_args!("mrs{cond}", cond = self.cond)),
+            Rd = style.reg(self.rd()),
             psr = if self.spsr_flag() { "SPSR" } else { "CPSR" }
         )
     }
 
     /// MSR - transfer register contents to PSR
-    fn fmt_msr_reg(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_msr_reg(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
         write!(
             f,
-            "msr{cond}\t{psr}, {Rm}",
-            cond = self.cond,
+            "{mnem}\t{psr}, {Rm}",
+            mnem = style.of(format_args!("msr{cond}", cond = self.cond)),
             psr = if self.spsr_flag() { "SPSR" } else { "CPSR" },
-            Rm = reg_string(self.rm()),
+            Rm = style.reg(self.rm()),
         )
     }
 
-    fn fmt_msr_flags(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_msr_flags(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
         write!(
             f,
-            "msr{cond}\t{psr}, ",
-            cond = self.cond,
+            "{mnem}\t{psr}, ",
+            mnem = style.of(format_args!("msr{cond}", cond = self.cond)),
             psr = if self.spsr_flag() { "SPSR_f" } else { "CPSR_f" },
         )?;
-        if let Ok(Some(op)) = self.fmt_operand2(f) {
+        if let Ok(Some(op)) = self.fmt_operand2(f, style) {
             let psr = RegPSR::new(op & 0xf000_0000);
             write!(
                 f,
@@ -314,27 +382,33 @@ impl ArmInstruction {
         Ok(())
     }
 
-    fn fmt_mul_mla(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_mul_mla(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
         if self.accumulate_flag() {
             write!(
                 f,
-                "mla{S}{cond}\t{Rd}, {Rm}, {Rs}, {Rn}",
-                S = self.set_cond_mark(),
-                cond = self.cond,
-                Rd = reg_string(self.rd()),
-                Rm = reg_string(self.rm()),
-                Rs = reg_string(self.rs()),
-                Rn = reg_string(self.rn()),
+                "{mnem}\t{Rd}, {Rm}, {Rs}, {Rn}",
+                mnem = style.of(format_args!(
+                    "mla{S}{cond}",
+                    S = self.set_cond_mark(),
+                    cond = self.cond
+                )),
+                Rd = style.reg(self.rd()),
+                Rm = style.reg(self.rm()),
+                Rs = style.reg(self.rs()),
+                Rn = style.reg(self.rn()),
             )
         } else {
             write!(
                 f,
-                "mul{S}{cond}\t{Rd}, {Rm}, {Rs}",
-                S = self.set_cond_mark(),
-                cond = self.cond,
-                Rd = reg_string(self.rd()),
-                Rm = reg_string(self.rm()),
-                Rs = reg_string(self.rs()),
+                "{mnem}\t{Rd}, {Rm}, {Rs}",
+                mnem = style.of(format_args!(
+                    "mul{S}{cond}",
+                    S = self.set_cond_mark(),
+                    cond = self.cond
+                )),
+                Rd = style.reg(self.rd()),
+                Rm = style.reg(self.rm()),
+                Rs = style.reg(self.rs()),
             )
         }
     }
@@ -347,77 +421,186 @@ impl ArmInstruction {
         }
     }
 
-    fn fmt_mull_mlal(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if self.accumulate_flag() {
-            write!(
-                f,
-                "{sign}mlal{S}{cond}\t{RdLo}, {RdHi}, {Rm}, {Rs}",
-                sign = self.sign_mark(),
-                S = self.set_cond_mark(),
-                cond = self.cond,
-                RdLo = reg_string(self.rd_lo()),
-                RdHi = reg_string(self.rd_hi()),
-                Rm = reg_string(self.rm()),
-                Rs = reg_string(self.rs()),
-            )
-        } else {
-            write!(
-                f,
-                "{sign}mull{S}{cond}\t{RdLo}, {RdHi}, {Rm}",
+    fn fmt_mull_mlal(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
+        write!(
+            f,
+            "{mnem}\t{RdLo}, {RdHi}, {Rm}, {Rs}",
+            mnem = style.of(format_args!(
+                "{sign}{op}{S}{cond}",
                 sign = self.sign_mark(),
+                op = if self.accumulate_flag() {
+                    "mlal"
+                } else {
+                    "mull"
+                },
                 S = self.set_cond_mark(),
-                cond = self.cond,
-                RdLo = reg_string(self.rd_lo()),
-                RdHi = reg_string(self.rd_hi()),
-                Rm = reg_string(self.rm())
-            )
-        }
+                cond = self.cond
+            )),
+            RdLo = style.reg(self.rd_lo()),
+            RdHi = style.reg(self.rd_hi()),
+            Rm = style.reg(self.rm()),
+            Rs = style.reg(self.rs()),
+        )
     }
 
-    fn fmt_ldr_str_hs(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_ldr_str_hs(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
         if let Ok(transfer_type) = self.halfword_data_transfer_type() {
             write!(
                 f,
-                "{mnem}{type}{cond}\t{Rd}, ",
-                mnem = if self.load_flag() { "ldr" } else { "str" },
-                cond = self.cond,
-                type = transfer_type,
-                Rd = reg_string(self.rd()),
+                "{mnem}\t{Rd}, ",
+                mnem = style.of(format_args!(
+                    "{mnem}{type}{cond}",
+                    mnem = if self.load_flag() { "ldr" } else { "str" },
+                    cond = self.cond,
+                    type = transfer_type,
+                )),
+                Rd = style.reg(self.rd()),
             )?;
-            self.fmt_rn_offset(f, self.ldr_str_hs_offset().unwrap())
+            self.fmt_rn_offset(f, self.ldr_str_hs_offset().unwrap(), style)
         } else {
             write!(f, "<undefined>")
         }
     }
 
-    fn fmt_swi(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_swp(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
+        write!(
+            f,
+            "{mnem}\t{Rd}, {Rm}, [{Rn}]",
+            mnem = style.of(format_args!(
+                "swp{B}{cond}",
+                B = if self.transfer_size() == 1 { "b" } else { "" },
+                cond = self.cond
+            )),
+            Rd = style.reg(self.rd()),
+            Rm = style.reg(self.rm()),
+            Rn = style.reg(self.rn()),
+        )
+    }
+
+    fn fmt_cdp(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
+        write!(
+            f,
+            "{mnem}\tp{cp}, {opc}, c{CRd}, c{CRn}, c{CRm}, {info}",
+            mnem = style.of(format_args!("cdp{cond}", cond = self.cond)),
+            cp = self.cp_num(),
+            opc = self.cp_opcode(),
+            CRd = self.crd(),
+            CRn = self.crn(),
+            CRm = self.crm(),
+            info = self.cp_info()
+        )
+    }
+
+    fn fmt_mrc_mcr(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
         write!(
             f,
-            "swi{cond}\t#{comm:#x}",
-            cond = self.cond,
+            "{mnem}\tp{cp}, {opc}, {Rd}, c{CRn}, c{CRm}, {info}",
+            mnem = style.of(format_args!(
+                "{mnem}{cond}",
+                mnem = if self.load_flag() { "mrc" } else { "mcr" },
+                cond = self.cond
+            )),
+            cp = self.cp_num(),
+            opc = self.cp_opcode(),
+            Rd = style.reg(self.rd()),
+            CRn = self.crn(),
+            CRm = self.crm(),
+            info = self.cp_info()
+        )
+    }
+
+    fn fmt_ldc_stc(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
+        write!(
+            f,
+            "{mnem}\tp{cp}, c{CRd}, [{Rn}",
+            mnem = style.of(format_args!(
+                "{mnem}{L}{cond}",
+                mnem = if self.load_flag() { "ldc" } else { "stc" },
+                L = if self.cp_long_flag() { "l" } else { "" },
+                cond = self.cond
+            )),
+            cp = self.cp_num(),
+            CRd = self.crd(),
+            Rn = style.reg(self.rn()),
+        )?;
+        if self.pre_index_flag() {
+            write!(
+                f,
+                ", #{}]{}",
+                self.ldc_stc_offset(),
+                self.auto_incremenet_mark()
+            )
+        } else {
+            write!(f, "], #{}", self.ldc_stc_offset())
+        }
+    }
+
+    fn fmt_swi(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
+        write!(
+            f,
+            "{mnem}\t#{comm:#x}",
+            mnem = style.of(format_args!("swi{cond}", cond = self.cond)),
             comm = self.swi_comment()
         )
     }
 }
 
-impl fmt::Display for ArmInstruction {
+impl fmt::Display for CycleCost {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parts: Vec<String> = [(self.s, "S"), (self.n, "N"), (self.i, "I")]
+            .iter()
+            .filter(|(count, _)| *count != 0)
+            .map(|(count, kind)| format!("{}{}", count, kind))
+            .collect();
+        write!(f, "{}", parts.join("+"))
+    }
+}
+
+impl ArmInstruction {
+    /// The disassembly followed by the cycle cost, e.g. `ldr r0, [r1] ; 1S+1N+1I`
+    fn fmt_with_cycles(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_insn(f, DisasmStyle::Lowercase)?;
+        write!(f, "\t; {}", self.cycle_cost())
+    }
+
+    fn fmt_insn(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
         use ArmFormat::*;
         match self.fmt {
-            BX => self.fmt_bx(f),
-            B_BL => self.fmt_branch(f),
-            DP => self.fmt_data_processing(f),
-            LDR_STR => self.fmt_ldr_str(f),
-            LDM_STM => self.fmt_ldm_stm(f),
-            MRS => self.fmt_mrs(f),
-            MSR_REG => self.fmt_msr_reg(f),
-            MSR_FLAGS => self.fmt_msr_flags(f),
-            MUL_MLA => self.fmt_mul_mla(f),
-            MULL_MLAL => self.fmt_mull_mlal(f),
-            LDR_STR_HS_IMM => self.fmt_ldr_str_hs(f),
-            LDR_STR_HS_REG => self.fmt_ldr_str_hs(f),
-            SWI => self.fmt_swi(f),
-            _ => write!(f, "({:?})", self),
+            BX => self.fmt_bx(f, style),
+            B_BL => self.fmt_branch(f, style),
+            DP => self.fmt_data_processing(f, style),
+            LDR_STR => self.fmt_ldr_str(f, style),
+            LDM_STM => self.fmt_ldm_stm(f, style),
+            MRS => self.fmt_mrs(f, style),
+            MSR_REG => self.fmt_msr_reg(f, style),
+            MSR_FLAGS => self.fmt_msr_flags(f, style),
+            MUL_MLA => self.fmt_mul_mla(f, style),
+            MULL_MLAL => self.fmt_mull_mlal(f, style),
+            LDR_STR_HS_IMM => self.fmt_ldr_str_hs(f, style),
+            LDR_STR_HS_REG => self.fmt_ldr_str_hs(f, style),
+            SWP => self.fmt_swp(f, style),
+            SWI => self.fmt_swi(f, style),
+            CDP => self.fmt_cdp(f, style),
+            MRC_MCR => self.fmt_mrc_mcr(f, style),
+            LDC_STC => self.fmt_ldc_stc(f, style),
+            UNDEFINED => write!(f, "<undefined>"),
+        }
+    }
+}
+
+impl StyledDisplay for ArmInstruction {
+    fn fmt_styled(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
+        self.fmt_insn(f, style)
+    }
+}
+
+/// The alternate form (`{:#}`) annotates the instruction with its cycle cost
+impl fmt::Display for ArmInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() {
+            self.fmt_with_cycles(f)
+        } else {
+            self.fmt_insn(f, DisasmStyle::Lowercase)
         }
     }
 }
@@ -28,6 +28,9 @@ impl Core {
             ArmFormat::MSR_FLAGS => self.exec_msr_flags(bus, insn),
             ArmFormat::MUL_MLA => self.exec_mul_mla(bus, insn),
             ArmFormat::MULL_MLAL => self.exec_mull_mlal(bus, insn),
+            ArmFormat::CDP | ArmFormat::MRC_MCR | ArmFormat::LDC_STC | ArmFormat::UNDEFINED => {
+                self.exec_undefined(bus, insn)
+            }
             _ => Err(CpuError::UnimplementedCpuInstruction(
                 insn.pc,
                 insn.raw,
@@ -66,8 +69,13 @@ impl Core {
         self.branch_exchange(self.get_reg(insn.rn()))
     }
 
-    fn exec_swi(&mut self, _bus: &mut Bus, _insn: ArmInstruction) -> CpuExecResult {
-        self.exception(Exception::SoftwareInterrupt);
+    fn exec_swi(&mut self, bus: &mut Bus, insn: ArmInstruction) -> CpuExecResult {
+        // The GBA BIOS takes the function number from bits 16..24 of the comment field
+        self.software_interrupt(bus, (insn.swi_comment() >> 16) as u8)
+    }
+
+    fn exec_undefined(&mut self, _bus: &mut Bus, _insn: ArmInstruction) -> CpuExecResult {
+        self.exception(Exception::UndefinedInstruction);
         Ok(CpuPipelineAction::Flush)
     }
 
@@ -111,25 +119,29 @@ impl Core {
     fn exec_msr_flags(&mut self, _bus: &mut Bus, insn: ArmInstruction) -> CpuExecResult {
         let op = insn.operand2()?;
         let op = self.decode_operand2(op, false)?;
+        self.exec_msr_fields(insn, op)
+    }
 
-        let old_mode = self.cpsr.mode();
-        if insn.spsr_flag() {
-            if let Some(index) = old_mode.spsr_index() {
-                self.spsr[index].set_flag_bits(op);
-            } else {
-                panic!("tried to change spsr from invalid mode {}", old_mode)
+    /// MSR that only writes the PSR bytes selected by the instruction's field mask
+    fn exec_msr_fields(&mut self, insn: ArmInstruction, value: u32) -> CpuExecResult {
+        // only N, Z, C and V exist in the flags byte of the ARM7TDMI
+        let mask = insn.psr_field_mask() & !0x0f00_0000;
+        let old = if insn.spsr_flag() {
+            match self.cpsr.mode().spsr_index() {
+                Some(index) => self.spsr[index].get(),
+                None => 0,
             }
         } else {
-            self.cpsr.set_flag_bits(op);
-        }
-        Ok(CpuPipelineAction::IncPC)
+            self.cpsr.get()
+        };
+        self.exec_msr(insn, (old & !mask) | (value & mask))
     }
 
     fn decode_operand2(&mut self, op2: BarrelShifterValue, set_flags: bool) -> CpuResult<u32> {
         match op2 {
             BarrelShifterValue::RotatedImmediate(imm, r) => {
                 let result = imm.rotate_right(r);
-                if set_flags {
+                if set_flags && r != 0 {
                     self.cpsr.set_C((result as u32).bit(31));
                 }
                 Ok(result)
@@ -141,7 +153,11 @@ impl Core {
             } => {
                 // +1I
                 self.add_cycle();
+                let carry = self.cpsr.C();
                 let result = self.register_shift(reg, shift)?;
+                if !set_flags {
+                    self.cpsr.set_C(carry);
+                }
                 Ok(result as u32)
             }
             _ => unreachable!(),
@@ -152,30 +168,36 @@ impl Core {
     ///
     /// Cycles: 1S+x+y (from GBATEK)
     ///         Add x=1I cycles if Op2 shifted-by-register. Add y=1S+1N cycles if Rd=R15.
-    fn exec_data_processing(&mut self, _bus: &mut Bus, insn: ArmInstruction) -> CpuExecResult {
-        // TODO handle carry flag
-
+    fn exec_data_processing(&mut self, bus: &mut Bus, insn: ArmInstruction) -> CpuExecResult {
         let mut pipeline_action = CpuPipelineAction::IncPC;
 
+        let op2 = insn.operand2()?;
         let op1 = if insn.rn() == REG_PC {
-            self.pc as i32 // prefething
+            // prefetching, one more word when the shift amount comes from a register
+            match op2 {
+                BarrelShifterValue::ShiftedRegister {
+                    shift: ShiftedRegister::ByRegister(_, _),
+                    ..
+                } => (self.pc + 4) as i32,
+                _ => self.pc as i32,
+            }
         } else {
             self.get_reg(insn.rn()) as i32
         };
 
         let opcode = insn.opcode().unwrap();
 
-        let set_flags = opcode.is_setting_flags() || insn.set_cond_flag();
-        let op2 = insn.operand2()?;
+        // the comparison opcodes without the S bit are MRS/MSR encodings
+        let set_flags = insn.set_cond_flag();
         let op2 = self.decode_operand2(op2, set_flags)? as i32;
 
-        if !insn.set_cond_flag() {
+        if !set_flags {
             match opcode {
                 AluOpCode::TEQ | AluOpCode::CMN => {
-                    return self.exec_msr(insn, op2 as u32);
+                    return self.exec_msr_fields(insn, op2 as u32);
                 }
                 AluOpCode::TST | AluOpCode::CMP => {
-                    unimplemented!("TODO implement MRS");
+                    return self.exec_mrs(bus, insn);
                 }
                 _ => (),
             }
@@ -223,11 +245,14 @@ impl Core {
             addr
         };
 
+        // LDRT/STRT (insn.force_user_mode_flag()) are regular post-indexed transfers done with
+        // user mode privileges, which only matter with memory protection and the GBA has none.
+
         if insn.load_flag() {
             let data = if insn.transfer_size() == 1 {
                 self.load_8(addr, bus) as u32
             } else {
-                self.load_32(addr, bus)
+                self.ldr_word(addr, bus)
             };
 
             self.set_reg(insn.rd(), data);
@@ -284,10 +309,8 @@ impl Core {
         if insn.load_flag() {
             let data = match insn.halfword_data_transfer_type().unwrap() {
                 ArmHalfwordTransferType::SignedByte => self.load_8(addr, bus) as u8 as i8 as u32,
-                ArmHalfwordTransferType::SignedHalfwords => {
-                    self.load_16(addr, bus) as u16 as i16 as u32
-                }
-                ArmHalfwordTransferType::UnsignedHalfwords => self.load_16(addr, bus) as u16 as u32,
+                ArmHalfwordTransferType::SignedHalfwords => self.ldr_sign_half(addr, bus),
+                ArmHalfwordTransferType::UnsignedHalfwords => self.ldr_half(addr, bus),
             };
 
             self.set_reg(insn.rd(), data);
@@ -343,6 +366,10 @@ impl Core {
             unimplemented!("Too tired to implement the mode enforcement");
         }
 
+        if rlist.is_empty() {
+            return Ok(self.exec_ldm_stm_empty(bus, insn));
+        }
+
         if is_load {
             if rlist.contains(&rn) {
                 writeback = false;
@@ -390,6 +417,33 @@ impl Core {
         Ok(pipeline_action)
     }
 
+    /// An empty register list transfers r15 alone, but the base moves by 0x40 as if all 16
+    /// registers were in the list. In a descending block r15 is the lowest of them.
+    fn exec_ldm_stm_empty(&mut self, bus: &mut Bus, insn: ArmInstruction) -> CpuPipelineAction {
+        let rn = insn.rn();
+        let base = self.gpr[rn];
+        let (addr, new_base) = match (insn.add_offset_flag(), insn.pre_index_flag()) {
+            (true, false) => (base, base.wrapping_add(0x40)),
+            (true, true) => (base.wrapping_add(4), base.wrapping_add(0x40)),
+            (false, false) => (base.wrapping_sub(0x3c), base.wrapping_sub(0x40)),
+            (false, true) => (base.wrapping_sub(0x40), base.wrapping_sub(0x40)),
+        };
+
+        let pipeline_action = if insn.load_flag() {
+            self.add_cycle();
+            let val = self.load_32(addr, bus);
+            self.set_reg(REG_PC, val);
+            CpuPipelineAction::Flush
+        } else {
+            self.store_32(addr, insn.pc + 12, bus);
+            CpuPipelineAction::IncPC
+        };
+        if insn.write_back_flag() {
+            self.set_reg(rn, new_base);
+        }
+        pipeline_action
+    }
+
     fn exec_mul_mla(&mut self, bus: &mut Bus, insn: ArmInstruction) -> CpuExecResult {
         let (rd, rn, rs, rm) = (insn.rd(), insn.rn(), insn.rs(), insn.rm());
 
@@ -426,40 +480,40 @@ impl Core {
     }
 
     fn exec_mull_mlal(&mut self, bus: &mut Bus, insn: ArmInstruction) -> CpuExecResult {
-        let (rd_hi, rd_lo, rn, rs, rm) =
-            (insn.rd_hi(), insn.rd_lo(), insn.rn(), insn.rs(), insn.rm());
+        let (rd_hi, rd_lo, rs, rm) = (insn.rd_hi(), insn.rd_lo(), insn.rs(), insn.rm());
 
         // check validity
-        if REG_PC == rd_hi || REG_PC == rd_lo || REG_PC == rn || REG_PC == rs || REG_PC == rm {
-            return Err(CpuError::IllegalInstruction);
-        }
-        if rd_hi != rd_hi && rd_hi != rm && rd_lo != rm {
+        if REG_PC == rd_hi || REG_PC == rd_lo || REG_PC == rs || REG_PC == rm {
             return Err(CpuError::IllegalInstruction);
         }
+        // overlapping RdHi, RdLo and Rm are unpredictable on paper, but the CPU runs them all the
+        // same: the operands are read first and RdHi is written last
 
-        let op1 = self.get_reg(rm) as u64;
-        let op2 = self.get_reg(rs) as u64;
+        let op1 = self.get_reg(rm);
+        let op2 = self.get_reg(rs);
         let mut result: u64 = if insn.u_flag() {
             // signed
-            (op1 as i64).wrapping_mul(op2 as i64) as u64
+            (op1 as i32 as i64).wrapping_mul(op2 as i32 as i64) as u64
         } else {
-            op1.wrapping_mul(op2)
+            (op1 as u64).wrapping_mul(op2 as u64)
         };
         self.add_cycle();
 
         if insn.accumulate_flag() {
-            result = result.wrapping_add(self.get_reg(rn) as u64);
+            let acc = (self.get_reg(rd_hi) as u64) << 32 | self.get_reg(rd_lo) as u64;
+            result = result.wrapping_add(acc);
             self.add_cycle();
         }
 
-        self.set_reg(rd_hi, (result >> 32) as u32);
         self.set_reg(rd_lo, (result & 0xffffffff) as u32);
+        self.set_reg(rd_hi, (result >> 32) as u32);
 
-        let m = self.get_required_multipiler_array_cycles(self.get_reg(rs) as i32);
+        let m = self.get_required_multipiler_array_cycles(op2 as i32);
         for _ in 0..m {
             self.add_cycle();
         }
 
+        // C and V are left as they were
         if insn.set_cond_flag() {
             self.cpsr.set_N((result as i64) < 0);
             self.cpsr.set_Z(result == 0);
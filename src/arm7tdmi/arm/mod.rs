@@ -2,7 +2,7 @@ pub mod display;
 pub mod exec;
 
 use super::alu::*;
-use crate::arm7tdmi::{Addr, InstructionDecoder, InstructionDecoderError};
+use crate::arm7tdmi::{Addr, InstructionDecoder, InstructionDecoderError, REG_PC};
 
 use crate::bit::BitIndex;
 use crate::byteorder::{LittleEndian, ReadBytesExt};
@@ -88,6 +88,14 @@ pub enum ArmFormat {
     MSR_REG,
     /// Tanssfer immediate/register to PSR flags only
     MSR_FLAGS,
+    /// Coprocessor Data Operation
+    CDP,
+    /// Coprocessor Register Transfer
+    MRC_MCR,
+    /// Coprocessor Data Transfer
+    LDC_STC,
+    /// Architecturally undefined
+    UNDEFINED,
 }
 
 #[derive(Debug, PartialEq, Primitive)]
@@ -97,6 +105,30 @@ pub enum ArmHalfwordTransferType {
     SignedHalfwords = 0b11,
 }
 
+/// Sequential, non-sequential and internal cycles taken by an instruction
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct CycleCost {
+    pub s: usize,
+    pub n: usize,
+    pub i: usize,
+}
+
+impl CycleCost {
+    pub fn new(s: usize, n: usize, i: usize) -> CycleCost {
+        CycleCost { s, n, i }
+    }
+
+    /// Refilling the pipeline after the PC was written
+    fn add_pc_write(mut self) -> CycleCost {
+        self.s += 1;
+        self.n += 1;
+        self
+    }
+}
+
+/// Internal cycles of the multiplier depend on the operand, this is the most it takes
+const MUL_MAX_INTERNAL_CYCLES: usize = 4;
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub struct ArmInstruction {
     pub cond: ArmCond,
@@ -124,8 +156,8 @@ impl InstructionDecoder for ArmInstruction {
             Ok(BX)
         } else if (0x0e00_0000 & raw) == 0x0a00_0000 {
             Ok(B_BL)
-        } else if (0xe000_0010 & raw) == 0x0600_0000 {
-            Err(ArmDecodeError::new(UnknownInstructionFormat, raw, addr))
+        } else if (0x0e00_0010 & raw) == 0x0600_0010 {
+            Ok(UNDEFINED)
         } else if (0x0fb0_0ff0 & raw) == 0x0100_0090 {
             Ok(SWP)
         } else if (0x0fc0_00f0 & raw) == 0x0000_0090 {
@@ -146,6 +178,12 @@ impl InstructionDecoder for ArmInstruction {
             Ok(LDR_STR_HS_IMM)
         } else if (0x0e00_0000 & raw) == 0x0800_0000 {
             Ok(LDM_STM)
+        } else if (0x0e00_0000 & raw) == 0x0c00_0000 {
+            Ok(LDC_STC)
+        } else if (0x0f00_0010 & raw) == 0x0e00_0000 {
+            Ok(CDP)
+        } else if (0x0f00_0010 & raw) == 0x0e00_0010 {
+            Ok(MRC_MCR)
         } else if (0x0f00_0000 & raw) == 0x0f00_0000 {
             Ok(SWI)
         } else if (0x0c00_0000 & raw) == 0x0000_0000 {
@@ -236,6 +274,12 @@ impl ArmInstruction {
         self.raw.bit(21)
     }
 
+    /// LDRT/STRT - for post-indexed LDR/STR the W bit isn't needed for writeback,
+    /// instead it forces the transfer to be done in user mode.
+    pub fn force_user_mode_flag(&self) -> bool {
+        !self.pre_index_flag() && self.write_back_flag()
+    }
+
     pub fn accumulate_flag(&self) -> bool {
         self.raw.bit(21)
     }
@@ -268,6 +312,17 @@ impl ArmInstruction {
         self.raw.bit(22)
     }
 
+    /// The PSR bytes written by MSR (bits 16..20 select control, extension, status, flags)
+    pub fn psr_field_mask(&self) -> u32 {
+        let mut mask = 0;
+        for i in 0..4 {
+            if self.raw.bit(16 + i) {
+                mask |= 0xff << (8 * i);
+            }
+        }
+        mask
+    }
+
     pub fn add_offset_flag(&self) -> bool {
         self.raw.bit(23)
     }
@@ -352,6 +407,107 @@ impl ArmInstruction {
     pub fn swi_comment(&self) -> u32 {
         self.raw.bit_range(0..24)
     }
+
+    /// The coprocessor number, p0-p15
+    pub fn cp_num(&self) -> usize {
+        self.raw.bit_range(8..12) as usize
+    }
+
+    /// Coprocessor operation, CDP has 4 bits of it and MRC/MCR 3
+    pub fn cp_opcode(&self) -> u32 {
+        match self.fmt {
+            ArmFormat::CDP => self.raw.bit_range(20..24),
+            _ => self.raw.bit_range(21..24),
+        }
+    }
+
+    /// The second coprocessor operation field of CDP and MRC/MCR
+    pub fn cp_info(&self) -> u32 {
+        self.raw.bit_range(5..8)
+    }
+
+    pub fn crn(&self) -> usize {
+        self.raw.bit_range(16..20) as usize
+    }
+
+    pub fn crd(&self) -> usize {
+        self.raw.bit_range(12..16) as usize
+    }
+
+    pub fn crm(&self) -> usize {
+        self.raw.bit_range(0..4) as usize
+    }
+
+    /// LDC/STC - the N bit, a long transfer
+    pub fn cp_long_flag(&self) -> bool {
+        self.raw.bit(22)
+    }
+
+    /// LDC/STC - the word offset in bytes, signed by the U bit
+    pub fn ldc_stc_offset(&self) -> i32 {
+        let offset = (self.raw.bit_range(0..8) * 4) as i32;
+        if self.add_offset_flag() {
+            offset
+        } else {
+            -offset
+        }
+    }
+
+    /// Cycles taken when the condition passes, per GBATEK's instruction cycle times.
+    /// Multiplies are counted with the most internal cycles their operand can take.
+    pub fn cycle_cost(&self) -> CycleCost {
+        use ArmFormat::*;
+        match self.fmt {
+            BX | B_BL | SWI => CycleCost::new(2, 1, 0),
+            DP => {
+                let mut cost = CycleCost::new(1, 0, 0);
+                if let Ok(BarrelShifterValue::ShiftedRegister {
+                    shift: ShiftedRegister::ByRegister(..),
+                    ..
+                }) = self.operand2()
+                {
+                    cost.i += 1;
+                }
+                let writes_rd = !self.opcode().unwrap().is_setting_flags();
+                if writes_rd && self.rd() == REG_PC {
+                    cost = cost.add_pc_write();
+                }
+                cost
+            }
+            MUL_MLA => CycleCost::new(
+                1,
+                0,
+                MUL_MAX_INTERNAL_CYCLES + self.accumulate_flag() as usize,
+            ),
+            MULL_MLAL => CycleCost::new(
+                1,
+                0,
+                MUL_MAX_INTERNAL_CYCLES + 1 + self.accumulate_flag() as usize,
+            ),
+            LDR_STR | LDR_STR_HS_REG | LDR_STR_HS_IMM => {
+                if !self.load_flag() {
+                    CycleCost::new(0, 2, 0)
+                } else if self.rd() == REG_PC {
+                    CycleCost::new(1, 1, 1).add_pc_write()
+                } else {
+                    CycleCost::new(1, 1, 1)
+                }
+            }
+            LDM_STM => {
+                let list = self.register_list();
+                if !self.load_flag() {
+                    CycleCost::new(list.len().saturating_sub(1), 2, 0)
+                } else if list.contains(&REG_PC) {
+                    CycleCost::new(list.len(), 1, 1).add_pc_write()
+                } else {
+                    CycleCost::new(list.len(), 1, 1)
+                }
+            }
+            SWP => CycleCost::new(1, 2, 1),
+            MRS | MSR_REG | MSR_FLAGS => CycleCost::new(1, 0, 0),
+            CDP | MRC_MCR | LDC_STC | UNDEFINED => CycleCost::new(2, 1, 1),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -361,6 +517,29 @@ mod tests {
     use crate::arm7tdmi::*;
     use crate::sysbus::BoxedMemory;
 
+    #[test]
+    fn cycle_annotations() {
+        let disass = |raw| format!("{:#}", ArmInstruction::decode(raw, 0).unwrap());
+        assert_eq!(disass(0xe5910000), "ldr\tr0, [r1, #0]\t; 0x0\t; 1S+1N+1I");
+        assert_eq!(disass(0xe591f000), "ldr\tpc, [r1, #0]\t; 0x0\t; 2S+2N+1I");
+        assert_eq!(disass(0xe5810000), "str\tr0, [r1, #0]\t; 0x0\t; 2N");
+        assert_eq!(
+            disass(0xe890800e),
+            "ldmia\tr0, {r1, r2, r3, pc}\t; 5S+2N+1I"
+        );
+        assert_eq!(disass(0xe92d4010), "stmdb\tsp!, {r4, lr}\t; 1S+2N");
+        assert_eq!(disass(0xe0810312), "add\tr0, r1, r2, lsl r3\t; 1S+1I");
+        assert_eq!(disass(0xe1a0f00e), "mov\tpc, lr\t; 2S+1N");
+        assert_eq!(disass(0xe15f000e), "cmp\tpc, lr\t; 1S");
+        assert_eq!(disass(0xea000002), "b\t0x10\t; 2S+1N");
+        assert_eq!(disass(0xe0010392), "mul\tr1, r2, r3\t; 1S+4I");
+
+        // plain Display stays the same
+        let decoded = ArmInstruction::decode(0xe5910000, 0).unwrap();
+        assert_eq!(format!("{}", decoded), "ldr\tr0, [r1, #0]\t; 0x0");
+        assert_eq!(decoded.cycle_cost(), CycleCost::new(1, 1, 1));
+    }
+
     #[test]
     fn swi() {
         let mut core = Core::new();
@@ -383,6 +562,225 @@ mod tests {
         assert_eq!(core.pc, Exception::SoftwareInterrupt as u32);
     }
 
+    #[test]
+    fn coprocessor_instructions() {
+        let disass = |raw| {
+            let decoded = ArmInstruction::decode(raw, 0).unwrap();
+            (decoded.fmt, format!("{}", decoded))
+        };
+        assert_eq!(
+            disass(0xee110f10),
+            (ArmFormat::MRC_MCR, "mrc\tp15, 0, r0, c1, c0, 0".to_string())
+        );
+        assert_eq!(
+            disass(0x1e2312b4),
+            (
+                ArmFormat::MRC_MCR,
+                "mcrne\tp2, 1, r1, c3, c4, 5".to_string()
+            )
+        );
+        assert_eq!(
+            disass(0xee000000),
+            (ArmFormat::CDP, "cdp\tp0, 0, c0, c0, c0, 0".to_string())
+        );
+        assert_eq!(
+            disass(0xee5312e4),
+            (ArmFormat::CDP, "cdp\tp2, 5, c1, c3, c4, 7".to_string())
+        );
+        assert_eq!(
+            disass(0xed912104),
+            (ArmFormat::LDC_STC, "ldc\tp1, c2, [r1, #16]".to_string())
+        );
+        assert_eq!(
+            disass(0xecc12104),
+            (ArmFormat::LDC_STC, "stcl\tp1, c2, [r1], #16".to_string())
+        );
+        assert_eq!(
+            disass(0xed312104),
+            (ArmFormat::LDC_STC, "ldc\tp1, c2, [r1, #-16]!".to_string())
+        );
+
+        // with no coprocessors attached they all take the undefined instruction trap
+        let mut core = Core::new();
+        core.pc = 0x100 + 8;
+        let mut mem = BoxedMemory::new(vec![].into_boxed_slice());
+        let decoded = ArmInstruction::decode(0xee110f10, 0x100).unwrap();
+        assert_eq!(
+            core.exec_arm(&mut mem, decoded),
+            Ok(CpuPipelineAction::Flush)
+        );
+        assert_eq!(core.cpsr.mode(), CpuMode::Undefined);
+    }
+
+    #[test]
+    fn ldm_stm_empty_list() {
+        let mut core = Core::new();
+        let mut mem = BoxedMemory::new(vec![0; 0x200].into_boxed_slice());
+        mem.write_32(0x100, 0x0800_0000);
+
+        // ldmia r0!, {}
+        core.set_reg(0, 0x100);
+        let decoded = ArmInstruction::decode(0xe8b00000, 0x20).unwrap();
+        assert_eq!(
+            core.exec_arm(&mut mem, decoded),
+            Ok(CpuPipelineAction::Flush)
+        );
+        assert_eq!(core.get_reg(REG_PC), 0x0800_0000);
+        assert_eq!(core.get_reg(0), 0x140);
+
+        // stmdb r1!, {}, r15 is stored at the bottom of the block
+        core.set_reg(1, 0x180);
+        let decoded = ArmInstruction::decode(0xe9210000, 0x20).unwrap();
+        assert_eq!(
+            core.exec_arm(&mut mem, decoded),
+            Ok(CpuPipelineAction::IncPC)
+        );
+        assert_eq!(mem.read_32(0x140), 0x20 + 12);
+        assert_eq!(core.get_reg(1), 0x140);
+
+        // stmda r2, {} without writeback
+        core.set_reg(2, 0x1c0);
+        let decoded = ArmInstruction::decode(0xe8020000, 0x20).unwrap();
+        core.exec_arm(&mut mem, decoded).unwrap();
+        assert_eq!(mem.read_32(0x184), 0x20 + 12);
+        assert_eq!(core.get_reg(2), 0x1c0);
+    }
+
+    #[test]
+    fn misaligned_loads() {
+        let mut core = Core::new();
+        let mut mem = BoxedMemory::new(vec![0; 0x200].into_boxed_slice());
+        mem.write_32(0x100, 0x4433_2211);
+        mem.write_32(0x104, 0x8877_6655);
+
+        // ldr r0, [r1]
+        let ldr = ArmInstruction::decode(0xe5910000, 0).unwrap();
+        let rotated = [0x4433_2211, 0x1144_3322, 0x2211_4433, 0x3322_1144];
+        for (offset, &expected) in rotated.iter().enumerate() {
+            core.set_reg(1, 0x100 + offset as u32);
+            core.exec_arm(&mut mem, ldr).unwrap();
+            assert_eq!(core.get_reg(0), expected, "ldr at {:#x}", 0x100 + offset);
+        }
+
+        // ldrh r0, [r1]
+        core.set_reg(1, 0x101);
+        core.exec_arm(&mut mem, ArmInstruction::decode(0xe1d100b0, 0).unwrap())
+            .unwrap();
+        assert_eq!(core.get_reg(0), 0x1100_0022);
+        // ldrsh r0, [r1], an odd address loads a signed byte
+        core.set_reg(1, 0x107);
+        core.exec_arm(&mut mem, ArmInstruction::decode(0xe1d100f0, 0).unwrap())
+            .unwrap();
+        assert_eq!(core.get_reg(0), 0xffff_ff88);
+        core.set_reg(1, 0x106);
+        core.exec_arm(&mut mem, ArmInstruction::decode(0xe1d100f0, 0).unwrap())
+            .unwrap();
+        assert_eq!(core.get_reg(0), 0xffff_8877);
+    }
+
+    #[test]
+    fn multiply_long() {
+        let mut core = Core::new();
+        let mut mem = BoxedMemory::new(vec![].into_boxed_slice());
+        let mut exec = |core: &mut Core, raw: u32, rm: u32, rs: u32| {
+            core.set_reg(2, rm);
+            core.set_reg(3, rs);
+            core.exec_arm(&mut mem, ArmInstruction::decode(raw, 0).unwrap())
+                .unwrap();
+            (core.get_reg(1), core.get_reg(0))
+        };
+        core.cpsr.set_C(true);
+        core.cpsr.set_V(true);
+
+        // smulls r0, r1, r2, r3
+        let decoded = ArmInstruction::decode(0xe0d10392, 0).unwrap();
+        assert_eq!(format!("{}", decoded), "smulls\tr0, r1, r2, r3");
+        assert_eq!(
+            exec(&mut core, 0xe0d10392, -3i32 as u32, 5),
+            (0xffff_ffff, -15i32 as u32)
+        );
+        assert!(core.cpsr.N() && !core.cpsr.Z());
+        assert!(core.cpsr.C() && core.cpsr.V());
+        assert_eq!(
+            exec(&mut core, 0xe0d10392, 0x8000_0000, 0x8000_0000),
+            (0x4000_0000, 0)
+        );
+        assert!(!core.cpsr.N() && !core.cpsr.Z());
+
+        // umulls r0, r1, r2, r3
+        assert_eq!(
+            exec(&mut core, 0xe0910392, 0xffff_ffff, 0xffff_ffff),
+            (0xffff_fffe, 1)
+        );
+        assert!(core.cpsr.N() && !core.cpsr.Z());
+        assert_eq!(exec(&mut core, 0xe0910392, 0x1_0000, 0), (0, 0));
+        assert!(!core.cpsr.N() && core.cpsr.Z());
+        // without S the flags stay, umull r0, r1, r2, r3
+        assert_eq!(exec(&mut core, 0xe0810392, 0x1_0000, 0x1_0000), (1, 0));
+        assert!(!core.cpsr.N() && core.cpsr.Z());
+
+        // umlals r0, r1, r2, r3 adds to r1:r0, carrying into the high word
+        core.set_reg(1, 0);
+        core.set_reg(0, 0xffff_ffff);
+        assert_eq!(exec(&mut core, 0xe0b10392, 2, 3), (1, 5));
+        assert!(!core.cpsr.N() && !core.cpsr.Z());
+
+        // smlals r0, r1, r2, r3 accumulates a negative product down to zero
+        core.set_reg(1, 0);
+        core.set_reg(0, 6);
+        assert_eq!(exec(&mut core, 0xe0f10392, -2i32 as u32, 3), (0, 0));
+        assert!(!core.cpsr.N() && core.cpsr.Z());
+        // and below
+        assert_eq!(
+            exec(&mut core, 0xe0f10392, -1i32 as u32, 1),
+            (0xffff_ffff, 0xffff_ffff)
+        );
+        assert!(core.cpsr.N() && !core.cpsr.Z());
+
+        // overlapping registers still run, umull r2, r1, r2, r3
+        assert_eq!(exec(&mut core, 0xe0812392, 3, 5), (0, 0xffff_ffff));
+        assert_eq!(core.get_reg(2), 15);
+        // and RdHi wins when it's RdLo too, umull r0, r0, r2, r3
+        exec(&mut core, 0xe0800392, 0x1_0000, 0x1_0000);
+        assert_eq!(core.get_reg(0), 1);
+    }
+
+    #[test]
+    fn swp() {
+        let decoded = ArmInstruction::decode(0xe1010092, 0).unwrap();
+        assert_eq!(decoded.fmt, ArmFormat::SWP);
+        assert_eq!(format!("{}", decoded), "swp\tr0, r2, [r1]");
+        let decoded = ArmInstruction::decode(0xb1410092, 0).unwrap();
+        assert_eq!(format!("{}", decoded), "swpblt\tr0, r2, [r1]");
+    }
+
+    #[test]
+    fn undefined_instruction() {
+        let mut core = Core::new();
+        core.set_cpsr(0x1f); // System
+        core.pc = 0x100 + 8;
+
+        let bytes = vec![];
+        let mut mem = BoxedMemory::new(bytes.into_boxed_slice());
+
+        // the permanently undefined encoding
+        let decoded = ArmInstruction::decode(0xe7f000f0, 0x100).unwrap();
+        assert_eq!(decoded.fmt, ArmFormat::UNDEFINED);
+        assert_eq!(format!("{}", decoded), "<undefined>");
+        assert_eq!(
+            core.exec_arm(&mut mem, decoded),
+            Ok(CpuPipelineAction::Flush)
+        );
+        assert_eq!(core.cpsr.mode(), CpuMode::Undefined);
+        assert_eq!(core.pc, Exception::UndefinedInstruction as u32);
+        assert_eq!(core.pc, 0x04);
+        assert!(core.cpsr.irq_disabled());
+        assert_eq!(
+            core.spsr[CpuMode::Undefined.spsr_index().unwrap()].get(),
+            0x1f
+        );
+    }
+
     #[test]
     fn branch_forwards() {
         // 0x20:   b 0x30
@@ -433,6 +831,38 @@ mod tests {
         assert_eq!(core.pc, 0x10);
     }
 
+    #[test]
+    fn branch_symbols() {
+        use std::collections::BTreeMap;
+
+        let mut symbols = BTreeMap::new();
+        symbols.insert(0x0800_00c8, "game_init".to_string());
+        symbols.insert(0x0800_0200, "main_loop".to_string());
+
+        // 0x08000000: bl 0x80000c8
+        let decoded = ArmInstruction::decode(0xeb_00_00_30, 0x0800_0000).unwrap();
+        assert_eq!(format!("{}", decoded), "bl\t0x80000c8");
+        assert_eq!(
+            decoded.disassemble_with_symbols(&symbols),
+            "bl\tgame_init <0x80000c8>"
+        );
+
+        // 0x08000000: bne 0x80000d8
+        let decoded = ArmInstruction::decode(0x1a_00_00_34, 0x0800_0000).unwrap();
+        assert_eq!(
+            decoded.disassemble_with_symbols(&symbols),
+            "bne\tgame_init+0x10 <0x80000d8>"
+        );
+
+        // nothing at or below the target
+        let decoded = ArmInstruction::decode(0xea_00_00_02, 0x20).unwrap();
+        assert_eq!(decoded.disassemble_with_symbols(&symbols), "b\t0x30");
+
+        // other instructions are left alone
+        let decoded = ArmInstruction::decode(0xe1_2f_ff_10, 0x0800_0000).unwrap();
+        assert_eq!(decoded.disassemble_with_symbols(&symbols), "bx\tr0");
+    }
+
     #[test]
     fn ldr_pre_index() {
         // ldreq r2, [r5, -r6, lsl #5]
@@ -477,6 +907,76 @@ mod tests {
         assert_eq!(core.gpr[2], 0x1337);
     }
 
+    #[test]
+    fn ldr_negative_immediate_offset() {
+        // ldr r0, [r1, #-4]
+        let decoded = ArmInstruction::decode(0xe5110004, 0).unwrap();
+        assert_eq!(decoded.fmt, ArmFormat::LDR_STR);
+        assert_eq!(decoded.add_offset_flag(), false);
+        assert_eq!(
+            decoded.ldr_str_offset(),
+            BarrelShifterValue::ImmediateValue(-4)
+        );
+        assert_eq!(format!("{}", decoded), "ldr\tr0, [r1, #-4]\t; -0x4");
+
+        // ldr r0, [pc, #-4] @ 0x10
+        let decoded_pc = ArmInstruction::decode(0xe51f0004, 0x10).unwrap();
+        assert_eq!(format!("{}", decoded_pc), "ldr\tr0, [pc, #-4]\t; 0x14");
+
+        let mut core = Core::new();
+        core.set_reg(1, 0x8);
+
+        #[rustfmt::skip]
+        let bytes = vec![
+            /*  0: */ 0xaa, 0xbb, 0xcc, 0xdd,
+            /*  4: */ 0x37, 0x13, 0x00, 0x00,
+            /*  8: */ 0xaa, 0xbb, 0xcc, 0xdd,
+        ];
+        let mut mem = BoxedMemory::new(bytes.into_boxed_slice());
+
+        assert_eq!(
+            core.exec_arm(&mut mem, decoded),
+            Ok(CpuPipelineAction::IncPC)
+        );
+        assert_eq!(core.get_reg(0), 0x1337);
+        // no writeback
+        assert_eq!(core.get_reg(1), 0x8);
+    }
+
+    #[test]
+    fn ldrt_post_index() {
+        // ldrt r0, [r1], #4
+        let decoded = ArmInstruction::decode(0xe4b10004, 0).unwrap();
+        assert_eq!(decoded.fmt, ArmFormat::LDR_STR);
+        assert_eq!(decoded.force_user_mode_flag(), true);
+        assert_eq!(format!("{}", decoded), "ldrt\tr0, [r1], #4\t; 0x4");
+
+        // ldr r0, [r1, #4]! - pre-indexed writeback isn't a user mode transfer
+        let decoded_pre = ArmInstruction::decode(0xe5b10004, 0).unwrap();
+        assert_eq!(decoded_pre.force_user_mode_flag(), false);
+        assert_eq!(format!("{}", decoded_pre), "ldr\tr0, [r1, #4]!\t; 0x4");
+
+        let mut core = Core::new();
+        core.set_reg(1, 0x4);
+
+        #[rustfmt::skip]
+        let bytes = vec![
+            /*  0: */ 0xaa, 0xbb, 0xcc, 0xdd,
+            /*  4: */ 0x37, 0x13, 0x00, 0x00,
+            /*  8: */ 0xaa, 0xbb, 0xcc, 0xdd,
+        ];
+        let mut mem = BoxedMemory::new(bytes.into_boxed_slice());
+
+        assert_eq!(
+            core.exec_arm(&mut mem, decoded),
+            Ok(CpuPipelineAction::IncPC)
+        );
+        // loaded from the base, then written back
+        assert_eq!(core.get_reg(0), 0x1337);
+        assert_eq!(core.get_reg(1), 0x8);
+        assert_eq!(core.cpsr.mode(), CpuMode::Supervisor);
+    }
+
     #[test]
     fn str_post_index() {
         // strteq r2, [r4], -r7, asr #8
@@ -549,4 +1049,116 @@ mod tests {
         );
         assert_eq!(mem.read_32(core.get_reg(REG_SP) + 0x10), 0x12345678);
     }
+
+    #[test]
+    fn compare_ops_flags() {
+        let bytes = vec![];
+        let mut mem = BoxedMemory::new(bytes.into_boxed_slice());
+        let mut core = Core::new();
+
+        let mut exec = |core: &mut Core, raw: u32| {
+            let decoded = ArmInstruction::decode(raw, 0).unwrap();
+            assert_eq!(decoded.fmt, ArmFormat::DP);
+            let regs = core.get_registers();
+            assert_eq!(
+                core.exec_arm(&mut mem, decoded),
+                Ok(CpuPipelineAction::IncPC)
+            );
+            // Rd is set to r2 in the encodings, nothing may be written
+            assert_eq!(core.get_registers(), regs);
+        };
+        let flags = |core: &Core| (core.cpsr.N(), core.cpsr.Z(), core.cpsr.C(), core.cpsr.V());
+
+        // cmp r0, r1 - unsigned borrow, C is set when r0 >= r1 unsigned
+        core.set_reg(0, 0xffff_ffff);
+        core.set_reg(1, 1);
+        core.set_reg(2, 0x1234);
+        exec(&mut core, 0xe1502001);
+        assert_eq!(flags(&core), (true, false, true, false));
+
+        core.set_reg(0, 1);
+        core.set_reg(1, 2);
+        exec(&mut core, 0xe1502001);
+        assert_eq!(flags(&core), (true, false, false, false));
+
+        // cmn r0, r1 - signed overflow
+        core.set_reg(0, 0x7fff_ffff);
+        core.set_reg(1, 1);
+        exec(&mut core, 0xe1702001);
+        assert_eq!(flags(&core), (true, false, false, true));
+
+        // tst r0, r1, lsl #1 - the carry comes from the shifter, V is left alone
+        core.set_reg(0, 1);
+        core.set_reg(1, 0x8000_0000);
+        exec(&mut core, 0xe1102081);
+        assert_eq!(flags(&core), (false, true, true, true));
+
+        // teq r0, #0x80000000 - the carry is bit 31 of the rotated immediate
+        core.cpsr.set_C(false);
+        core.set_reg(0, 0x8000_0000);
+        exec(&mut core, 0xe3302102);
+        assert_eq!(flags(&core), (false, true, true, true));
+
+        // teq r0, #1 - not rotated, the carry is unchanged
+        core.set_reg(0, 1);
+        core.cpsr.set_C(false);
+        exec(&mut core, 0xe3302001);
+        assert_eq!(flags(&core), (false, true, false, true));
+    }
+
+    #[test]
+    fn shifter_carry_without_s_bit() {
+        let bytes = vec![];
+        let mut mem = BoxedMemory::new(bytes.into_boxed_slice());
+        let mut core = Core::new();
+
+        // mov r0, r1, lsl #1
+        let decoded = ArmInstruction::decode(0xe1a00081, 0).unwrap();
+        core.set_reg(1, 0x8000_0000);
+        core.cpsr.set_C(false);
+        core.exec_arm(&mut mem, decoded).unwrap();
+        assert_eq!(core.get_reg(0), 0);
+        assert_eq!(core.cpsr.C(), false);
+    }
+
+    #[test]
+    fn psr_transfer_encodings() {
+        let bytes = vec![];
+        let mut mem = BoxedMemory::new(bytes.into_boxed_slice());
+        let mut core = Core::new();
+        core.cpsr.set_N(true);
+
+        // msr cpsr_c, r0 (TEQ without S)
+        let decoded = ArmInstruction::decode(0xe121f000, 0).unwrap();
+        assert_eq!(decoded.fmt, ArmFormat::DP);
+        core.set_reg(0, 0x0000_001f);
+        core.exec_arm(&mut mem, decoded).unwrap();
+        assert_eq!(core.cpsr.mode(), CpuMode::System);
+        assert_eq!(core.cpsr.N(), true);
+
+        // mrs r2, cpsr with nonzero SBZ bits (TST without S)
+        let decoded = ArmInstruction::decode(0xe10f2001, 0).unwrap();
+        assert_eq!(decoded.fmt, ArmFormat::DP);
+        core.exec_arm(&mut mem, decoded).unwrap();
+        assert_eq!(core.get_reg(2), core.cpsr.get());
+    }
+
+    #[test]
+    fn msr_flags_only() {
+        let bytes = vec![];
+        let mut mem = BoxedMemory::new(bytes.into_boxed_slice());
+        let mut core = Core::new();
+        core.set_cpsr(0x0000_00df); // System, IRQ and FIQ disabled
+
+        // msr cpsr_f, #0xf0000000
+        let decoded = ArmInstruction::decode(0xe328f20f, 0).unwrap();
+        assert_eq!(decoded.fmt, ArmFormat::MSR_FLAGS);
+        assert_eq!(
+            format!("{}", decoded),
+            "msr\tCPSR_f, #-268435456\t; 0xf0000000\t; N=true Z=true C=true V=true"
+        );
+        core.exec_arm(&mut mem, decoded).unwrap();
+        assert_eq!(core.cpsr.get(), 0xf000_00df);
+        assert_eq!(core.cpsr.mode(), CpuMode::System);
+    }
 }
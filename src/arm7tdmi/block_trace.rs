@@ -0,0 +1,121 @@
+use std::fmt;
+
+use super::cpu::Core;
+use super::cycle_breakdown::CycleCategory;
+use super::{Addr, DecodedInstruction};
+
+/// Called with the address of each executed basic block and its instructions in order
+pub type BlockHook = Box<FnMut(Addr, &[DecodedInstruction])>;
+
+/// Groups the executed instructions into basic blocks for a `BlockHook`.
+///
+/// A block ends at a branch, a software interrupt or any instruction that refilled the
+/// pipeline, such as a write to PC. Execution that doesn't continue with the next instruction,
+/// like entering an interrupt handler, ends the block before it too.
+#[derive(Default)]
+pub struct BlockTracer {
+    hook: Option<BlockHook>,
+    block: Vec<DecodedInstruction>,
+}
+
+impl BlockTracer {
+    pub fn is_enabled(&self) -> bool {
+        self.hook.is_some()
+    }
+
+    /// Add an executed instruction, `flushed` is whether it refilled the pipeline
+    pub fn record(&mut self, insn: &DecodedInstruction, flushed: bool) {
+        if let Some(last) = self.block.last() {
+            if insn.get_pc() != last.get_pc().wrapping_add(Self::size(last)) {
+                self.end_block();
+            }
+        }
+        self.block.push(*insn);
+        if flushed || CycleCategory::of(insn) == CycleCategory::Branch {
+            self.end_block();
+        }
+    }
+
+    fn end_block(&mut self) {
+        if let (Some(hook), Some(first)) = (self.hook.as_mut(), self.block.first()) {
+            hook(first.get_pc(), &self.block);
+        }
+        self.block.clear();
+    }
+
+    fn size(insn: &DecodedInstruction) -> Addr {
+        match insn {
+            DecodedInstruction::Arm(_) => 4,
+            DecodedInstruction::Thumb(_) => 2,
+        }
+    }
+}
+
+impl fmt::Debug for BlockTracer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "BlockTracer(hook: {}, pending: {})",
+            self.is_enabled(),
+            self.block.len()
+        )
+    }
+}
+
+impl Core {
+    /// Call `hook` with every basic block as it finishes executing, or stop tracing with `None`.
+    /// An unfinished block is dropped.
+    pub fn set_block_hook(&mut self, hook: Option<BlockHook>) {
+        self.block_tracer = BlockTracer {
+            hook,
+            block: vec![],
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysbus::BoxedMemory;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn loop_blocks() {
+        let code: [u32; 5] = [
+            0xe3a00000, // mov r0, #0
+            0xe2800001, // add r0, r0, #1
+            0xe3500003, // cmp r0, #3
+            0x1afffffc, // bne 4
+            0xeafffffe, // b .
+        ];
+        let mut bytes = vec![];
+        for word in code.iter() {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        // room for the prefetch past the end
+        bytes.resize(0x40, 0);
+        let mut mem = BoxedMemory::new(bytes.into_boxed_slice());
+
+        let blocks = Rc::new(RefCell::new(vec![]));
+        let mut cpu = Core::new();
+        let recorded = blocks.clone();
+        cpu.set_block_hook(Some(Box::new(move |addr, insns| {
+            let pcs: Vec<Addr> = insns.iter().map(|insn| insn.get_pc()).collect();
+            recorded.borrow_mut().push((addr, pcs));
+        })));
+        while blocks.borrow().len() < 5 {
+            cpu.step(&mut mem).unwrap();
+        }
+
+        let blocks = blocks.borrow();
+        assert_eq!(blocks[0], (0, vec![0, 4, 8, 12]));
+        // the loop body, the second time with the branch not taken
+        for block in &blocks[1..3] {
+            assert_eq!(*block, (4, vec![4, 8, 12]));
+        }
+        assert_eq!(blocks[3], (16, vec![16]));
+        assert_eq!(blocks[4], (16, vec![16]));
+    }
+}
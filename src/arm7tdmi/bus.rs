@@ -62,4 +62,32 @@ pub trait Bus {
 
     /// returns the number of cycles needed for this memory access
     fn get_cycles(&self, addr: Addr, access: MemoryAccess) -> usize;
+
+    /// Cycles of an instruction fetch, for buses where they can be cheaper than data reads
+    fn fetch_cycles(&self, addr: Addr, access: MemoryAccess) -> usize {
+        self.get_cycles(addr, access)
+    }
+
+    /// Instruction fetches, for buses that tell them apart from data reads
+    fn fetch_32(&self, addr: Addr) -> u32 {
+        self.read_32(addr)
+    }
+
+    fn fetch_16(&self, addr: Addr) -> u16 {
+        self.read_16(addr)
+    }
+
+    /// Reads made by the emulator itself rather than the emulated CPU, for buses that keep
+    /// statistics of the CPU's accesses
+    fn peek_32(&self, addr: Addr) -> u32 {
+        self.read_32(addr)
+    }
+
+    fn peek_16(&self, addr: Addr) -> u16 {
+        self.read_16(addr)
+    }
+
+    fn peek_8(&self, addr: Addr) -> u8 {
+        self.read_8(addr)
+    }
 }
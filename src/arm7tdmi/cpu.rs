@@ -1,17 +1,22 @@
+use std::collections::HashMap;
 use std::fmt;
 
 use ansi_term::{Colour, Style};
 use num_traits::Num;
 
-pub use super::exception::Exception;
+pub use super::block_trace::BlockHook;
+use super::block_trace::BlockTracer;
+pub use super::cycle_breakdown::{CycleBreakdown, CycleCategory};
+pub use super::exception::{Exception, ExceptionCounters};
 use super::{
     arm::*,
     bus::{Bus, MemoryAccess, MemoryAccessType, MemoryAccessType::*, MemoryAccessWidth::*},
     psr::RegPSR,
-    reg_string,
     thumb::ThumbInstruction,
     Addr, CpuMode, CpuResult, CpuState, DecodedInstruction, InstructionDecoder,
 };
+use crate::bios::SwiHandlers;
+use crate::disass::DisasmStyle;
 
 #[derive(Debug)]
 pub struct PipelineContext<D, N>
@@ -57,6 +62,16 @@ where
     pub fn is_ready_to_execute(&self) -> bool {
         self.fetched.is_some() && self.decoded.is_some()
     }
+
+    /// The last fetched opcode and its address
+    pub fn fetched(&self) -> Option<&(Addr, N)> {
+        self.fetched.as_ref()
+    }
+
+    /// The decoded instruction, next in line to execute
+    pub fn decoded(&self) -> Option<&D> {
+        self.decoded.as_ref()
+    }
 }
 
 #[derive(Debug, Default)]
@@ -83,6 +98,21 @@ pub struct Core {
     memreq: Addr,
 
     pub verbose: bool,
+    /// Service supported software interrupts natively instead of running the BIOS
+    pub hle_bios: bool,
+    pub(super) swi_handlers: SwiHandlers,
+
+    pub(super) counters: ExceptionCounters,
+    pub(super) cycle_breakdown: CycleBreakdown,
+    /// What refilling the pipeline is on, see `count_step_cycles`
+    pub(super) refill_category: Option<CycleCategory>,
+    /// Basic blocks for the `set_block_hook` hook
+    pub(super) block_tracer: BlockTracer,
+
+    /// PC sampling profiler, disabled when the interval is 0
+    profile_interval: usize,
+    next_profile_sample: usize,
+    profile_samples: HashMap<Addr, usize>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -121,6 +151,37 @@ impl Core {
         }
     }
 
+    /// Writes a register of `mode`'s bank, which doesn't have to be the current mode
+    pub fn set_banked_reg(&mut self, mode: CpuMode, reg_num: usize, val: u32) {
+        let curr_mode = self.cpsr.mode();
+        match reg_num {
+            8...12 if mode == CpuMode::Fiq && curr_mode != CpuMode::Fiq => {
+                self.gpr_banked_fiq_r8_12[reg_num - 8] = val
+            }
+            8...12 if mode != CpuMode::Fiq && curr_mode == CpuMode::Fiq => {
+                self.gpr_banked_old_r8_12[reg_num - 8] = val
+            }
+            13 if mode.bank_index() != curr_mode.bank_index() => {
+                self.gpr_banked_r13[mode.bank_index()] = val
+            }
+            14 if mode.bank_index() != curr_mode.bank_index() => {
+                self.gpr_banked_r14[mode.bank_index()] = val
+            }
+            _ => self.set_reg(reg_num, val),
+        }
+    }
+
+    /// Overwrites the CPSR, switching register banks if the mode changes.
+    /// Unlike a mode change by an exception, SPSR and LR are left untouched.
+    pub fn set_cpsr(&mut self, value: u32) {
+        let new_psr = RegPSR::new(value);
+        let curr_mode = self.cpsr.mode();
+        if curr_mode != new_psr.mode() {
+            self.map_banked_registers(curr_mode, new_psr.mode());
+        }
+        self.cpsr = new_psr;
+    }
+
     pub fn get_registers(&self) -> [u32; 15] {
         self.gpr.clone()
     }
@@ -155,11 +216,6 @@ impl Core {
             self.spsr[index] = self.cpsr;
         }
         self.map_banked_registers(curr_mode, new_mode);
-        let next_index = new_mode.bank_index();
-        self.gpr_banked_r14[next_index] = self
-            .pc
-            .wrapping_sub(self.word_size() as u32)
-            .wrapping_add(4);
     }
 
     /// Resets the cpu
@@ -182,6 +238,29 @@ impl Core {
         self.cycles
     }
 
+    /// Sample the address of the executing instruction every `interval` cycles, or stop sampling
+    pub fn set_profile_interval(&mut self, interval: Option<usize>) {
+        self.profile_interval = interval.unwrap_or(0);
+        self.next_profile_sample = self.cycles + self.profile_interval;
+    }
+
+    /// Histogram of sampled instruction addresses
+    pub fn profile_samples(&self) -> &HashMap<Addr, usize> {
+        &self.profile_samples
+    }
+
+    pub fn reset_profile_samples(&mut self) {
+        self.profile_samples.clear();
+    }
+
+    fn sample_pc(&mut self, pc: Addr) {
+        *self.profile_samples.entry(pc).or_insert(0) += 1;
+        self.next_profile_sample += self.profile_interval;
+        if self.next_profile_sample <= self.cycles {
+            self.next_profile_sample = self.cycles + self.profile_interval;
+        }
+    }
+
     pub fn add_cycle(&mut self) {
         // println!("<cycle I-Cyclel> total: {}", self.cycles);
         self.cycles += 1;
@@ -218,6 +297,18 @@ impl Core {
         bus.read_32(addr)
     }
 
+    fn fetch_32(&mut self, addr: Addr, bus: &mut Bus) -> u32 {
+        self.cycles += bus.fetch_cycles(addr, self.cycle_type(addr) + MemoryAccess32);
+        self.memreq = addr;
+        bus.fetch_32(addr)
+    }
+
+    fn fetch_16(&mut self, addr: Addr, bus: &mut Bus) -> u16 {
+        self.cycles += bus.fetch_cycles(addr, self.cycle_type(addr) + MemoryAccess16);
+        self.memreq = addr;
+        bus.fetch_16(addr)
+    }
+
     pub fn load_16(&mut self, addr: Addr, bus: &mut Bus) -> u16 {
         let cycle_type = self.cycle_type(addr);
         self.add_cycles(addr, bus, cycle_type + MemoryAccess16);
@@ -232,6 +323,25 @@ impl Core {
         bus.read_8(addr)
     }
 
+    /// LDR, a misaligned word comes rotated so the addressed byte is the lowest
+    pub fn ldr_word(&mut self, addr: Addr, bus: &mut Bus) -> u32 {
+        self.load_32(addr & !3, bus).rotate_right(8 * (addr & 3))
+    }
+
+    /// LDRH, a misaligned halfword comes rotated into the top byte
+    pub fn ldr_half(&mut self, addr: Addr, bus: &mut Bus) -> u32 {
+        (self.load_16(addr & !1, bus) as u32).rotate_right(8 * (addr & 1))
+    }
+
+    /// LDRSH, a misaligned one loads the addressed byte sign extended
+    pub fn ldr_sign_half(&mut self, addr: Addr, bus: &mut Bus) -> u32 {
+        if addr & 1 != 0 {
+            self.load_8(addr, bus) as i8 as u32
+        } else {
+            self.load_16(addr, bus) as i16 as u32
+        }
+    }
+
     pub fn store_32(&mut self, addr: Addr, value: u32, bus: &mut Bus) {
         let cycle_type = self.cycle_type(addr);
         self.add_cycles(addr, bus, cycle_type + MemoryAccess32);
@@ -280,7 +390,7 @@ impl Core {
     ) -> CpuResult<(Option<DecodedInstruction>, CpuPipelineAction)> {
         // fetch
         // let new_fetched = bus.read_16(self.pc);
-        let new_fetched = self.load_16(self.pc, bus);
+        let new_fetched = self.fetch_16(self.pc, bus);
 
         // decode
         let new_decoded = match self.pipeline_thumb.fetched {
@@ -314,7 +424,7 @@ impl Core {
         bus: &mut Bus,
     ) -> CpuResult<(Option<DecodedInstruction>, CpuPipelineAction)> {
         // let new_fetched = bus.read_32(self.pc);
-        let new_fetched = self.load_32(self.pc, bus);
+        let new_fetched = self.fetch_32(self.pc, bus);
 
         // decode
         let new_decoded = match self.pipeline_arm.fetched {
@@ -346,10 +456,24 @@ impl Core {
     /// Perform a pipeline step
     /// If an instruction was executed in this step, return it.
     pub fn step(&mut self, bus: &mut Bus) -> CpuResult<Option<DecodedInstruction>> {
+        let previous_cycles = self.cycles;
         let (executed_instruction, pipeline_action) = match self.cpsr.state() {
             CpuState::ARM => self.step_arm(bus),
             CpuState::THUMB => self.step_thumb(bus),
         }?;
+        self.count_step_cycles(executed_instruction.as_ref(), self.cycles - previous_cycles);
+        if self.block_tracer.is_enabled() {
+            if let Some(insn) = &executed_instruction {
+                let flushed = pipeline_action == CpuPipelineAction::Flush;
+                self.block_tracer.record(insn, flushed);
+            }
+        }
+
+        if self.cycles >= self.next_profile_sample && self.profile_interval != 0 {
+            if let Some(insn) = &executed_instruction {
+                self.sample_pc(insn.get_pc());
+            }
+        }
 
         match pipeline_action {
             CpuPipelineAction::IncPC => self.advance_pc(),
@@ -390,6 +514,35 @@ impl Core {
         }
     }
 
+    /// The pipeline of the current cpu state: the instruction about to execute and the opcode
+    /// fetched after it
+    pub fn pipeline_contents(&self) -> (Option<DecodedInstruction>, Option<(Addr, u32)>) {
+        match self.cpsr.state() {
+            CpuState::ARM => (
+                self.pipeline_arm
+                    .decoded()
+                    .map(|&d| DecodedInstruction::Arm(d)),
+                self.pipeline_arm.fetched().cloned(),
+            ),
+            CpuState::THUMB => (
+                self.pipeline_thumb
+                    .decoded()
+                    .map(|&d| DecodedInstruction::Thumb(d)),
+                self.pipeline_thumb
+                    .fetched()
+                    .map(|&(addr, opcode)| (addr, opcode as u32)),
+            ),
+        }
+    }
+
+    /// Throw away the prefetched instructions and fetch again from the next instruction to
+    /// execute, for when memory they were fetched from was modified
+    pub fn invalidate_pipeline(&mut self) {
+        self.pc = self.get_next_pc();
+        self.pipeline_arm.flush();
+        self.pipeline_thumb.flush();
+    }
+
     /// A step that returns only once an instruction was executed.
     /// Returns the address of PC before executing an instruction,
     /// and the address of the next instruction to be executed;
@@ -412,8 +565,7 @@ impl fmt::Display for Core {
         let reg_dirty_style = Colour::Black.bold().on(Colour::Yellow);
         let gpr = self.get_registers();
         for i in 0..15 {
-            let mut reg_name = reg_string(i).to_string();
-            reg_name.make_ascii_uppercase();
+            let reg_name = DisasmStyle::Uppercase.reg(i);
 
             let style = if gpr[i] != self.gpr_previous[i] {
                 &reg_dirty_style
@@ -434,3 +586,89 @@ impl fmt::Display for Core {
         writeln!(f, "{}", reg_normal_style.paint(pc))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysbus::BoxedMemory;
+
+    #[test]
+    fn invalidate_pipeline() {
+        let code: [u32; 3] = [
+            0xe3a00001, // 0x00: mov r0, #1
+            0xe3a01002, // 0x04: mov r1, #2
+            0xeafffffe, // 0x08: b 0x08
+        ];
+        let mut bytes = vec![];
+        for word in code.iter() {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes.resize(0x40, 0);
+
+        // fetch and decode the first instruction, then patch it to mov r0, #3
+        let run = |invalidate: bool| {
+            let mut mem = BoxedMemory::new(bytes.clone().into_boxed_slice());
+            let mut core = Core::new();
+            assert_eq!(core.step(&mut mem), Ok(None));
+            assert_eq!(core.step(&mut mem), Ok(None));
+            let (decoded, fetched) = core.pipeline_contents();
+            assert_eq!(decoded.unwrap().get_pc(), 0x00);
+            assert_eq!(fetched, Some((0x04, 0xe3a01002)));
+
+            mem.write_32(0x00, 0xe3a00003);
+            if invalidate {
+                core.invalidate_pipeline();
+                assert_eq!(core.pipeline_contents(), (None, None));
+                assert_eq!(core.get_next_pc(), 0x00);
+            }
+            assert_eq!(core.step_one(&mut mem).unwrap().get_pc(), 0x00);
+            core.step_one(&mut mem).unwrap();
+            assert_eq!(core.get_reg(1), 2);
+            core.get_reg(0)
+        };
+
+        // the stale opcode in the pipeline runs unless it's invalidated
+        assert_eq!(run(false), 1);
+        assert_eq!(run(true), 3);
+    }
+
+    #[test]
+    fn profile_samples() {
+        let code: [u32; 5] = [
+            0xe3a00000, // 0x00: mov r0, #0
+            0xe3a01000, // 0x04: mov r1, #0
+            0xe2800001, // 0x08: add r0, r0, #1
+            0xe2811002, // 0x0c: add r1, r1, #2
+            0xeafffffc, // 0x10: b 0x08
+        ];
+        let mut bytes = vec![];
+        for word in code.iter() {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        // room for prefetching
+        bytes.resize(0x40, 0);
+        let mut mem = BoxedMemory::new(bytes.into_boxed_slice());
+
+        let mut core = Core::new();
+        core.set_profile_interval(Some(16));
+        for _ in 0..1000 {
+            core.step_one(&mut mem).unwrap();
+        }
+
+        let samples = core.profile_samples();
+        let total: usize = samples.values().sum();
+        let hot: usize = [0x08, 0x0c, 0x10]
+            .iter()
+            .filter_map(|pc| samples.get(pc))
+            .sum();
+        assert!(total > 0);
+        assert!(hot * 100 >= total * 95);
+
+        core.reset_profile_samples();
+        core.set_profile_interval(None);
+        for _ in 0..100 {
+            core.step_one(&mut mem).unwrap();
+        }
+        assert!(core.profile_samples().is_empty());
+    }
+}
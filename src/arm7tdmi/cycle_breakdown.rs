@@ -0,0 +1,160 @@
+use super::arm::{ArmFormat, ArmInstruction};
+use super::thumb::{OpFormat5, ThumbFormat, ThumbInstruction};
+use super::{Core, DecodedInstruction};
+
+use crate::bit::BitIndex;
+
+/// What the CPU spent a cycle on
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CycleCategory {
+    /// ARM data processing and PSR transfers
+    ArmAlu,
+    /// THUMB instructions that aren't memory transfers, multiplies or branches
+    Thumb,
+    Load,
+    Store,
+    Multiply,
+    /// Branches, software interrupts and refilling the pipeline after an exception
+    Branch,
+    /// Skipped over while polling in an idle loop
+    Idle,
+    /// Stalled by DMA transfers
+    Dma,
+}
+
+impl CycleCategory {
+    pub fn of(insn: &DecodedInstruction) -> CycleCategory {
+        match insn {
+            DecodedInstruction::Arm(insn) => Self::of_arm(insn),
+            DecodedInstruction::Thumb(insn) => Self::of_thumb(insn),
+        }
+    }
+
+    fn transfer(load: bool) -> CycleCategory {
+        if load {
+            CycleCategory::Load
+        } else {
+            CycleCategory::Store
+        }
+    }
+
+    fn of_arm(insn: &ArmInstruction) -> CycleCategory {
+        match insn.fmt {
+            ArmFormat::DP | ArmFormat::MRS | ArmFormat::MSR_REG | ArmFormat::MSR_FLAGS => {
+                CycleCategory::ArmAlu
+            }
+            ArmFormat::MUL_MLA | ArmFormat::MULL_MLAL => CycleCategory::Multiply,
+            ArmFormat::LDR_STR
+            | ArmFormat::LDR_STR_HS_REG
+            | ArmFormat::LDR_STR_HS_IMM
+            | ArmFormat::LDM_STM => Self::transfer(insn.load_flag()),
+            // reads before it writes, the load is what the code waits on
+            ArmFormat::SWP => CycleCategory::Load,
+            // the rest end up in an exception vector
+            _ => CycleCategory::Branch,
+        }
+    }
+
+    fn of_thumb(insn: &ThumbInstruction) -> CycleCategory {
+        match insn.fmt {
+            ThumbFormat::Mul => CycleCategory::Multiply,
+            ThumbFormat::LdrPc => CycleCategory::Load,
+            ThumbFormat::LdrStrRegOffset
+            | ThumbFormat::LdrStrImmOffset
+            | ThumbFormat::LdrStrHalfWord
+            | ThumbFormat::LdrStrSp
+            | ThumbFormat::PushPop
+            | ThumbFormat::LdmStm => Self::transfer(insn.is_load()),
+            // STRH is the only store, with both the sign extend and halfword bits clear
+            ThumbFormat::LdrStrSHB => Self::transfer(insn.raw.bit_range(10..12) != 0),
+            ThumbFormat::HiRegOpOrBranchExchange if insn.format5_op() == OpFormat5::BX => {
+                CycleCategory::Branch
+            }
+            ThumbFormat::BranchConditional
+            | ThumbFormat::Swi
+            | ThumbFormat::Branch
+            | ThumbFormat::BranchLongWithLink => CycleCategory::Branch,
+            _ => CycleCategory::Thumb,
+        }
+    }
+}
+
+/// Cycles spent per `CycleCategory`, they add up to the cycles the CPU ran
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct CycleBreakdown {
+    pub arm_alu: usize,
+    pub thumb: usize,
+    pub load: usize,
+    pub store: usize,
+    pub multiply: usize,
+    pub branch: usize,
+    pub idle: usize,
+    pub dma: usize,
+}
+
+impl CycleBreakdown {
+    pub fn get(&self, category: CycleCategory) -> usize {
+        match category {
+            CycleCategory::ArmAlu => self.arm_alu,
+            CycleCategory::Thumb => self.thumb,
+            CycleCategory::Load => self.load,
+            CycleCategory::Store => self.store,
+            CycleCategory::Multiply => self.multiply,
+            CycleCategory::Branch => self.branch,
+            CycleCategory::Idle => self.idle,
+            CycleCategory::Dma => self.dma,
+        }
+    }
+
+    fn add(&mut self, category: CycleCategory, cycles: usize) {
+        let counter = match category {
+            CycleCategory::ArmAlu => &mut self.arm_alu,
+            CycleCategory::Thumb => &mut self.thumb,
+            CycleCategory::Load => &mut self.load,
+            CycleCategory::Store => &mut self.store,
+            CycleCategory::Multiply => &mut self.multiply,
+            CycleCategory::Branch => &mut self.branch,
+            CycleCategory::Idle => &mut self.idle,
+            CycleCategory::Dma => &mut self.dma,
+        };
+        *counter += cycles;
+    }
+
+    pub fn total(&self) -> usize {
+        self.arm_alu
+            + self.thumb
+            + self.load
+            + self.store
+            + self.multiply
+            + self.branch
+            + self.idle
+            + self.dma
+    }
+}
+
+impl Core {
+    /// Cycles per category since the CPU was created or `reset_cycle_breakdown` was called
+    pub fn cycle_breakdown(&self) -> &CycleBreakdown {
+        &self.cycle_breakdown
+    }
+
+    pub fn reset_cycle_breakdown(&mut self) {
+        self.cycle_breakdown = Default::default();
+    }
+
+    /// Run the clock for `cycles` the CPU didn't spend on instructions, e.g. stalled by DMA
+    pub fn add_cycles_of(&mut self, category: CycleCategory, cycles: usize) {
+        self.cycles += cycles;
+        self.cycle_breakdown.add(category, cycles);
+    }
+
+    /// Account the cycles of a pipeline step. A step without an instruction to execute refills
+    /// the pipeline, which is on the instruction or exception that flushed it.
+    pub(super) fn count_step_cycles(&mut self, insn: Option<&DecodedInstruction>, cycles: usize) {
+        if let Some(insn) = insn {
+            self.refill_category = Some(CycleCategory::of(insn));
+        }
+        let category = self.refill_category.unwrap_or(CycleCategory::Branch);
+        self.cycle_breakdown.add(category, cycles);
+    }
+}
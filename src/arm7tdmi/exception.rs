@@ -1,4 +1,11 @@
-use super::{cpu::Core, CpuMode, CpuState};
+use super::{
+    bus::Bus,
+    cpu::{Core, CpuExecResult, CpuPipelineAction},
+    cycle_breakdown::CycleCategory,
+    CpuMode, CpuState,
+};
+use crate::bios::{self, SwiHandler};
+use crate::interrupt::Interrupt;
 
 use colored::*;
 
@@ -30,15 +37,55 @@ impl From<Exception> for CpuMode {
     }
 }
 
+/// Profiling counters for exceptions taken and interrupts raised
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ExceptionCounters {
+    vectors: [usize; 8],
+    interrupts: [usize; 14],
+}
+
+impl ExceptionCounters {
+    /// Number of times the exception vector for `e` was taken
+    pub fn exception(&self, e: Exception) -> usize {
+        self.vectors[(e as usize) / 4]
+    }
+
+    /// Number of times the interrupt source `irq` was raised
+    pub fn interrupt(&self, irq: Interrupt) -> usize {
+        self.interrupts[irq as usize]
+    }
+}
+
 impl Core {
+    pub fn counters(&self) -> &ExceptionCounters {
+        &self.counters
+    }
+
+    pub fn reset_counters(&mut self) {
+        self.counters = Default::default();
+    }
+
+    pub fn count_interrupt(&mut self, irq: Interrupt) {
+        self.counters.interrupts[irq as usize] += 1;
+    }
+
     pub fn exception(&mut self, e: Exception) {
         let vector = e as u32;
         let new_mode = CpuMode::from(e);
+        self.counters.vectors[(vector / 4) as usize] += 1;
+        self.refill_category = Some(CycleCategory::Branch);
         if self.verbose {
             println!("{}: {:?}, new_mode: {:?}", "Exception".cyan(), e, new_mode);
         }
 
+        // interrupts are taken between instructions and return with `subs pc, lr, #4`, the
+        // others are raised by the executing instruction, which pc is two instructions past
+        let return_addr = match e {
+            Exception::Irq | Exception::Fiq => self.get_next_pc().wrapping_add(4),
+            _ => self.pc.wrapping_sub(self.word_size() as u32),
+        };
         self.change_mode(new_mode);
+        self.gpr[14] = return_addr;
         // Set appropriate CPSR bits
         self.cpsr.set_state(CpuState::ARM);
         self.cpsr.set_mode(new_mode);
@@ -52,4 +99,27 @@ impl Core {
         self.pipeline_arm.flush();
         self.pipeline_thumb.flush();
     }
+
+    /// Run `handler` instead of the BIOS function `swi_number`, replacing any previous handler
+    pub fn set_swi_handler(&mut self, swi_number: u8, handler: SwiHandler) {
+        self.swi_handlers.insert(swi_number, handler);
+    }
+
+    pub fn remove_swi_handler(&mut self, swi_number: u8) -> Option<SwiHandler> {
+        self.swi_handlers.remove(swi_number)
+    }
+
+    /// Handle a SWI instruction, either natively or by entering the BIOS
+    pub fn software_interrupt(&mut self, bus: &mut Bus, swi_number: u8) -> CpuExecResult {
+        if let Some(mut handler) = self.swi_handlers.begin_call(swi_number) {
+            handler(self, bus);
+            self.swi_handlers.end_call(swi_number, handler);
+            return Ok(CpuPipelineAction::IncPC);
+        }
+        if self.hle_bios && bios::hle_swi(self, bus, swi_number) {
+            return Ok(CpuPipelineAction::IncPC);
+        }
+        self.exception(Exception::SoftwareInterrupt);
+        Ok(CpuPipelineAction::Flush)
+    }
 }
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+
+use super::arm::{ArmFormat, ArmInstruction};
+use super::thumb::{ThumbFormat, ThumbInstruction};
+use super::{Addr, Bus, Core, DecodedInstruction, InstructionDecoder, REG_PC};
+
+use crate::bit::BitIndex;
+
+/// Longest loop body, in bytes, that is considered for idle detection
+const MAX_LOOP_SIZE: Addr = 32;
+
+/// Recognizes loops that only poll memory, like waiting for VCOUNT or a DMA flag.
+///
+/// A loop is a short backward branch, and it's idle when its body has no stores or other
+/// side effects and two iterations in a row end with the same registers. Nothing the CPU does
+/// can change the outcome of the next iteration then, only an outside event can.
+#[derive(Debug, Default)]
+pub struct IdleLoopDetector {
+    /// Whether the body of a loop, by its start and branch address, is free of side effects
+    checked_loops: HashMap<(Addr, Addr), bool>,
+    /// The loop branched back last, and the registers and CPSR at the time
+    last_iteration: Option<(Addr, Addr, [u32; 15], u32)>,
+}
+
+impl IdleLoopDetector {
+    pub fn new() -> IdleLoopDetector {
+        Default::default()
+    }
+
+    /// Call with every executed instruction. Returns true when `insn` closed an iteration of an
+    /// idle loop.
+    pub fn check(&mut self, insn: &DecodedInstruction, core: &Core, bus: &Bus) -> bool {
+        let target = match Self::backward_branch_target(insn) {
+            Some(target) if core.get_next_pc() == target => target,
+            _ => {
+                // the body of the loop keeps it going, anything else leaves it
+                if let Some((start, branch, ..)) = self.last_iteration {
+                    let pc = insn.get_pc();
+                    if pc < start || pc >= branch {
+                        self.last_iteration = None;
+                    }
+                }
+                return false;
+            }
+        };
+        let branch = insn.get_pc();
+
+        let is_pure = *self
+            .checked_loops
+            .entry((target, branch))
+            .or_insert_with(|| Self::is_pure_loop(insn, target, bus));
+        if !is_pure {
+            self.last_iteration = None;
+            return false;
+        }
+
+        let iteration = (target, branch, core.get_registers(), core.cpsr.get());
+        let idle = self.last_iteration == Some(iteration);
+        self.last_iteration = Some(iteration);
+        idle
+    }
+
+    /// Forget the current loop, e.g. after skipping ahead, since its registers are stale
+    pub fn reset(&mut self) {
+        self.last_iteration = None;
+    }
+
+    /// Target of a plain branch a short way back
+    fn backward_branch_target(insn: &DecodedInstruction) -> Option<Addr> {
+        let (pc, offset) = match insn {
+            DecodedInstruction::Arm(insn) if insn.fmt == ArmFormat::B_BL && !insn.link_flag() => {
+                (insn.pc + 8, insn.branch_offset())
+            }
+            DecodedInstruction::Thumb(insn) => match insn.fmt {
+                ThumbFormat::Branch => (insn.pc + 4, insn.branch_offset()),
+                ThumbFormat::BranchConditional => (insn.pc + 4, insn.bcond_offset()),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        let target = pc.wrapping_add(offset as Addr);
+        let branch = insn.get_pc();
+        if target < branch && branch - target <= MAX_LOOP_SIZE {
+            Some(target)
+        } else {
+            None
+        }
+    }
+
+    /// Whether every instruction from `target` up to the branch only computes and loads
+    fn is_pure_loop(branch: &DecodedInstruction, target: Addr, bus: &Bus) -> bool {
+        match branch {
+            DecodedInstruction::Arm(insn) => (target..insn.pc).step_by(4).all(|addr| {
+                match ArmInstruction::decode(bus.peek_32(addr), addr) {
+                    Ok(insn) => Self::is_pure_arm(&insn),
+                    Err(_) => false,
+                }
+            }),
+            DecodedInstruction::Thumb(insn) => (target..insn.pc).step_by(2).all(|addr| {
+                match ThumbInstruction::decode(bus.peek_16(addr), addr) {
+                    Ok(insn) => Self::is_pure_thumb(&insn),
+                    Err(_) => false,
+                }
+            }),
+        }
+    }
+
+    fn is_pure_arm(insn: &ArmInstruction) -> bool {
+        use ArmFormat::*;
+        match insn.fmt {
+            DP => insn.rd() != REG_PC || insn.opcode().unwrap().is_setting_flags(),
+            LDR_STR | LDR_STR_HS_IMM | LDR_STR_HS_REG => insn.load_flag() && insn.rd() != REG_PC,
+            LDM_STM => insn.load_flag() && !insn.register_list().contains(&REG_PC),
+            MUL_MLA | MULL_MLAL | MRS => true,
+            _ => false,
+        }
+    }
+
+    fn is_pure_thumb(insn: &ThumbInstruction) -> bool {
+        use ThumbFormat::*;
+        match insn.fmt {
+            MoveShiftedReg | AddSub | DataProcessImm | Mul | AluOps | LdrPc | LoadAddress
+            | AddSp => true,
+            LdrStrRegOffset | LdrStrImmOffset | LdrStrHalfWord | LdrStrSp => insn.is_load(),
+            // STRH is the only store, the sign extending loads have bit 10 set
+            LdrStrSHB => insn.raw.bit(10) || insn.raw.bit(11),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysbus::BoxedMemory;
+
+    fn make_mem(code: &[u32]) -> BoxedMemory {
+        let mut bytes = vec![];
+        for word in code {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        BoxedMemory::new(bytes.into_boxed_slice())
+    }
+
+    #[test]
+    fn pure_loops() {
+        let is_pure = |code: &[u32]| {
+            let mem = make_mem(code);
+            let branch_addr = 4 * (code.len() - 1) as Addr;
+            let branch = ArmInstruction::decode(code[code.len() - 1], branch_addr).unwrap();
+            let branch = DecodedInstruction::Arm(branch);
+            let target = IdleLoopDetector::backward_branch_target(&branch).unwrap();
+            IdleLoopDetector::is_pure_loop(&branch, target, &mem)
+        };
+        assert!(is_pure(&[
+            0xe1d100b6, // ldrh r0, [r1, #6]
+            0xe3500064, // cmp r0, #100
+            0x1afffffc, // bne 0
+        ]));
+        assert!(!is_pure(&[
+            0xe5810000, // str r0, [r1]
+            0xe3500064, // cmp r0, #100
+            0x1afffffc, // bne 0
+        ]));
+        assert!(!is_pure(&[
+            0xe3a00000, // mov r0, #0
+            0xeb000000, // bl 0x10
+            0xeafffffc, // b 0
+        ]));
+    }
+}
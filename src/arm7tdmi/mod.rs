@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::fmt;
 
 use num::Num;
@@ -12,10 +13,15 @@ pub mod cpu;
 pub use cpu::*;
 pub mod alu;
 pub use alu::*;
+pub mod block_trace;
 pub mod bus;
 pub use bus::*;
+pub mod cycle_breakdown;
 pub mod exception;
+pub mod idle_loop;
 pub mod psr;
+#[cfg(test)]
+mod test_harness;
 
 pub const REG_PC: usize = 15;
 pub const REG_LR: usize = 14;
@@ -74,6 +80,20 @@ pub trait InstructionDecoder: Sized + fmt::Display {
     fn get_raw(&self) -> Self::IntType;
 }
 
+/// Names addresses in disassembly, e.g. from a ROM's symbol table
+pub trait SymbolResolver {
+    /// The closest symbol at or below `addr`, and its address
+    fn nearest_symbol(&self, addr: Addr) -> Option<(&str, Addr)>;
+}
+
+impl SymbolResolver for BTreeMap<Addr, String> {
+    fn nearest_symbol(&self, addr: Addr) -> Option<(&str, Addr)> {
+        self.range(..=addr)
+            .next_back()
+            .map(|(&sym_addr, name)| (name.as_str(), sym_addr))
+    }
+}
+
 pub fn reg_string(reg: usize) -> &'static str {
     let reg_names = &[
         "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "fp", "ip", "sp", "lr",
@@ -0,0 +1,415 @@
+//! Runs single instruction CPU tests from JSON fixtures, in a subset of the SingleStepTests
+//! layout:
+//!
+//! ```json
+//! [
+//!   {
+//!     "name": "add r0, r1, r2",
+//!     "opcode": 3766550530,
+//!     "initial": { "R": [...16 registers...], "CPSR": 31, "SPSR": [...5, optional...] },
+//!     "final": { "R": [...], "CPSR": 31 },
+//!     "transactions": [ { "kind": 1, "size": 4, "addr": 33554432, "data": 48879 } ]
+//!   }
+//! ]
+//! ```
+//!
+//! `R` is the view of the current mode and R15 is the pipelined PC, 8 (ARM) or 4 (Thumb) bytes
+//! past the instruction. Transactions of kind 1 are reads and are also where read data comes
+//! from, kind 2 are writes. Instruction fetches (kind 0) are not compared.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+
+use nom::branch::alt;
+use nom::bytes::complete::{tag, take_while};
+use nom::character::complete::{char, digit1, multispace0};
+use nom::combinator::{map, map_res};
+use nom::error::ErrorKind;
+use nom::multi::separated_list;
+use nom::sequence::{delimited, preceded, separated_pair, terminated};
+use nom::IResult;
+
+use super::arm::ArmInstruction;
+use super::bus::{Bus, MemoryAccess};
+use super::psr::RegPSR;
+use super::thumb::ThumbInstruction;
+use super::{Addr, Core, CpuPipelineAction, CpuState, InstructionDecoder};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Num(u64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_u32(&self) -> Option<u32> {
+        match self {
+            Json::Num(n) => Some(*n as u32),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+fn ws<'a, O, F>(f: F) -> impl Fn(&'a str) -> IResult<&'a str, O>
+where
+    F: Fn(&'a str) -> IResult<&'a str, O>,
+{
+    delimited(multispace0, f, multispace0)
+}
+
+/// Strings without escape sequences, which fixtures don't need
+fn parse_string(i: &str) -> IResult<&str, String> {
+    map(
+        delimited(char('"'), take_while(|c| c != '"'), char('"')),
+        String::from,
+    )(i)
+}
+
+fn parse_json(i: &str) -> IResult<&str, Json> {
+    ws(alt((
+        map(tag("null"), |_| Json::Null),
+        map(tag("true"), |_| Json::Bool(true)),
+        map(tag("false"), |_| Json::Bool(false)),
+        map_res(digit1, |s: &str| s.parse().map(Json::Num)),
+        map(parse_string, Json::Str),
+        map(
+            delimited(
+                char('['),
+                separated_list(char(','), parse_json),
+                preceded(multispace0, char(']')),
+            ),
+            Json::Array,
+        ),
+        map(
+            delimited(
+                char('{'),
+                separated_list(
+                    char(','),
+                    separated_pair(ws(parse_string), char(':'), parse_json),
+                ),
+                preceded(multispace0, char('}')),
+            ),
+            Json::Object,
+        ),
+    )))(i)
+}
+
+pub fn parse_fixture(text: &str) -> Result<Json, String> {
+    match terminated(parse_json, multispace0)(text) {
+        Ok(("", json)) => Ok(json),
+        Ok((rest, _)) => Err(format!("trailing input: {:.20}", rest)),
+        Err(nom::Err::Error((rest, kind))) | Err(nom::Err::Failure((rest, kind))) => {
+            Err(format!("{:?} at: {:.20}", kind, rest))
+        }
+        Err(nom::Err::Incomplete(_)) => Err(format!("{:?}", ErrorKind::Eof)),
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Transaction {
+    pub kind: u32,
+    pub size: u32,
+    pub addr: Addr,
+    pub data: u32,
+}
+
+impl Transaction {
+    pub const READ: u32 = 1;
+    pub const WRITE: u32 = 2;
+
+    fn from_json(json: &Json) -> Result<Transaction, String> {
+        let field = |name| {
+            json.get(name)
+                .and_then(Json::as_u32)
+                .ok_or_else(|| format!("transaction without {}", name))
+        };
+        Ok(Transaction {
+            kind: field("kind")?,
+            size: field("size")?,
+            addr: field("addr")?,
+            data: field("data")?,
+        })
+    }
+}
+
+/// A bus that serves reads from a table and records every data access
+#[derive(Debug, Default)]
+pub struct RecordingBus {
+    memory: HashMap<Addr, u32>,
+    pub transactions: RefCell<Vec<Transaction>>,
+}
+
+impl RecordingBus {
+    pub fn new() -> RecordingBus {
+        Default::default()
+    }
+
+    /// Data returned by reads of `addr`, unlisted addresses read as 0
+    pub fn set(&mut self, addr: Addr, data: u32) {
+        self.memory.insert(addr, data);
+    }
+
+    fn record(&self, kind: u32, size: u32, addr: Addr, data: u32) {
+        self.transactions.borrow_mut().push(Transaction {
+            kind,
+            size,
+            addr,
+            data,
+        });
+    }
+
+    fn read(&self, size: u32, addr: Addr) -> u32 {
+        let data = self.memory.get(&addr).cloned().unwrap_or(0);
+        self.record(Transaction::READ, size, addr, data);
+        data
+    }
+}
+
+impl Bus for RecordingBus {
+    fn read_32(&self, addr: Addr) -> u32 {
+        self.read(4, addr)
+    }
+
+    fn read_16(&self, addr: Addr) -> u16 {
+        self.read(2, addr) as u16
+    }
+
+    fn read_8(&self, addr: Addr) -> u8 {
+        self.read(1, addr) as u8
+    }
+
+    fn write_32(&mut self, addr: Addr, value: u32) {
+        self.record(Transaction::WRITE, 4, addr, value);
+    }
+
+    fn write_16(&mut self, addr: Addr, value: u16) {
+        self.record(Transaction::WRITE, 2, addr, value as u32);
+    }
+
+    fn write_8(&mut self, addr: Addr, value: u8) {
+        self.record(Transaction::WRITE, 1, addr, value as u32);
+    }
+
+    fn get_bytes(&self, _addr: Addr) -> &[u8] {
+        &[]
+    }
+
+    fn get_bytes_mut(&mut self, _addr: Addr) -> &mut [u8] {
+        &mut []
+    }
+
+    fn get_cycles(&self, _addr: Addr, _access: MemoryAccess) -> usize {
+        1
+    }
+}
+
+fn registers(state: &Json) -> Result<Vec<u32>, String> {
+    let regs = state
+        .get("R")
+        .and_then(Json::as_array)
+        .ok_or("state without R")?;
+    if regs.len() != 16 {
+        return Err(format!("expected 16 registers, got {}", regs.len()));
+    }
+    regs.iter()
+        .map(|r| r.as_u32().ok_or_else(|| "bad register value".to_string()))
+        .collect()
+}
+
+fn load_state(core: &mut Core, state: &Json) -> Result<(), String> {
+    let cpsr = state
+        .get("CPSR")
+        .and_then(Json::as_u32)
+        .ok_or("state without CPSR")?;
+    core.set_cpsr(cpsr);
+    for (reg, value) in registers(state)?.into_iter().enumerate() {
+        core.set_reg(reg, value);
+    }
+    if let Some(spsr) = state.get("SPSR").and_then(Json::as_array) {
+        for (index, value) in spsr.iter().enumerate().take(core.spsr.len()) {
+            core.spsr[index] = RegPSR::new(value.as_u32().ok_or("bad SPSR value")?);
+        }
+    }
+    Ok(())
+}
+
+/// Run one test case, returns a description of every mismatch
+pub fn run_test(test: &Json) -> Result<(), Vec<String>> {
+    let setup = || -> Result<(Core, RecordingBus, u32, Vec<Transaction>), String> {
+        let opcode = test
+            .get("opcode")
+            .and_then(Json::as_u32)
+            .ok_or("test without opcode")?;
+        let mut core = Core::new();
+        load_state(
+            &mut core,
+            test.get("initial").ok_or("test without initial")?,
+        )?;
+
+        let mut expected = vec![];
+        let mut bus = RecordingBus::new();
+        if let Some(transactions) = test.get("transactions").and_then(Json::as_array) {
+            for t in transactions {
+                let t = Transaction::from_json(t)?;
+                match t.kind {
+                    Transaction::READ => bus.set(t.addr, t.data),
+                    Transaction::WRITE => (),
+                    _ => continue,
+                }
+                expected.push(t);
+            }
+        }
+        Ok((core, bus, opcode, expected))
+    };
+    let (mut core, mut bus, opcode, expected) = setup().map_err(|e| vec![e])?;
+
+    let word_size = core.word_size() as u32;
+    let insn_addr = core.pc.wrapping_sub(2 * word_size);
+    let result = match core.cpsr.state() {
+        CpuState::ARM => ArmInstruction::decode(opcode, insn_addr)
+            .map_err(|e| vec![format!("{:?}", e)])
+            .and_then(|insn| {
+                core.exec_arm(&mut bus, insn)
+                    .map_err(|e| vec![e.to_string()])
+            }),
+        CpuState::THUMB => ThumbInstruction::decode(opcode as u16, insn_addr)
+            .map_err(|e| vec![format!("{:?}", e)])
+            .and_then(|insn| {
+                core.exec_thumb(&mut bus, insn)
+                    .map_err(|e| vec![e.to_string()])
+            }),
+    };
+    // move on to where the pipelined PC is once the next instruction executes
+    let word_size = core.word_size() as u32;
+    match result? {
+        CpuPipelineAction::IncPC => core.pc = core.pc.wrapping_add(word_size),
+        CpuPipelineAction::Flush => core.pc = core.pc.wrapping_add(2 * word_size),
+    }
+
+    let mut errors = vec![];
+    let final_state = test
+        .get("final")
+        .ok_or_else(|| vec!["test without final".to_string()])?;
+    for (reg, value) in registers(final_state)
+        .map_err(|e| vec![e])?
+        .into_iter()
+        .enumerate()
+    {
+        if core.get_reg(reg) != value {
+            errors.push(format!(
+                "r{}: expected {:#010x}, got {:#010x}",
+                reg,
+                value,
+                core.get_reg(reg)
+            ));
+        }
+    }
+    if let Some(cpsr) = final_state.get("CPSR").and_then(Json::as_u32) {
+        if core.cpsr.get() != cpsr {
+            errors.push(format!(
+                "cpsr: expected {:#010x}, got {:#010x}",
+                cpsr,
+                core.cpsr.get()
+            ));
+        }
+    }
+    let actual = bus.transactions.borrow();
+    if *actual != expected {
+        errors.push(format!(
+            "transactions: expected {:x?}, got {:x?}",
+            expected, *actual
+        ));
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Run every test of a fixture, returns the failures by test name
+pub fn run_fixture(text: &str) -> Result<Vec<(String, Vec<String>)>, String> {
+    let fixture = parse_fixture(text)?;
+    let tests = fixture.as_array().ok_or("fixture is not a list of tests")?;
+    Ok(tests
+        .iter()
+        .enumerate()
+        .filter_map(|(i, test)| {
+            run_test(test).err().map(|errors| {
+                let name = test.get("name").and_then(Json::as_str);
+                (name.map_or(format!("#{}", i), String::from), errors)
+            })
+        })
+        .collect())
+}
+
+#[test]
+fn parse_json_values() {
+    let json = parse_fixture(r#" { "a": [1, 2, {"b": null}], "c": "text", "d": true } "#);
+    assert_eq!(
+        json,
+        Ok(Json::Object(vec![
+            (
+                "a".to_string(),
+                Json::Array(vec![
+                    Json::Num(1),
+                    Json::Num(2),
+                    Json::Object(vec![("b".to_string(), Json::Null)])
+                ])
+            ),
+            ("c".to_string(), Json::Str("text".to_string())),
+            ("d".to_string(), Json::Bool(true)),
+        ]))
+    );
+    assert!(parse_fixture("[1, 2").is_err());
+}
+
+#[test]
+fn sample_fixture() {
+    let fixture = include_str!("../../tests/fixtures/arm7tdmi/sample.json");
+    let failures = run_fixture(fixture).unwrap();
+    assert!(failures.is_empty(), "{:#?}", failures);
+}
+
+/// Point `ARM7TDMI_TESTS` at a directory of fixtures to run them all
+#[test]
+#[ignore]
+fn external_fixtures() {
+    let dir = std::env::var("ARM7TDMI_TESTS").expect("ARM7TDMI_TESTS is not set");
+    let mut failed = 0;
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let failures = run_fixture(&fs::read_to_string(&path).unwrap()).unwrap();
+        for (name, errors) in failures.iter() {
+            println!("{}: {}: {:?}", path.display(), name, errors);
+        }
+        failed += failures.len();
+    }
+    assert_eq!(failed, 0);
+}
@@ -4,39 +4,49 @@ use crate::bit::BitIndex;
 
 use super::*;
 use crate::arm7tdmi::*;
+use crate::disass::{DisasmStyle, StyledDisplay};
 
 impl ThumbInstruction {
-    fn fmt_thumb_move_shifted_reg(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_thumb_move_shifted_reg(
+        &self,
+        f: &mut fmt::Formatter,
+        style: DisasmStyle,
+    ) -> fmt::Result {
         write!(
             f,
             "{op}\t{Rd}, {Rs}, #{Offset5}",
-            op = self.format1_op(),
-            Rd = reg_string(self.rd()),
-            Rs = reg_string(self.rs()),
+            op = style.of(self.format1_op()),
+            Rd = style.reg(self.rd()),
+            Rs = style.reg(self.rs()),
             Offset5 = self.offset5()
         )
     }
 
-    fn fmt_thumb_data_process_imm(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_thumb_data_process_imm(
+        &self,
+        f: &mut fmt::Formatter,
+        style: DisasmStyle,
+    ) -> fmt::Result {
         write!(
             f,
             "{op}\t{Rd}, #{Offset8:#x}",
-            op = self.format3_op(),
-            Rd = reg_string(self.rd()),
+            op = style.of(self.format3_op()),
+            Rd = style.reg(self.rd()),
             Offset8 = self.offset8()
         )
     }
 
-    fn fmt_thumb_mul(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_thumb_mul(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
         write!(
             f,
-            "mul\t{Rd}, {Rs}",
-            Rd = reg_string(self.rd()),
-            Rs = reg_string(self.rs())
+            "{mul}\t{Rd}, {Rs}",
+            mul = style.of("mul"),
+            Rd = style.reg(self.rd()),
+            Rs = style.reg(self.rs())
         )
     }
 
-    fn fmt_thumb_alu_ops(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_thumb_alu_ops(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
         use ShiftedRegister::ByRegister;
         let (op, shft) = self.alu_opcode();
         if let Some(BarrelShifterValue::ShiftedRegister {
@@ -44,21 +54,25 @@ impl ThumbInstruction {
             ..
         }) = shft
         {
-            write!(f, "{}", op)?;
+            write!(f, "{}", style.of(op))?;
         } else if op == AluOpCode::RSB {
-            write!(f, "neg")?;
+            write!(f, "{}", style.of("neg"))?;
         } else {
-            write!(f, "{}", op)?;
+            write!(f, "{}", style.of(op))?;
         }
         write!(
             f,
             "\t{Rd}, {Rs}",
-            Rd = reg_string(self.rd()),
-            Rs = reg_string(self.rs())
+            Rd = style.reg(self.rd()),
+            Rs = style.reg(self.rs())
         )
     }
 
-    fn fmt_thumb_high_reg_op_or_bx(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_thumb_high_reg_op_or_bx(
+        &self,
+        f: &mut fmt::Formatter,
+        style: DisasmStyle,
+    ) -> fmt::Result {
         let op = self.format5_op();
         let dst_reg = if self.flag(ThumbInstruction::FLAG_H1) {
             self.rd() + 8
@@ -71,49 +85,55 @@ impl ThumbInstruction {
             self.rs()
         };
 
-        write!(f, "{}\t", op)?;
+        write!(f, "{}\t", style.of(&op))?;
         match op {
-            OpFormat5::BX => write!(f, "{}", reg_string(src_reg)),
+            OpFormat5::BX => write!(f, "{}", style.reg(src_reg)),
             _ => write!(
                 f,
                 "{dst}, {src}",
-                dst = reg_string(dst_reg),
-                src = reg_string(src_reg)
+                dst = style.reg(dst_reg),
+                src = style.reg(src_reg)
             ),
         }
     }
 
-    fn fmt_thumb_ldr_pc(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_thumb_ldr_pc(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
         write!(
             f,
-            "ldr\t{Rd}, [pc, #{Imm:#x}] ; = #{effective:#x}",
-            Rd = reg_string(self.rd()),
+            "{ldr}\t{Rd}, [{pc}, #{Imm:#x}]\t; {effective:#x}",
+            ldr = style.of("ldr"),
+            pc = style.reg(REG_PC),
+            Rd = style.reg(self.rd()),
             Imm = self.word8(),
-            effective = (self.pc + 4 & !0b10) + (self.word8() as Addr)
+            effective = ((self.pc + 4) & !0b10) + (self.word8() as Addr)
         )
     }
 
-    fn fmt_thumb_ldr_str_reg_offset(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_thumb_ldr_str_reg_offset(
+        &self,
+        f: &mut fmt::Formatter,
+        style: DisasmStyle,
+    ) -> fmt::Result {
         write!(
             f,
             "{op}{b}\t{Rd}, [{Rb}, {Ro}]",
-            op = if self.is_load() { "ldr" } else { "str" },
-            b = if self.is_transferring_bytes() {
+            op = style.of(if self.is_load() { "ldr" } else { "str" }),
+            b = style.of(if self.is_transferring_bytes() {
                 "b"
             } else {
                 ""
-            },
-            Rd = reg_string(self.rd()),
-            Rb = reg_string(self.rb()),
-            Ro = reg_string(self.ro()),
+            }),
+            Rd = style.reg(self.rd()),
+            Rb = style.reg(self.rb()),
+            Ro = style.reg(self.ro()),
         )
     }
 
-    fn fmt_thumb_ldr_str_shb(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_thumb_ldr_str_shb(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
         write!(
             f,
             "{op}\t{Rd}, [{Rb}, {Ro}]",
-            op = {
+            op = style.of({
                 match (
                     self.flag(ThumbInstruction::FLAG_SIGN_EXTEND),
                     self.flag(ThumbInstruction::FLAG_HALFWORD),
@@ -123,106 +143,121 @@ impl ThumbInstruction {
                     (true, false) => "ldsb",
                     (true, true) => "ldsh",
                 }
-            },
-            Rd = reg_string(self.rd()),
-            Rb = reg_string(self.rb()),
-            Ro = reg_string(self.ro()),
+            }),
+            Rd = style.reg(self.rd()),
+            Rb = style.reg(self.rb()),
+            Ro = style.reg(self.ro()),
         )
     }
 
-    fn fmt_thumb_ldr_str_imm_offset(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_thumb_ldr_str_imm_offset(
+        &self,
+        f: &mut fmt::Formatter,
+        style: DisasmStyle,
+    ) -> fmt::Result {
         write!(
             f,
             "{op}{b}\t{Rd}, [{Rb}, #{imm:#x}]",
-            op = if self.is_load() { "ldr" } else { "str" },
-            b = if self.is_transferring_bytes() {
+            op = style.of(if self.is_load() { "ldr" } else { "str" }),
+            b = style.of(if self.is_transferring_bytes() {
                 "b"
             } else {
                 ""
-            },
-            Rd = reg_string(self.rd()),
-            Rb = reg_string(self.rb()),
-            imm = {
-                let offset5 = self.offset5();
-                if self.is_transferring_bytes() {
-                    offset5
-                } else {
-                    (offset5 << 3) >> 1
-                }
-            },
+            }),
+            Rd = style.reg(self.rd()),
+            Rb = style.reg(self.rb()),
+            imm = self.imm_offset(),
         )
     }
 
-    fn fmt_thumb_ldr_str_halfword(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_thumb_ldr_str_halfword(
+        &self,
+        f: &mut fmt::Formatter,
+        style: DisasmStyle,
+    ) -> fmt::Result {
         write!(
             f,
             "{op}\t{Rd}, [{Rb}, #{imm:#x}]",
-            op = if self.is_load() { "ldrh" } else { "strh" },
-            Rd = reg_string(self.rd()),
-            Rb = reg_string(self.rb()),
+            op = style.of(if self.is_load() { "ldrh" } else { "strh" }),
+            Rd = style.reg(self.rd()),
+            Rb = style.reg(self.rb()),
             imm = self.offset5() << 1
         )
     }
 
-    fn fmt_thumb_ldr_str_sp(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_thumb_ldr_str_sp(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
         write!(
             f,
-            "{op}\t{Rd}, [sp, #{Imm:#x}]",
-            op = if self.is_load() { "ldr" } else { "str" },
-            Rd = reg_string(self.rd()),
+            "{op}\t{Rd}, [{sp}, #{Imm:#x}]",
+            op = style.of(if self.is_load() { "ldr" } else { "str" }),
+            sp = style.reg(REG_SP),
+            Rd = style.reg(self.rd()),
             Imm = self.word8(),
         )
     }
 
-    fn fmt_thumb_load_address(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_thumb_load_address(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
         write!(
             f,
-            "add\t{Rd}, {r}, #{Imm:#x}",
-            Rd = reg_string(self.rd()),
-            r = if self.flag(ThumbInstruction::FLAG_SP) {
-                "sp"
+            "{add}\t{Rd}, {r}, #{Imm:#x}",
+            add = style.of("add"),
+            Rd = style.reg(self.rd()),
+            r = style.reg(if self.flag(ThumbInstruction::FLAG_SP) {
+                REG_SP
             } else {
-                "pc"
-            },
+                REG_PC
+            }),
             Imm = self.word8(),
         )
     }
 
-    fn fmt_thumb_add_sub(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_thumb_add_sub(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
         let operand = if self.is_immediate_operand() {
             format!("#{:x}", self.raw.bit_range(6..9))
         } else {
-            String::from(reg_string(self.rn()))
+            String::from(style.reg(self.rn()))
         };
 
         write!(
             f,
             "{op}\t{Rd}, {Rs}, {operand}",
-            op = if self.is_subtract() { "sub" } else { "add" },
-            Rd = reg_string(self.rd()),
-            Rs = reg_string(self.rs()),
+            op = style.of(if self.is_subtract() { "sub" } else { "add" }),
+            Rd = style.reg(self.rd()),
+            Rs = style.reg(self.rs()),
             operand = operand
         )
     }
 
-    fn fmt_thumb_add_sp(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "add\tsp, #{imm:x}", imm = self.sword7())
+    fn fmt_thumb_add_sp(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
+        let imm = self.sword7();
+        write!(
+            f,
+            "{add}\t{sp}, #{sign}{imm:#x}",
+            add = style.of("add"),
+            sp = style.reg(REG_SP),
+            sign = if imm < 0 { "-" } else { "" },
+            imm = imm.abs()
+        )
     }
 
-    fn fmt_thumb_push_pop(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}\t{{", if self.is_load() { "pop" } else { "push" })?;
+    fn fmt_thumb_push_pop(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{{",
+            style.of(if self.is_load() { "pop" } else { "push" })
+        )?;
         let mut register_list = self.register_list().into_iter();
         let mut has_reg = false;
         if let Some(reg) = register_list.next() {
-            write!(f, "{}", reg_string(reg))?;
+            write!(f, "{}", style.reg(reg))?;
             has_reg = true;
         }
         for reg in register_list {
             has_reg = true;
-            write!(f, ", {}", reg_string(reg))?;
+            write!(f, ", {}", style.reg(reg))?;
         }
         if self.flag(ThumbInstruction::FLAG_R) {
-            let r = if self.is_load() { "pc" } else { "lr" };
+            let r = style.reg(if self.is_load() { REG_PC } else { REG_LR });
             if has_reg {
                 write!(f, ", {}", r)?;
             } else {
@@ -232,51 +267,63 @@ impl ThumbInstruction {
         write!(f, "}}")
     }
 
-    fn fmt_thumb_ldm_stm(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_thumb_ldm_stm(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
         write!(
             f,
             "{op}\t{Rb}!, {{",
-            op = if self.is_load() { "ldm" } else { "stm" },
-            Rb = reg_string(self.rb()),
+            op = style.of(if self.is_load() { "ldm" } else { "stm" }),
+            Rb = style.reg(self.rb()),
         )?;
         let mut register_list = self.register_list().into_iter();
         let mut has_reg = false;
         if let Some(reg) = register_list.next() {
-            write!(f, "{}", reg_string(reg))?;
+            write!(f, "{}", style.reg(reg))?;
             has_reg = true;
         }
         for reg in register_list {
             has_reg = true;
-            write!(f, ", {}", reg_string(reg))?;
+            write!(f, ", {}", style.reg(reg))?;
         }
         write!(f, "}}")
     }
 
-    fn fmt_thumb_branch_with_cond(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_thumb_branch_with_cond(
+        &self,
+        f: &mut fmt::Formatter,
+        style: DisasmStyle,
+    ) -> fmt::Result {
         write!(
             f,
-            "b{cond}\t{addr:#x}",
-            cond = self.cond(),
-            addr = {
-                let offset = ((self.offset8() as i8) << 1) as i32;
-                (self.pc as i32 + 4).wrapping_add(offset) as Addr
-            }
+            "{mnem}\t{addr:#x}",
+            mnem = style.of(format_args!("b{cond}", cond = self.cond())),
+            addr = (self.pc as i32 + 4).wrapping_add(self.bcond_offset()) as Addr
         )
     }
 
-    fn fmt_thumb_branch(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    fn fmt_thumb_branch(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
         write!(
             f,
-            "b\t{addr:#x}",
-            addr = {
-                let offset = (self.offset11() << 21) >> 20;
-                (self.pc as i32 + 4).wrapping_add(offset) as Addr
-            }
+            "{b}\t{addr:#x}",
+            b = style.of("b"),
+            addr = (self.pc as i32 + 4).wrapping_add(self.branch_offset()) as Addr
         )
     }
 
-    fn fmt_thumb_branch_long_with_link(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "bl\t#0x{:08x}", {
+    fn fmt_thumb_swi(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
+        write!(
+            f,
+            "{swi}\t#{comment:#x}",
+            swi = style.of("swi"),
+            comment = self.raw & 0xff
+        )
+    }
+
+    fn fmt_thumb_branch_long_with_link(
+        &self,
+        f: &mut fmt::Formatter,
+        style: DisasmStyle,
+    ) -> fmt::Result {
+        write!(f, "{}\t#0x{:08x}", style.of("bl"), {
             let offset11 = self.offset11();
             if self.flag(ThumbInstruction::FLAG_LOW_OFFSET) {
                 (offset11 << 1) as i32
@@ -287,33 +334,39 @@ impl ThumbInstruction {
     }
 }
 
-impl fmt::Display for ThumbInstruction {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+impl StyledDisplay for ThumbInstruction {
+    fn fmt_styled(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result {
         match self.fmt {
-            ThumbFormat::MoveShiftedReg => self.fmt_thumb_move_shifted_reg(f),
-            ThumbFormat::AddSub => self.fmt_thumb_add_sub(f),
-            ThumbFormat::DataProcessImm => self.fmt_thumb_data_process_imm(f),
-            ThumbFormat::Mul => self.fmt_thumb_mul(f),
-            ThumbFormat::AluOps => self.fmt_thumb_alu_ops(f),
-            ThumbFormat::HiRegOpOrBranchExchange => self.fmt_thumb_high_reg_op_or_bx(f),
-            ThumbFormat::LdrPc => self.fmt_thumb_ldr_pc(f),
-            ThumbFormat::LdrStrRegOffset => self.fmt_thumb_ldr_str_reg_offset(f),
-            ThumbFormat::LdrStrSHB => self.fmt_thumb_ldr_str_shb(f),
-            ThumbFormat::LdrStrImmOffset => self.fmt_thumb_ldr_str_imm_offset(f),
-            ThumbFormat::LdrStrHalfWord => self.fmt_thumb_ldr_str_halfword(f),
-            ThumbFormat::LdrStrSp => self.fmt_thumb_ldr_str_sp(f),
-            ThumbFormat::LoadAddress => self.fmt_thumb_load_address(f),
-            ThumbFormat::AddSp => self.fmt_thumb_add_sp(f),
-            ThumbFormat::PushPop => self.fmt_thumb_push_pop(f),
-            ThumbFormat::LdmStm => self.fmt_thumb_ldm_stm(f),
-            ThumbFormat::BranchConditional => self.fmt_thumb_branch_with_cond(f),
-            ThumbFormat::Branch => self.fmt_thumb_branch(f),
-            ThumbFormat::BranchLongWithLink => self.fmt_thumb_branch_long_with_link(f),
-            _ => write!(f, "({:?})", self),
+            ThumbFormat::MoveShiftedReg => self.fmt_thumb_move_shifted_reg(f, style),
+            ThumbFormat::AddSub => self.fmt_thumb_add_sub(f, style),
+            ThumbFormat::DataProcessImm => self.fmt_thumb_data_process_imm(f, style),
+            ThumbFormat::Mul => self.fmt_thumb_mul(f, style),
+            ThumbFormat::AluOps => self.fmt_thumb_alu_ops(f, style),
+            ThumbFormat::HiRegOpOrBranchExchange => self.fmt_thumb_high_reg_op_or_bx(f, style),
+            ThumbFormat::LdrPc => self.fmt_thumb_ldr_pc(f, style),
+            ThumbFormat::LdrStrRegOffset => self.fmt_thumb_ldr_str_reg_offset(f, style),
+            ThumbFormat::LdrStrSHB => self.fmt_thumb_ldr_str_shb(f, style),
+            ThumbFormat::LdrStrImmOffset => self.fmt_thumb_ldr_str_imm_offset(f, style),
+            ThumbFormat::LdrStrHalfWord => self.fmt_thumb_ldr_str_halfword(f, style),
+            ThumbFormat::LdrStrSp => self.fmt_thumb_ldr_str_sp(f, style),
+            ThumbFormat::LoadAddress => self.fmt_thumb_load_address(f, style),
+            ThumbFormat::AddSp => self.fmt_thumb_add_sp(f, style),
+            ThumbFormat::PushPop => self.fmt_thumb_push_pop(f, style),
+            ThumbFormat::LdmStm => self.fmt_thumb_ldm_stm(f, style),
+            ThumbFormat::BranchConditional => self.fmt_thumb_branch_with_cond(f, style),
+            ThumbFormat::Swi => self.fmt_thumb_swi(f, style),
+            ThumbFormat::Branch => self.fmt_thumb_branch(f, style),
+            ThumbFormat::BranchLongWithLink => self.fmt_thumb_branch_long_with_link(f, style),
         }
     }
 }
 
+impl fmt::Display for ThumbInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_styled(f, DisasmStyle::Lowercase)
+    }
+}
+
 impl fmt::Display for OpFormat3 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -169,7 +169,7 @@ impl Core {
             let data = if insn.is_transferring_bytes() {
                 self.load_8(addr, bus) as u32
             } else {
-                self.load_32(addr, bus)
+                self.ldr_word(addr, bus)
             };
 
             self.set_reg(insn.rd(), data);
@@ -216,7 +216,7 @@ impl Core {
             (false, true) =>
             /* ldrh */
             {
-                self.gpr[rd] = self.load_16(addr, bus) as u32
+                self.gpr[rd] = self.ldr_half(addr, bus)
             }
             (true, false) =>
             /* ldsb */
@@ -227,8 +227,7 @@ impl Core {
             (true, true) =>
             /* ldsh */
             {
-                let val = self.load_16(addr, bus) as i16 as i32 as u32;
-                self.gpr[rd] = val;
+                self.gpr[rd] = self.ldr_sign_half(addr, bus);
             }
         }
 
@@ -240,12 +239,7 @@ impl Core {
         bus: &mut Bus,
         insn: ThumbInstruction,
     ) -> CpuExecResult {
-        let offset = if insn.is_transferring_bytes() {
-            insn.offset5()
-        } else {
-            (insn.offset5() << 3) >> 1
-        };
-        let addr = self.get_reg(insn.rb()).wrapping_add(offset as u32);
+        let addr = self.get_reg(insn.rb()).wrapping_add(insn.imm_offset());
         self.do_exec_thumb_ldr_str(bus, insn, addr)
     }
 
@@ -257,9 +251,9 @@ impl Core {
         let base = self.gpr[insn.rb()] as i32;
         let addr = base.wrapping_add((insn.offset5() << 1) as i32) as Addr;
         if insn.is_load() {
-            let data = self.load_16(addr, bus);
+            let data = self.ldr_half(addr, bus);
             self.add_cycle();
-            self.gpr[insn.rd()] = data as u32;
+            self.gpr[insn.rd()] = data;
         } else {
             self.store_16(addr, self.gpr[insn.rd()] as u16, bus);
         }
@@ -272,11 +266,13 @@ impl Core {
     }
 
     fn exec_thumb_load_address(&mut self, _bus: &mut Bus, insn: ThumbInstruction) -> CpuExecResult {
-        let result = if insn.flag(ThumbInstruction::FLAG_SP) {
-            self.gpr[REG_SP] + (insn.word8() as Addr)
+        let base = if insn.flag(ThumbInstruction::FLAG_SP) {
+            self.gpr[REG_SP]
         } else {
-            (insn.pc & !0b10) + 4 + (insn.word8() as Addr)
+            // bit 1 of the PC is forced to 0 so the result is word aligned
+            (insn.pc + 4) & !0b10
         };
+        let result = base.wrapping_add(insn.word8() as Addr);
         self.gpr[insn.rd()] = result;
 
         Ok(CpuPipelineAction::IncPC)
@@ -289,7 +285,7 @@ impl Core {
         addr: Addr,
     ) -> CpuExecResult {
         if insn.is_load() {
-            let data = self.load_32(addr, bus);
+            let data = self.ldr_word(addr, bus);
             self.add_cycle();
             self.gpr[insn.rd()] = data;
         } else {
@@ -299,14 +295,7 @@ impl Core {
     }
 
     fn exec_thumb_add_sp(&mut self, _bus: &mut Bus, insn: ThumbInstruction) -> CpuExecResult {
-        let op1 = self.gpr[REG_SP] as i32;
-        let op2 = insn.sword7();
-        let arm_alu_op = AluOpCode::ADD;
-
-        let result = self.alu(arm_alu_op, op1, op2, false);
-        if let Some(result) = result {
-            self.gpr[REG_SP] = result as u32;
-        }
+        self.gpr[REG_SP] = self.gpr[REG_SP].wrapping_add(insn.sword7() as u32);
 
         Ok(CpuPipelineAction::IncPC)
     }
@@ -376,15 +365,13 @@ impl Core {
         if !self.check_arm_cond(insn.cond()) {
             Ok(CpuPipelineAction::IncPC)
         } else {
-            let offset = ((insn.offset8() as i8) << 1) as i32;
-            self.pc = (self.pc as i32).wrapping_add(offset) as u32;
+            self.pc = (self.pc as i32).wrapping_add(insn.bcond_offset()) as u32;
             Ok(CpuPipelineAction::Flush)
         }
     }
 
     fn exec_thumb_branch(&mut self, _bus: &mut Bus, insn: ThumbInstruction) -> CpuExecResult {
-        let offset = ((insn.offset11() << 21) >> 20) as i32;
-        self.pc = (self.pc as i32).wrapping_add(offset) as u32;
+        self.pc = (self.pc as i32).wrapping_add(insn.branch_offset()) as u32;
         Ok(CpuPipelineAction::Flush)
     }
 
@@ -409,6 +396,10 @@ impl Core {
         }
     }
 
+    fn exec_thumb_swi(&mut self, bus: &mut Bus, insn: ThumbInstruction) -> CpuExecResult {
+        self.software_interrupt(bus, (insn.raw & 0xff) as u8)
+    }
+
     pub fn exec_thumb(&mut self, bus: &mut Bus, insn: ThumbInstruction) -> CpuExecResult {
         match insn.fmt {
             ThumbFormat::MoveShiftedReg => self.exec_thumb_move_shifted_reg(bus, insn),
@@ -428,9 +419,9 @@ impl Core {
             ThumbFormat::PushPop => self.exec_thumb_push_pop(bus, insn),
             ThumbFormat::LdmStm => self.exec_thumb_ldm_stm(bus, insn),
             ThumbFormat::BranchConditional => self.exec_thumb_branch_with_cond(bus, insn),
+            ThumbFormat::Swi => self.exec_thumb_swi(bus, insn),
             ThumbFormat::Branch => self.exec_thumb_branch(bus, insn),
             ThumbFormat::BranchLongWithLink => self.exec_thumb_branch_long_with_link(bus, insn),
-            _ => unimplemented!("thumb not implemented {:#x?}", insn),
         }
     }
 }
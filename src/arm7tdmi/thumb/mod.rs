@@ -124,10 +124,10 @@ impl InstructionDecoder for ThumbInstruction {
             Ok(PushPop)
         } else if raw & 0xf000 == 0xc000 {
             Ok(LdmStm)
-        } else if raw & 0xf000 == 0xd000 {
-            Ok(BranchConditional)
         } else if raw & 0xff00 == 0xdf00 {
             Ok(Swi)
+        } else if raw & 0xf000 == 0xd000 {
+            Ok(BranchConditional)
         } else if raw & 0xf800 == 0xe000 {
             Ok(Branch)
         } else if raw & 0xf000 == 0xf000 {
@@ -290,6 +290,17 @@ impl ThumbInstruction {
         self.raw.bit_range(6..11) as i8
     }
 
+    /// Byte offset of a load/store with immediate offset (format 9), given in words unless
+    /// transferring bytes
+    pub fn imm_offset(&self) -> u32 {
+        let offset5 = self.offset5() as u32;
+        if self.is_transferring_bytes() {
+            offset5
+        } else {
+            offset5 << 2
+        }
+    }
+
     pub fn offset8(&self) -> i8 {
         self.raw.bit_range(0..8) as i8
     }
@@ -298,6 +309,16 @@ impl ThumbInstruction {
         (self.raw & 0x7FF) as i32
     }
 
+    /// Sign extended byte offset of a conditional branch (format 16)
+    pub fn bcond_offset(&self) -> i32 {
+        (self.offset8() as i32) << 1
+    }
+
+    /// Sign extended byte offset of an unconditional branch (format 18)
+    pub fn branch_offset(&self) -> i32 {
+        (self.offset11() << 21) >> 20
+    }
+
     pub fn word8(&self) -> u16 {
         self.raw.bit_range(0..8) << 2
     }
@@ -357,7 +378,7 @@ mod tests {
     use super::*;
     use crate::arm7tdmi::{
         cpu::{Core, CpuPipelineAction},
-        Bus,
+        Bus, REG_SP,
     };
     use crate::sysbus::BoxedMemory;
 
@@ -382,6 +403,121 @@ mod tests {
         assert_eq!(core.get_reg(0), 0x27);
     }
 
+    #[test]
+    fn branch_cond_backwards() {
+        let bytes = vec![];
+        let mut mem = BoxedMemory::new(bytes.into_boxed_slice());
+        let mut core = Core::new();
+
+        // 0x100: beq 0xfc
+        let insn = ThumbInstruction::decode(0xd0fc, 0x100).unwrap();
+        assert_eq!(insn.fmt, ThumbFormat::BranchConditional);
+        assert_eq!(insn.bcond_offset(), -8);
+        assert_eq!(format!("{}", insn), "beq\t0xfc");
+
+        // not taken
+        core.pc = 0x104;
+        core.cpsr.set_Z(false);
+        assert_eq!(
+            core.exec_thumb(&mut mem, insn),
+            Ok(CpuPipelineAction::IncPC)
+        );
+        assert_eq!(core.pc, 0x104);
+
+        core.cpsr.set_Z(true);
+        assert_eq!(
+            core.exec_thumb(&mut mem, insn),
+            Ok(CpuPipelineAction::Flush)
+        );
+        assert_eq!(core.pc, 0xfc);
+
+        // 0x100: bne 0x4 (the most negative offset)
+        let insn = ThumbInstruction::decode(0xd180, 0x100).unwrap();
+        assert_eq!(insn.bcond_offset(), -256);
+        assert_eq!(format!("{}", insn), "bne\t0x4");
+    }
+
+    #[test]
+    fn branch_forwards() {
+        let bytes = vec![];
+        let mut mem = BoxedMemory::new(bytes.into_boxed_slice());
+        let mut core = Core::new();
+
+        // 0x20: b 0x44
+        let insn = ThumbInstruction::decode(0xe010, 0x20).unwrap();
+        assert_eq!(insn.fmt, ThumbFormat::Branch);
+        assert_eq!(insn.branch_offset(), 0x20);
+        assert_eq!(format!("{}", insn), "b\t0x44");
+
+        core.pc = 0x24;
+        assert_eq!(
+            core.exec_thumb(&mut mem, insn),
+            Ok(CpuPipelineAction::Flush)
+        );
+        assert_eq!(core.pc, 0x44);
+
+        // 0x1000: b 0x804 (the most negative offset)
+        let insn = ThumbInstruction::decode(0xe400, 0x1000).unwrap();
+        assert_eq!(insn.branch_offset(), -2048);
+        assert_eq!(format!("{}", insn), "b\t0x804");
+    }
+
+    #[test]
+    fn load_address() {
+        let bytes = vec![];
+        let mut mem = BoxedMemory::new(bytes.into_boxed_slice());
+        let mut core = Core::new();
+
+        // 0x102: add r0, pc, #0x10
+        let insn = ThumbInstruction::decode(0xa004, 0x102).unwrap();
+        assert_eq!(insn.fmt, ThumbFormat::LoadAddress);
+        assert_eq!(format!("{}", insn), "add\tr0, pc, #0x10");
+        assert_eq!(
+            core.exec_thumb(&mut mem, insn),
+            Ok(CpuPipelineAction::IncPC)
+        );
+        // (0x102 + 4) & !2
+        assert_eq!(core.get_reg(0), 0x114);
+
+        // same result from the word aligned address before it
+        let insn = ThumbInstruction::decode(0xa004, 0x100).unwrap();
+        core.exec_thumb(&mut mem, insn).unwrap();
+        assert_eq!(core.get_reg(0), 0x114);
+
+        // add r1, sp, #0x3fc
+        core.set_reg(REG_SP, 0x0300_7f00);
+        let insn = ThumbInstruction::decode(0xa9ff, 0x100).unwrap();
+        assert_eq!(format!("{}", insn), "add\tr1, sp, #0x3fc");
+        core.exec_thumb(&mut mem, insn).unwrap();
+        assert_eq!(core.get_reg(1), 0x0300_82fc);
+        assert_eq!(core.get_reg(REG_SP), 0x0300_7f00);
+    }
+
+    #[test]
+    fn add_sp() {
+        let bytes = vec![];
+        let mut mem = BoxedMemory::new(bytes.into_boxed_slice());
+        let mut core = Core::new();
+        core.set_reg(REG_SP, 0x0300_7f00);
+
+        // add sp, #0x1fc
+        let insn = ThumbInstruction::decode(0xb07f, 0).unwrap();
+        assert_eq!(insn.fmt, ThumbFormat::AddSp);
+        assert_eq!(format!("{}", insn), "add\tsp, #0x1fc");
+        assert_eq!(
+            core.exec_thumb(&mut mem, insn),
+            Ok(CpuPipelineAction::IncPC)
+        );
+        assert_eq!(core.get_reg(REG_SP), 0x0300_80fc);
+
+        // add sp, #-0x1fc
+        let insn = ThumbInstruction::decode(0xb0ff, 0).unwrap();
+        assert_eq!(format!("{}", insn), "add\tsp, #-0x1fc");
+        core.exec_thumb(&mut mem, insn).unwrap();
+        core.exec_thumb(&mut mem, insn).unwrap();
+        assert_eq!(core.get_reg(REG_SP), 0x0300_7d04);
+    }
+
     // #[test]
     // fn decode_add_sub() {
     //     let insn = ThumbInstruction::decode(0xac19, 0).unwrap();
@@ -406,7 +542,7 @@ mod tests {
         let mut core = Core::new();
         core.set_reg(0, 0);
 
-        assert_eq!(format!("{}", insn), "ldr\tr0, [pc, #0x4] ; = #0xc");
+        assert_eq!(format!("{}", insn), "ldr\tr0, [pc, #0x4]\t; 0xc");
         assert_eq!(
             core.exec_thumb(&mut mem, insn),
             Ok(CpuPipelineAction::IncPC)
@@ -414,6 +550,37 @@ mod tests {
         assert_eq!(core.get_reg(0), 0x12345678);
     }
 
+    #[test]
+    fn ldr_str_imm_offset() {
+        // ldr r1, [r2, #0x50]
+        let insn = ThumbInstruction::decode(0x6d11, 0).unwrap();
+        assert_eq!(format!("{}", insn), "ldr\tr1, [r2, #0x50]");
+        assert_eq!(insn.imm_offset(), 0x50);
+
+        let mut bytes = vec![0; 0x58];
+        bytes[0x54..].copy_from_slice(&[0x78, 0x56, 0x34, 0x12]);
+        let mut mem = BoxedMemory::new(bytes.into_boxed_slice());
+        let mut core = Core::new();
+        core.set_reg(2, 4);
+        core.exec_thumb(&mut mem, insn).unwrap();
+        assert_eq!(core.get_reg(1), 0x12345678);
+
+        // strb r1, [r2, #0x1f]
+        let insn = ThumbInstruction::decode(0x77d1, 0).unwrap();
+        assert_eq!(format!("{}", insn), "strb\tr1, [r2, #0x1f]");
+        core.exec_thumb(&mut mem, insn).unwrap();
+        assert_eq!(mem.read_8(0x23), 0x78);
+    }
+
+    #[test]
+    fn disassemble_misc() {
+        let disass = |raw| format!("{}", ThumbInstruction::decode(raw, 0).unwrap());
+        assert_eq!(disass(0xdf10), "swi\t#0x10");
+        assert_eq!(disass(0xb510), "push\t{r4, lr}");
+        assert_eq!(disass(0xbd01), "pop\t{r0, pc}");
+        assert_eq!(disass(0xbd00), "pop\t{pc}");
+    }
+
     #[test]
     fn ldr_str_reg_offset() {
         // str	r0, [r4, r1]
@@ -1,6 +1,8 @@
 #[macro_use]
 extern crate clap;
 
+use std::path::Path;
+
 use clap::{App, ArgMatches};
 
 extern crate rustboyadvance_ng;
@@ -9,7 +11,7 @@ use rustboyadvance_ng::arm7tdmi::Core;
 use rustboyadvance_ng::cartridge::Cartridge;
 use rustboyadvance_ng::debugger::Debugger;
 use rustboyadvance_ng::util::read_bin_file;
-use rustboyadvance_ng::{GBAResult, GameBoyAdvance};
+use rustboyadvance_ng::{GBAError, GBAResult, GameBoyAdvance};
 
 fn run_debug(matches: &ArgMatches) -> GBAResult<()> {
     let skip_bios = match matches.occurrences_of("skip_bios") {
@@ -19,34 +21,59 @@ fn run_debug(matches: &ArgMatches) -> GBAResult<()> {
 
     let bios_bin = read_bin_file(matches.value_of("bios").unwrap_or_default())?;
 
-    let gamepak = Cartridge::load(matches.value_of("game_rom").unwrap())?;
-    println!("loaded rom: {:#?}", gamepak.header);
+    // the save file goes next to the rom
+    let save_path = matches
+        .value_of("game_rom")
+        .map(|path| Path::new(path).with_extension("sav"));
+
+    let gamepak = match matches.value_of("game_rom") {
+        Some(path) => {
+            let mut gamepak = Cartridge::load(path)?;
+            println!("loaded rom: {:#?}", gamepak.header());
+            match gamepak.detected_save_type() {
+                Some(save_type) => println!("save: {}", save_type),
+                None => println!("save: {} (not detected)", gamepak.save_type()),
+            }
+            if let Some(save_path) = &save_path {
+                let save_path = save_path.to_string_lossy();
+                let result = if matches.occurrences_of("resize_save") != 0 {
+                    gamepak.load_from_resized(&save_path)
+                } else {
+                    gamepak.load_from(&save_path)
+                };
+                if let Err(GBAError::SaveMismatch(mismatch)) = &result {
+                    println!(
+                        "{}: {}, use --resize-save to load it anyway",
+                        save_path, mismatch
+                    );
+                }
+                result?;
+            }
+            gamepak
+        }
+        None => {
+            println!("no rom given, running the bios only");
+            Cartridge::empty()
+        }
+    };
 
     let mut core = Core::new();
     core.reset();
     core.set_verbose(true);
-    if skip_bios {
-        core.gpr[13] = 0x0300_7f00;
-        core.gpr_banked_r13[0] = 0x0300_7f00; // USR/SYS
-        core.gpr_banked_r13[1] = 0x0300_7f00; // FIQ
-        core.gpr_banked_r13[2] = 0x0300_7fa0; // IRQ
-        core.gpr_banked_r13[3] = 0x0300_7fe0; // SVC
-        core.gpr_banked_r13[4] = 0x0300_7f00; // ABT
-        core.gpr_banked_r13[5] = 0x0300_7f00; // UND
-
-        core.pc = 0x0800_0000;
 
-        core.cpsr.set(0x5f);
+    let mut gba = GameBoyAdvance::new(core, bios_bin, gamepak);
+    if skip_bios {
+        gba.direct_boot();
     }
 
-    let gba = GameBoyAdvance::new(core, bios_bin, gamepak);
-
     let mut debugger = Debugger::new(gba);
 
     println!("starting debugger...");
     debugger.repl()?;
     println!("ending debugger...");
 
+    debugger.gba.sysbus.cartridge().flush_save()?;
+
     Ok(())
 }
 
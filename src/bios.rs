@@ -0,0 +1,422 @@
+//! High level emulation of the GBA BIOS functions
+//!
+//! When `Core::hle_bios` is set, software interrupts that are implemented here are serviced
+//! natively instead of jumping to the BIOS exception vector.
+//! The algorithms follow the real BIOS, so results should be bit-identical to hardware.
+//!
+//! Handlers registered with `Core::set_swi_handler` take precedence over both.
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::arm7tdmi::{Addr, Bus, Core};
+use crate::ioregs::consts::REG_HALTCNT;
+
+pub const SWI_STOP: u8 = 0x03;
+pub const SWI_ARCTAN: u8 = 0x09;
+pub const SWI_ARCTAN2: u8 = 0x0a;
+pub const SWI_BG_AFFINE_SET: u8 = 0x0e;
+pub const SWI_OBJ_AFFINE_SET: u8 = 0x0f;
+
+/// Native replacement for a BIOS function, called with the registers and bus at the SWI.
+/// Results are left in the registers, execution continues after the SWI instruction.
+pub type SwiHandler = Box<FnMut(&mut Core, &mut Bus)>;
+
+/// User registered `SwiHandler`s by SWI number
+#[derive(Default)]
+pub struct SwiHandlers {
+    handlers: HashMap<u8, SwiHandler>,
+    /// The SWI whose handler is being called, and whether it was unregistered during the call
+    running: Option<(u8, bool)>,
+}
+
+impl SwiHandlers {
+    pub fn insert(&mut self, swi_number: u8, handler: SwiHandler) -> Option<SwiHandler> {
+        self.handlers.insert(swi_number, handler)
+    }
+
+    pub fn remove(&mut self, swi_number: u8) -> Option<SwiHandler> {
+        if let Some((running, ref mut removed)) = self.running {
+            if running == swi_number {
+                *removed = true;
+            }
+        }
+        self.handlers.remove(&swi_number)
+    }
+
+    pub fn contains(&self, swi_number: u8) -> bool {
+        self.handlers.contains_key(&swi_number)
+    }
+
+    /// Take out the handler of `swi_number` to call it, give it back with `end_call`
+    pub fn begin_call(&mut self, swi_number: u8) -> Option<SwiHandler> {
+        let handler = self.handlers.remove(&swi_number)?;
+        self.running = Some((swi_number, false));
+        Some(handler)
+    }
+
+    /// Put the handler back after its call, unless it was unregistered or replaced meanwhile
+    pub fn end_call(&mut self, swi_number: u8, handler: SwiHandler) {
+        let removed = self.running == Some((swi_number, true));
+        self.running = None;
+        if !removed && !self.contains(swi_number) {
+            self.insert(swi_number, handler);
+        }
+    }
+}
+
+impl fmt::Debug for SwiHandlers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut swi_numbers: Vec<_> = self.handlers.keys().collect();
+        swi_numbers.sort();
+        write!(f, "SwiHandlers({:x?})", swi_numbers)
+    }
+}
+
+/// Service BIOS function `swi_number`.
+/// Returns false if the function is not implemented and should be handled by the BIOS itself.
+pub fn hle_swi(cpu: &mut Core, bus: &mut Bus, swi_number: u8) -> bool {
+    match swi_number {
+        // the same as the BIOS, the system takes care of the rest
+        SWI_STOP => bus.write_8(REG_HALTCNT, 0x80),
+        SWI_ARCTAN => {
+            let (result, r1, r3) = arctan(cpu.gpr[0] as i32);
+            cpu.gpr[0] = result as i32 as u32;
+            cpu.gpr[1] = r1 as u32;
+            cpu.gpr[3] = r3 as u32;
+        }
+        SWI_ARCTAN2 => {
+            let (result, r1) = arctan2(cpu.gpr[0] as i32, cpu.gpr[1] as i32);
+            cpu.gpr[0] = result as u32;
+            if let Some(r1) = r1 {
+                cpu.gpr[1] = r1 as u32;
+            }
+            cpu.gpr[3] = 0x170;
+        }
+        SWI_BG_AFFINE_SET => bg_affine_set(bus, cpu.gpr[0], cpu.gpr[1], cpu.gpr[2]),
+        SWI_OBJ_AFFINE_SET => obj_affine_set(bus, cpu.gpr[0], cpu.gpr[1], cpu.gpr[2], cpu.gpr[3]),
+        _ => return false,
+    }
+    true
+}
+
+/// The BIOS ArcTan polynomial approximation.
+///
+/// Input is tan(θ) in signed 1.1.14 fixed-point, result is θ in the range -0x4000..0x4000
+/// (representing -π/2..π/2). Also returns the intermediate values the BIOS leaves in r1 and r3.
+pub fn arctan(i: i32) -> (i16, i32, i32) {
+    let a = -(i.wrapping_mul(i) >> 14);
+    let mut b = (0xa9i32.wrapping_mul(a) >> 14) + 0x390;
+    for c in &[0x91c, 0xfb6, 0x16aa, 0x2081, 0x3651, 0xa2f9] {
+        b = (b.wrapping_mul(a) >> 14) + c;
+    }
+    ((i.wrapping_mul(b) >> 16) as i16, a, b)
+}
+
+/// The BIOS ArcTan2, returns the angle of the vector (x, y) in the range 0..0xffff
+/// (representing 0..2π), and the value the BIOS leaves in r1 if the polynomial was evaluated.
+pub fn arctan2(x: i32, y: i32) -> (u16, Option<i32>) {
+    if y == 0 {
+        return (if x >= 0 { 0 } else { 0x8000 }, None);
+    }
+    if x == 0 {
+        return (if y >= 0 { 0x4000 } else { 0xc000 }, None);
+    }
+
+    let atan = |n: i32, d: i32| {
+        let (result, r1, _) = arctan(n.wrapping_shl(14) / d);
+        (result as i32, r1)
+    };
+
+    let (result, r1) = if y >= 0 {
+        if x >= 0 && x >= y {
+            atan(y, x)
+        } else if x < 0 && -x >= y {
+            let (result, r1) = atan(y, x);
+            (result + 0x8000, r1)
+        } else {
+            let (result, r1) = atan(x, y);
+            (0x4000 - result, r1)
+        }
+    } else {
+        if x <= 0 && -x > -y {
+            let (result, r1) = atan(y, x);
+            (result + 0x8000, r1)
+        } else if x > 0 && x >= -y {
+            let (result, r1) = atan(y, x);
+            (result + 0x10000, r1)
+        } else {
+            let (result, r1) = atan(x, y);
+            (0xc000 - result, r1)
+        }
+    };
+
+    (result as u16, Some(r1))
+}
+
+/// Entry `angle` of the BIOS sine table, sin(angle * 2π / 256) in 1.1.14 fixed-point
+fn sine(angle: u8) -> i32 {
+    let theta = (angle as f64) * std::f64::consts::PI / 128.0;
+    (theta.sin() * 16384.0).round() as i32
+}
+
+/// Rotation/scaling matrix as the BIOS computes it, the angle's low 8 bits are ignored.
+/// Scales and the resulting matrix entries are in 1.7.8 fixed-point.
+fn affine_matrix(sx: i32, sy: i32, theta: u16) -> (i32, i32, i32, i32) {
+    let angle = (theta >> 8) as u8;
+    let sin = sine(angle);
+    let cos = sine(angle.wrapping_add(64));
+    (
+        (sx * cos) >> 14,
+        -((sx * sin) >> 14),
+        (sy * sin) >> 14,
+        (sy * cos) >> 14,
+    )
+}
+
+/// BgAffineSet: `count` 20 byte source entries of
+/// (ox: i32, oy: i32, cx: i16, cy: i16, sx: i16, sy: i16, theta: u16)
+/// to 16 byte destination entries of (pa: i16, pb: i16, pc: i16, pd: i16, x: i32, y: i32)
+/// where (ox, oy) is the texture coordinate in 8-bit fixed-point that lands on screen at (cx, cy)
+pub fn bg_affine_set(bus: &mut Bus, src: Addr, dst: Addr, count: u32) {
+    for i in 0..count {
+        let src = src + 20 * i;
+        let dst = dst + 16 * i;
+
+        let ox = bus.read_32(src) as i32;
+        let oy = bus.read_32(src + 4) as i32;
+        let cx = bus.read_16(src + 8) as i16 as i32;
+        let cy = bus.read_16(src + 10) as i16 as i32;
+        let sx = bus.read_16(src + 12) as i16 as i32;
+        let sy = bus.read_16(src + 14) as i16 as i32;
+        let theta = bus.read_16(src + 16);
+
+        let (pa, pb, pc, pd) = affine_matrix(sx, sy, theta);
+        let x = ox - (pa * cx + pb * cy);
+        let y = oy - (pc * cx + pd * cy);
+
+        bus.write_16(dst, pa as u16);
+        bus.write_16(dst + 2, pb as u16);
+        bus.write_16(dst + 4, pc as u16);
+        bus.write_16(dst + 6, pd as u16);
+        bus.write_32(dst + 8, x as u32);
+        bus.write_32(dst + 12, y as u32);
+    }
+}
+
+/// ObjAffineSet: `count` 8 byte source entries of (sx: i16, sy: i16, theta: u16).
+/// PA, PB, PC and PD are written `stride` bytes apart, 2 for a packed matrix or 8 to write
+/// directly into the OAM rotation/scaling parameters.
+pub fn obj_affine_set(bus: &mut Bus, src: Addr, dst: Addr, count: u32, stride: u32) {
+    for i in 0..count {
+        let src = src + 8 * i;
+        let dst = dst + 4 * stride * i;
+
+        let sx = bus.read_16(src) as i16 as i32;
+        let sy = bus.read_16(src + 2) as i16 as i32;
+        let theta = bus.read_16(src + 4);
+
+        let (pa, pb, pc, pd) = affine_matrix(sx, sy, theta);
+        bus.write_16(dst, pa as u16);
+        bus.write_16(dst + stride, pb as u16);
+        bus.write_16(dst + 2 * stride, pc as u16);
+        bus.write_16(dst + 3 * stride, pd as u16);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arm7tdmi::arm::ArmInstruction;
+    use crate::arm7tdmi::exception::Exception;
+    use crate::arm7tdmi::thumb::ThumbInstruction;
+    use crate::arm7tdmi::Bus;
+    use crate::arm7tdmi::{CpuPipelineAction, InstructionDecoder};
+    use crate::sysbus::BoxedMemory;
+
+    #[test]
+    fn arctan_values() {
+        assert_eq!(arctan(0).0, 0);
+        // tan(45°) = 1.0
+        assert_eq!(arctan(0x4000).0, 0x2000);
+        assert_eq!(arctan(-0x4000).0, -0x2000);
+        // tan(26.565°) = 0.5
+        assert_eq!(arctan(0x2000).0, 0x12e4);
+    }
+
+    #[test]
+    fn arctan2_quadrants() {
+        // axis
+        assert_eq!(arctan2(0x100, 0).0, 0x0000);
+        assert_eq!(arctan2(0, 0x100).0, 0x4000);
+        assert_eq!(arctan2(-0x100, 0).0, 0x8000);
+        assert_eq!(arctan2(0, -0x100).0, 0xc000);
+        // diagonals
+        assert_eq!(arctan2(0x100, 0x100).0, 0x2000);
+        assert_eq!(arctan2(-0x100, 0x100).0, 0x6000);
+        assert_eq!(arctan2(-0x100, -0x100).0, 0xa000);
+        assert_eq!(arctan2(0x100, -0x100).0, 0xe000);
+        // off the diagonals
+        assert_eq!(arctan2(0x1000, 0x800).0, 0x12e4);
+        assert_eq!(arctan2(0x800, 0x1000).0, 0x4000 - 0x12e4);
+        assert_eq!(arctan2(-0x1000, -0x800).0, 0x8000 + 0x12e4);
+    }
+
+    #[test]
+    fn bg_affine_set_rotation() {
+        let mut mem = BoxedMemory::new(vec![0; 0x100].into_boxed_slice());
+
+        let write_source = |mem: &mut BoxedMemory, addr, sx: u16, theta: u16| {
+            // texture center (120.0, 80.0) lands on screen center (120, 80)
+            mem.write_32(addr, 120 << 8);
+            mem.write_32(addr + 4, 80 << 8);
+            mem.write_16(addr + 8, 120);
+            mem.write_16(addr + 10, 80);
+            mem.write_16(addr + 12, sx);
+            mem.write_16(addr + 14, sx);
+            mem.write_16(addr + 16, theta);
+        };
+        // identity
+        write_source(&mut mem, 0x00, 0x100, 0);
+        // 90 degrees
+        write_source(&mut mem, 0x14, 0x100, 0x4000);
+        // 45 degrees at 2x (the low byte of the angle is ignored)
+        write_source(&mut mem, 0x28, 0x200, 0x20ff);
+
+        bg_affine_set(&mut mem, 0, 0x80, 3);
+
+        let read_dest = |mem: &BoxedMemory, addr| {
+            (
+                mem.read_16(addr) as i16,
+                mem.read_16(addr + 2) as i16,
+                mem.read_16(addr + 4) as i16,
+                mem.read_16(addr + 6) as i16,
+                mem.read_32(addr + 8) as i32,
+                mem.read_32(addr + 12) as i32,
+            )
+        };
+        assert_eq!(read_dest(&mem, 0x80), (0x100, 0, 0, 0x100, 0, 0));
+        assert_eq!(
+            read_dest(&mem, 0x90),
+            (0, -0x100, 0x100, 0, 200 << 8, -40 << 8)
+        );
+        // 2 * cos(45) = 1.414
+        assert_eq!(read_dest(&mem, 0xa0), (362, -362, 362, 362, 16240, -51920));
+    }
+
+    #[test]
+    fn obj_affine_set_stride() {
+        let mut mem = BoxedMemory::new(vec![0; 0x100].into_boxed_slice());
+        // 90 degrees, x scaled by 0.5
+        mem.write_16(0, 0x80);
+        mem.write_16(2, 0x100);
+        mem.write_16(4, 0x4000);
+
+        obj_affine_set(&mut mem, 0, 0x40, 1, 2);
+        assert_eq!(mem.read_16(0x40) as i16, 0);
+        assert_eq!(mem.read_16(0x42) as i16, -0x80);
+        assert_eq!(mem.read_16(0x44) as i16, 0x100);
+        assert_eq!(mem.read_16(0x46) as i16, 0);
+
+        // OAM layout, interleaved with the sprite attributes
+        obj_affine_set(&mut mem, 0, 0x80 + 6, 1, 8);
+        assert_eq!(mem.read_16(0x86) as i16, 0);
+        assert_eq!(mem.read_16(0x8e) as i16, -0x80);
+        assert_eq!(mem.read_16(0x96) as i16, 0x100);
+        assert_eq!(mem.read_16(0x9e) as i16, 0);
+        assert_eq!(mem.read_16(0x88), 0);
+    }
+
+    #[test]
+    fn custom_swi_handler() {
+        let mut mem = BoxedMemory::new(vec![0; 4].into_boxed_slice());
+        let mut core = Core::new();
+        core.pc = 0x0800_0008;
+        core.gpr[0] = 6;
+        core.gpr[1] = 7;
+
+        let mut calls = 0;
+        core.set_swi_handler(
+            0x80,
+            Box::new(move |cpu, bus| {
+                calls += 1;
+                cpu.gpr[0] *= cpu.gpr[1];
+                cpu.gpr[2] = calls;
+                bus.write_32(0, 0x1234_5678);
+            }),
+        );
+
+        // swi 0x800000
+        let insn = ArmInstruction::decode(0xef800000, 0x0800_0000).unwrap();
+        assert_eq!(core.exec_arm(&mut mem, insn), Ok(CpuPipelineAction::IncPC));
+        assert_eq!(core.gpr[0], 42);
+        assert_eq!(core.gpr[2], 1);
+        assert_eq!(core.pc, 0x0800_0008);
+        assert_eq!(mem.read_32(0), 0x1234_5678);
+        assert_eq!(core.counters().exception(Exception::SoftwareInterrupt), 0);
+
+        // the handler keeps its state between calls
+        let insn = ThumbInstruction::decode(0xdf80, 0x0800_0000).unwrap();
+        assert_eq!(
+            core.exec_thumb(&mut mem, insn),
+            Ok(CpuPipelineAction::IncPC)
+        );
+        assert_eq!(core.gpr[0], 42 * 7);
+        assert_eq!(core.gpr[2], 2);
+
+        // takes precedence over HLE
+        core.hle_bios = true;
+        core.set_swi_handler(SWI_ARCTAN, Box::new(|cpu, _| cpu.gpr[0] = 0xbeef));
+        let insn = ThumbInstruction::decode(0xdf09, 0x0800_0000).unwrap();
+        core.exec_thumb(&mut mem, insn).unwrap();
+        assert_eq!(core.gpr[0], 0xbeef);
+
+        // back to the BIOS once removed
+        assert!(core.remove_swi_handler(0x80).is_some());
+        let insn = ThumbInstruction::decode(0xdf80, 0x0800_0000).unwrap();
+        assert_eq!(
+            core.exec_thumb(&mut mem, insn),
+            Ok(CpuPipelineAction::Flush)
+        );
+        assert_eq!(core.pc, Exception::SoftwareInterrupt as u32);
+
+        // a handler can unregister itself
+        core.set_swi_handler(
+            0x81,
+            Box::new(|cpu, _| {
+                cpu.remove_swi_handler(0x81);
+            }),
+        );
+        let insn = ThumbInstruction::decode(0xdf81, 0x0800_0000).unwrap();
+        assert_eq!(
+            core.exec_thumb(&mut mem, insn),
+            Ok(CpuPipelineAction::IncPC)
+        );
+        assert!(core.remove_swi_handler(0x81).is_none());
+    }
+
+    #[test]
+    fn hle_swi_arctan2() {
+        let mut mem = BoxedMemory::new(vec![].into_boxed_slice());
+        let mut core = Core::new();
+        core.hle_bios = true;
+        core.pc = 0x0800_0008;
+
+        // swi 0x0a0000
+        let insn = ArmInstruction::decode(0xef0a0000, 0x0800_0000).unwrap();
+        core.gpr[0] = -0x100i32 as u32;
+        core.gpr[1] = 0x100;
+        assert_eq!(core.exec_arm(&mut mem, insn), Ok(CpuPipelineAction::IncPC));
+        assert_eq!(core.gpr[0], 0x6000);
+        assert_eq!(core.gpr[3], 0x170);
+        assert_eq!(core.pc, 0x0800_0008);
+
+        // swi 0x09
+        let insn = ThumbInstruction::decode(0xdf09, 0x0800_0000).unwrap();
+        core.gpr[0] = 0x4000;
+        assert_eq!(
+            core.exec_thumb(&mut mem, insn),
+            Ok(CpuPipelineAction::IncPC)
+        );
+        assert_eq!(core.gpr[0], 0x2000);
+    }
+}
@@ -1,4 +1,5 @@
-use std::str::from_utf8;
+use std::fmt;
+use std::io;
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
@@ -6,8 +7,11 @@ use crate::arm7tdmi::{
     bus::{Bus, MemoryAccess, MemoryAccessWidth},
     Addr,
 };
+use crate::eeprom::Eeprom;
+use crate::flash::Flash;
+use crate::sram::Sram;
 use crate::sysbus::WaitState;
-use crate::util::read_bin_file;
+use crate::util::{read_bin_file, write_bin_file};
 use crate::GBAError;
 
 /// From GBATEK
@@ -35,104 +39,439 @@ use crate::GBAError;
 ///   0C6h    26    Not used         (seems to be unused)
 ///   0E0h    4     JOYBUS Entry Pt. (32bit ARM branch opcode, eg. "B joy_start")
 ///
-#[derive(Debug)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct CartridgeHeader {
-    // rom_entry_point: Addr,
-    game_title: String,
-    game_code: String,
-    maker_code: String,
-    software_version: u8,
-    checksum: u8,
-    // ram_entry_point: Addr,
-    // joybus_entry_point: Addr,
+    /// Up to 12 characters, the zero padding is trimmed
+    pub title: String,
+    pub game_code: String,
+    pub maker_code: String,
+    /// The software version byte
+    pub version: u8,
+    /// Whether the complement check at 0BDh matches bytes 0A0h-0BCh, the BIOS won't boot the
+    /// game otherwise
+    pub header_checksum_valid: bool,
 }
 
 impl CartridgeHeader {
     fn parse(bytes: &[u8]) -> CartridgeHeader {
-        // let (_, rom_entry_point) = le_u32(bytes).unwrap();
-        let game_title = from_utf8(&bytes[0xa0..0xac]).unwrap();
-        let game_code = from_utf8(&bytes[0xac..0xb0]).unwrap();
-        let maker_code = from_utf8(&bytes[0xb0..0xb2]).unwrap();
-        // let (_, ram_entry_point) = le_u32(&bytes[0xc0..]).unwrap();
-        // let (_, joybus_entry_point) = le_u32(&bytes[0xc0..]).unwrap();
+        // non-ascii bytes show up as replacement characters rather than failing the load
+        let ascii = |range: std::ops::Range<usize>| {
+            String::from_utf8_lossy(&bytes[range])
+                .trim_end_matches('\0')
+                .to_string()
+        };
 
         CartridgeHeader {
-            // rom_entry_point: rom_entry_point,
-            game_title: String::from(game_title),
-            game_code: String::from(game_code),
-            maker_code: String::from(maker_code),
-            software_version: bytes[0xbc],
-            checksum: bytes[0xbd],
-            // ram_entry_point: ram_entry_point,
-            // joybus_entry_point: joybus_entry_point,
+            title: ascii(0xa0..0xac),
+            game_code: ascii(0xac..0xb0),
+            maker_code: ascii(0xb0..0xb2),
+            version: bytes[0xbc],
+            header_checksum_valid: CartridgeHeader::checksum(&bytes[0xa0..0xbd]) == bytes[0xbd],
         }
     }
+
+    /// The complement check as the BIOS computes it
+    fn checksum(bytes: &[u8]) -> u8 {
+        bytes
+            .iter()
+            .fold(0u8, |chk, b| chk.wrapping_sub(*b))
+            .wrapping_sub(0x19)
+    }
+}
+
+/// The kind of save memory on the cartridge
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SaveType {
+    None,
+    Sram,
+    Flash64k,
+    Flash128k,
+    /// 4Kbit EEPROM
+    Eeprom512,
+    /// 64Kbit EEPROM
+    Eeprom8k,
+}
+
+impl SaveType {
+    /// ID strings the Nintendo SDK libraries put in the ROM, these are word aligned.
+    /// FLASH_V is older 64K flash. 512 byte EEPROMs can only be told apart at runtime, the
+    /// larger one is assumed.
+    const SIGNATURES: [(&'static [u8], SaveType); 6] = [
+        (b"EEPROM_V", SaveType::Eeprom8k),
+        (b"SRAM_V", SaveType::Sram),
+        (b"SRAM_F_V", SaveType::Sram),
+        (b"FLASH_V", SaveType::Flash64k),
+        (b"FLASH512_V", SaveType::Flash64k),
+        (b"FLASH1M_V", SaveType::Flash128k),
+    ];
+
+    /// Size of the save file in bytes
+    pub fn size(self) -> usize {
+        match self {
+            SaveType::None => 0,
+            SaveType::Sram => 32 * 1024,
+            SaveType::Flash64k => 64 * 1024,
+            SaveType::Flash128k => 128 * 1024,
+            SaveType::Eeprom512 => 512,
+            SaveType::Eeprom8k => 8 * 1024,
+        }
+    }
+
+    /// The save memory the ROM asks for with an SDK ID string, if it has one
+    pub fn detect(rom: &[u8]) -> Option<SaveType> {
+        for ofs in (0..rom.len()).step_by(4) {
+            for (signature, save_type) in Self::SIGNATURES.iter() {
+                if rom[ofs..].starts_with(signature) {
+                    return Some(*save_type);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// The save memory a ROM uses going by the SDK ID strings, games without one get SRAM
+pub fn detect_save_type(rom: &[u8]) -> SaveType {
+    SaveType::detect(rom).unwrap_or(SaveType::Sram)
+}
+
+impl fmt::Display for SaveType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SaveType::None => return write!(f, "no save memory"),
+            SaveType::Sram => "SRAM",
+            SaveType::Flash64k | SaveType::Flash128k => "Flash",
+            SaveType::Eeprom512 | SaveType::Eeprom8k => "EEPROM",
+        };
+        match self.size() {
+            size if size < 1024 => write!(f, "{} {} bytes", name, size),
+            size => write!(f, "{} {}K", name, size / 1024),
+        }
+    }
+}
+
+/// A save file that doesn't fit the save memory of the cartridge
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SaveMismatch {
+    pub save_type: SaveType,
+    /// Size of the save file in bytes
+    pub file_size: usize,
+}
+
+impl fmt::Display for SaveMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "the save file is {} bytes, which doesn't fit {}",
+            self.file_size, self.save_type
+        )
+    }
 }
 
 #[derive(Debug)]
 pub struct Cartridge {
-    pub header: CartridgeHeader,
+    header: CartridgeHeader,
     bytes: Box<[u8]>,
     ws: WaitState,
+    /// What the ROM asks for, `None` without an SDK ID string
+    detected_save_type: Option<SaveType>,
+    save_type: SaveType,
+    /// Only there for games that save to EEPROM, the system bus maps it at 0x0D00_0000
+    eeprom: Option<Eeprom>,
+    /// Only there for games that save to Flash, mapped at 0x0E00_0000
+    flash: Option<Flash>,
+    /// Only there for games that save to SRAM, mapped at 0x0E00_0000
+    sram: Option<Sram>,
+    /// Set by the game's profile, nothing in the ROM tells
+    rtc: bool,
+    /// The save file last loaded, where `flush_save` writes to
+    save_path: Option<String>,
 }
 
 impl Cartridge {
     const MIN_SIZE: usize = 4 * 1024 * 1024;
 
-    pub fn load(path: &str) -> Result<Cartridge, GBAError> {
-        let mut rom_bin = read_bin_file(path)?;
+    pub fn new(mut rom_bin: Vec<u8>) -> Cartridge {
+        let detected_save_type = SaveType::detect(&rom_bin);
+        let save_type = detect_save_type(&rom_bin);
         if rom_bin.len() < Cartridge::MIN_SIZE {
             rom_bin.resize_with(Cartridge::MIN_SIZE, Default::default);
         }
 
         let header = CartridgeHeader::parse(&rom_bin);
-        Ok(Cartridge {
+        let mut cartridge = Cartridge {
             header: header,
             bytes: rom_bin.into_boxed_slice(),
             ws: WaitState::new(5, 5, 8),
-        })
+            detected_save_type: detected_save_type,
+            save_type: save_type,
+            eeprom: None,
+            flash: None,
+            sram: None,
+            rtc: false,
+            save_path: None,
+        };
+        cartridge.attach_backup();
+        cartridge
+    }
+
+    /// Set up the save memory device for `save_type()`
+    fn attach_backup(&mut self) {
+        self.eeprom = None;
+        self.flash = None;
+        self.sram = None;
+        match self.save_type {
+            SaveType::None => {}
+            SaveType::Sram => self.sram = Some(Sram::new()),
+            SaveType::Flash64k | SaveType::Flash128k => {
+                self.flash = Some(Flash::new(self.save_type.size()))
+            }
+            // the size of the one the ROM asks for is told by the game's first command
+            SaveType::Eeprom8k if self.detected_save_type == Some(SaveType::Eeprom8k) => {
+                self.eeprom = Some(Eeprom::new())
+            }
+            SaveType::Eeprom512 | SaveType::Eeprom8k => {
+                self.eeprom = Some(Eeprom::with_size(self.save_type.size()))
+            }
+        }
+    }
+
+    /// No game inserted, the whole Game Pak region reads as open bus
+    pub fn empty() -> Cartridge {
+        Cartridge {
+            header: Default::default(),
+            bytes: Box::new([]),
+            ws: WaitState::new(5, 5, 8),
+            detected_save_type: None,
+            save_type: SaveType::None,
+            eeprom: None,
+            flash: None,
+            sram: None,
+            rtc: false,
+            save_path: None,
+        }
+    }
+
+    /// Reads past the end of the ROM see what is left on the address bus, which is the
+    /// halfword address
+    fn open_bus_16(addr: Addr) -> u16 {
+        (addr / 2) as u16
+    }
+
+    fn in_rom(&self, addr: Addr, width: usize) -> bool {
+        addr as usize + width <= self.bytes.len()
+    }
+
+    /// The title, codes and version from the ROM header
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    /// The save memory the game uses, as detected from the ROM contents
+    pub fn detected_save_type(&self) -> Option<SaveType> {
+        self.detected_save_type
+    }
+
+    /// The save memory the cartridge was given, SRAM unless the ROM asks for something else.
+    /// EEPROMs show up as 8K until the game's first access tells the size.
+    pub fn save_type(&self) -> SaveType {
+        match &self.eeprom {
+            Some(eeprom) if eeprom.size() == Some(512) => SaveType::Eeprom512,
+            _ => self.save_type,
+        }
+    }
+
+    /// Use `save_type` instead of what the ROM asks for, for games the detection gets wrong.
+    /// The save memory starts out blank, load the save file afterwards.
+    pub fn set_save_type(&mut self, save_type: SaveType) {
+        self.save_type = save_type;
+        self.attach_backup();
+    }
+
+    /// Whether the cartridge has a real-time clock. It's only recorded for now, the clock on the
+    /// GPIO port isn't emulated.
+    pub fn has_rtc(&self) -> bool {
+        self.rtc
+    }
+
+    pub fn set_rtc(&mut self, rtc: bool) {
+        self.rtc = rtc;
+    }
+
+    pub fn eeprom(&self) -> Option<&Eeprom> {
+        self.eeprom.as_ref()
+    }
+
+    pub fn eeprom_mut(&mut self) -> Option<&mut Eeprom> {
+        self.eeprom.as_mut()
+    }
+
+    pub fn flash(&self) -> Option<&Flash> {
+        self.flash.as_ref()
+    }
+
+    pub fn flash_mut(&mut self) -> Option<&mut Flash> {
+        self.flash.as_mut()
+    }
+
+    pub fn sram(&self) -> Option<&Sram> {
+        self.sram.as_ref()
+    }
+
+    pub fn sram_mut(&mut self) -> Option<&mut Sram> {
+        self.sram.as_mut()
+    }
+
+    pub fn load(path: &str) -> Result<Cartridge, GBAError> {
+        let rom_bin = read_bin_file(path)?;
+        Ok(Cartridge::new(rom_bin))
+    }
+
+    /// Write the save memory to `path`, without save memory there's nothing to write
+    pub fn save_to(&self, path: &str) -> Result<(), GBAError> {
+        if let Some(eeprom) = &self.eeprom {
+            write_bin_file(path, &eeprom.save_data())?;
+        }
+        if let Some(flash) = &self.flash {
+            write_bin_file(path, flash.save_data())?;
+        }
+        if let Some(sram) = &self.sram {
+            write_bin_file(path, sram.save_data())?;
+        }
+        Ok(())
+    }
+
+    /// Write the save memory back to the file it was loaded from, if any
+    pub fn flush_save(&self) -> Result<(), GBAError> {
+        match &self.save_path {
+            Some(path) => self.save_to(path),
+            None => Ok(()),
+        }
+    }
+
+    /// Restore the save memory from a file written by `save_to`, a missing file is a blank
+    /// save. Flash keeps writing to the file from then on and `flush_save` writes to it.
+    ///
+    /// A file that doesn't fit the save memory fails with `GBAError::SaveMismatch` and is left
+    /// alone, it's likely from another game or emulator.
+    pub fn load_from(&mut self, path: &str) -> Result<(), GBAError> {
+        self.load_save(path, false)
+    }
+
+    /// Like `load_from`, but a file of the wrong size is taken as raw bytes, truncated or padded
+    /// with erased bytes to fit
+    pub fn load_from_resized(&mut self, path: &str) -> Result<(), GBAError> {
+        self.load_save(path, true)
+    }
+
+    /// The sizes a save file may have, smallest first
+    fn save_sizes(&self) -> Vec<usize> {
+        match self.save_type {
+            SaveType::None => vec![],
+            SaveType::Eeprom512 | SaveType::Eeprom8k => vec![512, 8 * 1024],
+            save_type => vec![save_type.size()],
+        }
+    }
+
+    /// Without save memory nothing fits, any save file is a mismatch
+    fn load_save(&mut self, path: &str, resize: bool) -> Result<(), GBAError> {
+        let sizes = self.save_sizes();
+        let data = match read_bin_file(path) {
+            Ok(mut data) => {
+                if !sizes.contains(&data.len()) {
+                    if !resize || sizes.is_empty() {
+                        return Err(GBAError::SaveMismatch(SaveMismatch {
+                            save_type: self.save_type,
+                            file_size: data.len(),
+                        }));
+                    }
+                    let size = sizes.iter().find(|&&size| size >= data.len());
+                    data.resize(*size.unwrap_or(&sizes[sizes.len() - 1]), 0xff);
+                }
+                Some(data)
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+
+        if let Some(eeprom) = &mut self.eeprom {
+            if let Some(data) = &data {
+                eeprom.load_save_data(data)?;
+            }
+        }
+        if let Some(flash) = &mut self.flash {
+            if let Some(data) = &data {
+                flash.load_save_data(data)?;
+            }
+            flash.set_save_file(path)?;
+        }
+        self.save_path = Some(path.to_string());
+        if let Some(sram) = &mut self.sram {
+            if let Some(data) = &data {
+                sram.load_save_data(data);
+            }
+        }
+        Ok(())
     }
 }
 
 impl Bus for Cartridge {
     fn read_32(&self, addr: Addr) -> u32 {
+        if !self.in_rom(addr, 4) {
+            return self.read_16(addr) as u32 | (self.read_16(addr + 2) as u32) << 16;
+        }
         (&self.bytes[addr as usize..])
             .read_u32::<LittleEndian>()
             .unwrap()
     }
 
     fn read_16(&self, addr: Addr) -> u16 {
+        if !self.in_rom(addr, 2) {
+            return Self::open_bus_16(addr);
+        }
         (&self.bytes[addr as usize..])
             .read_u16::<LittleEndian>()
             .unwrap()
     }
 
     fn read_8(&self, addr: Addr) -> u8 {
+        if !self.in_rom(addr, 1) {
+            return (Self::open_bus_16(addr) >> (8 * (addr & 1))) as u8;
+        }
         (&self.bytes[addr as usize..])[0]
     }
 
     fn write_32(&mut self, addr: Addr, value: u32) {
+        if !self.in_rom(addr, 4) {
+            return;
+        }
         (&mut self.bytes[addr as usize..])
             .write_u32::<LittleEndian>(value)
             .unwrap()
     }
 
     fn write_16(&mut self, addr: Addr, value: u16) {
+        if !self.in_rom(addr, 2) {
+            return;
+        }
         (&mut self.bytes[addr as usize..])
             .write_u16::<LittleEndian>(value)
             .unwrap()
     }
 
     fn write_8(&mut self, addr: Addr, value: u8) {
+        if !self.in_rom(addr, 1) {
+            return;
+        }
         (&mut self.bytes[addr as usize..]).write_u8(value).unwrap()
     }
 
     fn get_bytes(&self, addr: Addr) -> &[u8] {
-        &self.bytes[addr as usize..]
+        self.bytes.get(addr as usize..).unwrap_or(&[])
     }
 
     fn get_bytes_mut(&mut self, addr: Addr) -> &mut [u8] {
-        &mut self.bytes[addr as usize..]
+        self.bytes.get_mut(addr as usize..).unwrap_or(&mut [])
     }
 
     fn get_cycles(&self, _addr: Addr, access: MemoryAccess) -> usize {
@@ -143,3 +482,208 @@ impl Bus for Cartridge {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom_with_id(id: &[u8]) -> Cartridge {
+        let mut rom = vec![0; 0x1000];
+        rom[0x800..0x800 + id.len()].copy_from_slice(id);
+        Cartridge::new(rom)
+    }
+
+    #[test]
+    fn detected_save_type() {
+        let cases: &[(&[u8], SaveType, usize, &str)] = &[
+            (b"EEPROM_V124", SaveType::Eeprom8k, 0x2000, "EEPROM 8K"),
+            (b"SRAM_V113", SaveType::Sram, 0x8000, "SRAM 32K"),
+            (b"SRAM_F_V100", SaveType::Sram, 0x8000, "SRAM 32K"),
+            (b"FLASH_V126", SaveType::Flash64k, 0x10000, "Flash 64K"),
+            (b"FLASH512_V131", SaveType::Flash64k, 0x10000, "Flash 64K"),
+            (b"FLASH1M_V103", SaveType::Flash128k, 0x2_0000, "Flash 128K"),
+        ];
+        for (id, save_type, size, name) in cases {
+            let detected = rom_with_id(id).detected_save_type().unwrap();
+            assert_eq!(detected, *save_type);
+            assert_eq!(detected.size(), *size);
+            assert_eq!(detected.to_string(), *name);
+        }
+        assert_eq!(SaveType::Eeprom512.to_string(), "EEPROM 512 bytes");
+
+        assert_eq!(Cartridge::new(vec![]).detected_save_type(), None);
+    }
+
+    #[test]
+    fn header() {
+        let mut rom = vec![0; 0x1000];
+        rom[0xa0..0xb2].copy_from_slice(b"POKEMON EMERBPEE01");
+        rom[0xb2] = 0x96;
+        rom[0xbc] = 1;
+        rom[0xbd] = 0x71;
+        let header = Cartridge::new(rom.clone()).header().clone();
+        assert_eq!(header.title, "POKEMON EMER");
+        assert_eq!(header.game_code, "BPEE");
+        assert_eq!(header.maker_code, "01");
+        assert_eq!(header.version, 1);
+        assert!(header.header_checksum_valid);
+
+        rom[0xa0..0xac].copy_from_slice(b"HOMEBREW\0\0\0\0");
+        let header = Cartridge::new(rom).header().clone();
+        assert_eq!(header.title, "HOMEBREW");
+        assert!(!header.header_checksum_valid);
+    }
+
+    #[test]
+    fn save_type() {
+        assert_eq!(detect_save_type(b"...."), SaveType::Sram);
+        assert_eq!(detect_save_type(b"....FLASH1M_V103"), SaveType::Flash128k);
+        // the ID strings are word aligned
+        assert_eq!(detect_save_type(b"..EEPROM_V124"), SaveType::Sram);
+
+        let cart = rom_with_id(b"EEPROM_V124");
+        assert_eq!(cart.save_type(), SaveType::Eeprom8k);
+        assert!(cart.eeprom().is_some() && cart.flash().is_none());
+
+        // once the game tells the EEPROM size
+        let mut cart = rom_with_id(b"EEPROM_V124");
+        cart.eeprom_mut()
+            .unwrap()
+            .load_save_data(&[0xff; 512])
+            .unwrap();
+        assert_eq!(cart.save_type(), SaveType::Eeprom512);
+
+        let mut cart = Cartridge::new(vec![]);
+        cart.set_save_type(SaveType::Eeprom512);
+        assert_eq!(cart.eeprom().unwrap().size(), Some(512));
+        assert_eq!(cart.save_type(), SaveType::Eeprom512);
+
+        let cart = rom_with_id(b"FLASH512_V131");
+        assert_eq!(cart.save_type(), SaveType::Flash64k);
+        assert_eq!(cart.flash().unwrap().size(), 64 * 1024);
+
+        // no ID string, like homebrew
+        let cart = Cartridge::new(vec![]);
+        assert_eq!(cart.save_type(), SaveType::Sram);
+        assert!(cart.eeprom().is_none() && cart.flash().is_none());
+        assert!(cart.sram().is_some());
+
+        let cart = Cartridge::empty();
+        assert_eq!(cart.save_type(), SaveType::None);
+        assert!(cart.eeprom().is_none() && cart.flash().is_none() && cart.sram().is_none());
+    }
+
+    #[test]
+    fn eeprom_save_file() {
+        let path = std::env::temp_dir().join("rustboyadvance_eeprom_save_file.sav");
+        let path = path.to_str().unwrap();
+
+        let mut cart = rom_with_id(b"EEPROM_V124");
+        let mut data = vec![0; 8 * 1024];
+        data[0x10] = 0xab;
+        cart.eeprom_mut().unwrap().load_save_data(&data).unwrap();
+        cart.save_to(path).unwrap();
+
+        let mut cart = rom_with_id(b"EEPROM_V124");
+        cart.load_from(path).unwrap();
+        assert_eq!(cart.eeprom().unwrap().save_data(), data);
+        std::fs::remove_file(path).unwrap();
+
+        // nothing to save without save memory
+        Cartridge::empty().save_to(path).unwrap();
+        assert!(!std::path::Path::new(path).exists());
+    }
+
+    #[test]
+    fn sram_save_file() {
+        let path = std::env::temp_dir().join("rustboyadvance_sram_save_file.sav");
+        let path = path.to_str().unwrap();
+
+        let mut cart = rom_with_id(b"SRAM_V113");
+        cart.sram_mut().unwrap().write_8(0x7fff, 0xcd);
+        cart.save_to(path).unwrap();
+        assert_eq!(std::fs::read(path).unwrap().len(), 32 * 1024);
+
+        let mut cart = rom_with_id(b"SRAM_V113");
+        cart.load_from(path).unwrap();
+        assert_eq!(cart.sram().unwrap().read_8(0x7fff), 0xcd);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn save_size_mismatch() {
+        let path = std::env::temp_dir().join("rustboyadvance_save_size_mismatch.sav");
+        let path = path.to_str().unwrap();
+
+        // a 64K flash save for a 128K flash game
+        std::fs::write(path, vec![0x42; 64 * 1024]).unwrap();
+        let mut cart = rom_with_id(b"FLASH1M_V103");
+        match cart.load_from(path) {
+            Err(GBAError::SaveMismatch(mismatch)) => {
+                assert_eq!(mismatch.save_type, SaveType::Flash128k);
+                assert_eq!(mismatch.file_size, 64 * 1024);
+                assert_eq!(
+                    mismatch.to_string(),
+                    "the save file is 65536 bytes, which doesn't fit Flash 128K"
+                );
+            }
+            result => panic!("expected a mismatch, got {:?}", result),
+        }
+        // and the file is left alone
+        assert_eq!(std::fs::read(path).unwrap().len(), 64 * 1024);
+
+        // padded with erased bytes when asked to
+        cart.load_from_resized(path).unwrap();
+        let data = cart.flash().unwrap().save_data();
+        assert_eq!((data[0xffff], data[0x1_0000]), (0x42, 0xff));
+        assert_eq!(std::fs::read(path).unwrap().len(), 128 * 1024);
+
+        // EEPROMs come in two sizes, anything in between goes to the larger one
+        std::fs::write(path, vec![0; 1000]).unwrap();
+        let mut cart = rom_with_id(b"EEPROM_V124");
+        assert!(cart.load_from(path).is_err());
+        cart.load_from_resized(path).unwrap();
+        assert_eq!(cart.eeprom().unwrap().size(), Some(8 * 1024));
+
+        // a 64KB Flash save loaded for an SRAM game, truncated to the 32K of SRAM when asked to
+        let mut data = vec![0x42; 64 * 1024];
+        data[0x7fff] = 0x43;
+        std::fs::write(path, data).unwrap();
+        let mut cart = rom_with_id(b"SRAM_V113");
+        match cart.load_from(path) {
+            Err(GBAError::SaveMismatch(mismatch)) => {
+                assert_eq!(mismatch.save_type, SaveType::Sram);
+                assert_eq!(
+                    mismatch.to_string(),
+                    "the save file is 65536 bytes, which doesn't fit SRAM 32K"
+                );
+            }
+            result => panic!("expected a mismatch, got {:?}", result),
+        }
+        assert_eq!(cart.sram().unwrap().read_8(0), 0);
+        cart.load_from_resized(path).unwrap();
+        let sram = cart.sram().unwrap();
+        assert_eq!((sram.read_8(0), sram.read_8(0x7fff)), (0x42, 0x43));
+
+        // and nothing fits a cartridge without save memory
+        let mut cart = Cartridge::empty();
+        assert!(cart.load_from(path).is_err());
+        assert!(cart.load_from_resized(path).is_err());
+        std::fs::remove_file(path).unwrap();
+        cart.load_from(path).unwrap();
+    }
+
+    #[test]
+    fn empty_reads_open_bus() {
+        let mut cart = Cartridge::empty();
+        assert_eq!(cart.read_16(0), 0);
+        assert_eq!(cart.read_16(0x1234), 0x091a);
+        assert_eq!(cart.read_32(0x1234), 0x091b_091a);
+        assert_eq!(cart.read_8(0x1235), 0x09);
+        assert!(cart.get_bytes(0x100).is_empty());
+
+        // nothing to write to
+        cart.write_16(0x1234, 0xffff);
+        assert_eq!(cart.read_16(0x1234), 0x091a);
+    }
+}
@@ -1,14 +1,21 @@
+use std::collections::VecDeque;
+
 use crate::arm7tdmi::bus::Bus;
-use crate::arm7tdmi::{Addr, CpuState};
+use crate::arm7tdmi::psr::RegPSR;
+use crate::arm7tdmi::{Addr, CpuMode, CpuState};
 use crate::disass::Disassembler;
 use crate::ioregs::consts::*;
 use crate::lcd::*;
+use crate::num::FromPrimitive;
 use crate::GBAError;
 
 use super::palette_view::create_palette_view;
 use super::render_view::create_render_view;
 use super::tile_view::create_tile_view;
-use super::{parser::Value, Debugger, DebuggerError, DebuggerResult};
+use super::{
+    parser::{DerefType, Value},
+    Debugger, DebuggerError, DebuggerResult,
+};
 
 use ansi_term::Colour;
 
@@ -21,6 +28,16 @@ pub enum DisassMode {
     ModeThumb,
 }
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RegisterTarget {
+    /// A register of the current mode
+    Gpr(usize),
+    /// A register of another mode's bank, e.g sp_irq
+    Banked(CpuMode, usize),
+    Cpsr,
+    Spsr,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Command {
     Info,
@@ -37,6 +54,12 @@ pub enum Command {
     TileView(u32),
     ClearBreakpoints,
     ListBreakpoints,
+    SetRegister(RegisterTarget, u32),
+    SetMemory(Addr, u32, DerefType),
+    /// Every address from the first up to the second where the bytes are found
+    Find(Addr, Addr, Vec<u8>),
+    Pipeline,
+    FlushPipeline,
     Reset,
     Quit,
 }
@@ -179,6 +202,50 @@ impl Command {
             }
             PaletteView => create_palette_view(debugger.gba.sysbus.get_bytes(0x0500_0000)),
             TileView(bg) => create_tile_view(bg, &debugger.gba),
+            SetRegister(target, value) => {
+                let cpu = &mut debugger.gba.cpu;
+                match target {
+                    RegisterTarget::Gpr(reg) => cpu.set_reg(reg, value),
+                    RegisterTarget::Banked(mode, reg) => cpu.set_banked_reg(mode, reg, value),
+                    RegisterTarget::Cpsr => cpu.set_cpsr(value),
+                    RegisterTarget::Spsr => match cpu.cpsr.mode().spsr_index() {
+                        Some(index) => cpu.spsr[index] = RegPSR::new(value),
+                        None => println!("{} mode has no SPSR", cpu.cpsr.mode()),
+                    },
+                }
+                // changing PC or the cpu state invalidates whatever was prefetched
+                if target == RegisterTarget::Gpr(15) || target == RegisterTarget::Cpsr {
+                    cpu.pipeline_arm.flush();
+                    cpu.pipeline_thumb.flush();
+                }
+            }
+            SetMemory(addr, value, ref width) => {
+                let sysbus = &mut debugger.gba.sysbus;
+                match width {
+                    DerefType::Word => sysbus.write_32(addr, value),
+                    DerefType::HalfWord => sysbus.write_16(addr, value as u16),
+                    DerefType::Byte => sysbus.write_8(addr, value as u8),
+                }
+            }
+            Find(start, end, ref pattern) => {
+                let matches = debugger.find_bytes(start, end, pattern);
+                for addr in matches.iter() {
+                    println!("0x{:08x}", addr);
+                }
+                println!("{} matches", matches.len());
+            }
+            Pipeline => {
+                let (decoded, fetched) = debugger.gba.cpu.pipeline_contents();
+                match decoded {
+                    Some(insn) => println!("decoded:\t@0x{:08x}:\t{}", insn.get_pc(), insn),
+                    None => println!("decoded:\t-"),
+                }
+                match fetched {
+                    Some((addr, opcode)) => println!("fetched:\t@0x{:08x}:\t{:08x}", addr, opcode),
+                    None => println!("fetched:\t-"),
+                }
+            }
+            FlushPipeline => debugger.gba.cpu.invalidate_pipeline(),
             Reset => {
                 println!("resetting cpu...");
                 debugger.gba.cpu.reset();
@@ -189,6 +256,55 @@ impl Command {
 }
 
 impl Debugger {
+    /// Addresses in `start..end` where `pattern` starts and fits before `end`, read through the
+    /// bus so mirrors and I/O registers are searched as the CPU sees them. The reads don't show
+    /// up in the memory access stats.
+    pub fn find_bytes(&self, start: Addr, end: Addr, pattern: &[u8]) -> Vec<Addr> {
+        let mut matches = vec![];
+        if pattern.is_empty() || end < start {
+            return matches;
+        }
+        let mut window = VecDeque::with_capacity(pattern.len());
+        for addr in start..end {
+            if window.len() == pattern.len() {
+                window.pop_front();
+            }
+            window.push_back(self.gba.sysbus.peek_8(addr));
+            if window.len() == pattern.len() && window.iter().eq(pattern.iter()) {
+                matches.push(addr + 1 - pattern.len() as Addr);
+            }
+        }
+        matches
+    }
+
+    /// Hex digits two per byte, e.g. `deadbeef`, bytes may be grouped with `-` or `_`. A pattern
+    /// of decimal digits only would read as a number, so it needs one of these: `00_12`.
+    fn val_hex_bytes(&self, arg: &Value) -> DebuggerResult<Vec<u8>> {
+        let digits: String = match arg {
+            Value::Identifier(s) => s.chars().filter(|&c| c != '-' && c != '_').collect(),
+            v => {
+                return Err(DebuggerError::InvalidArgument(format!(
+                    "expected hex bytes like deadbeef or 00_12, got {:?}",
+                    v
+                )))
+            }
+        };
+        if digits.is_empty() || digits.len() % 2 != 0 {
+            return Err(DebuggerError::InvalidArgument(format!(
+                "{} is not a whole number of bytes",
+                digits
+            )));
+        }
+        (0..digits.len())
+            .step_by(2)
+            .map(|i| {
+                u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| {
+                    DebuggerError::InvalidArgument(format!("{} is not hex", &digits[i..i + 2]))
+                })
+            })
+            .collect()
+    }
+
     fn get_disassembler_args(&self, args: Vec<Value>) -> DebuggerResult<(Addr, usize)> {
         match args.len() {
             2 => {
@@ -328,6 +444,61 @@ impl Debugger {
                 let bg = self.val_number(&args[0])?;
                 Ok(Command::TileView(bg))
             }
+            "set" => match (args.first(), args.len()) {
+                (Some(Value::Identifier(ref what)), 3) if what == "reg" => {
+                    let target = self.val_reg_target(&args[1])?;
+                    let value = self.val_number(&args[2])?;
+                    if target == RegisterTarget::Cpsr && CpuMode::from_u32(value & 0x1f).is_none() {
+                        return Err(DebuggerError::InvalidArgument(format!(
+                            "{:#x} is not a valid cpu mode",
+                            value & 0x1f
+                        )));
+                    }
+                    Ok(Command::SetRegister(target, value))
+                }
+                (Some(Value::Identifier(ref what)), 3...4) if what == "mem" => {
+                    let addr = self.val_address(&args[1])?;
+                    let value = self.val_number(&args[2])?;
+                    let width = match args.get(3) {
+                        None => DerefType::Word,
+                        Some(width) => match self.val_number(width)? {
+                            32 => DerefType::Word,
+                            16 => DerefType::HalfWord,
+                            8 => DerefType::Byte,
+                            n => {
+                                return Err(DebuggerError::InvalidArgument(format!(
+                                    "invalid width {}, expected 8, 16 or 32",
+                                    n
+                                )))
+                            }
+                        },
+                    };
+                    Ok(Command::SetMemory(addr, value, width))
+                }
+                _ => Err(DebuggerError::InvalidCommandFormat(
+                    "set reg <name> <value> | set mem <addr> <value> [width]".to_string(),
+                )),
+            },
+            "find" => {
+                if args.len() != 3 {
+                    return Err(DebuggerError::InvalidCommandFormat(
+                        "find <addr_start> <addr_end> <hex-bytes>".to_string(),
+                    ));
+                }
+                let start = self.val_address(&args[0])?;
+                let end = self.val_address(&args[1])?;
+                let pattern = self.val_hex_bytes(&args[2])?;
+                Ok(Command::Find(start, end, pattern))
+            }
+            "pipeline" => match args.first() {
+                None => Ok(Command::Pipeline),
+                Some(Value::Identifier(ref what)) if what == "flush" && args.len() == 1 => {
+                    Ok(Command::FlushPipeline)
+                }
+                _ => Err(DebuggerError::InvalidCommandFormat(
+                    "pipeline [flush]".to_string(),
+                )),
+            },
             "bl" => Ok(Command::ListBreakpoints),
             "q" | "quit" => Ok(Command::Quit),
             "r" | "reset" => Ok(Command::Reset),
@@ -336,3 +507,112 @@ impl Debugger {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::parser::{parse_expr, Expr};
+    use super::*;
+    use crate::arm7tdmi::Core;
+    use crate::cartridge::Cartridge;
+    use crate::GameBoyAdvance;
+
+    fn make_debugger() -> Debugger {
+        let mut cpu = Core::new();
+        cpu.reset();
+        Debugger::new(GameBoyAdvance::new(
+            cpu,
+            vec![0; 0x4000],
+            Cartridge::new(vec![]),
+        ))
+    }
+
+    fn exec(debugger: &mut Debugger, line: &str) {
+        debugger.eval_expr(parse_expr(line).unwrap());
+    }
+
+    #[test]
+    fn set_reg_and_mem() {
+        let mut debugger = make_debugger();
+
+        exec(&mut debugger, "set reg r0 0xcafe");
+        exec(&mut debugger, "set mem 0x02000010 0xdeadbeef");
+        exec(&mut debugger, "set mem 0x02000014 0x1234 8");
+
+        let dump = format!("{}", debugger.gba.cpu);
+        assert!(dump.contains("R0  = 0x0000cafe"));
+        assert_eq!(debugger.gba.sysbus.read_32(0x0200_0010), 0xdeadbeef);
+        assert_eq!(debugger.gba.sysbus.read_32(0x0200_0014), 0x34);
+    }
+
+    #[test]
+    fn set_banked_reg_and_pc() {
+        let mut debugger = make_debugger();
+        assert_eq!(debugger.gba.cpu.cpsr.mode(), CpuMode::Supervisor);
+
+        exec(&mut debugger, "set reg sp_irq 0x03007fa0");
+        exec(&mut debugger, "set reg sp 0x03007fe0");
+        assert_eq!(debugger.gba.cpu.get_reg(13), 0x03007fe0);
+
+        // switching to irq mode brings in the banked sp
+        exec(&mut debugger, "set reg cpsr 0xd2");
+        assert_eq!(debugger.gba.cpu.cpsr.mode(), CpuMode::Irq);
+        assert_eq!(debugger.gba.cpu.get_reg(13), 0x03007fa0);
+
+        debugger.gba.step().unwrap();
+        exec(&mut debugger, "set reg pc 0x100");
+        assert_eq!(debugger.gba.cpu.get_next_pc(), 0x100);
+    }
+
+    #[test]
+    fn find_bytes() {
+        let mut debugger = make_debugger();
+        exec(&mut debugger, "set mem 0x02000100 0xefbeadde");
+        exec(&mut debugger, "set mem 0x02000123 0xde 8");
+        exec(&mut debugger, "set mem 0x02000124 0xefbead 32");
+
+        let command = |debugger: &Debugger, line| match parse_expr(line).unwrap() {
+            Expr::Command(command, args) => debugger.eval_command(command, args),
+            _ => unreachable!(),
+        };
+        let find = command(&debugger, "find 0x02000000 0x02000200 deadbeef").unwrap();
+        assert_eq!(
+            find,
+            Command::Find(0x0200_0000, 0x0200_0200, vec![0xde, 0xad, 0xbe, 0xef])
+        );
+        assert_eq!(
+            debugger.find_bytes(0x0200_0000, 0x0200_0200, &[0xde, 0xad, 0xbe, 0xef]),
+            vec![0x0200_0100, 0x0200_0123]
+        );
+        // the whole pattern has to fit before the end
+        assert_eq!(
+            debugger.find_bytes(0x0200_0000, 0x0200_0126, &[0xde, 0xad, 0xbe, 0xef]),
+            vec![0x0200_0100]
+        );
+        // mirrors are searched too
+        assert_eq!(
+            debugger.find_bytes(0x0204_0000, 0x0204_0200, &[0xad, 0xbe]),
+            vec![0x0204_0101, 0x0204_0124]
+        );
+        debugger.gba.sysbus.set_count_accesses(true);
+        debugger.find_bytes(0x0300_0000, 0x0300_8000, &[0xde]);
+        assert_eq!(debugger.gba.sysbus.memory_stats(), Default::default());
+
+        assert!(command(&debugger, "find 0x02000000 0x02000200 de-ad_be").is_ok());
+        assert!(command(&debugger, "find 0x02000000 0x02000200 00_12").is_ok());
+        assert!(command(&debugger, "find 0x02000000 0x02000200 dea").is_err());
+        assert!(command(&debugger, "find 0x02000000 0x02000200 0x12").is_err());
+        assert!(command(&debugger, "find 0x02000000 deadbeef").is_err());
+    }
+
+    #[test]
+    fn pipeline_flush() {
+        let mut debugger = make_debugger();
+        debugger.gba.step().unwrap();
+        let next_pc = debugger.gba.cpu.get_next_pc();
+        assert!(debugger.gba.cpu.pipeline_contents().1.is_some());
+
+        exec(&mut debugger, "pipeline flush");
+        assert_eq!(debugger.gba.cpu.pipeline_contents(), (None, None));
+        assert_eq!(debugger.gba.cpu.get_next_pc(), next_pc);
+    }
+}
@@ -3,14 +3,14 @@ use rustyline::Editor;
 
 use colored::*;
 
-use super::arm7tdmi::{Addr, Bus, CpuError};
+use super::arm7tdmi::{Addr, Bus, CpuError, CpuMode};
 use super::GameBoyAdvance;
 
 mod parser;
 use parser::{parse_expr, DerefType, Expr, Value};
 
 mod command;
-use command::Command;
+use command::{Command, RegisterTarget};
 
 mod palette_view;
 mod render_view;
@@ -91,6 +91,56 @@ impl Debugger {
         }
     }
 
+    fn decode_reg_target(&self, s: &str) -> DebuggerResult<RegisterTarget> {
+        match s {
+            "cpsr" => return Ok(RegisterTarget::Cpsr),
+            "spsr" => return Ok(RegisterTarget::Spsr),
+            _ => {}
+        }
+        let mut parts = s.splitn(2, '_');
+        let reg = match parts.next().unwrap() {
+            "r11" => 11,
+            "r12" => 12,
+            "r13" => 13,
+            "r14" => 14,
+            "r15" => 15,
+            reg => self.decode_reg(reg)?,
+        };
+        let mode = match parts.next() {
+            None => return Ok(RegisterTarget::Gpr(reg)),
+            Some("usr") => CpuMode::User,
+            Some("fiq") => CpuMode::Fiq,
+            Some("irq") => CpuMode::Irq,
+            Some("svc") => CpuMode::Supervisor,
+            Some("abt") => CpuMode::Abort,
+            Some("und") => CpuMode::Undefined,
+            Some("sys") => CpuMode::System,
+            Some(mode) => {
+                return Err(DebuggerError::InvalidArgument(format!(
+                    "{:?} is not a cpu mode",
+                    mode
+                )))
+            }
+        };
+        match reg {
+            8...14 => Ok(RegisterTarget::Banked(mode, reg)),
+            _ => Err(DebuggerError::InvalidArgument(format!(
+                "{:?} is not a banked register",
+                s
+            ))),
+        }
+    }
+
+    fn val_reg_target(&self, arg: &Value) -> DebuggerResult<RegisterTarget> {
+        match arg {
+            Value::Identifier(reg) => self.decode_reg_target(reg),
+            v => Err(DebuggerError::InvalidArgument(format!(
+                "expected a register name, got {:?}",
+                v
+            ))),
+        }
+    }
+
     fn val_number(&self, arg: &Value) -> DebuggerResult<u32> {
         match arg {
             Value::Num(n) => Ok(*n),
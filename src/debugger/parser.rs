@@ -2,7 +2,7 @@ use nom;
 use nom::branch::alt;
 use nom::bytes::complete::{tag, take_while1, take_while_m_n};
 use nom::character::complete::{char, digit1, multispace0, multispace1};
-use nom::combinator::{cut, map, map_res, opt};
+use nom::combinator::{cut, map, map_res, not, opt};
 use nom::error::{context, convert_error, ParseError, VerboseError};
 use nom::multi::separated_list;
 use nom::sequence::{delimited, preceded, separated_pair, terminated, tuple};
@@ -45,8 +45,19 @@ fn parse_u32<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, u32, E>
     context("u32", map_res(digit1, |s| u32::from_str_radix(s, 10)))(i)
 }
 
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
 fn parse_num<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Value, E> {
-    map(alt((parse_u32_hex, parse_u32)), |n| Value::Num(n))(i)
+    // a number takes the whole word, "12ab" is a name and not 12 followed by garbage
+    map(
+        terminated(
+            alt((parse_u32_hex, parse_u32)),
+            not(take_while_m_n(1, 1, is_word_char)),
+        ),
+        |n| Value::Num(n),
+    )(i)
 }
 
 fn parse_boolean<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Value, E> {
@@ -60,10 +71,9 @@ fn parse_boolean<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Val
 }
 
 fn parse_identifier<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, Value, E> {
-    map(
-        take_while1(|c: char| c.is_alphanumeric() || c == '_' || c == '-'),
-        |s: &str| Value::Identifier(String::from(s)),
-    )(i)
+    map(take_while1(is_word_char), |s: &str| {
+        Value::Identifier(String::from(s))
+    })(i)
 }
 
 fn parse_deref_type<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, DerefType, E> {
@@ -184,6 +194,13 @@ mod tests {
                 ]
             ))
         );
+        assert_eq!(
+            parse_expr("find 12ab 0x12"),
+            Ok(Expr::Command(
+                Value::Identifier("find".to_string()),
+                vec![Value::Identifier("12ab".to_string()), Value::Num(0x12)]
+            ))
+        );
     }
 
     #[test]
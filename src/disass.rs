@@ -1,8 +1,74 @@
 use std::marker::PhantomData;
 
-use super::arm7tdmi::{Addr, InstructionDecoder, InstructionDecoderError};
+use super::arm7tdmi::{reg_string, Addr, InstructionDecoder, InstructionDecoderError};
+use std::fmt;
 use std::io::ErrorKind;
 
+/// Letter case of the disassembly text
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum DisasmStyle {
+    Lowercase,
+    /// `LDR R0, [R1]`, as in the official ARM listings. Only mnemonics, shifts and registers
+    /// change, numbers and comments keep their case.
+    Uppercase,
+}
+
+impl Default for DisasmStyle {
+    fn default() -> DisasmStyle {
+        DisasmStyle::Lowercase
+    }
+}
+
+impl DisasmStyle {
+    /// `insn` formatted in this style
+    pub fn format<T: StyledDisplay>(self, insn: &T) -> String {
+        Disassembly(insn, self).to_string()
+    }
+
+    /// A mnemonic, condition code or shift in this style
+    pub fn of<T: fmt::Display>(self, word: T) -> Styled<T> {
+        Styled(word, self)
+    }
+
+    /// The name of register `reg` in this style
+    pub fn reg(self, reg: usize) -> &'static str {
+        match self {
+            DisasmStyle::Lowercase => reg_string(reg),
+            DisasmStyle::Uppercase => REG_NAMES_UPPERCASE[reg],
+        }
+    }
+}
+
+const REG_NAMES_UPPERCASE: [&str; 16] = [
+    "R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7", "R8", "R9", "R10", "FP", "IP", "SP", "LR", "PC",
+];
+
+/// A word of the disassembly written in a `DisasmStyle`, see `DisasmStyle::of`
+pub struct Styled<T>(T, DisasmStyle);
+
+impl<T: fmt::Display> fmt::Display for Styled<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.1 {
+            DisasmStyle::Lowercase => write!(f, "{}", self.0),
+            DisasmStyle::Uppercase => f.write_str(&self.0.to_string().to_ascii_uppercase()),
+        }
+    }
+}
+
+/// Instructions that can be disassembled in either `DisasmStyle`. Only the mnemonics, shifts
+/// and registers change, comments and symbols are written as they are.
+pub trait StyledDisplay {
+    fn fmt_styled(&self, f: &mut fmt::Formatter, style: DisasmStyle) -> fmt::Result;
+}
+
+struct Disassembly<'a, T>(&'a T, DisasmStyle);
+
+impl<'a, T: StyledDisplay> fmt::Display for Disassembly<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt_styled(f, self.1)
+    }
+}
+
 pub struct Disassembler<'a, D>
 where
     D: InstructionDecoder,
@@ -11,6 +77,7 @@ where
     pos: usize,
     bytes: &'a [u8],
     pub word_size: usize,
+    pub style: DisasmStyle,
     instruction_decoder: PhantomData<D>,
 }
 
@@ -24,6 +91,7 @@ where
             pos: 0,
             bytes: bytes,
             word_size: std::mem::size_of::<D::IntType>(),
+            style: DisasmStyle::default(),
             instruction_decoder: PhantomData,
         }
     }
@@ -31,7 +99,7 @@ where
 
 impl<'a, D> Iterator for Disassembler<'a, D>
 where
-    D: InstructionDecoder,
+    D: InstructionDecoder + StyledDisplay,
     <D as InstructionDecoder>::IntType: std::fmt::LowerHex,
 {
     type Item = (Addr, String);
@@ -56,12 +124,46 @@ where
             };
 
         match decoded {
-            Some(insn) => {
-                line.push_str(&format!("{:8x}:\t{:08x} \t{}", addr, insn.get_raw(), insn))
-            }
+            Some(insn) => line.push_str(&format!(
+                "{:8x}:\t{:08x} \t{}",
+                addr,
+                insn.get_raw(),
+                self.style.format(&insn)
+            )),
             _ => line.push_str(&format!("{:8x}:\t \t<UNDEFINED>", addr)),
         };
 
         Some((self.pos as Addr, line))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arm7tdmi::arm::ArmInstruction;
+    use crate::arm7tdmi::thumb::ThumbInstruction;
+
+    #[test]
+    fn uppercase_style() {
+        let upper = |raw| DisasmStyle::Uppercase.format(&ArmInstruction::decode(raw, 0).unwrap());
+        assert_eq!(upper(0xe5910000), "LDR\tR0, [R1, #0]\t; 0x0");
+        assert_eq!(upper(0x10810312), "ADDNE\tR0, R1, R2, LSL R3");
+        assert_eq!(upper(0xe3a0001f), "MOV\tR0, #31\t; 0x1f");
+        assert_eq!(upper(0xe12fff1e), "BX\tLR");
+        // comments keep their case
+        assert_eq!(
+            upper(0xe328f20f),
+            "MSR\tCPSR_f, #-268435456\t; 0xf0000000\t; N=true Z=true C=true V=true"
+        );
+        let thumb = ThumbInstruction::decode(0xb07f, 0).unwrap();
+        assert_eq!(DisasmStyle::Uppercase.format(&thumb), "ADD\tSP, #0x1fc");
+
+        // the default stays lowercase
+        let bytes = [0x1e, 0xff, 0x2f, 0xe1];
+        let mut disass = Disassembler::<ArmInstruction>::new(0, &bytes);
+        assert!(disass.next().unwrap().1.ends_with("bx\tlr"));
+        let mut disass = Disassembler::<ArmInstruction>::new(0, &bytes);
+        disass.style = DisasmStyle::Uppercase;
+        assert!(disass.next().unwrap().1.ends_with("BX\tLR"));
+    }
+}
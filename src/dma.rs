@@ -1,7 +1,11 @@
+use super::arm7tdmi::bus::{MemoryAccessType::*, MemoryAccessWidth::*};
 use super::arm7tdmi::{Addr, Bus};
 use super::ioregs::consts::*;
 use super::sysbus::SysBus;
-use super::{EmuIoDev, Interrupt};
+use super::Interrupt;
+
+use crate::bit::BitIndex;
+use crate::num::FromPrimitive;
 
 #[allow(non_camel_case_types)]
 #[derive(Debug)]
@@ -9,9 +13,15 @@ pub struct DmaChannel {
     src_ioreg: Addr, /* Source Address register */
     dst_ioreg: Addr, /* Destination Address register */
     wc_ioreg: Addr,  /* Word Count 14bit */
+    index: usize,
+    /// Set while the channel is enabled, the registers below are latched when it's enabled
+    running: bool,
+    internal_src: Addr,
+    internal_dst: Addr,
+    internal_count: usize,
 }
 
-#[derive(Debug, Primitive)]
+#[derive(Debug, Primitive, Copy, Clone, PartialEq)]
 enum DmaAddrControl {
     Increment = 0,
     Decrement = 1,
@@ -19,21 +29,21 @@ enum DmaAddrControl {
     IncrementReloadProhibited = 3,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 enum DmaTransferType {
     Xfer16bit,
     Xfer32bit,
 }
 
-#[derive(Debug, Primitive)]
-enum DmaStartTiming {
+#[derive(Debug, Primitive, Copy, Clone, PartialEq)]
+pub enum DmaStartTiming {
     Immediately = 0,
     VBlank = 1,
     HBlank = 2,
     Special = 3,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 struct DmaControl {
     dst_addr_ctl: DmaAddrControl,
     src_addr_ctl: DmaAddrControl,
@@ -44,31 +54,188 @@ struct DmaControl {
     enable: bool,
 }
 
+impl From<u16> for DmaControl {
+    fn from(v: u16) -> DmaControl {
+        DmaControl {
+            dst_addr_ctl: DmaAddrControl::from_u16(v.bit_range(5..7)).unwrap(),
+            src_addr_ctl: DmaAddrControl::from_u16(v.bit_range(7..9)).unwrap(),
+            repeat: v.bit(9),
+            xfer: if v.bit(10) {
+                DmaTransferType::Xfer32bit
+            } else {
+                DmaTransferType::Xfer16bit
+            },
+            start_timing: DmaStartTiming::from_u16(v.bit_range(12..14)).unwrap(),
+            irq_upon_end_of_wc: v.bit(14),
+            enable: v.bit(15),
+        }
+    }
+}
+
 impl DmaChannel {
     pub fn new(src_ioreg: Addr, dst_ioreg: Addr, wc_ioreg: Addr) -> DmaChannel {
         DmaChannel {
             src_ioreg,
             dst_ioreg,
             wc_ioreg,
+            index: ((src_ioreg - REG_DMA0SAD) / 12) as usize,
+            running: false,
+            internal_src: 0,
+            internal_dst: 0,
+            internal_count: 0,
         }
     }
 
+    fn read_reg_32(sysbus: &SysBus, addr: Addr) -> u32 {
+        sysbus.ioregs.read_reg(addr) as u32 | (sysbus.ioregs.read_reg(addr + 2) as u32) << 16
+    }
+
+    /// DMA0 only reaches internal memory, DMA3 is the only one that can write to the gamepak
     fn src_addr(&self, sysbus: &SysBus) -> Addr {
-        sysbus.ioregs.read_32(self.src_ioreg - IO_BASE) as Addr
+        let mask = if self.index == 0 {
+            0x07ff_ffff
+        } else {
+            0x0fff_ffff
+        };
+        Self::read_reg_32(sysbus, self.src_ioreg) & mask
     }
 
     fn dst_addr(&self, sysbus: &SysBus) -> Addr {
-        sysbus.ioregs.read_32(self.dst_ioreg - IO_BASE) as Addr
+        let mask = if self.index == 3 {
+            0x0fff_ffff
+        } else {
+            0x07ff_ffff
+        };
+        Self::read_reg_32(sysbus, self.dst_ioreg) & mask
     }
 
+    /// Units to transfer, 0 is the most the channel can do
     fn word_count(&self, sysbus: &SysBus) -> usize {
-        sysbus.ioregs.read_reg(self.wc_ioreg) as usize
+        match sysbus.ioregs.read_reg(self.wc_ioreg) as usize {
+            0 if self.index == 3 => 0x1_0000,
+            0 => 0x4000,
+            count if self.index == 3 => count,
+            count => count & 0x3fff,
+        }
+    }
+
+    fn ctl_ioreg(&self) -> Addr {
+        self.wc_ioreg + 2
+    }
+
+    pub fn irq(&self) -> Interrupt {
+        Interrupt::from_usize(Interrupt::DMA0 as usize + self.index).unwrap()
     }
-}
 
-impl EmuIoDev for DmaChannel {
-    fn step(&mut self, cycles: usize, sysbus: &mut SysBus) -> (usize, Option<Interrupt>) {
-        // TODO
-        (0, None)
+    /// Whether the channel feeds a sound FIFO, the special timing of DMA1 and DMA2
+    pub fn is_fifo(&self) -> bool {
+        self.index == 1 || self.index == 2
+    }
+
+    /// Whether the channel is a FIFO channel writing to `fifo`, REG_FIFO_A or REG_FIFO_B
+    pub fn feeds_fifo(&self, fifo: Addr, sysbus: &SysBus) -> bool {
+        self.is_fifo() && self.dst_addr(sysbus) == fifo
+    }
+
+    /// Start the channel if it's enabled and waiting for `timing`, returns the cycles the
+    /// transfer took and the interrupt it requests when done.
+    ///
+    /// `Immediately` is checked right after the control register is written, `VBlank` and
+    /// `HBlank` when the LCD enters VBlank or the HBlank of a visible line. `Special` means a
+    /// sound FIFO asking for data on DMA1 and DMA2, and the video capture of lines 2 to 161 on
+    /// DMA3. DMA0 has no special timing.
+    ///
+    /// The address and count registers are latched the first time an enabled channel is seen,
+    /// a channel that doesn't repeat disables itself when it's done.
+    pub fn start(
+        &mut self,
+        timing: DmaStartTiming,
+        sysbus: &mut SysBus,
+    ) -> (usize, Option<Interrupt>) {
+        let ctl = DmaControl::from(sysbus.ioregs.read_reg(self.ctl_ioreg()));
+        if !ctl.enable {
+            self.running = false;
+            return (0, None);
+        }
+        if !self.running {
+            self.running = true;
+            self.internal_src = self.src_addr(sysbus);
+            self.internal_dst = self.dst_addr(sysbus);
+            self.internal_count = self.word_count(sysbus);
+        }
+        if ctl.start_timing != timing || (timing == DmaStartTiming::Special && self.index == 0) {
+            return (0, None);
+        }
+
+        let fifo = timing == DmaStartTiming::Special && self.is_fifo();
+        let ctl = if fifo {
+            // 4 words whatever the count and width, to the same address for as long as the
+            // channel is enabled
+            self.internal_count = 4;
+            DmaControl {
+                xfer: DmaTransferType::Xfer32bit,
+                dst_addr_ctl: DmaAddrControl::Fixed,
+                repeat: true,
+                ..ctl
+            }
+        } else {
+            ctl
+        };
+        let cycles = self.transfer(&ctl, sysbus);
+
+        if ctl.repeat && timing != DmaStartTiming::Immediately {
+            self.internal_count = self.word_count(sysbus);
+            if ctl.dst_addr_ctl == DmaAddrControl::IncrementReloadProhibited {
+                self.internal_dst = self.dst_addr(sysbus);
+            }
+        } else {
+            self.running = false;
+            let value = sysbus.ioregs.read_reg(self.ctl_ioreg());
+            sysbus.ioregs.write_reg(self.ctl_ioreg(), value & !0x8000);
+        }
+        let irq = if ctl.irq_upon_end_of_wc {
+            Some(self.irq())
+        } else {
+            None
+        };
+        (cycles, irq)
+    }
+
+    /// Copy `internal_count` units, returns the cycles taken: 2 internal cycles, then a
+    /// non-sequential read and write followed by sequential ones
+    fn transfer(&mut self, ctl: &DmaControl, sysbus: &mut SysBus) -> usize {
+        let width: Addr = match ctl.xfer {
+            DmaTransferType::Xfer16bit => 2,
+            DmaTransferType::Xfer32bit => 4,
+        };
+        let step = |ctl: DmaAddrControl| match ctl {
+            DmaAddrControl::Increment | DmaAddrControl::IncrementReloadProhibited => width,
+            DmaAddrControl::Decrement => width.wrapping_neg(),
+            DmaAddrControl::Fixed => 0,
+        };
+        let (src_step, dst_step) = (step(ctl.src_addr_ctl), step(ctl.dst_addr_ctl));
+
+        let mut cycles = 2;
+        for i in 0..self.internal_count {
+            let access = || {
+                let access = if i == 0 { NonSeq } else { Seq };
+                match ctl.xfer {
+                    DmaTransferType::Xfer16bit => access + MemoryAccess16,
+                    DmaTransferType::Xfer32bit => access + MemoryAccess32,
+                }
+            };
+            cycles += sysbus.get_cycles(self.internal_src, access());
+            cycles += sysbus.get_cycles(self.internal_dst, access());
+            if width == 4 {
+                let value = sysbus.read_32(self.internal_src & !3);
+                sysbus.write_32(self.internal_dst & !3, value);
+            } else {
+                let value = sysbus.read_16(self.internal_src & !1);
+                sysbus.write_16(self.internal_dst & !1, value);
+            }
+            self.internal_src = self.internal_src.wrapping_add(src_step);
+            self.internal_dst = self.internal_dst.wrapping_add(dst_step);
+        }
+        cycles
     }
 }
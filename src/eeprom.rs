@@ -0,0 +1,325 @@
+use std::cell::RefCell;
+use std::io;
+
+use super::arm7tdmi::{
+    bus::{Bus, MemoryAccess, MemoryAccessWidth},
+    Addr,
+};
+use super::sysbus::WaitState;
+
+/// The largest EEPROM, 1024 blocks of 64 bits
+const MAX_SIZE: usize = 8 * 1024;
+
+/// Bits in each command, for the 6 and the 14 bit address EEPROMs
+const READ_REQUEST_BITS: [usize; 2] = [2 + 6 + 1, 2 + 14 + 1];
+const WRITE_REQUEST_BITS: [usize; 2] = [2 + 6 + 64 + 1, 2 + 14 + 64 + 1];
+
+/// Bits shifted out for a read, 4 dummy bits and then the data
+const READ_RESPONSE_BITS: usize = 4 + 64;
+
+#[derive(Debug)]
+struct EepromState {
+    memory: Box<[u8]>,
+    /// 6 or 14, decided by the length of the first command
+    addr_bits: Option<usize>,
+    /// Bits of the command being written, the first bit is the most significant
+    command: u128,
+    command_bits: usize,
+    /// Data being read out, the next bit is the most significant
+    response: u128,
+    response_bits: usize,
+}
+
+/// Serial EEPROM save memory at 0x0D00_0000, transferred one bit per halfword (bit 0) with DMA.
+///
+/// The size isn't stored anywhere, the address width is inferred from the length of the first
+/// command just like games make the hardware do: a 9 bit read request or a 73 bit write means a
+/// 512 byte EEPROM with 6 bit addresses, 17 and 81 bits mean an 8K EEPROM with 14 bit addresses.
+#[derive(Debug)]
+pub struct Eeprom {
+    state: RefCell<EepromState>,
+    ws: WaitState,
+}
+
+impl Eeprom {
+    pub fn new() -> Eeprom {
+        Eeprom {
+            state: RefCell::new(EepromState {
+                memory: vec![0xff; MAX_SIZE].into_boxed_slice(),
+                addr_bits: None,
+                command: 0,
+                command_bits: 0,
+                response: 0,
+                response_bits: 0,
+            }),
+            ws: WaitState::new(5, 5, 8),
+        }
+    }
+
+    /// An EEPROM of `size` bytes, 512 or 8K, for when it's known up front
+    pub fn with_size(size: usize) -> Eeprom {
+        let eeprom = Eeprom::new();
+        eeprom.state.borrow_mut().addr_bits = Some(if size == 512 { 6 } else { 14 });
+        eeprom
+    }
+
+    /// The detected size in bytes, `None` until the game sent its first command
+    pub fn size(&self) -> Option<usize> {
+        self.state.borrow().addr_bits.map(|bits| match bits {
+            6 => 512,
+            _ => MAX_SIZE,
+        })
+    }
+
+    /// What goes in a save file, as big as the detected size and the whole 8K until then
+    pub fn save_data(&self) -> Vec<u8> {
+        let size = self.size().unwrap_or(MAX_SIZE);
+        self.state.borrow().memory[..size].to_vec()
+    }
+
+    /// Restore a save file. Its length gives the size away just like the first command would,
+    /// anything but 512 bytes or 8K is rejected.
+    pub fn load_save_data(&mut self, data: &[u8]) -> io::Result<()> {
+        let addr_bits = match data.len() {
+            512 => 6,
+            MAX_SIZE => 14,
+            len => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} bytes is not an EEPROM size", len),
+                ))
+            }
+        };
+        let mut state = self.state.borrow_mut();
+        state.memory[..data.len()].copy_from_slice(data);
+        state.addr_bits = Some(addr_bits);
+        Ok(())
+    }
+}
+
+impl Default for Eeprom {
+    fn default() -> Eeprom {
+        Eeprom::new()
+    }
+}
+
+impl EepromState {
+    fn push_bit(&mut self, bit: bool) {
+        // a new command cancels whatever was being read
+        self.response_bits = 0;
+        if self.command_bits < 128 {
+            self.command = (self.command << 1) | bit as u128;
+            self.command_bits += 1;
+        }
+    }
+
+    fn detect_addr_bits(command_bits: usize) -> Option<usize> {
+        match command_bits {
+            n if n == READ_REQUEST_BITS[0] || n == WRITE_REQUEST_BITS[0] => Some(6),
+            n if n == READ_REQUEST_BITS[1] || n == WRITE_REQUEST_BITS[1] => Some(14),
+            _ => None,
+        }
+    }
+
+    /// Decode the command once the game starts reading back
+    fn finish_command(&mut self) {
+        let (command, command_bits) = (self.command, self.command_bits);
+        self.command = 0;
+        self.command_bits = 0;
+
+        if self.addr_bits.is_none() {
+            self.addr_bits = Self::detect_addr_bits(command_bits);
+        }
+        let addr_bits = match self.addr_bits {
+            Some(addr_bits) => addr_bits,
+            None => return,
+        };
+        let size = if addr_bits == 6 { 0 } else { 1 };
+        // anything else is malformed and dropped, rather than guessing at it
+        if command_bits != READ_REQUEST_BITS[size] && command_bits != WRITE_REQUEST_BITS[size] {
+            return;
+        }
+        // only 10 of the 14 address bits select a block
+        let block_mask = (1 << addr_bits.min(10)) - 1;
+
+        let opcode = command >> (command_bits - 2);
+        if command_bits == READ_REQUEST_BITS[size] && opcode == 0b11 {
+            let block = (command >> 1) as usize & block_mask;
+            let mut data = [0; 8];
+            data.copy_from_slice(&self.memory[block * 8..block * 8 + 8]);
+            self.response = (u64::from_be_bytes(data) as u128) << 64;
+            self.response_bits = READ_RESPONSE_BITS;
+        } else if command_bits == WRITE_REQUEST_BITS[size] && opcode == 0b10 {
+            let block = (command >> 65) as usize & block_mask;
+            let data = (command >> 1) as u64;
+            self.memory[block * 8..block * 8 + 8].copy_from_slice(&data.to_be_bytes());
+        }
+    }
+
+    fn pop_bit(&mut self) -> u16 {
+        if self.command_bits != 0 {
+            self.finish_command();
+        }
+        if self.response_bits == 0 {
+            // idle, or done writing
+            return 1;
+        }
+        self.response_bits -= 1;
+        if self.response_bits >= 64 {
+            // the dummy bits
+            return 0;
+        }
+        let bit = (self.response >> 127) as u16;
+        self.response <<= 1;
+        bit
+    }
+}
+
+impl Bus for Eeprom {
+    fn read_32(&self, addr: Addr) -> u32 {
+        self.read_16(addr) as u32
+    }
+
+    fn read_16(&self, _addr: Addr) -> u16 {
+        self.state.borrow_mut().pop_bit()
+    }
+
+    fn read_8(&self, addr: Addr) -> u8 {
+        self.read_16(addr) as u8
+    }
+
+    fn write_32(&mut self, addr: Addr, value: u32) {
+        self.write_16(addr, value as u16)
+    }
+
+    fn write_16(&mut self, _addr: Addr, value: u16) {
+        self.state.borrow_mut().push_bit(value & 1 != 0);
+    }
+
+    fn write_8(&mut self, addr: Addr, value: u8) {
+        self.write_16(addr, value as u16)
+    }
+
+    fn get_bytes(&self, _addr: Addr) -> &[u8] {
+        &[]
+    }
+
+    fn get_bytes_mut(&mut self, _addr: Addr) -> &mut [u8] {
+        &mut []
+    }
+
+    fn get_cycles(&self, _addr: Addr, access: MemoryAccess) -> usize {
+        match access.1 {
+            MemoryAccessWidth::MemoryAccess8 => self.ws.access8,
+            MemoryAccessWidth::MemoryAccess16 => self.ws.access16,
+            MemoryAccessWidth::MemoryAccess32 => self.ws.access32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+    use crate::sysbus::SysBus;
+
+    const EEPROM_ADDR: Addr = 0x0d00_0000;
+
+    fn send(bus: &mut Bus, value: u128, bits: usize) {
+        for i in (0..bits).rev() {
+            bus.write_16(EEPROM_ADDR, (value >> i) as u16 & 1);
+        }
+    }
+
+    fn read_request(bus: &mut Bus, addr_bits: usize, block: u128) {
+        send(bus, (0b11 << (addr_bits + 1)) | (block << 1), addr_bits + 3);
+    }
+
+    fn receive(bus: &mut Bus) -> u64 {
+        let mut data = 0;
+        for i in 0..READ_RESPONSE_BITS {
+            let bit = bus.read_16(EEPROM_ADDR);
+            if i < 4 {
+                assert_eq!(bit, 0);
+            }
+            data = (data << 1) | (bit & 1) as u64;
+        }
+        data
+    }
+
+    #[test]
+    fn detect_14bit_from_read_request() {
+        let mut eeprom = Eeprom::new();
+        eeprom.state.borrow_mut().memory[0x123 * 8..0x124 * 8]
+            .copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(eeprom.size(), None);
+
+        read_request(&mut eeprom, 14, 0x123);
+        assert_eq!(receive(&mut eeprom), 0x0102_0304_0506_0708);
+        assert_eq!(eeprom.size(), Some(8 * 1024));
+
+        // write to block 5 then poll until ready
+        let data = 0xdead_beef_cafe_f00d;
+        send(&mut eeprom, (0b10 << 79) | (5 << 65) | (data << 1), 81);
+        assert_eq!(eeprom.read_16(EEPROM_ADDR), 1);
+        read_request(&mut eeprom, 14, 5);
+        assert_eq!(receive(&mut eeprom), data as u64);
+
+        // a 6 bit command is malformed now, and ignored
+        read_request(&mut eeprom, 6, 5);
+        assert_eq!(eeprom.read_16(EEPROM_ADDR), 1);
+        assert_eq!(eeprom.size(), Some(8 * 1024));
+    }
+
+    #[test]
+    fn detect_6bit_from_read_request() {
+        let mut eeprom = Eeprom::new();
+        eeprom.state.borrow_mut().memory[0x3f * 8] = 0x80;
+
+        read_request(&mut eeprom, 6, 0x3f);
+        assert_eq!(receive(&mut eeprom), 0x80ff_ffff_ffff_ffff);
+        assert_eq!(eeprom.size(), Some(512));
+    }
+
+    #[test]
+    fn short_command_ignored() {
+        // the size is known up front, so the first command isn't used to detect it
+        let mut eeprom = Eeprom::with_size(8 * 1024);
+        send(&mut eeprom, 1, 1);
+        assert_eq!(eeprom.read_16(EEPROM_ADDR), 1);
+
+        read_request(&mut eeprom, 14, 0);
+        assert_eq!(receive(&mut eeprom), 0xffff_ffff_ffff_ffff);
+    }
+
+    #[test]
+    fn save_data() {
+        let mut eeprom = Eeprom::new();
+        assert_eq!(eeprom.save_data().len(), 8 * 1024);
+
+        let mut data = vec![0xff; 512];
+        data[0x3f * 8..].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        eeprom.load_save_data(&data).unwrap();
+        assert_eq!(eeprom.size(), Some(512));
+        assert_eq!(eeprom.save_data(), data);
+
+        read_request(&mut eeprom, 6, 0x3f);
+        assert_eq!(receive(&mut eeprom), 0x0102_0304_0506_0708);
+
+        assert!(Eeprom::new().load_save_data(&[0; 100]).is_err());
+    }
+
+    #[test]
+    fn mapped_for_eeprom_games() {
+        let mut rom = vec![0; 0x1000];
+        rom[0x800..0x80b].copy_from_slice(b"EEPROM_V124");
+        let mut sysbus = SysBus::new(vec![], Cartridge::new(rom));
+
+        read_request(&mut sysbus, 14, 0);
+        assert_eq!(receive(&mut sysbus), 0xffff_ffff_ffff_ffff);
+
+        // no EEPROM in this one
+        let sysbus = SysBus::new(vec![], Cartridge::new(vec![]));
+        assert_eq!(sysbus.read_16(EEPROM_ADDR), 0);
+    }
+}
@@ -0,0 +1,341 @@
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+
+use super::arm7tdmi::{
+    bus::{Bus, MemoryAccess, MemoryAccessWidth},
+    Addr,
+};
+use super::sysbus::WaitState;
+
+const BANK_SIZE: usize = 64 * 1024;
+const SECTOR_SIZE: usize = 4 * 1024;
+
+/// Manufacturer and device IDs, a Macronix chip for 64K and a Sanyo one for 128K
+const ID_64K: (u8, u8) = (0xc2, 0x1c);
+const ID_128K: (u8, u8) = (0x62, 0x13);
+
+/// The two command unlock writes
+const UNLOCK_ADDR1: Addr = 0x5555;
+const UNLOCK_ADDR2: Addr = 0x2aaa;
+
+const CMD_ERASE: u8 = 0x80;
+const CMD_CHIP_ERASE: u8 = 0x10;
+const CMD_SECTOR_ERASE: u8 = 0x30;
+const CMD_ENTER_ID_MODE: u8 = 0x90;
+const CMD_EXIT_ID_MODE: u8 = 0xf0;
+const CMD_WRITE_BYTE: u8 = 0xa0;
+const CMD_SELECT_BANK: u8 = 0xb0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum FlashState {
+    Ready,
+    /// Got 0xAA at 0x5555
+    Unlock1,
+    /// Got 0x55 at 0x2AAA, the next write at 0x5555 is a command
+    Unlock2,
+    /// The next write is programmed
+    WriteByte,
+    /// The next write at address 0 selects the bank
+    SelectBank,
+}
+
+/// Flash save memory at 0x0E00_0000, 64K or two switchable 64K banks for 128K.
+///
+/// Commands are sent as 0xAA to 0x5555, 0x55 to 0x2AAA and the command to 0x5555. Erasing takes
+/// the same sequence twice, the first time with 0x80. Erased bytes read as 0xff.
+#[derive(Debug)]
+pub struct Flash {
+    memory: Box<[u8]>,
+    state: FlashState,
+    /// Set after an erase command, until the second half of the sequence
+    erase_pending: bool,
+    id_mode: bool,
+    bank: usize,
+    /// Every change is written through to the save file, so nothing is lost on a crash
+    file: Option<File>,
+    ws: WaitState,
+}
+
+impl Flash {
+    pub fn new(size: usize) -> Flash {
+        Flash {
+            memory: vec![0xff; size].into_boxed_slice(),
+            state: FlashState::Ready,
+            erase_pending: false,
+            id_mode: false,
+            bank: 0,
+            file: None,
+            ws: WaitState::sram(4),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.memory.len()
+    }
+
+    /// Manufacturer and device ID, what the chip answers in ID mode
+    pub fn id(&self) -> (u8, u8) {
+        if self.memory.len() > BANK_SIZE {
+            ID_128K
+        } else {
+            ID_64K
+        }
+    }
+
+    pub fn save_data(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Restore a save file, which has to be exactly as big as the chip
+    pub fn load_save_data(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.len() != self.memory.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} bytes is not the size of the flash", data.len()),
+            ));
+        }
+        self.memory.copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Keep the save in `path`, it's replaced by the current contents and from then on every
+    /// write and erase is flushed to it
+    pub fn set_save_file(&mut self, path: &str) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        file.write_all(&self.memory)?;
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn flush(&mut self, offset: usize, len: usize) {
+        let memory = &self.memory;
+        if let Some(file) = &mut self.file {
+            let result = file
+                .seek(SeekFrom::Start(offset as u64))
+                .and_then(|_| file.write_all(&memory[offset..offset + len]));
+            if let Err(err) = result {
+                eprintln!("warning: failed to write the flash save: {}", err);
+            }
+        }
+    }
+
+    fn offset(&self, addr: Addr) -> usize {
+        self.bank * BANK_SIZE + (addr as usize & 0xffff)
+    }
+
+    fn command(&mut self, command: u8) {
+        if self.erase_pending {
+            self.erase_pending = false;
+            if command == CMD_CHIP_ERASE {
+                for byte in self.memory.iter_mut() {
+                    *byte = 0xff;
+                }
+                self.flush(0, self.memory.len());
+            }
+            return;
+        }
+        match command {
+            CMD_ERASE => self.erase_pending = true,
+            CMD_ENTER_ID_MODE => self.id_mode = true,
+            CMD_EXIT_ID_MODE => self.id_mode = false,
+            CMD_WRITE_BYTE => self.state = FlashState::WriteByte,
+            CMD_SELECT_BANK if self.memory.len() > BANK_SIZE => self.state = FlashState::SelectBank,
+            _ => {}
+        }
+    }
+
+    fn erase_sector(&mut self, addr: Addr) {
+        let start = self.offset(addr) & !(SECTOR_SIZE - 1);
+        for byte in self.memory[start..start + SECTOR_SIZE].iter_mut() {
+            *byte = 0xff;
+        }
+        self.flush(start, SECTOR_SIZE);
+    }
+}
+
+impl Bus for Flash {
+    fn read_32(&self, addr: Addr) -> u32 {
+        self.read_8(addr) as u32 * 0x0101_0101
+    }
+
+    fn read_16(&self, addr: Addr) -> u16 {
+        self.read_8(addr) as u16 * 0x0101
+    }
+
+    fn read_8(&self, addr: Addr) -> u8 {
+        let addr = addr & 0xffff;
+        if self.id_mode && addr < 2 {
+            let (manufacturer, device) = self.id();
+            return if addr == 0 { manufacturer } else { device };
+        }
+        self.memory[self.offset(addr)]
+    }
+
+    /// Only 8 bit accesses reach the chip
+    fn write_32(&mut self, addr: Addr, value: u32) {
+        self.write_8(addr, value as u8)
+    }
+
+    fn write_16(&mut self, addr: Addr, value: u16) {
+        self.write_8(addr, value as u8)
+    }
+
+    fn write_8(&mut self, addr: Addr, value: u8) {
+        let addr = addr & 0xffff;
+        self.state = match (self.state, addr, value) {
+            (FlashState::WriteByte, ..) => {
+                let offset = self.offset(addr);
+                self.memory[offset] = value;
+                self.flush(offset, 1);
+                FlashState::Ready
+            }
+            (FlashState::SelectBank, 0, _) => {
+                self.bank = value as usize & 1;
+                FlashState::Ready
+            }
+            (FlashState::Ready, UNLOCK_ADDR1, 0xaa) => FlashState::Unlock1,
+            (FlashState::Unlock1, UNLOCK_ADDR2, 0x55) => FlashState::Unlock2,
+            (FlashState::Unlock2, UNLOCK_ADDR1, command) => {
+                self.state = FlashState::Ready;
+                self.command(command);
+                self.state
+            }
+            (FlashState::Unlock2, _, CMD_SECTOR_ERASE) if self.erase_pending => {
+                self.erase_pending = false;
+                self.erase_sector(addr);
+                FlashState::Ready
+            }
+            // anything out of sequence starts over
+            _ => FlashState::Ready,
+        };
+    }
+
+    fn get_bytes(&self, addr: Addr) -> &[u8] {
+        let offset = self.offset(addr);
+        &self.memory[offset..self.bank * BANK_SIZE + BANK_SIZE]
+    }
+
+    fn get_bytes_mut(&mut self, addr: Addr) -> &mut [u8] {
+        let offset = self.offset(addr);
+        let end = self.bank * BANK_SIZE + BANK_SIZE;
+        &mut self.memory[offset..end]
+    }
+
+    fn get_cycles(&self, _addr: Addr, access: MemoryAccess) -> usize {
+        match access.1 {
+            MemoryAccessWidth::MemoryAccess8 => self.ws.access8,
+            MemoryAccessWidth::MemoryAccess16 => self.ws.access16,
+            MemoryAccessWidth::MemoryAccess32 => self.ws.access32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+    use crate::sysbus::SysBus;
+
+    fn send_command(flash: &mut Flash, command: u8) {
+        flash.write_8(UNLOCK_ADDR1, 0xaa);
+        flash.write_8(UNLOCK_ADDR2, 0x55);
+        flash.write_8(UNLOCK_ADDR1, command);
+    }
+
+    fn write_byte(flash: &mut Flash, addr: Addr, value: u8) {
+        send_command(flash, CMD_WRITE_BYTE);
+        flash.write_8(addr, value);
+    }
+
+    #[test]
+    fn device_id() {
+        let mut flash = Flash::new(64 * 1024);
+        send_command(&mut flash, CMD_ENTER_ID_MODE);
+        assert_eq!((flash.read_8(0), flash.read_8(1)), ID_64K);
+        send_command(&mut flash, CMD_EXIT_ID_MODE);
+        assert_eq!(flash.read_8(0), 0xff);
+
+        let mut flash = Flash::new(128 * 1024);
+        send_command(&mut flash, CMD_ENTER_ID_MODE);
+        assert_eq!((flash.read_8(0), flash.read_8(1)), ID_128K);
+    }
+
+    #[test]
+    fn write_and_erase() {
+        let mut flash = Flash::new(64 * 1024);
+        write_byte(&mut flash, 0x1234, 0x42);
+        write_byte(&mut flash, 0x2000, 0x43);
+        assert_eq!(flash.read_8(0x1234), 0x42);
+        assert_eq!(flash.read_16(0x1234), 0x4242);
+
+        // plain writes are ignored
+        flash.write_8(0x1234, 0);
+        assert_eq!(flash.read_8(0x1234), 0x42);
+
+        // erase the sector at 0x1000
+        send_command(&mut flash, CMD_ERASE);
+        flash.write_8(UNLOCK_ADDR1, 0xaa);
+        flash.write_8(UNLOCK_ADDR2, 0x55);
+        flash.write_8(0x1000, CMD_SECTOR_ERASE);
+        assert_eq!(flash.read_8(0x1234), 0xff);
+        assert_eq!(flash.read_8(0x2000), 0x43);
+
+        send_command(&mut flash, CMD_ERASE);
+        send_command(&mut flash, CMD_CHIP_ERASE);
+        assert_eq!(flash.read_8(0x2000), 0xff);
+    }
+
+    #[test]
+    fn bank_switching() {
+        let mut flash = Flash::new(128 * 1024);
+        write_byte(&mut flash, 0x10, 1);
+        send_command(&mut flash, CMD_SELECT_BANK);
+        flash.write_8(0, 1);
+        assert_eq!(flash.read_8(0x10), 0xff);
+        write_byte(&mut flash, 0x10, 2);
+        assert_eq!(flash.save_data()[0x1_0010], 2);
+
+        send_command(&mut flash, CMD_SELECT_BANK);
+        flash.write_8(0, 0);
+        assert_eq!(flash.read_8(0x10), 1);
+    }
+
+    #[test]
+    fn mapped_for_flash_games() {
+        let mut rom = vec![0; 0x1000];
+        rom[0x800..0x80c].copy_from_slice(b"FLASH1M_V103");
+        let mut sysbus = SysBus::new(vec![], Cartridge::new(rom));
+
+        sysbus.write_8(0x0e00_5555, 0xaa);
+        sysbus.write_8(0x0e00_2aaa, 0x55);
+        sysbus.write_8(0x0e00_5555, CMD_ENTER_ID_MODE);
+        assert_eq!(sysbus.read_8(0x0e00_0000), ID_128K.0);
+        assert_eq!(sysbus.read_8(0x0e00_0001), ID_128K.1);
+
+        // plain SRAM otherwise
+        let mut sysbus = SysBus::new(vec![], Cartridge::new(vec![]));
+        sysbus.write_8(0x0e00_0000, 0x12);
+        assert_eq!(sysbus.read_8(0x0e00_0000), 0x12);
+    }
+
+    #[test]
+    fn save_file() {
+        let path = std::env::temp_dir().join("rustboyadvance_flash_save_file.sav");
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        let mut flash = Flash::new(64 * 1024);
+        flash.set_save_file(path).unwrap();
+        // written through right away
+        write_byte(&mut flash, 0x100, 0x5a);
+        let saved = std::fs::read(path).unwrap();
+        assert_eq!(saved.len(), 64 * 1024);
+        assert_eq!(saved[0x100], 0x5a);
+
+        let mut flash = Flash::new(64 * 1024);
+        flash.load_save_data(&saved).unwrap();
+        assert_eq!(flash.read_8(0x100), 0x5a);
+        assert!(flash.load_save_data(&saved[..0x8000]).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,162 @@
+use std::time::{Duration, Instant};
+
+/// A frame of the real hardware, 280896 cycles at 16.78MHz
+pub const FRAME_DURATION: Duration = Duration::from_nanos(16_742_706);
+
+/// Wall-clock time for the frame skip policy, so tests can drive it by hand
+pub trait Clock {
+    /// Time since some fixed point
+    fn now(&self) -> Duration;
+}
+
+pub struct SystemClock(Instant);
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock(Instant::now())
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.0.elapsed()
+    }
+}
+
+/// Skips rendering on a host that can't keep up, so emulation and audio stay real-time.
+///
+/// Time spent over the frame budget adds up as lag, and time under it pays the lag back. The
+/// skip rate goes up by one after each frame that leaves more than a frame of lag, and down by
+/// one after each frame that leaves none. It's the number of frames skipped after every rendered
+/// one, never more than `max_skip` in a row.
+pub struct FrameSkip {
+    clock: Box<Clock>,
+    target: Duration,
+    max_skip: usize,
+    skip_rate: usize,
+    /// Frames skipped since the last rendered one
+    skipped: usize,
+    lag: Duration,
+    frame_start: Option<Duration>,
+}
+
+impl FrameSkip {
+    pub fn new(clock: Box<Clock>, target: Duration, max_skip: usize) -> FrameSkip {
+        FrameSkip {
+            clock,
+            target,
+            max_skip,
+            skip_rate: 0,
+            skipped: 0,
+            lag: Duration::from_secs(0),
+            frame_start: None,
+        }
+    }
+
+    /// Keep up with the real hardware on the host's clock
+    pub fn realtime(max_skip: usize) -> FrameSkip {
+        FrameSkip::new(Box::new(SystemClock::new()), FRAME_DURATION, max_skip)
+    }
+
+    /// Frames currently skipped after each rendered one, 0 when every frame is rendered
+    pub fn skip_rate(&self) -> usize {
+        self.skip_rate
+    }
+
+    /// Call before running a frame, returns whether to render it
+    pub fn begin_frame(&mut self) -> bool {
+        self.frame_start = Some(self.clock.now());
+        if self.skipped < self.skip_rate {
+            self.skipped += 1;
+            false
+        } else {
+            self.skipped = 0;
+            true
+        }
+    }
+
+    /// Call after running a frame, to account for the time it took
+    pub fn end_frame(&mut self) {
+        let start = match self.frame_start.take() {
+            Some(start) => start,
+            None => return,
+        };
+        let elapsed = self.clock.now() - start;
+        self.lag = if elapsed > self.target {
+            self.lag + (elapsed - self.target)
+        } else {
+            self.lag
+                .checked_sub(self.target - elapsed)
+                .unwrap_or_default()
+        };
+        // a long stall, like the host suspending, shouldn't take forever to pay back
+        self.lag = self.lag.min(self.target * (self.max_skip as u32 + 1));
+
+        if self.lag > self.target {
+            self.skip_rate = (self.skip_rate + 1).min(self.max_skip);
+        } else if self.lag == Duration::from_secs(0) {
+            self.skip_rate = self.skip_rate.saturating_sub(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct MockClock(Rc<Cell<Duration>>);
+
+    impl Clock for MockClock {
+        fn now(&self) -> Duration {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn adapts_to_slow_frames() {
+        let time = Rc::new(Cell::new(Duration::from_secs(0)));
+        let target = Duration::from_millis(16);
+        let mut frame_skip = FrameSkip::new(Box::new(MockClock(time.clone())), target, 3);
+
+        // runs a frame taking `millis`, returns whether it was rendered
+        let run_frame = |frame_skip: &mut FrameSkip, millis| {
+            let render = frame_skip.begin_frame();
+            time.set(time.get() + Duration::from_millis(millis));
+            frame_skip.end_frame();
+            render
+        };
+
+        for _ in 0..10 {
+            assert!(run_frame(&mut frame_skip, 15));
+        }
+        assert_eq!(frame_skip.skip_rate(), 0);
+
+        // 25ms frames on a 16ms budget
+        let mut skipped_in_a_row = 0;
+        for _ in 0..30 {
+            if run_frame(&mut frame_skip, 25) {
+                skipped_in_a_row = 0;
+            } else {
+                skipped_in_a_row += 1;
+                assert!(skipped_in_a_row <= 3);
+            }
+        }
+        assert_eq!(frame_skip.skip_rate(), 3);
+
+        // the host catches up
+        for _ in 0..30 {
+            run_frame(&mut frame_skip, 8);
+        }
+        assert_eq!(frame_skip.skip_rate(), 0);
+        assert!(run_frame(&mut frame_skip, 8));
+        assert!(run_frame(&mut frame_skip, 8));
+    }
+}
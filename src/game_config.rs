@@ -0,0 +1,110 @@
+/// Per-game settings profiles.
+///
+/// A frontend keeps a `GameConfig` for each game that needs something other than the defaults,
+/// looks it up by the game code in the cartridge header and hands it to
+/// `GameBoyAdvance::apply_config` after loading the game.
+use super::cartridge::SaveType;
+use super::gba::GameBoyAdvance;
+use super::keypad::{Keys, SocdMode};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameConfig {
+    /// The 4 character game code of the cartridge header the profile is for, e.g. "BPEE"
+    pub game_code: String,
+    /// Save memory to use instead of the one detected from the ROM
+    pub save_type: Option<SaveType>,
+    /// Whether the cartridge has a real-time clock. Only recorded on the cartridge for now, the
+    /// clock itself isn't emulated yet.
+    pub rtc: bool,
+    /// See `Keypad::set_key_map`
+    pub input_map: Option<Vec<(Keys, Keys)>>,
+    /// See `Lcd::set_color_correction`
+    pub color_correction: Option<bool>,
+    pub socd_mode: Option<SocdMode>,
+    /// See `GameBoyAdvance::set_idle_loop_detection`
+    pub idle_loop_detection: Option<bool>,
+}
+
+impl GameConfig {
+    /// A profile for `game_code` that leaves everything as it is
+    pub fn new(game_code: &str) -> GameConfig {
+        GameConfig {
+            game_code: game_code.to_string(),
+            save_type: None,
+            rtc: false,
+            input_map: None,
+            color_correction: None,
+            socd_mode: None,
+            idle_loop_detection: None,
+        }
+    }
+}
+
+impl GameBoyAdvance {
+    /// Apply the overrides of `config` if it's the profile of the inserted game, returns whether
+    /// it was. Meant to be called right after the game is loaded, overriding the save type
+    /// starts the save memory out blank.
+    pub fn apply_config(&mut self, config: GameConfig) -> bool {
+        if self.sysbus.cartridge().header().game_code != config.game_code {
+            return false;
+        }
+        let cartridge = self.sysbus.cartridge_mut();
+        if let Some(save_type) = config.save_type {
+            cartridge.set_save_type(save_type);
+        }
+        cartridge.set_rtc(config.rtc);
+        if let Some(key_map) = config.input_map {
+            self.set_key_map(key_map);
+        }
+        if let Some(enabled) = config.color_correction {
+            self.set_color_correction(enabled);
+        }
+        if let Some(mode) = config.socd_mode {
+            self.set_socd_mode(mode);
+        }
+        if let Some(enabled) = config.idle_loop_detection {
+            self.set_idle_loop_detection(enabled);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arm7tdmi::Core;
+    use crate::cartridge::Cartridge;
+
+    #[test]
+    fn apply_config() {
+        let mut rom = vec![0; 0x1000];
+        rom[0xac..0xb0].copy_from_slice(b"AXVE");
+        let mut gba = GameBoyAdvance::new(Core::new(), vec![0; 0x4000], Cartridge::new(rom));
+        assert_eq!(gba.sysbus.cartridge().save_type(), SaveType::Sram);
+
+        let mut config = GameConfig::new("AXVE");
+        config.save_type = Some(SaveType::Flash128k);
+        config.rtc = true;
+        config.input_map = Some(vec![(Keys::ButtonA, Keys::ButtonB)]);
+        config.color_correction = Some(true);
+
+        // another game's profile is ignored
+        let other = GameConfig {
+            game_code: "BPEE".to_string(),
+            ..config.clone()
+        };
+        assert!(!gba.apply_config(other));
+        assert!(!gba.sysbus.cartridge().has_rtc());
+
+        assert!(gba.apply_config(config));
+        let cartridge = gba.sysbus.cartridge();
+        assert_eq!(cartridge.save_type(), SaveType::Flash128k);
+        assert_eq!(
+            cartridge.flash().map(|flash| flash.size()),
+            Some(128 * 1024)
+        );
+        assert!(cartridge.has_rtc());
+        assert_eq!(gba.keypad.key_map(), &[(Keys::ButtonA, Keys::ButtonB)]);
+        assert!(gba.lcd.color_correction());
+    }
+}
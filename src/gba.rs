@@ -1,15 +1,31 @@
 /// Struct containing everything
 ///
-use super::arm7tdmi::{exception::*, Core, DecodedInstruction};
+use super::arm7tdmi::{
+    exception::*, idle_loop::IdleLoopDetector, Core, CpuMode, CycleCategory, DecodedInstruction,
+};
 use super::cartridge::Cartridge;
-use super::dma::DmaChannel;
+use super::dma::{DmaChannel, DmaStartTiming};
+use super::frame_skip::FrameSkip;
 use super::interrupt::*;
 use super::ioregs::consts::*;
+use super::keypad::*;
 use super::lcd::*;
+use super::palette::Rgb15;
+use super::scheduler::{EventKind, Scheduler};
 use super::sysbus::SysBus;
 
 use super::{EmuIoDev, GBAError, GBAResult};
 
+use crate::bit::BitIndex;
+use crate::num::FromPrimitive;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum FrameStatus {
+    Completed,
+    /// The frame took more instructions than the configured limit, it was cut short
+    RunawayDetected,
+}
+
 pub struct GameBoyAdvance {
     pub cpu: Core,
     pub sysbus: SysBus,
@@ -20,37 +36,241 @@ pub struct GameBoyAdvance {
     pub dma1: DmaChannel,
     pub dma2: DmaChannel,
     pub dma3: DmaChannel,
+    pub keypad: Keypad,
 
+    /// Device events on the CPU cycle count, only the LCD is driven by it so far
+    scheduler: Scheduler,
     post_bool_flags: bool,
+    /// Stop mode, the CPU and LCD are halted until a keypad, serial or gamepak interrupt
+    stopped: bool,
+    /// Halt mode, the CPU waits for an interrupt enabled in IE while the devices keep running
+    halted: bool,
+    /// Upper bound on the instructions run by `run_frame`, 0 for no limit
+    frame_instruction_limit: usize,
+    /// Frames run by `run_frame` so far
+    frame_count: usize,
+    input_script: InputScript,
+    /// Skips loops that only poll memory when set, see `set_idle_loop_detection`
+    idle_loop: Option<IdleLoopDetector>,
+    /// Rendering is skipped on slow hosts when set, see `set_frame_skip`
+    frame_skip: Option<FrameSkip>,
+    /// Nothing is drawn, see `set_benchmark_mode`
+    benchmark_mode: bool,
 }
 
 impl GameBoyAdvance {
+    /// Game code runs from the game pak, where even the fastest waitstates take 2 cycles an
+    /// instruction, so a frame takes fewer instructions than half its cycles. A game that ran
+    /// off the rails usually ends up executing zeroed work RAM, a cycle per instruction.
+    pub const DEFAULT_FRAME_INSTRUCTION_LIMIT: usize = (Lcd::CYCLES_VDRAW + Lcd::CYCLES_VBLANK) / 2;
+
     pub fn new(cpu: Core, bios_rom: Vec<u8>, gamepak: Cartridge) -> GameBoyAdvance {
-        let sysbus = SysBus::new(bios_rom, gamepak);
+        let mut sysbus = SysBus::new(bios_rom, gamepak);
+        let keypad = Keypad::new();
+        keypad.update_keyinput(&mut sysbus);
+        sysbus.ioregs.timers.set_now(cpu.cycles());
+        let lcd = Lcd::new();
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule_at(EventKind::Lcd, cpu.cycles() + lcd.cycles_to_next_event());
 
         GameBoyAdvance {
             cpu: cpu,
             sysbus: sysbus,
 
-            lcd: Lcd::new(),
-            dma0: DmaChannel::new(REG_DMA0SAD, REG_DMA0DAD, REG_DMA0DAD),
-            dma1: DmaChannel::new(REG_DMA1SAD, REG_DMA1DAD, REG_DMA1DAD),
-            dma2: DmaChannel::new(REG_DMA2SAD, REG_DMA2DAD, REG_DMA2DAD),
-            dma3: DmaChannel::new(REG_DMA3SAD, REG_DMA3DAD, REG_DMA3DAD),
+            lcd: lcd,
+            dma0: DmaChannel::new(REG_DMA0SAD, REG_DMA0DAD, REG_DMA0CNT_L),
+            dma1: DmaChannel::new(REG_DMA1SAD, REG_DMA1DAD, REG_DMA1CNT_L),
+            dma2: DmaChannel::new(REG_DMA2SAD, REG_DMA2DAD, REG_DMA2CNT_L),
+            dma3: DmaChannel::new(REG_DMA3SAD, REG_DMA3DAD, REG_DMA3CNT_L),
+            keypad: keypad,
 
+            scheduler: scheduler,
             post_bool_flags: false,
+            stopped: false,
+            halted: false,
+            frame_instruction_limit: Self::DEFAULT_FRAME_INSTRUCTION_LIMIT,
+            frame_count: 0,
+            input_script: InputScript::default(),
+            idle_loop: None,
+            frame_skip: None,
+            benchmark_mode: false,
+        }
+    }
+
+    /// Swap the game without recreating the machine. The save of the current game is flushed
+    /// to its save file first, the new one saves to wherever its own save was loaded from.
+    /// Everything but the BIOS is brought back to its power-on state and the CPU is reset, so
+    /// the new game boots through the BIOS. Returns the cartridge that was removed, save memory
+    /// included.
+    pub fn load_cartridge(&mut self, gamepak: Cartridge) -> GBAResult<Cartridge> {
+        self.sysbus.cartridge().flush_save()?;
+        let old_gamepak = self.sysbus.reset_with_cartridge(gamepak);
+
+        self.sysbus.ioregs.timers.set_now(self.cpu.cycles());
+        self.lcd.reset();
+        self.scheduler.clear();
+        self.scheduler.schedule_at(
+            EventKind::Lcd,
+            self.cpu.cycles() + self.lcd.cycles_to_next_event(),
+        );
+        self.dma0 = DmaChannel::new(REG_DMA0SAD, REG_DMA0DAD, REG_DMA0CNT_L);
+        self.dma1 = DmaChannel::new(REG_DMA1SAD, REG_DMA1DAD, REG_DMA1CNT_L);
+        self.dma2 = DmaChannel::new(REG_DMA2SAD, REG_DMA2DAD, REG_DMA2CNT_L);
+        self.dma3 = DmaChannel::new(REG_DMA3SAD, REG_DMA3DAD, REG_DMA3CNT_L);
+        self.keypad.update_keyinput(&mut self.sysbus);
+        self.post_bool_flags = false;
+        self.stopped = false;
+        self.halted = false;
+
+        self.cpu.reset();
+
+        Ok(old_gamepak)
+    }
+
+    /// Start the game at 0x0800_0000 without running the BIOS, with the machine as the BIOS
+    /// leaves it: the work RAMs, palette, VRAM and OAM zeroed, the stacks set up at the top of
+    /// IWRAM, POSTFLG set, SOUNDBIAS at its midpoint and the CPU in system mode.
+    pub fn direct_boot(&mut self) {
+        self.sysbus.clear_ram();
+        self.sysbus.ioregs.write_reg(REG_POSTFLG, 1);
+        self.sysbus.ioregs.write_reg(REG_SOUNDBIAS, 0x0200);
+
+        let cpu = &mut self.cpu;
+        cpu.set_cpsr(0x1f);
+        for r in 0..13 {
+            cpu.set_reg(r, 0);
+        }
+        cpu.set_reg(14, 0);
+        cpu.set_reg(13, 0x0300_7f00);
+        cpu.set_banked_reg(CpuMode::Irq, 13, 0x0300_7fa0);
+        cpu.set_banked_reg(CpuMode::Supervisor, 13, 0x0300_7fe0);
+        cpu.pc = 0x0800_0000;
+        cpu.pipeline_arm.flush();
+        cpu.pipeline_thumb.flush();
+    }
+
+    /// Toggle the approximate CPU/LCD video memory contention model.
+    pub fn set_vram_contention(&mut self, enabled: bool) {
+        self.sysbus.set_vram_contention(enabled);
+    }
+
+    pub fn set_keypad_state(&mut self, state: KeypadState) {
+        self.keypad.set_state(state);
+        self.keypad_changed();
+    }
+
+    pub fn set_key(&mut self, key: Keys, pressed: bool) {
+        self.keypad.set_key(key, pressed);
+        self.keypad_changed();
+    }
+
+    /// Press keys on schedule: each `(frame, state)` replaces the keypad state right before
+    /// `run_frame` runs that frame, counting from the next frame as returned by `frame_count`.
+    pub fn set_input_script(&mut self, events: Vec<(usize, KeypadState)>) {
+        self.input_script = InputScript::new(events);
+    }
+
+    /// Frames run so far, which is also the number of the next frame
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    fn keypad_changed(&mut self) {
+        self.keypad.update_keyinput(&mut self.sysbus);
+        let keycnt = self.sysbus.ioregs.read_reg(REG_KEYCNT);
+        if self.keypad.irq_condition(keycnt) {
+            self.request_irq(Interrupt::Keypad);
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    fn check_stop_request(&mut self) {
+        if self.sysbus.take_halt_request() {
+            // an interrupt that's already waiting wakes it right away
+            let intc = &self.sysbus.ioregs.intc;
+            self.halted = intc.ie & intc.reg_if == 0;
+        }
+        if self.sysbus.take_stop_request() {
+            self.stopped = true;
+            // the LCD is powered off
+            for pixel in self.lcd.pixeldata.iter_mut() {
+                *pixel = Rgb15::from(0);
+            }
         }
     }
 
+    pub fn set_socd_mode(&mut self, mode: SocdMode) {
+        self.keypad.set_socd_mode(mode);
+        self.keypad.update_keyinput(&mut self.sysbus);
+    }
+
+    /// See `Keypad::set_key_map`
+    pub fn set_key_map(&mut self, key_map: Vec<(Keys, Keys)>) {
+        self.keypad.set_key_map(key_map);
+        self.keypad.update_keyinput(&mut self.sysbus);
+    }
+
+    /// See `Lcd::set_color_correction`
+    pub fn set_color_correction(&mut self, enabled: bool) {
+        self.lcd.set_color_correction(enabled);
+    }
+
+    /// Fast forward through loops that poll memory waiting for something to happen, e.g. a
+    /// VCOUNT change. The CPU jumps ahead to the next LCD state change or timer overflow
+    /// instead of running the loop over and over. Off by default.
+    pub fn set_idle_loop_detection(&mut self, enabled: bool) {
+        self.idle_loop = if enabled {
+            Some(IdleLoopDetector::new())
+        } else {
+            None
+        };
+    }
+
+    /// Skip rendering frames in `run_frame` as the policy decides, to keep a slow host running in
+    /// real-time. The emulation itself is unaffected. `None` renders every frame.
+    pub fn set_frame_skip(&mut self, frame_skip: Option<FrameSkip>) {
+        self.frame_skip = frame_skip;
+        self.lcd.set_rendering(!self.benchmark_mode);
+    }
+
+    /// Frames currently skipped after each rendered one
+    pub fn frame_skip_rate(&self) -> usize {
+        self.frame_skip
+            .as_ref()
+            .map_or(0, |frame_skip| frame_skip.skip_rate())
+    }
+
+    /// Run frames without drawing them, to measure how fast the CPU and bus are emulated on their
+    /// own. The LCD timing, DMA and timers still run so interrupts come when they should, and the
+    /// frame skip policy is ignored. There's no audio mixing to turn off yet.
+    pub fn set_benchmark_mode(&mut self, enabled: bool) {
+        self.benchmark_mode = enabled;
+        self.lcd.set_rendering(!enabled);
+    }
+
+    pub fn benchmark_mode(&self) -> bool {
+        self.benchmark_mode
+    }
+
+    /// Decoded view of the graphics registers, for debuggers
+    pub fn graphics_state(&self) -> GraphicsState {
+        self.lcd.graphics_state(&self.sysbus)
+    }
+
     fn emulate_n_cycles(&mut self, mut n: usize) {
         let mut cycles = 0;
         loop {
             let previous_cycles = self.cpu.cycles;
             self.cpu.step_one(&mut self.sysbus).unwrap();
-            let new_cycles = self.cpu.cycles - previous_cycles;
-
-            self.lcd.step(new_cycles, &mut self.sysbus);
-            cycles += new_cycles;
+            self.step_devices();
+            cycles += self.cpu.cycles - previous_cycles;
 
             if n <= cycles {
                 break;
@@ -58,65 +278,1346 @@ impl GameBoyAdvance {
         }
     }
 
+    /// Limit the instructions a single `run_frame` may take before giving up on the frame,
+    /// `None` to run frames to completion no matter what.
+    pub fn set_frame_instruction_limit(&mut self, limit: Option<usize>) {
+        self.frame_instruction_limit = limit.unwrap_or(0);
+    }
+
     pub fn frame(&mut self) {
-        while self.lcd.state == LcdState::VBlank {
-            self.emulate();
+        self.run_frame();
+    }
+
+    /// Like `frame`, but reports frames that didn't finish within the instruction limit,
+    /// so a frontend doesn't hang on a game that ran off the rails.
+    pub fn run_frame(&mut self) -> FrameStatus {
+        if let Some(state) = self.input_script.advance(self.frame_count) {
+            self.set_keypad_state(state);
+        }
+        let skip_frames = self.frame_skip.is_some() && !self.benchmark_mode;
+        if skip_frames {
+            let render = self.frame_skip.as_mut().unwrap().begin_frame();
+            self.lcd.set_rendering(render);
+        }
+        let status = self.run_frame_with_limit(self.frame_instruction_limit);
+        if skip_frames {
+            self.frame_skip.as_mut().unwrap().end_frame();
         }
-        while self.lcd.state != LcdState::VBlank {
+        self.frame_count += 1;
+        status
+    }
+
+    fn run_frame_with_limit(&mut self, limit: usize) -> FrameStatus {
+        let mut instructions = 0;
+        for &in_vblank in &[true, false] {
+            while (self.lcd.state == LcdState::VBlank) == in_vblank && !self.stopped {
+                if limit != 0 && instructions >= limit {
+                    return FrameStatus::RunawayDetected;
+                }
+                if self.emulate_step() {
+                    instructions += 1;
+                }
+            }
+        }
+        FrameStatus::Completed
+    }
+
+    /// Run until the start of the next VBlank period, so that the following call to `frame`
+    /// renders a whole frame. Useful after restoring a mid-frame state.
+    /// Does nothing if the LCD is already in VBlank, or returns early when the CPU is stopped.
+    pub fn advance_to_vblank(&mut self) {
+        while self.lcd.state != LcdState::VBlank && !self.stopped {
             self.emulate();
         }
     }
 
     pub fn emulate(&mut self) {
-        let previous_cycles = self.cpu.cycles;
-        self.cpu.step(&mut self.sysbus).unwrap();
-        let cycles = self.cpu.cycles - previous_cycles;
-        self.lcd.step(cycles, &mut self.sysbus);
+        self.emulate_step();
+    }
+
+    /// Like `emulate`, returns whether an instruction was executed rather than the pipeline
+    /// being refilled
+    fn emulate_step(&mut self) -> bool {
+        if self.stopped {
+            return false;
+        }
+        if self.halted {
+            self.idle_to_next_event();
+            return false;
+        }
+        let executed_insn = self.cpu.step(&mut self.sysbus).unwrap();
+        self.check_stop_request();
+        self.step_devices();
+
+        if let (Some(insn), Some(detector)) = (executed_insn, &mut self.idle_loop) {
+            if detector.check(&insn, &self.cpu, &self.sysbus) {
+                detector.reset();
+                self.idle_to_next_event();
+            }
+        }
+        executed_insn.is_some()
+    }
+
+    /// Let the CPU idle up to the next device event, while it's halted or spins in an idle loop.
+    /// Any interrupt that could wake a halted CPU is raised by an event.
+    fn idle_to_next_event(&mut self) {
+        // the LCD always has an event coming up
+        let cycle = self.scheduler.next_event_cycle().unwrap();
+        let cycles = cycle.saturating_sub(self.cpu.cycles).max(1);
+        self.cpu.add_cycles_of(CycleCategory::Idle, cycles);
+        self.step_devices();
+    }
+
+    /// Catch the devices up with the CPU. DMA transfers stall the CPU, their cycles are added to
+    /// its count.
+    fn step_devices(&mut self) {
+        if self.sysbus.ioregs.take_dma_write() {
+            self.scheduler
+                .schedule_at(EventKind::DmaImmediate, self.cpu.cycles);
+        }
+        self.run_until(self.cpu.cycles);
+        self.check_irq();
+    }
+
+    /// Cycles until the LCD changes state, at least one
+    fn cycles_to_lcd_event(&self) -> usize {
+        let cycle = self.scheduler.event_cycle(EventKind::Lcd).unwrap();
+        cycle.saturating_sub(self.cpu.cycles).max(1)
+    }
+
+    /// Run the devices up to `cycle`, dispatching every event due by then in the order they're
+    /// due. The DMA transfers they start stall the CPU past `cycle`, the devices run through the
+    /// stall as well.
+    fn run_until(&mut self, cycle: usize) {
+        let mut cycle = cycle;
+        loop {
+            if self.sysbus.ioregs.timers.take_write() {
+                self.schedule_timers();
+            }
+            match self.scheduler.pop_due(cycle) {
+                Some((event, due)) => {
+                    self.sysbus.ioregs.timers.set_now(due);
+                    self.dispatch(event, due);
+                }
+                None if self.cpu.cycles > cycle => cycle = self.cpu.cycles,
+                None => break,
+            }
+        }
+        self.sysbus.ioregs.timers.set_now(cycle);
+    }
+
+    fn dispatch(&mut self, event: EventKind, due: usize) {
+        match event {
+            EventKind::Lcd => {
+                // the LCD is only stepped here, so this lands exactly on its next state
+                let cycles = self.lcd.cycles_to_next_event();
+                self.step_lcd(cycles);
+                let next = due + self.lcd.cycles_to_next_event();
+                self.scheduler.schedule_at(EventKind::Lcd, next);
+            }
+            EventKind::TimerOverflow => {
+                self.run_timers(due);
+                self.schedule_timers();
+            }
+            EventKind::DmaImmediate => self.start_dma(DmaStartTiming::Immediately),
+            EventKind::FifoRefill(fifo) => self.refill_fifo(fifo),
+        }
+    }
+
+    /// Schedule the next timer overflow, after the timers were written or one overflowed
+    fn schedule_timers(&mut self) {
+        self.scheduler.cancel(EventKind::TimerOverflow);
+        if let Some(cycle) = self.sysbus.ioregs.timers.next_overflow_cycle() {
+            self.scheduler.schedule_at(EventKind::TimerOverflow, cycle);
+        }
+    }
+
+    /// The CPU cycle at which the earliest enabled interrupt could fire, which is how long a CPU
+    /// that's only waiting for one will wait. `None` when IME is off or nothing enabled is coming
+    /// up.
+    ///
+    /// Timer overflows are exact. LCD interrupts are taken at the next LCD event, which can be
+    /// early but never late. Keypad, serial and gamepak interrupts come from outside and DMA
+    /// isn't run here, so those can't be predicted.
+    pub fn next_irq_cycle(&self) -> Option<usize> {
+        if self.interrupts_disabled() {
+            return None;
+        }
+        let intc = &self.sysbus.ioregs.intc;
+        let enabled = |irq: Interrupt| intc.enabled(irq);
+
+        let dispstat = DisplayStatus::from(self.sysbus.ioregs.read_reg(REG_DISPSTAT));
+        let lcd_irq = (enabled(Interrupt::LCD_VBlank) && dispstat.vblank_irq_enable)
+            || (enabled(Interrupt::LCD_HBlank) && dispstat.hblank_irq_enable)
+            || (enabled(Interrupt::LCD_VCounterMatch) && dispstat.vcount_irq_enable);
+        let lcd_cycle = if lcd_irq {
+            Some(self.cpu.cycles + self.cycles_to_lcd_event())
+        } else {
+            None
+        };
+
+        let timers = &self.sysbus.ioregs.timers;
+        let timer_cycle = (0..4)
+            .filter(|&index| {
+                let irq = Interrupt::from_usize(Interrupt::Timer0_Overflow as usize + index);
+                enabled(irq.unwrap()) && timers.timer(index).irq_enabled()
+            })
+            .filter_map(|index| timers.timer_overflow_cycle(index));
+
+        lcd_cycle.into_iter().chain(timer_cycle).min()
+    }
+
+    /// Step the LCD, latch the interrupt it raised and start the DMA channels waiting for the
+    /// VBlank or HBlank it entered.
+    /// VBlank is entered once per frame and HBlank only on visible lines, so a VBlank DMA runs
+    /// once per frame and an HBlank DMA once per visible line.
+    fn step_lcd(&mut self, cycles: usize) {
+        let previous_state = self.lcd.state;
+        let previous_line = self.lcd.current_scanline;
+        let (_, irq) = self.lcd.step(cycles, &mut self.sysbus);
+        if let Some(irq) = irq {
+            self.request_irq(irq);
+        }
+        if self.lcd.take_vcount_irq() {
+            self.request_irq(Interrupt::LCD_VCounterMatch);
+        }
+        match (previous_state, self.lcd.state) {
+            (LcdState::HBlank, LcdState::VBlank) => self.start_dma(DmaStartTiming::VBlank),
+            (LcdState::HDraw, LcdState::HBlank) => self.start_dma(DmaStartTiming::HBlank),
+            _ => {}
+        }
+        // DMA3's video capture transfers a line at the end of each of lines 2 to 161, including
+        // the first VBlank lines which have no HBlank DMA
+        if self.lcd.current_scanline != previous_line {
+            if let 2...161 = previous_line {
+                self.run_dma(3, DmaStartTiming::Special);
+            }
+        }
+    }
+
+    /// Start every channel waiting for `timing`, channel 0 has the highest priority
+    fn start_dma(&mut self, timing: DmaStartTiming) {
+        for index in 0..4 {
+            self.run_dma(index, timing);
+        }
+    }
+
+    fn run_dma(&mut self, index: usize, timing: DmaStartTiming) {
+        let (cycles, irq) = {
+            let sysbus = &mut self.sysbus;
+            match index {
+                0 => self.dma0.start(timing, sysbus),
+                1 => self.dma1.start(timing, sysbus),
+                2 => self.dma2.start(timing, sysbus),
+                _ => self.dma3.start(timing, sysbus),
+            }
+        };
+        self.cpu.add_cycles_of(CycleCategory::Dma, cycles);
+        if let Some(irq) = irq {
+            self.request_irq(irq);
+        }
+    }
+
+    /// Run the timers up to `cycle`, latch the interrupts of those that overflowed and refill the
+    /// sound FIFOs they play
+    fn run_timers(&mut self, cycle: usize) {
+        let irqs = self.sysbus.ioregs.timers.run_to(cycle);
+        for timer in 0..4 {
+            if irqs.bit(timer) {
+                let irq = Interrupt::from_usize(Interrupt::Timer0_Overflow as usize + timer);
+                self.request_irq(irq.unwrap());
+            }
+        }
+        for fifo in 0..2 {
+            let timer = self.sysbus.ioregs.direct_sound.timer(fifo);
+            for _ in 0..self.sysbus.ioregs.timers.overflows(timer) {
+                if self.sysbus.ioregs.direct_sound.fifos[fifo].timer_overflow() {
+                    self.scheduler
+                        .schedule_at(EventKind::FifoRefill(fifo), cycle);
+                }
+            }
+        }
+    }
+
+    /// Run the DMA channel feeding sound FIFO `fifo` (0 for A, 1 for B), if there's one
+    fn refill_fifo(&mut self, fifo: usize) {
+        let addr = [REG_FIFO_A, REG_FIFO_B][fifo];
+        if self.dma1.feeds_fifo(addr, &self.sysbus) {
+            self.run_dma(1, DmaStartTiming::Special);
+        } else if self.dma2.feeds_fifo(addr, &self.sysbus) {
+            self.run_dma(2, DmaStartTiming::Special);
+        }
+    }
+
+    /// IME, IE, IF and the CPSR I bit decoded per interrupt source
+    pub fn interrupt_state(&self) -> InterruptState {
+        let intc = &self.sysbus.ioregs.intc;
+        InterruptState::new(intc.ime, intc.ie, intc.reg_if, self.cpu.cpsr.irq_disabled())
     }
 
     fn interrupts_disabled(&self) -> bool {
-        self.sysbus.ioregs.read_reg(REG_IME) & 1 == 0
+        !self.sysbus.ioregs.intc.ime
     }
 
+    /// Set the IF bit of `irq`, the CPU takes it in `check_irq` once it's enabled and unmasked
     fn request_irq(&mut self, irq: Interrupt) {
-        // if self.interrupts_disabled() {
-        //     return;
-        // }
-        // let irq_bit = irq as usize;
-        // let reg_ie = self.sysbus.ioregs.read_reg(REG_IE);
-        // if reg_ie & (1 << irq_bit) != 0 {
-        //     println!("entering {:?}", irq);
-        //     self.cpu.exception(Exception::Irq);
-        // }
+        self.cpu.count_interrupt(irq);
+        let intc = &mut self.sysbus.ioregs.intc;
+        intc.request_irq(irq);
+        if intc.enabled(irq) {
+            self.halted = false;
+        }
+        if self.stopped && intc.enabled(irq) {
+            match irq {
+                Interrupt::Keypad | Interrupt::SerialCommunication | Interrupt::GamePak => {
+                    self.stopped = false
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Enter the IRQ handler if an interrupt is pending and the CPU accepts it. Entering it sets
+    /// the I bit, so the handler isn't interrupted again until it acknowledges IF and returns.
+    fn check_irq(&mut self) {
+        if self.sysbus.ioregs.intc.irq_pending() && !self.cpu.cpsr.irq_disabled() {
+            self.cpu.exception(Exception::Irq);
+        }
     }
 
     pub fn step(&mut self) -> GBAResult<DecodedInstruction> {
-        let previous_cycles = self.cpu.cycles;
         let executed_insn = self.cpu.step_one(&mut self.sysbus)?;
+        self.check_stop_request();
+
+        self.step_devices();
+
+        Ok(executed_insn)
+    }
+}
 
-        let mut cycles = self.cpu.cycles - previous_cycles;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arm7tdmi::{Bus, CpuMode};
 
-        // // drop interrupts at the moment
+    fn make_mock_gba() -> GameBoyAdvance {
+        let mut cpu = Core::new();
+        cpu.reset();
+        GameBoyAdvance::new(cpu, vec![0; 0x4000], Cartridge::new(vec![]))
+    }
 
-        // let (dma_cycles, _) = self.dma0.step(cycles, &mut self.sysbus);
-        // cycles += dma_cycles;
+    fn make_rom(code: &[u32]) -> Vec<u8> {
+        let mut rom = vec![];
+        for word in code.iter() {
+            rom.extend_from_slice(&word.to_le_bytes());
+        }
+        rom
+    }
 
-        // let (dma_cycles, _) = self.dma1.step(cycles, &mut self.sysbus);
-        // cycles += dma_cycles;
+    #[test]
+    fn load_cartridge() {
+        let mut bios = make_rom(&[0xe3a0f302]); // mov pc, #0x08000000
+        bios.resize(0x4000, 0);
+        let rom_a = make_rom(&[
+            0xe3a00001, // mov r0, #1
+            0xeafffffe, // b .
+        ]);
+        let rom_b = make_rom(&[
+            0xe3a00002, // mov r0, #2
+            0xeafffffe, // b .
+        ]);
 
-        // let (dma_cycles, _) = self.dma2.step(cycles, &mut self.sysbus);
-        // cycles += dma_cycles;
+        let mut cpu = Core::new();
+        cpu.reset();
+        let path = std::env::temp_dir().join("rustboyadvance_load_cartridge.sav");
+        let path = path.to_str().unwrap();
+        let mut cart_a = Cartridge::new(rom_a);
+        cart_a.load_from(path).unwrap();
+        let mut gba = GameBoyAdvance::new(cpu, bios, cart_a);
+        gba.frame();
+        assert_eq!(gba.cpu.get_reg(0), 1);
+        gba.sysbus.write_32(0x0300_0000, 0xdeadbeef);
+        gba.sysbus.write_8(0x0e00_0010, 0xab);
 
-        // let (dma_cycles, _) = self.dma3.step(cycles, &mut self.sysbus);
-        // cycles += dma_cycles;
+        let old = gba.load_cartridge(Cartridge::new(rom_b)).unwrap();
+        assert_eq!(old.read_32(0), 0xe3a00001);
+        // the SRAM goes with its cartridge, and was flushed to its save file
+        assert_eq!(old.sram().unwrap().read_8(0x10), 0xab);
+        assert_eq!(std::fs::read(path).unwrap()[0x10], 0xab);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(gba.sysbus.read_8(0x0e00_0010), 0);
+        assert_eq!(gba.cpu.get_next_pc(), 0);
+        assert_eq!(gba.sysbus.read_32(0x0300_0000), 0);
+        assert_eq!(gba.lcd.current_scanline, 0);
 
-        /* let (_, irq) = */
-        self.lcd.step(cycles, &mut self.sysbus);
-        // if let Some(irq) = irq {
-        //     self.request_irq(irq);
-        // }
-        // cycles += lcd_cycles;
+        for _ in 0..3 {
+            gba.step().unwrap();
+        }
+        assert_eq!(gba.cpu.get_reg(0), 2);
+        assert_eq!(gba.cpu.get_next_pc(), 0x0800_0004);
+    }
 
-        Ok(executed_insn)
+    #[test]
+    fn input_script() {
+        let mut bios = make_rom(&[0xeafffffe]); // b .
+        bios.resize(0x4000, 0);
+        let mut cpu = Core::new();
+        cpu.reset();
+        let mut gba = GameBoyAdvance::new(cpu, bios, Cartridge::empty());
+
+        let start = KeypadState::new().with(Keys::Start);
+        let a = KeypadState::new().with(Keys::ButtonA);
+        gba.set_input_script(vec![(4, a), (2, start), (5, KeypadState::new())]);
+
+        let mut keyinput = vec![];
+        for _ in 0..7 {
+            gba.run_frame();
+            keyinput.push(gba.sysbus.ioregs.read_reg(REG_KEYINPUT));
+        }
+        assert_eq!(gba.frame_count(), 7);
+        let released = KEYINPUT_ALL_RELEASED;
+        assert_eq!(
+            keyinput,
+            vec![
+                released,
+                released,
+                start.keyinput(),
+                start.keyinput(),
+                a.keyinput(),
+                released,
+                released,
+            ]
+        );
+    }
+
+    #[test]
+    fn bios_read_protection() {
+        let mut bios = make_rom(&[
+            0xe3a05000, // mov r5, #0
+            0xe5954000, // ldr r4, [r5]
+            0xe3a00302, // mov r0, #0x08000000
+            0xe12fff10, // bx r0
+            0xdeadbeef, // fetched, but not last
+            0xe3a02004, // prefetched while the bx executes
+        ]);
+        bios.resize(0x4000, 0);
+        let rom = make_rom(&[
+            0xe3a01000, // mov r1, #0
+            0xe5912000, // ldr r2, [r1]
+            0xe1d130b2, // ldrh r3, [r1, #2]
+            0xe5d16007, // ldrb r6, [r1, #7]
+            0xeafffffe, // b .
+        ]);
+
+        let mut cpu = Core::new();
+        cpu.reset();
+        let mut gba = GameBoyAdvance::new(cpu, bios, Cartridge::new(rom));
+        for _ in 0..9 {
+            gba.step().unwrap();
+        }
+        assert_eq!(gba.cpu.get_next_pc(), 0x0800_0010);
+        // the BIOS can read itself
+        assert_eq!(gba.cpu.get_reg(4), 0xe3a05000);
+        // but from the ROM all reads return the last opcode fetched from the BIOS
+        assert_eq!(gba.cpu.get_reg(2), 0xe3a02004);
+        assert_eq!(gba.cpu.get_reg(3), 0xe3a0);
+        assert_eq!(gba.cpu.get_reg(6), 0xe3);
+    }
+
+    #[test]
+    fn idle_loop_skipping() {
+        let mut bios = make_rom(&[0xe3a0f302]); // mov pc, #0x08000000
+        bios.resize(0x4000, 0);
+        let rom = make_rom(&[
+            0xe3a01301, // mov r1, #0x04000000
+            0xe1d100b6, // ldrh r0, [r1, #6]
+            0xe3500064, // cmp r0, #100
+            0x1afffffc, // bne 0x08000004
+            0xeafffffe, // b .
+        ]);
+
+        // instructions and cycles until VCOUNT reaches 100
+        let wait_for_line = |idle_loop_detection| {
+            let mut cpu = Core::new();
+            cpu.reset();
+            let mut gba = GameBoyAdvance::new(cpu, bios.clone(), Cartridge::new(rom.clone()));
+            gba.set_idle_loop_detection(idle_loop_detection);
+            let mut instructions = 0;
+            while gba.cpu.get_next_pc() != 0x0800_0010 {
+                gba.emulate();
+                instructions += 1;
+            }
+            assert_eq!(gba.cpu.get_reg(0), 100);
+            assert_eq!(gba.lcd.current_scanline, 100);
+            (instructions, gba.cpu.cycles)
+        };
+
+        let (slow_instructions, slow_cycles) = wait_for_line(false);
+        let (fast_instructions, fast_cycles) = wait_for_line(true);
+        // a few iterations per LCD state change instead of all of them
+        assert!(slow_instructions > 10_000);
+        assert!(fast_instructions * 5 < slow_instructions);
+        // and no time is lost or gained on the way
+        assert!(fast_cycles <= slow_cycles);
+        assert!(slow_cycles - fast_cycles < 100);
+    }
+
+    #[test]
+    fn next_irq_cycle() {
+        let mut gba = make_mock_gba();
+        // a CPU that's only waiting, with timer 1 overflowing in 0x100 ticks of 64 cycles
+        gba.cpu.cycles = 100;
+        gba.step_devices();
+        gba.sysbus.write_16(REG_TM1CNT_L, 0xff00);
+        gba.sysbus.write_16(REG_TM1CNT_H, 0x00c1);
+        let overflow = 100 + 0x100 * 64;
+        assert_eq!(
+            gba.sysbus.ioregs.timers.cycles_to_timer_overflow(1),
+            Some(0x100 * 64)
+        );
+
+        // IME is off
+        gba.sysbus
+            .write_16(REG_IE, 1 << Interrupt::Timer1_Overflow as usize);
+        assert_eq!(gba.next_irq_cycle(), None);
+
+        gba.sysbus.write_16(REG_IME, 1);
+        assert_eq!(gba.next_irq_cycle(), Some(overflow));
+
+        // timer 0 overflows first but its interrupt isn't enabled in IE
+        gba.sysbus.write_16(REG_TM0CNT_L, 0xfff0);
+        gba.sysbus.write_16(REG_TM0CNT_H, 0x00c0);
+        assert_eq!(gba.next_irq_cycle(), Some(overflow));
+
+        // nor is the LCD's, until it is
+        let mut dispstat = gba.sysbus.ioregs.read_reg(REG_DISPSTAT);
+        dispstat.set_bit(3, true);
+        gba.sysbus.write_16(REG_DISPSTAT, dispstat);
+        assert_eq!(gba.next_irq_cycle(), Some(overflow));
+        gba.sysbus
+            .write_16(REG_IE, 1 << Interrupt::LCD_VBlank as usize);
+        // the LCD runs on the scheduler, at the cycle it was scheduled for
+        assert_eq!(
+            gba.next_irq_cycle(),
+            gba.scheduler.event_cycle(EventKind::Lcd)
+        );
+    }
+
+    #[test]
+    fn halt_until_irq() {
+        let mut bios = make_rom(&[
+            0xe3a00301, // mov r0, #0x04000000
+            0xe2800c03, // add r0, r0, #0x300
+            0xe3a01000, // mov r1, #0
+            0xe5c01001, // strb r1, [r0, #1]
+            0xe3a02001, // mov r2, #1
+            0xeafffffe, // b .
+        ]);
+        bios.resize(0x4000, 0);
+        let mut cpu = Core::new();
+        cpu.reset();
+        let mut gba = GameBoyAdvance::new(cpu, bios, Cartridge::new(vec![]));
+        // the IRQ wakes the CPU even while it's masked by the I bit
+        gba.cpu.set_cpsr(0x9f);
+        gba.sysbus.write_16(REG_TM1CNT_L, 0xff00);
+        gba.sysbus.write_16(REG_TM1CNT_H, 0x00c1);
+        gba.sysbus
+            .write_16(REG_IE, 1 << Interrupt::Timer1_Overflow as usize);
+        gba.sysbus.write_16(REG_IME, 1);
+
+        while !gba.is_halted() {
+            gba.emulate();
+        }
+        let pc = gba.cpu.get_next_pc();
+        let halt_cycle = gba.cpu.cycles;
+        let idle_cycles = gba.cpu.cycle_breakdown().idle;
+        let wake_cycle = gba.next_irq_cycle().unwrap();
+        assert!(wake_cycle - halt_cycle > 0x80 * 64);
+
+        // the devices run from one LCD event to the next, the CPU doesn't
+        let mut steps = 0;
+        while gba.is_halted() {
+            gba.emulate();
+            assert!(gba.cpu.cycles <= wake_cycle);
+            steps += 1;
+        }
+        assert_eq!(gba.cpu.cycles, wake_cycle);
+        assert!(steps <= (0x100 * 64) / Lcd::CYCLES_HBLANK);
+        assert_eq!(gba.cpu.get_next_pc(), pc);
+        assert_eq!(
+            gba.cpu.cycle_breakdown().idle - idle_cycles,
+            wake_cycle - halt_cycle
+        );
+
+        for _ in 0..3 {
+            gba.emulate();
+        }
+        assert_eq!(gba.cpu.get_reg(2), 1);
+    }
+
+    #[test]
+    fn cycle_breakdown() {
+        let mut bios = make_rom(&[
+            0xe3a00402, // mov r0, #0x02000000
+            0xe0811002, // add r1, r1, r2
+            0xe5903000, // ldr r3, [r0]
+            0xe5803004, // str r3, [r0, #4]
+            0xe0040291, // mul r4, r1, r2
+            0xe28f5001, // add r5, pc, #1
+            0xe12fff15, // bx r5
+            0xe7fd2101, // movs r1, #1; b 0x1c
+        ]);
+        bios.resize(0x4000, 0);
+        let mut cpu = Core::new();
+        cpu.reset();
+        let mut gba = GameBoyAdvance::new(cpu, bios, Cartridge::new(vec![]));
+        gba.set_idle_loop_detection(true);
+        gba.sysbus.write_32(REG_DMA0SAD, 0x0200_0000);
+        gba.sysbus.write_32(REG_DMA0DAD, 0x0300_0000);
+        gba.sysbus.write_32(REG_DMA0CNT_L, 0x8400_0004);
+        for _ in 0..32 {
+            gba.emulate();
+        }
+
+        let breakdown = *gba.cpu.cycle_breakdown();
+        for &category in &[
+            CycleCategory::ArmAlu,
+            CycleCategory::Thumb,
+            CycleCategory::Load,
+            CycleCategory::Store,
+            CycleCategory::Multiply,
+            CycleCategory::Branch,
+            CycleCategory::Idle,
+            CycleCategory::Dma,
+        ] {
+            assert!(breakdown.get(category) > 0, "no {:?} cycles", category);
+        }
+        // a load from EWRAM waits longer than a single cycle add, and the loop that's spun in
+        // the most is made of branches
+        assert!(breakdown.load > breakdown.thumb);
+        assert!(breakdown.branch > breakdown.arm_alu);
+        assert_eq!(breakdown.total(), gba.cpu.cycles);
+
+        gba.cpu.reset_cycle_breakdown();
+        assert_eq!(gba.cpu.cycle_breakdown().total(), 0);
+        let previous_cycles = gba.cpu.cycles;
+        gba.emulate();
+        assert_eq!(
+            gba.cpu.cycle_breakdown().total(),
+            gba.cpu.cycles - previous_cycles
+        );
+    }
+
+    #[test]
+    fn immediate_and_special_dma() {
+        let mut bios = make_rom(&[0xeafffffe]); // b .
+        bios.resize(0x4000, 0);
+        let mut cpu = Core::new();
+        cpu.reset();
+        let mut gba = GameBoyAdvance::new(cpu, bios, Cartridge::new(vec![]));
+        for i in 0..8 {
+            gba.sysbus.write_32(0x0200_0000 + 4 * i, 0x1000 + i);
+        }
+        gba.sysbus.write_32(0x0200_0004, 0xbbbb_aaaa);
+        gba.emulate();
+        gba.emulate();
+        let previous_cycles = gba.cpu.cycles;
+        gba.emulate();
+        let insn_cycles = gba.cpu.cycles - previous_cycles;
+
+        // DMA1 copies what DMA0 copied, as DMA0 goes first
+        gba.sysbus.write_32(REG_DMA0SAD, 0x0200_0000);
+        gba.sysbus.write_32(REG_DMA0DAD, 0x0300_0000);
+        gba.sysbus.write_32(REG_DMA0CNT_L, 0x8400_0002);
+        gba.sysbus.write_32(REG_DMA1SAD, 0x0300_0000);
+        gba.sysbus.write_32(REG_DMA1DAD, 0x0300_0100);
+        gba.sysbus.write_32(REG_DMA1CNT_L, 0xc400_0002);
+        // DMA3 copies halfwords backwards
+        gba.sysbus.write_32(REG_DMA3SAD, 0x0200_0006);
+        gba.sysbus.write_32(REG_DMA3DAD, 0x0300_0200);
+        gba.sysbus.write_32(REG_DMA3CNT_L, 0x8080_0002);
+        let counters = gba.cpu.counters().clone();
+
+        let previous_cycles = gba.cpu.cycles;
+        gba.emulate();
+        assert_eq!(gba.sysbus.read_32(0x0300_0100), 0x1000);
+        assert_eq!(gba.sysbus.read_32(0x0300_0104), 0xbbbb_aaaa);
+        assert_eq!(gba.sysbus.read_16(0x0300_0200), 0xbbbb);
+        assert_eq!(gba.sysbus.read_16(0x0300_0202), 0xaaaa);
+        for &reg in &[REG_DMA0CNT_H, REG_DMA1CNT_H, REG_DMA3CNT_H] {
+            assert!(!gba.sysbus.ioregs.read_reg(reg).bit(15));
+        }
+        // only DMA1 asked for an interrupt
+        let raised = |irq| gba.cpu.counters().interrupt(irq) - counters.interrupt(irq);
+        assert_eq!(raised(Interrupt::DMA0), 0);
+        assert_eq!(raised(Interrupt::DMA1), 1);
+        // the CPU waited 2 cycles per channel, plus a word from EWRAM (6 cycles) or IWRAM (1) per
+        // unit, or a halfword from EWRAM (3)
+        let dma_cycles = (2 + 2 * (6 + 1)) + (2 + 2 * (1 + 1)) + (2 + 2 * (3 + 1));
+        assert_eq!(gba.cpu.cycles - previous_cycles, insn_cycles + dma_cycles);
+
+        // a FIFO DMA sends 4 words to the same address, whatever the count and width, and stays on
+        gba.sysbus.write_32(REG_DMA1SAD, 0x0200_0000);
+        gba.sysbus.write_32(REG_DMA1DAD, REG_FIFO_A);
+        gba.sysbus.write_32(REG_DMA1CNT_L, 0xb000_0001);
+        gba.emulate();
+        assert_eq!(gba.sysbus.ioregs.read_reg(REG_FIFO_A), 0);
+        gba.run_dma(1, DmaStartTiming::Special);
+        assert_eq!(gba.sysbus.ioregs.read_reg(REG_FIFO_A), 0x1003);
+        gba.run_dma(1, DmaStartTiming::Special);
+        assert_eq!(gba.sysbus.ioregs.read_reg(REG_FIFO_A), 0x1007);
+        assert!(gba.sysbus.ioregs.read_reg(REG_DMA1CNT_H).bit(15));
+    }
+
+    #[test]
+    fn timer_overflow_event() {
+        let mut gba = make_mock_gba();
+        let timer0 = 1 << (Interrupt::Timer0_Overflow as usize);
+        gba.sysbus.write_16(REG_TM0CNT_L, 0xff00);
+        gba.sysbus.write_16(REG_TM0CNT_H, 0x00c0);
+        let start = gba.cpu.cycles;
+        gba.step_devices();
+        let overflow = start + 0x100;
+        assert_eq!(
+            gba.scheduler.event_cycle(EventKind::TimerOverflow),
+            Some(overflow)
+        );
+
+        // the timers don't run until then, reads catch the counter up on the fly
+        gba.cpu.cycles = overflow - 1;
+        gba.step_devices();
+        assert_eq!(gba.sysbus.ioregs.timers.timer(0).counter(), 0xff00);
+        assert_eq!(gba.sysbus.read_16(REG_TM0CNT_L), 0xffff);
+        assert_eq!(gba.sysbus.read_16(REG_IF) & timer0, 0);
+
+        // they run to the exact cycle of the overflow, even when the CPU gets past it
+        gba.cpu.cycles = overflow + 5;
+        gba.step_devices();
+        assert_eq!(gba.sysbus.read_16(REG_IF) & timer0, timer0);
+        assert_eq!(gba.sysbus.ioregs.timers.timer(0).counter(), 0xff00);
+        assert_eq!(gba.sysbus.read_16(REG_TM0CNT_L), 0xff05);
+        assert_eq!(
+            gba.scheduler.event_cycle(EventKind::TimerOverflow),
+            Some(overflow + 0x100)
+        );
+
+        // stopping the timer drops its event
+        gba.sysbus.write_16(REG_TM0CNT_H, 0x0000);
+        gba.step_devices();
+        assert_eq!(gba.scheduler.event_cycle(EventKind::TimerOverflow), None);
+        assert_eq!(gba.sysbus.ioregs.timers.timer(0).counter(), 0xff05);
+    }
+
+    #[test]
+    fn sound_fifo_dma() {
+        let mut gba = make_mock_gba();
+        // samples 1, 2, 3...
+        for i in 0..16 {
+            let word = (4 * i + 1) * 0x0101_0101 + 0x0302_0100;
+            gba.sysbus.write_32(0x0200_0000 + 4 * i, word);
+        }
+        // DMA1 feeds FIFO A, which plays on timer 0 overflowing every 0x100 cycles. The count and
+        // the 16-bit width don't matter.
+        gba.sysbus.write_32(REG_DMA1SAD, 0x0200_0000);
+        gba.sysbus.write_32(REG_DMA1DAD, REG_FIFO_A);
+        gba.sysbus.write_32(REG_DMA1CNT_L, 0xb200_0001);
+        gba.sysbus.write_16(REG_SOUNDCNT_H, 0x0b00);
+        gba.sysbus.write_16(REG_TM0CNT_L, 0xff00);
+        gba.sysbus.write_16(REG_TM0CNT_H, 0x0080);
+        gba.step_devices();
+        assert!(gba.sysbus.ioregs.direct_sound.fifos[0].is_empty());
+
+        // the CPU runs up to each overflow, the refills stall it for less than a timer period
+        let start = gba.cpu.cycles;
+        let run_to_overflow = |gba: &mut GameBoyAdvance, overflow: usize| {
+            gba.cpu.cycles = start + overflow * 0x100;
+            gba.step_devices();
+        };
+
+        // the empty FIFO asks for 4 words, 16 samples
+        run_to_overflow(&mut gba, 1);
+        let fifo = &gba.sysbus.ioregs.direct_sound.fifos[0];
+        assert_eq!(fifo.samples(), (1..=16).collect::<Vec<i8>>());
+        // playing one leaves it half full, so 4 more words
+        run_to_overflow(&mut gba, 2);
+        let fifo = &gba.sysbus.ioregs.direct_sound.fifos[0];
+        assert_eq!(fifo.current_sample(), 1);
+        assert_eq!(fifo.samples(), (2..=32).collect::<Vec<i8>>());
+        run_to_overflow(&mut gba, 3);
+        let fifo = &gba.sysbus.ioregs.direct_sound.fifos[0];
+        assert_eq!(fifo.current_sample(), 2);
+        assert_eq!(fifo.len(), 30);
+        assert!(gba.sysbus.ioregs.read_reg(REG_DMA1CNT_H).bit(15));
+
+        // FIFO B isn't fed by any channel
+        gba.sysbus.write_16(REG_SOUNDCNT_H, 0x0000);
+        run_to_overflow(&mut gba, 4);
+        assert!(gba.sysbus.ioregs.direct_sound.fifos[1].is_empty());
+    }
+
+    #[test]
+    fn sound_fifo_dma_cycles() {
+        let mut gba = make_mock_gba();
+        gba.sysbus.write_32(REG_DMA1SAD, 0x0200_0000);
+        gba.sysbus.write_32(REG_DMA1DAD, REG_FIFO_A);
+        gba.sysbus.write_32(REG_DMA1CNT_L, 0xb600_0001);
+        gba.sysbus.write_16(REG_SOUNDCNT_H, 0x0b00);
+        gba.sysbus.write_16(REG_TM0CNT_L, 0xff00);
+        gba.sysbus.write_16(REG_TM0CNT_H, 0x0080);
+        gba.step_devices();
+
+        // the timer overflows right at the end of the instruction, and the refill stalls the CPU
+        let previous_cycles = gba.cpu.cycles;
+        gba.cpu.cycles += 0x100;
+        gba.step_devices();
+        assert_eq!(gba.sysbus.ioregs.direct_sound.fifos[0].len(), 16);
+        let dma_cycles = gba.cpu.cycles - previous_cycles - 0x100;
+        assert!(dma_cycles > 0);
+        // the timer ran through the stall too
+        assert_eq!(gba.sysbus.read_16(REG_TM0CNT_L), 0xff00 + dma_cycles as u16);
+    }
+
+    #[test]
+    fn vblank_and_hblank_dma() {
+        let mut bios = make_rom(&[0xeafffffe]); // b .
+        bios.resize(0x4000, 0);
+        let mut cpu = Core::new();
+        cpu.reset();
+        let mut gba = GameBoyAdvance::new(cpu, bios, Cartridge::new(vec![]));
+        for i in 0..12 {
+            gba.sysbus.write_32(0x0200_0000 + 4 * i, 0x1000 + i);
+        }
+        // DMA0 copies 2 words at VBlank, once
+        gba.sysbus.write_32(REG_DMA0SAD, 0x0200_0000);
+        gba.sysbus.write_32(REG_DMA0DAD, 0x0300_0000);
+        gba.sysbus.write_16(REG_DMA0CNT_L, 2);
+        gba.sysbus.write_16(REG_DMA0CNT_H, 0x9400);
+        // DMA1 repeats a word from a fixed source at every VBlank, without reloading the destination
+        gba.sysbus.write_32(REG_DMA1SAD, 0x0200_0010);
+        gba.sysbus.write_32(REG_DMA1DAD, 0x0300_0100);
+        gba.sysbus.write_16(REG_DMA1CNT_L, 1);
+        gba.sysbus.write_16(REG_DMA1CNT_H, 0x9700);
+        // and DMA3 a halfword at every HBlank
+        gba.sysbus.write_32(REG_DMA3SAD, 0x0200_0020);
+        gba.sysbus.write_32(REG_DMA3DAD, 0x0300_1000);
+        gba.sysbus.write_16(REG_DMA3CNT_L, 1);
+        gba.sysbus.write_16(REG_DMA3CNT_H, 0xa300);
+
+        let hblank_transfers = |gba: &GameBoyAdvance| {
+            (0..0x800)
+                .take_while(|i| gba.sysbus.read_16(0x0300_1000 + 2 * i) != 0)
+                .count()
+        };
+
+        gba.frame();
+        let per_frame = hblank_transfers(&gba);
+        assert!(per_frame >= Lcd::DISPLAY_HEIGHT - 1);
+        // the non-repeating DMA is done and turned itself off
+        assert_eq!(gba.sysbus.read_32(0x0300_0000), 0x1000);
+        assert_eq!(gba.sysbus.read_32(0x0300_0004), 0x1001);
+        assert!(!gba.sysbus.ioregs.read_reg(REG_DMA0CNT_H).bit(15));
+        assert!(gba.sysbus.ioregs.read_reg(REG_DMA1CNT_H).bit(15));
+        gba.sysbus.write_32(0x0200_0000, 0xdead);
+
+        for frame in 1..=3 {
+            assert_eq!(gba.sysbus.read_32(0x0300_0100 + 4 * (frame - 1)), 0x1004);
+            assert_eq!(gba.sysbus.read_32(0x0300_0100 + 4 * frame), 0);
+            assert_eq!(hblank_transfers(&gba), per_frame * frame as usize);
+
+            // nothing happens during VBlank
+            while gba.lcd.state == LcdState::VBlank {
+                gba.emulate();
+            }
+            assert_eq!(gba.sysbus.read_32(0x0300_0100 + 4 * frame), 0);
+            assert_eq!(hblank_transfers(&gba), per_frame * frame as usize);
+            gba.frame();
+        }
+        assert_eq!(gba.sysbus.read_32(0x0300_0000), 0x1000);
+    }
+
+    #[test]
+    fn bios_only() {
+        let mut bios = make_rom(&[
+            0xe3a01302, // mov r1, #0x08000000
+            0xe5910010, // ldr r0, [r1, #0x10]
+            0xeafffffe, // b .
+        ]);
+        bios.resize(0x4000, 0);
+
+        let mut cpu = Core::new();
+        cpu.reset();
+        let mut gba = GameBoyAdvance::new(cpu, bios, Cartridge::empty());
+        for _ in 0..2 {
+            assert_eq!(gba.run_frame(), FrameStatus::Completed);
+        }
+        assert_eq!(gba.cpu.get_next_pc(), 0x08);
+        assert_eq!(gba.cpu.get_reg(0), 0x0009_0008);
+        assert_eq!(gba.sysbus.read_16(0x0800_1234), 0x091a);
+    }
+
+    #[test]
+    fn runaway_frame() {
+        let mut bios = make_rom(&[0xe3a0f302]); // mov pc, #0x08000000
+        bios.resize(0x4000, 0);
+        let rom = make_rom(&[
+            0xe10f0000, // mrs r0, cpsr
+            0xe38000c0, // orr r0, r0, #0xc0
+            0xe129f000, // msr cpsr_fc, r0
+            0xeafffffe, // b .
+        ]);
+
+        let mut cpu = Core::new();
+        cpu.reset();
+        let mut gba = GameBoyAdvance::new(cpu, bios, Cartridge::new(rom));
+        assert_eq!(gba.run_frame(), FrameStatus::Completed);
+        assert!(gba.cpu.cpsr.irq_disabled());
+
+        gba.set_frame_instruction_limit(Some(1000));
+        let cycles = gba.cpu.cycles();
+        assert_eq!(gba.run_frame(), FrameStatus::RunawayDetected);
+        assert_eq!(gba.cpu.get_next_pc(), 0x0800_000c);
+        // 1000 runs of a branch loop, nowhere near a whole frame
+        assert!(gba.cpu.cycles() - cycles < 280_896 / 10);
+
+        gba.set_frame_instruction_limit(None);
+        assert_eq!(gba.run_frame(), FrameStatus::Completed);
+    }
+
+    #[test]
+    fn default_frame_instruction_limit() {
+        let mut bios = make_rom(&[0xe3a0f302]); // mov pc, #0x08000000
+        bios.resize(0x4000, 0);
+        let rom = make_rom(&[
+            0xe2800001, // add r0, r0, #1
+            0xe0811000, // add r1, r1, r0
+            0xeafffffc, // b 0x08000000
+        ]);
+
+        // a game busy for the whole frame stays under the limit
+        let mut cpu = Core::new();
+        cpu.reset();
+        let mut gba = GameBoyAdvance::new(cpu, bios.clone(), Cartridge::new(rom));
+        assert_eq!(gba.run_frame(), FrameStatus::Completed);
+        assert_eq!(gba.run_frame(), FrameStatus::Completed);
+
+        // jumping into work RAM full of zeroes, `andeq r0, r0, r0`
+        let mut bios = make_rom(&[0xe3a0f403]); // mov pc, #0x03000000
+        bios.resize(0x4000, 0);
+        let mut cpu = Core::new();
+        cpu.reset();
+        let mut gba = GameBoyAdvance::new(cpu, bios, Cartridge::new(vec![]));
+        assert_eq!(gba.run_frame(), FrameStatus::RunawayDetected);
+    }
+
+    #[test]
+    fn benchmark_mode() {
+        let mut bios = make_rom(&[0xe3a0f302]); // mov pc, #0x08000000
+        bios.resize(0x4000, 0);
+        let rom = make_rom(&[0xeafffffe]); // b .
+
+        let mut cpu = Core::new();
+        cpu.reset();
+        let mut gba = GameBoyAdvance::new(cpu, bios, Cartridge::new(rom));
+        // mode 3 with a red first pixel
+        gba.sysbus.write_16(REG_DISPCNT, 0x0403);
+        gba.sysbus.write_16(0x0600_0000, 0x001f);
+
+        gba.set_benchmark_mode(true);
+        gba.run_frame();
+        let cycles = gba.cpu.cycles();
+        gba.run_frame();
+        let benchmark_frame = gba.cpu.cycles() - cycles;
+        assert_eq!(gba.lcd.pixeldata[0], Rgb15::from(0));
+        assert_eq!(gba.frame_count(), 2);
+
+        gba.set_benchmark_mode(false);
+        let cycles = gba.cpu.cycles();
+        gba.run_frame();
+        let frame = gba.cpu.cycles() - cycles;
+        assert_eq!(gba.lcd.pixeldata[0], Rgb15::from(0x001f));
+        // the same frame, give or take the branch the CPU was in
+        assert!(benchmark_frame > 270_000);
+        assert!((benchmark_frame as isize - frame as isize).abs() < 8);
+    }
+
+    #[test]
+    fn advance_to_vblank_mid_frame() {
+        let mut gba = make_mock_gba();
+        for _ in 0..5000 {
+            gba.step().unwrap();
+        }
+        assert_ne!(gba.lcd.state, LcdState::VBlank);
+
+        gba.advance_to_vblank();
+        assert_eq!(gba.lcd.state, LcdState::VBlank);
+        assert_eq!(
+            gba.sysbus.ioregs.read_reg(REG_VCOUNT) as usize,
+            Lcd::DISPLAY_HEIGHT
+        );
+
+        // already aligned
+        gba.advance_to_vblank();
+        assert_eq!(
+            gba.sysbus.ioregs.read_reg(REG_VCOUNT) as usize,
+            Lcd::DISPLAY_HEIGHT
+        );
+    }
+
+    #[test]
+    fn exception_counters() {
+        let mut gba = make_mock_gba();
+        gba.sysbus.write_16(REG_IME, 1);
+        gba.sysbus
+            .write_16(REG_IE, 1 << (Interrupt::LCD_VBlank as usize));
+        gba.cpu.reset_counters();
+
+        for _ in 0..3 {
+            // the handler acknowledges and returns with interrupts enabled
+            gba.cpu.cpsr.set_irq_disabled(false);
+            gba.request_irq(Interrupt::LCD_VBlank);
+            gba.check_irq();
+            gba.sysbus.write_16(REG_IF, 0xffff);
+        }
+        // not enabled in IE, counted but not taken
+        gba.cpu.cpsr.set_irq_disabled(false);
+        gba.request_irq(Interrupt::LCD_HBlank);
+        gba.check_irq();
+
+        let counters = gba.cpu.counters();
+        assert_eq!(counters.exception(Exception::Irq), 3);
+        assert_eq!(counters.exception(Exception::SoftwareInterrupt), 0);
+        assert_eq!(counters.interrupt(Interrupt::LCD_VBlank), 3);
+        assert_eq!(counters.interrupt(Interrupt::LCD_HBlank), 1);
+
+        gba.cpu.reset_counters();
+        assert_eq!(gba.cpu.counters().exception(Exception::Irq), 0);
+        assert_eq!(gba.cpu.counters().interrupt(Interrupt::LCD_VBlank), 0);
+    }
+
+    #[test]
+    fn graphics_state() {
+        let mut gba = make_mock_gba();
+        let ioregs = &mut gba.sysbus.ioregs;
+        // mode 1, BG0 and BG2 on
+        ioregs.write_reg(REG_DISPCNT, 0x0501);
+        // 512x256, 256 colors, screen block 31, char block 1
+        ioregs.write_reg(REG_BG0CNT, 0x5f84);
+        ioregs.write_reg(REG_BG0HOFS, 0x123);
+        ioregs.write_reg(REG_BG0VOFS, 0x45);
+        ioregs.write_reg(REG_BG2PA, 0x0100);
+        ioregs.write_reg(REG_BG2PB, 0xff00);
+        ioregs.write_reg(REG_BG2X, 0xff00);
+        ioregs.write_reg(REG_BG2X + 2, 0x0fff);
+        ioregs.write_reg(REG_WIN0H, 0x10f0);
+        ioregs.write_reg(REG_WIN0V, 0x20a0);
+        ioregs.write_reg(REG_WININ, 0x0111);
+        ioregs.write_reg(REG_BLDCNT, 0x0241);
+        ioregs.write_reg(REG_BLDALPHA, 0x0c04);
+
+        let state = gba.graphics_state();
+        assert_eq!(state.dispcnt.bg_mode, BGMode::BGMode1);
+        assert_eq!(state.dispcnt.disp_bg, [true, false, true, false]);
+        assert_eq!(state.bgcnt[0].screen_width, 512);
+        assert_eq!(state.bgcnt[0].screen_height, 256);
+        assert!(state.bgcnt[0].palette256);
+        assert_eq!(state.bgcnt[0].screen_base_block, 31);
+        assert_eq!(state.bgcnt[0].character_base_block, 1);
+        assert_eq!(state.bgofs[0], (0x123, 0x45));
+        assert_eq!(state.bg_affine[0].pa, 0x100);
+        assert_eq!(state.bg_affine[0].pb, -0x100);
+        assert_eq!(state.bg_affine[0].x, -0x100);
+        assert_eq!(
+            state.window.win0,
+            WindowBounds {
+                left: 0x10,
+                right: 0xf0,
+                top: 0x20,
+                bottom: 0xa0
+            }
+        );
+        assert_eq!(state.window.win0_control.bg, [true, false, false, false]);
+        assert!(state.window.win0_control.obj);
+        assert_eq!(state.window.win1_control.bg, [true, false, false, false]);
+        assert_eq!(state.blend.mode, BlendMode::BldAlpha);
+        assert_eq!(state.blend.target1.bg, [true, false, false, false]);
+        assert_eq!(state.blend.target2.bg, [false, true, false, false]);
+        assert_eq!((state.blend.eva, state.blend.evb), (4, 12));
+    }
+
+    #[test]
+    fn interrupt_state() {
+        let mut gba = make_mock_gba();
+        let intc = &mut gba.sysbus.ioregs.intc;
+        intc.ie = 1 << (Interrupt::Timer0_Overflow as usize);
+        intc.request_irq(Interrupt::Timer0_Overflow);
+        intc.request_irq(Interrupt::Keypad);
+        intc.ime = false;
+
+        let state = gba.interrupt_state();
+        assert!(!state.ime);
+        let timer0 = state.source(Interrupt::Timer0_Overflow);
+        assert!(timer0.pending && timer0.enabled && timer0.masked);
+        assert!(!timer0.fires());
+        // requested but not enabled
+        let keypad = state.source(Interrupt::Keypad);
+        assert!(keypad.pending && !keypad.enabled);
+        assert_eq!(state.pending_and_enabled(), [Interrupt::Timer0_Overflow]);
+
+        gba.sysbus.ioregs.intc.ime = true;
+        gba.cpu.set_cpsr(0x1f);
+        assert!(gba
+            .interrupt_state()
+            .source(Interrupt::Timer0_Overflow)
+            .fires());
+        // System mode with the I bit set
+        gba.cpu.set_cpsr(0x9f);
+        let state = gba.interrupt_state();
+        assert!(state.cpsr_irq_disabled);
+        assert!(state.source(Interrupt::Timer0_Overflow).masked);
+    }
+
+    #[test]
+    fn irq_delivery() {
+        let mut bios = make_rom(&[0xe1a00000; 16]); // mov r0, r0
+        bios.resize(0x4000, 0);
+        let mut cpu = Core::new();
+        cpu.reset();
+        let mut gba = GameBoyAdvance::new(cpu, bios, Cartridge::new(vec![]));
+        gba.cpu.set_cpsr(0x1f);
+        gba.sysbus
+            .write_16(REG_IE, 1 << (Interrupt::Timer0_Overflow as usize));
+        gba.emulate();
+        gba.emulate();
+
+        // requested while IME is off, it stays pending
+        gba.request_irq(Interrupt::Timer0_Overflow);
+        gba.emulate();
+        assert_eq!(gba.cpu.cpsr.mode(), CpuMode::System);
+        assert!(
+            gba.interrupt_state()
+                .source(Interrupt::Timer0_Overflow)
+                .pending
+        );
+        // and while the CPSR I bit is set
+        gba.sysbus.write_16(REG_IME, 1);
+        gba.cpu.set_cpsr(0x9f);
+        gba.emulate();
+        assert_eq!(gba.cpu.cpsr.mode(), CpuMode::System);
+
+        // taken once unmasked, returning to the next instruction with subs pc, lr, #4
+        gba.cpu.set_cpsr(0x1f);
+        let return_addr = gba.cpu.get_next_pc();
+        gba.check_irq();
+        assert_eq!(gba.cpu.cpsr.mode(), CpuMode::Irq);
+        assert!(gba.cpu.cpsr.irq_disabled());
+        assert_eq!(gba.cpu.get_next_pc(), 0x18);
+        assert_eq!(gba.cpu.get_reg(14), return_addr + 4);
+        assert_eq!(gba.cpu.spsr[CpuMode::Irq.spsr_index().unwrap()].get(), 0x1f);
+
+        // the handler acknowledges it with a 1, other bits are left alone
+        gba.request_irq(Interrupt::LCD_VBlank);
+        gba.sysbus
+            .write_16(REG_IF, 1 << (Interrupt::Timer0_Overflow as usize));
+        assert_eq!(
+            gba.sysbus.read_16(REG_IF),
+            1 << (Interrupt::LCD_VBlank as usize)
+        );
+        // a byte write acknowledges only its byte
+        gba.request_irq(Interrupt::Keypad);
+        gba.sysbus.write_8(REG_IF, 0xff);
+        assert_eq!(
+            gba.sysbus.read_16(REG_IF),
+            1 << (Interrupt::Keypad as usize)
+        );
+        assert_eq!(gba.sysbus.read_8(REG_IF + 1), 0x10);
+        gba.sysbus.write_8(REG_IF + 1, 0xff);
+        assert_eq!(gba.sysbus.read_16(REG_IF), 0);
+        // the high byte reads back the acknowledged flags too
+        gba.request_irq(Interrupt::Keypad);
+        gba.sysbus.write_16(REG_IF, 0xffff);
+        assert_eq!(gba.sysbus.read_8(REG_IF + 1), 0);
+    }
+
+    #[test]
+    fn simultaneous_irqs() {
+        let mut bios = make_rom(&[0xeafffffe]); // b .
+        bios.resize(0x4000, 0);
+        let mut cpu = Core::new();
+        cpu.reset();
+        let mut gba = GameBoyAdvance::new(cpu, bios, Cartridge::new(vec![]));
+        let vblank = 1 << (Interrupt::LCD_VBlank as usize);
+        let timer0 = 1 << (Interrupt::Timer0_Overflow as usize);
+        gba.sysbus.write_16(REG_DISPSTAT, 1 << 3);
+        while !(gba.lcd.state == LcdState::HBlank && gba.lcd.current_scanline == 159) {
+            gba.emulate();
+        }
+
+        // timer 0 overflows while the devices catch up to the start of VBlank
+        gba.sysbus.write_16(REG_TM0CNT_L, 0xfff0);
+        gba.sysbus.write_16(REG_TM0CNT_H, 0x00c0);
+        gba.cpu.cycles = gba.scheduler.event_cycle(EventKind::Lcd).unwrap();
+        gba.step_devices();
+        assert_eq!(gba.lcd.state, LcdState::VBlank);
+        assert_eq!(gba.sysbus.read_16(REG_IF), vblank | timer0);
+
+        // the IRQ is taken for both, and again after the handler acknowledges only one
+        gba.sysbus.write_16(REG_IE, vblank | timer0);
+        gba.sysbus.write_16(REG_IME, 1);
+        gba.cpu.set_cpsr(0x1f);
+        gba.check_irq();
+        assert_eq!(gba.cpu.cpsr.mode(), CpuMode::Irq);
+        gba.sysbus.write_16(REG_IF, vblank);
+        assert_eq!(gba.sysbus.read_16(REG_IF), timer0);
+        gba.cpu.set_cpsr(0x1f);
+        gba.check_irq();
+        assert_eq!(gba.cpu.cpsr.mode(), CpuMode::Irq);
+
+        // any subset is acknowledged at once
+        gba.request_irq(Interrupt::LCD_VBlank);
+        gba.request_irq(Interrupt::Keypad);
+        gba.sysbus.write_16(REG_IF, vblank | timer0);
+        assert_eq!(
+            gba.sysbus.read_16(REG_IF),
+            1 << (Interrupt::Keypad as usize)
+        );
+        gba.cpu.set_cpsr(0x1f);
+        gba.check_irq();
+        assert_eq!(gba.cpu.cpsr.mode(), CpuMode::System);
+    }
+
+    #[test]
+    fn lcd_irq_timing() {
+        let mut gba = make_mock_gba();
+        let start = gba.cpu.cycles;
+        // VBlank, HBlank and VCOUNT interrupts, matching line 100
+        gba.sysbus.write_16(REG_DISPSTAT, (100 << 8) | 0x38);
+
+        // the cycle of each interrupt over a frame and the first line of the next one
+        let mut irqs = vec![];
+        while gba.cpu.cycles - start < 229 * Lcd::CYCLES_SCANLINE {
+            gba.cpu.cycles = gba.scheduler.event_cycle(EventKind::Lcd).unwrap();
+            gba.step_devices();
+            let reg_if = gba.sysbus.read_16(REG_IF);
+            for irq in 0..3 {
+                if reg_if.bit(irq) {
+                    irqs.push((gba.cpu.cycles - start, Interrupt::from_usize(irq).unwrap()));
+                }
+            }
+            gba.sysbus.write_16(REG_IF, reg_if);
+        }
+
+        let at = |line: usize, cycle: usize| line * Lcd::CYCLES_SCANLINE + cycle;
+        let hblanks: Vec<usize> = irqs
+            .iter()
+            .filter(|&&(_, irq)| irq == Interrupt::LCD_HBlank)
+            .map(|&(cycle, _)| cycle)
+            .collect();
+        let expected: Vec<usize> = (0..160)
+            .chain(228..229)
+            .map(|line| at(line, Lcd::CYCLES_HDRAW))
+            .collect();
+        assert_eq!(hblanks, expected);
+        let others: Vec<_> = irqs
+            .into_iter()
+            .filter(|&(_, irq)| irq != Interrupt::LCD_HBlank)
+            .collect();
+        assert_eq!(
+            others,
+            [
+                (at(100, 0), Interrupt::LCD_VCounterMatch),
+                (at(160, 0), Interrupt::LCD_VBlank),
+            ]
+        );
+
+        // the VCOUNT flag is set for the matching line only
+        let mut gba = make_mock_gba();
+        gba.sysbus.write_16(REG_DISPSTAT, 100 << 8);
+        let mut flagged = vec![];
+        while gba.lcd.state != LcdState::VBlank {
+            gba.cpu.cycles = gba.scheduler.event_cycle(EventKind::Lcd).unwrap();
+            gba.step_devices();
+            if gba.sysbus.read_16(REG_DISPSTAT).bit(2) {
+                flagged.push(gba.sysbus.read_16(REG_VCOUNT));
+            }
+        }
+        assert_eq!(flagged, [100, 100]);
+        assert_eq!(gba.sysbus.read_16(REG_IF), 0);
+    }
+
+    #[test]
+    fn stop_mode() {
+        let mut gba = make_mock_gba();
+        gba.sysbus
+            .write_16(REG_IE, 1 << (Interrupt::Keypad as usize));
+        gba.sysbus
+            .ioregs
+            .write_reg(REG_KEYCNT, (1 << 14) | (1 << (Keys::Start as usize)));
+        gba.lcd.pixeldata[0] = Rgb15::from(0x7fff);
+
+        gba.sysbus.write_8(REG_HALTCNT, 0x80);
+        gba.emulate();
+        assert!(gba.is_stopped());
+        assert_eq!(gba.lcd.pixeldata[0], Rgb15::from(0));
+
+        let cycles = gba.cpu.cycles;
+        let scanline = gba.lcd.current_scanline;
+        gba.frame();
+        gba.advance_to_vblank();
+        assert_eq!(gba.cpu.cycles, cycles);
+        assert_eq!(gba.lcd.current_scanline, scanline);
+
+        // only keypad, serial and gamepak interrupts wake the system
+        gba.sysbus.write_16(REG_IE, 0xffff);
+        gba.request_irq(Interrupt::LCD_VBlank);
+        assert!(gba.is_stopped());
+
+        gba.set_key(Keys::Start, true);
+        assert!(!gba.is_stopped());
+        gba.emulate();
+        assert!(gba.cpu.cycles > cycles);
+    }
+
+    #[test]
+    fn direct_boot() {
+        let rom = make_rom(&[0xe3a00001]); // mov r0, #1
+        let mut cpu = Core::new();
+        cpu.reset();
+        let mut gba = GameBoyAdvance::new(cpu, vec![0; 0x4000], Cartridge::new(rom));
+        // whatever ran before, e.g. a multiboot loader
+        let regions = [
+            (0x0200_0000, 0x4_0000),
+            (0x0300_0000, 0x8000),
+            (0x0500_0000, 0x400),
+            (0x0600_0000, 0x1_8000),
+            (0x0700_0000, 0x400),
+        ];
+        for &(base, size) in regions.iter() {
+            gba.sysbus.write_32(base, 0xdead_beef);
+            gba.sysbus.write_32(base + size - 4, 0xdead_beef);
+        }
+        gba.cpu.set_reg(0, 0x1234);
+
+        gba.direct_boot();
+        for &(base, size) in regions.iter() {
+            assert_eq!(gba.sysbus.read_32(base), 0, "{:#x}", base);
+            assert_eq!(gba.sysbus.read_32(base + size - 4), 0, "{:#x}", base);
+        }
+        assert_eq!(gba.sysbus.read_8(REG_POSTFLG), 1);
+        assert_eq!(gba.sysbus.read_16(REG_SOUNDBIAS), 0x200);
+        assert_eq!(gba.cpu.cpsr.mode(), CpuMode::System);
+        assert!(!gba.cpu.cpsr.irq_disabled());
+        assert_eq!(gba.cpu.get_reg(0), 0);
+        assert_eq!(gba.cpu.get_reg(13), 0x0300_7f00);
+        gba.cpu.set_cpsr(0x12);
+        assert_eq!(gba.cpu.get_reg(13), 0x0300_7fa0);
+        gba.cpu.set_cpsr(0x13);
+        assert_eq!(gba.cpu.get_reg(13), 0x0300_7fe0);
+        gba.cpu.set_cpsr(0x1f);
+
+        assert_eq!(gba.cpu.get_next_pc(), 0x0800_0000);
+        gba.emulate();
+        gba.emulate();
+        gba.emulate();
+        assert_eq!(gba.cpu.get_reg(0), 1);
     }
 }
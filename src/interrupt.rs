@@ -1,3 +1,9 @@
+use super::arm7tdmi::Addr;
+use super::ioregs::consts::*;
+
+use crate::bit::BitIndex;
+use crate::num::FromPrimitive;
+
 #[derive(Debug, Primitive, Copy, Clone, PartialEq)]
 #[allow(non_camel_case_types)]
 pub enum Interrupt {
@@ -17,4 +23,119 @@ pub enum Interrupt {
     GamePak = 13,
 }
 
-pub struct InterruptController;
+/// IE, IF and IME.
+///
+/// Devices request interrupts by setting their IF bit, which stays set until the handler
+/// acknowledges it by writing a 1 to it. The CPU takes the IRQ exception whenever IME is on and
+/// an interrupt is both requested and enabled in IE, unless its CPSR I bit is set.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct InterruptController {
+    pub ie: u16,
+    pub reg_if: u16,
+    pub ime: bool,
+}
+
+impl InterruptController {
+    pub fn new() -> InterruptController {
+        Default::default()
+    }
+
+    pub fn request_irq(&mut self, irq: Interrupt) {
+        self.reg_if.set_bit(irq as usize, true);
+    }
+
+    /// Whether `irq` is enabled in IE
+    pub fn enabled(&self, irq: Interrupt) -> bool {
+        self.ie.bit(irq as usize)
+    }
+
+    /// Whether the CPU should take an IRQ, as far as IME, IE and IF go
+    pub fn irq_pending(&self) -> bool {
+        self.ime && self.ie & self.reg_if != 0
+    }
+
+    /// Reads of IE, IF and IME, `None` for other addresses
+    pub fn read(&self, addr: Addr) -> Option<u16> {
+        match addr {
+            REG_IE => Some(self.ie),
+            REG_IF => Some(self.reg_if),
+            REG_IME => Some(self.ime as u16),
+            _ => None,
+        }
+    }
+
+    /// Writes to IE, IF and IME, other addresses are ignored. Writing 1s to IF clears them.
+    pub fn write(&mut self, addr: Addr, value: u16) {
+        match addr {
+            REG_IE => self.ie = value & 0x3fff,
+            REG_IF => self.reg_if &= !value,
+            REG_IME => self.ime = value.bit(0),
+            _ => {}
+        }
+    }
+}
+
+/// How one interrupt source stands, see `InterruptState`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct IrqSourceState {
+    pub irq: Interrupt,
+    /// Its IE bit
+    pub enabled: bool,
+    /// Its IF bit
+    pub pending: bool,
+    /// IME or the CPSR I bit keep it from being taken
+    pub masked: bool,
+}
+
+impl IrqSourceState {
+    /// Whether the CPU takes it as soon as it can
+    pub fn fires(&self) -> bool {
+        self.enabled && self.pending && !self.masked
+    }
+}
+
+/// Decoded snapshot of the interrupt registers, for figuring out why an IRQ isn't taken
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterruptState {
+    pub ime: bool,
+    pub ie: u16,
+    pub reg_if: u16,
+    /// The CPSR I bit
+    pub cpsr_irq_disabled: bool,
+    /// Every source in IE/IF bit order
+    pub sources: Vec<IrqSourceState>,
+}
+
+impl InterruptState {
+    pub fn new(ime: bool, ie: u16, reg_if: u16, cpsr_irq_disabled: bool) -> InterruptState {
+        let masked = !ime || cpsr_irq_disabled;
+        let sources = (0..=Interrupt::GamePak as usize)
+            .map(|bit| IrqSourceState {
+                irq: Interrupt::from_usize(bit).unwrap(),
+                enabled: ie.bit(bit),
+                pending: reg_if.bit(bit),
+                masked,
+            })
+            .collect();
+        InterruptState {
+            ime,
+            ie,
+            reg_if,
+            cpsr_irq_disabled,
+            sources,
+        }
+    }
+
+    pub fn source(&self, irq: Interrupt) -> IrqSourceState {
+        self.sources[irq as usize]
+    }
+
+    /// Sources that are both requested and enabled in IE, whether or not they're masked
+    pub fn pending_and_enabled(&self) -> Vec<Interrupt> {
+        self.sources
+            .iter()
+            .filter(|source| source.enabled && source.pending)
+            .map(|source| source.irq)
+            .collect()
+    }
+}
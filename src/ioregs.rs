@@ -1,8 +1,14 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::io;
+use std::mem;
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
 use crate::arm7tdmi::{Addr, Bus, MemoryAccess};
+use crate::interrupt::InterruptController;
+use crate::sound::{DirectSound, WaveRam};
+use crate::timer::Timers;
 
 pub mod consts {
     use super::*;
@@ -121,19 +127,287 @@ pub mod consts {
 
 use consts::*;
 
+/// Registers that are acted upon by the emulator, any other access is reported as unhandled.
+/// Extend when implementing a register.
+const HANDLED_REGISTERS: &[Addr] = &[
+    REG_DISPCNT,
+    REG_DISPSTAT,
+    REG_VCOUNT,
+    REG_BG0CNT,
+    REG_BG1CNT,
+    REG_BG2CNT,
+    REG_BG3CNT,
+    REG_BG0HOFS,
+    REG_BG0VOFS,
+    REG_BG1HOFS,
+    REG_BG1VOFS,
+    REG_BG2HOFS,
+    REG_BG2VOFS,
+    REG_BG3HOFS,
+    REG_BG3VOFS,
+    REG_BG2PA,
+    REG_BG2PB,
+    REG_BG2PC,
+    REG_BG2PD,
+    REG_BG2X,
+    REG_BG2X + 0x2,
+    REG_BG2Y,
+    REG_BG2Y + 0x2,
+    REG_BG3PA,
+    REG_BG3PB,
+    REG_BG3PC,
+    REG_BG3PD,
+    REG_BG3X,
+    REG_BG3X + 0x2,
+    REG_BG3Y,
+    REG_BG3Y + 0x2,
+    REG_WIN0H,
+    REG_WIN1H,
+    REG_WIN0V,
+    REG_WIN1V,
+    REG_WININ,
+    REG_WINOUT,
+    REG_MOSAIC,
+    REG_BLDCNT,
+    REG_BLDALPHA,
+    REG_BLDY,
+    REG_FIFO_A,
+    REG_FIFO_A + 0x2,
+    REG_FIFO_B,
+    REG_FIFO_B + 0x2,
+    REG_DMA0SAD,
+    REG_DMA0SAD + 0x2,
+    REG_DMA0DAD,
+    REG_DMA0DAD + 0x2,
+    REG_DMA0CNT_L,
+    REG_DMA0CNT_H,
+    REG_DMA1SAD,
+    REG_DMA1SAD + 0x2,
+    REG_DMA1DAD,
+    REG_DMA1DAD + 0x2,
+    REG_DMA1CNT_L,
+    REG_DMA1CNT_H,
+    REG_DMA2SAD,
+    REG_DMA2SAD + 0x2,
+    REG_DMA2DAD,
+    REG_DMA2DAD + 0x2,
+    REG_DMA2CNT_L,
+    REG_DMA2CNT_H,
+    REG_DMA3SAD,
+    REG_DMA3SAD + 0x2,
+    REG_DMA3DAD,
+    REG_DMA3DAD + 0x2,
+    REG_DMA3CNT_L,
+    REG_DMA3CNT_H,
+    REG_TM0CNT_L,
+    REG_TM0CNT_H,
+    REG_TM1CNT_L,
+    REG_TM1CNT_H,
+    REG_TM2CNT_L,
+    REG_TM2CNT_H,
+    REG_TM3CNT_L,
+    REG_TM3CNT_H,
+    REG_SOUND3CNT_L, // only the wave RAM banks
+    REG_SOUNDCNT_H,
+    REG_WAVE_RAM,
+    REG_WAVE_RAM + 0x2,
+    REG_WAVE_RAM + 0x4,
+    REG_WAVE_RAM + 0x6,
+    REG_WAVE_RAM + 0x8,
+    REG_WAVE_RAM + 0xa,
+    REG_WAVE_RAM + 0xc,
+    REG_WAVE_RAM + 0xe,
+    REG_KEYINPUT,
+    REG_KEYCNT,
+    REG_IE,
+    REG_IF,
+    REG_WAITCNT,
+    REG_IME,
+    REG_POSTFLG, // shares a halfword with HALTCNT
+];
+
+/// Name, address and size in bytes of every I/O register. Registers that share an address
+/// (the SIO data registers) are listed widest first.
+pub const REGISTERS: &[(&str, Addr, usize)] = &[
+    ("DISPCNT", REG_DISPCNT, 2),
+    ("DISPSTAT", REG_DISPSTAT, 2),
+    ("VCOUNT", REG_VCOUNT, 2),
+    ("BG0CNT", REG_BG0CNT, 2),
+    ("BG1CNT", REG_BG1CNT, 2),
+    ("BG2CNT", REG_BG2CNT, 2),
+    ("BG3CNT", REG_BG3CNT, 2),
+    ("BG0HOFS", REG_BG0HOFS, 2),
+    ("BG0VOFS", REG_BG0VOFS, 2),
+    ("BG1HOFS", REG_BG1HOFS, 2),
+    ("BG1VOFS", REG_BG1VOFS, 2),
+    ("BG2HOFS", REG_BG2HOFS, 2),
+    ("BG2VOFS", REG_BG2VOFS, 2),
+    ("BG3HOFS", REG_BG3HOFS, 2),
+    ("BG3VOFS", REG_BG3VOFS, 2),
+    ("BG2PA", REG_BG2PA, 2),
+    ("BG2PB", REG_BG2PB, 2),
+    ("BG2PC", REG_BG2PC, 2),
+    ("BG2PD", REG_BG2PD, 2),
+    ("BG2X", REG_BG2X, 4),
+    ("BG2Y", REG_BG2Y, 4),
+    ("BG3PA", REG_BG3PA, 2),
+    ("BG3PB", REG_BG3PB, 2),
+    ("BG3PC", REG_BG3PC, 2),
+    ("BG3PD", REG_BG3PD, 2),
+    ("BG3X", REG_BG3X, 4),
+    ("BG3Y", REG_BG3Y, 4),
+    ("WIN0H", REG_WIN0H, 2),
+    ("WIN1H", REG_WIN1H, 2),
+    ("WIN0V", REG_WIN0V, 2),
+    ("WIN1V", REG_WIN1V, 2),
+    ("WININ", REG_WININ, 2),
+    ("WINOUT", REG_WINOUT, 2),
+    ("MOSAIC", REG_MOSAIC, 2),
+    ("BLDCNT", REG_BLDCNT, 2),
+    ("BLDALPHA", REG_BLDALPHA, 2),
+    ("BLDY", REG_BLDY, 2),
+    ("SOUND1CNT_L", REG_SOUND1CNT_L, 2),
+    ("SOUND1CNT_H", REG_SOUND1CNT_H, 2),
+    ("SOUND1CNT_X", REG_SOUND1CNT_X, 2),
+    ("SOUND2CNT_L", REG_SOUND2CNT_L, 2),
+    ("SOUND2CNT_H", REG_SOUND2CNT_H, 2),
+    ("SOUND3CNT_L", REG_SOUND3CNT_L, 2),
+    ("SOUND3CNT_H", REG_SOUND3CNT_H, 2),
+    ("SOUND3CNT_X", REG_SOUND3CNT_X, 2),
+    ("SOUND4CNT_L", REG_SOUND4CNT_L, 2),
+    ("SOUND4CNT_H", REG_SOUND4CNT_H, 2),
+    ("SOUNDCNT_L", REG_SOUNDCNT_L, 2),
+    ("SOUNDCNT_H", REG_SOUNDCNT_H, 2),
+    ("SOUNDCNT_X", REG_SOUNDCNT_X, 2),
+    ("SOUNDBIAS", REG_SOUNDBIAS, 2),
+    ("WAVE_RAM", REG_WAVE_RAM, 16),
+    ("FIFO_A", REG_FIFO_A, 4),
+    ("FIFO_B", REG_FIFO_B, 4),
+    ("DMA0SAD", REG_DMA0SAD, 4),
+    ("DMA0DAD", REG_DMA0DAD, 4),
+    ("DMA0CNT_L", REG_DMA0CNT_L, 2),
+    ("DMA0CNT_H", REG_DMA0CNT_H, 2),
+    ("DMA1SAD", REG_DMA1SAD, 4),
+    ("DMA1DAD", REG_DMA1DAD, 4),
+    ("DMA1CNT_L", REG_DMA1CNT_L, 2),
+    ("DMA1CNT_H", REG_DMA1CNT_H, 2),
+    ("DMA2SAD", REG_DMA2SAD, 4),
+    ("DMA2DAD", REG_DMA2DAD, 4),
+    ("DMA2CNT_L", REG_DMA2CNT_L, 2),
+    ("DMA2CNT_H", REG_DMA2CNT_H, 2),
+    ("DMA3SAD", REG_DMA3SAD, 4),
+    ("DMA3DAD", REG_DMA3DAD, 4),
+    ("DMA3CNT_L", REG_DMA3CNT_L, 2),
+    ("DMA3CNT_H", REG_DMA3CNT_H, 2),
+    ("TM0CNT_L", REG_TM0CNT_L, 2),
+    ("TM0CNT_H", REG_TM0CNT_H, 2),
+    ("TM1CNT_L", REG_TM1CNT_L, 2),
+    ("TM1CNT_H", REG_TM1CNT_H, 2),
+    ("TM2CNT_L", REG_TM2CNT_L, 2),
+    ("TM2CNT_H", REG_TM2CNT_H, 2),
+    ("TM3CNT_L", REG_TM3CNT_L, 2),
+    ("TM3CNT_H", REG_TM3CNT_H, 2),
+    ("SIODATA32", REG_SIODATA32, 4),
+    ("SIOMULTI0", REG_SIOMULTI0, 2),
+    ("SIOMULTI1", REG_SIOMULTI1, 2),
+    ("SIOMULTI2", REG_SIOMULTI2, 2),
+    ("SIOMULTI3", REG_SIOMULTI3, 2),
+    ("SIOCNT", REG_SIOCNT, 2),
+    ("SIOMLT_SEND", REG_SIOMLT_SEND, 2),
+    ("SIODATA8", REG_SIODATA8, 2),
+    ("KEYINPUT", REG_KEYINPUT, 2),
+    ("KEYCNT", REG_KEYCNT, 2),
+    ("RCNT", REG_RCNT, 2),
+    ("IR", REG_IR, 2),
+    ("JOYCNT", REG_JOYCNT, 2),
+    ("JOY_RECV", REG_JOY_RECV, 4),
+    ("JOY_TRANS", REG_JOY_TRANS, 4),
+    ("JOYSTAT", REG_JOYSTAT, 2),
+    ("IE", REG_IE, 2),
+    ("IF", REG_IF, 2),
+    ("WAITCNT", REG_WAITCNT, 2),
+    ("IME", REG_IME, 2),
+    ("POSTFLG", REG_POSTFLG, 1),
+    ("HALTCNT", REG_HALTCNT, 1),
+];
+
+/// Write-only registers by address and size. Reading them gives the open bus rather than what
+/// was written. The timer reloads aren't here, reading TMxCNT_L gives the counter instead.
+const WRITE_ONLY_REGISTERS: &[(Addr, usize)] = &[
+    // scrolling, rotation/scaling and the window dimensions, BG0HOFS up to WIN1V
+    (REG_BG0HOFS, 0x38),
+    (REG_MOSAIC, 2),
+    (REG_BLDY, 2),
+    (REG_FIFO_A, 4),
+    (REG_FIFO_B, 4),
+    // source, destination and word count, DMAxCNT_H is readable
+    (REG_DMA0SAD, 10),
+    (REG_DMA1SAD, 10),
+    (REG_DMA2SAD, 10),
+    (REG_DMA3SAD, 10),
+];
+
+/// Whether the register at `addr` can't be read back
+pub fn is_write_only(addr: Addr) -> bool {
+    WRITE_ONLY_REGISTERS
+        .iter()
+        .any(|&(reg, size)| addr >= reg && addr < reg + size as Addr)
+}
+
+/// Name of the register at `addr`, or of the register `addr` falls inside of
+pub fn register_name(addr: Addr) -> Option<&'static str> {
+    REGISTERS
+        .iter()
+        .find(|&&(_, reg, _)| reg == addr)
+        .or_else(|| {
+            REGISTERS
+                .iter()
+                .find(|&&(_, reg, size)| addr >= reg && addr < reg + size as Addr)
+        })
+        .map(|&(name, _, _)| name)
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct UnhandledAccesses {
+    pub reads: usize,
+    pub writes: usize,
+}
+
 #[derive(Debug)]
 pub struct IoRegs {
     bytes: Box<[u8]>,
+    pub timers: Timers,
+    pub wave_ram: WaveRam,
+    pub direct_sound: DirectSound,
+    pub intc: InterruptController,
+    log_unhandled: bool,
+    unhandled: RefCell<BTreeMap<Addr, UnhandledAccesses>>,
+    /// Set when the CPU writes the BG2 or BG3 reference point, see `take_bg_ref_write`
+    bg_ref_written: [bool; 2],
+    /// Set when the CPU writes a DMA control register, see `take_dma_write`
+    dma_written: bool,
 }
 
 impl Default for IoRegs {
     fn default() -> IoRegs {
         let mut ioregs = IoRegs {
             bytes: vec![0; 4096].into_boxed_slice(),
+            timers: Timers::new(),
+            wave_ram: WaveRam::new(),
+            direct_sound: DirectSound::new(),
+            intc: InterruptController::new(),
+            log_unhandled: false,
+            unhandled: RefCell::new(BTreeMap::new()),
+            bg_ref_written: [false; 2],
+            dma_written: false,
         };
 
         // init default values
         ioregs.write_reg(REG_DISPCNT, 0x0080);
+        // BG2 and BG3 aren't rotated or scaled
+        for &reg in [REG_BG2PA, REG_BG2PD, REG_BG3PA, REG_BG3PD].iter() {
+            ioregs.write_reg(reg, 0x0100);
+        }
 
         ioregs
     }
@@ -153,34 +427,157 @@ impl IoRegs {
             .write_u16::<LittleEndian>(value)
             .unwrap();
     }
+
+    /// Toggle tracking of CPU accesses to registers that the emulator doesn't implement
+    pub fn set_log_unhandled(&mut self, enabled: bool) {
+        self.log_unhandled = enabled;
+    }
+
+    pub fn log_unhandled(&self) -> bool {
+        self.log_unhandled
+    }
+
+    /// Access counts per unhandled register address, sorted by address
+    pub fn unhandled_accesses(&self) -> Vec<(Addr, UnhandledAccesses)> {
+        self.unhandled
+            .borrow()
+            .iter()
+            .map(|(addr, accesses)| (*addr, *accesses))
+            .collect()
+    }
+
+    pub fn unhandled_report(&self) -> String {
+        let mut report = String::new();
+        for (addr, accesses) in self.unhandled_accesses() {
+            report += &format!(
+                "unhandled I/O at {:#010x} ({} reads, {} writes)\n",
+                addr, accesses.reads, accesses.writes
+            );
+        }
+        report
+    }
+
+    pub fn reset_unhandled(&mut self) {
+        self.unhandled.borrow_mut().clear();
+    }
+
+    /// A CPU read, some registers don't read back what was written to them
+    fn read_io(&self, offset: Addr) -> u16 {
+        let addr = IO_BASE + offset;
+        let value = self
+            .timers
+            .read(addr)
+            .or_else(|| self.wave_ram.read(addr))
+            .or_else(|| self.intc.read(addr));
+        match value {
+            Some(value) => value,
+            None => self.read_reg(addr),
+        }
+    }
+
+    /// Whether BGxX or BGxY of `bg` (2 or 3) was written since the last call
+    pub fn take_bg_ref_write(&mut self, bg: usize) -> bool {
+        mem::replace(&mut self.bg_ref_written[bg - 2], false)
+    }
+
+    /// Whether a DMA control register was written since the last call, an immediate transfer
+    /// may have to start
+    pub fn take_dma_write(&mut self) -> bool {
+        mem::replace(&mut self.dma_written, false)
+    }
+
+    fn write_io(&mut self, offset: Addr, value: u16) {
+        match (IO_BASE + offset) & !1 {
+            REG_BG2X...0x0400_002f => self.bg_ref_written[0] = true,
+            REG_BG3X...0x0400_003f => self.bg_ref_written[1] = true,
+            REG_DMA0CNT_H | REG_DMA1CNT_H | REG_DMA2CNT_H | REG_DMA3CNT_H => {
+                self.dma_written = true
+            }
+            _ => {}
+        }
+        self.write_reg(IO_BASE + offset, value);
+        self.timers.write(IO_BASE + offset, value);
+        self.wave_ram.write(IO_BASE + offset, value);
+        self.direct_sound.write(IO_BASE + offset, value);
+        self.intc.write(IO_BASE + offset, value);
+    }
+
+    /// Track an access of `size` bytes at `offset` from the I/O base, in halfword units
+    fn track_access(&self, offset: Addr, size: u32, write: bool) {
+        if !self.log_unhandled {
+            return;
+        }
+        let mut unhandled = self.unhandled.borrow_mut();
+        let mut addr = IO_BASE + (offset & !1);
+        while addr < IO_BASE + offset + size {
+            if !HANDLED_REGISTERS.contains(&addr) {
+                let accesses = unhandled.entry(addr).or_default();
+                if write {
+                    accesses.writes += 1;
+                } else {
+                    accesses.reads += 1;
+                }
+            }
+            addr += 2;
+        }
+    }
 }
 
 impl Bus for IoRegs {
     fn read_32(&self, addr: Addr) -> u32 {
-        self.get_bytes(addr).read_u32::<LittleEndian>().unwrap()
+        self.track_access(addr, 4, false);
+        self.peek_32(addr)
     }
 
     fn read_16(&self, addr: Addr) -> u16 {
-        self.read_reg(IO_BASE + addr)
+        self.track_access(addr, 2, false);
+        self.peek_16(addr)
     }
 
     fn read_8(&self, addr: Addr) -> u8 {
-        self.read_reg(IO_BASE + addr) as u8
+        self.track_access(addr, 1, false);
+        self.peek_8(addr)
+    }
+
+    fn peek_32(&self, addr: Addr) -> u32 {
+        (self.read_io(addr) as u32) | (self.read_io(addr + 2) as u32) << 16
+    }
+
+    fn peek_16(&self, addr: Addr) -> u16 {
+        self.read_io(addr)
+    }
+
+    fn peek_8(&self, addr: Addr) -> u8 {
+        (self.read_io(addr & !1) >> (8 * (addr & 1))) as u8
     }
 
     fn write_32(&mut self, addr: Addr, value: u32) {
-        self.get_bytes_mut(addr)
-            .write_u32::<LittleEndian>(value)
-            .unwrap()
+        self.track_access(addr, 4, true);
+        self.write_io(addr, value as u16);
+        self.write_io(addr + 2, (value >> 16) as u16);
     }
 
     fn write_16(&mut self, addr: Addr, value: u16) {
-        self.write_reg(IO_BASE + addr, value);
+        self.track_access(addr, 2, true);
+        self.write_io(addr, value);
     }
 
     fn write_8(&mut self, addr: Addr, value: u8) {
-        let new_value = self.read_reg(IO_BASE + addr) & 0xff00 | (value as u16);
-        self.write_reg(IO_BASE + addr, new_value);
+        self.track_access(addr, 1, true);
+        if self.wave_ram.write_8(IO_BASE + addr, value)
+            || self.direct_sound.write_8(IO_BASE + addr, value)
+        {
+            return;
+        }
+        let aligned = addr & !1;
+        let shift = 8 * (addr & 1);
+        // the other byte is written back as it was, except for IF where that would acknowledge it
+        let old_value = if IO_BASE + aligned == REG_IF {
+            0
+        } else {
+            self.read_reg(IO_BASE + aligned) & !(0xff << shift)
+        };
+        self.write_io(aligned, old_value | (value as u16) << shift);
     }
 
     /// Return a slice of bytes
@@ -198,3 +595,62 @@ impl Bus for IoRegs {
         1
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_names() {
+        assert_eq!(register_name(0x0400_0000), Some("DISPCNT"));
+        assert_eq!(register_name(REG_KEYINPUT), Some("KEYINPUT"));
+        assert_eq!(register_name(0x0400_0202), Some("IF"));
+        assert_eq!(register_name(REG_TM3CNT_H), Some("TM3CNT_H"));
+        // inside a wider register
+        assert_eq!(register_name(REG_BG2X + 2), Some("BG2X"));
+        assert_eq!(register_name(REG_WAVE_RAM + 0xf), Some("WAVE_RAM"));
+        // aliases resolve by their exact address first
+        assert_eq!(register_name(REG_SIOMULTI1), Some("SIOMULTI1"));
+        assert_eq!(register_name(0x0400_004e), None);
+        assert_eq!(register_name(0x0400_0400), None);
+
+        assert!(REGISTERS
+            .iter()
+            .any(|&(name, addr, size)| name == "DMA3SAD" && addr == REG_DMA3SAD && size == 4));
+    }
+
+    #[test]
+    fn unhandled_report() {
+        let mut ioregs = IoRegs::default();
+
+        // not logged unless enabled
+        ioregs.read_16(REG_SOUNDCNT_L - IO_BASE);
+        assert!(ioregs.unhandled_accesses().is_empty());
+
+        ioregs.set_log_unhandled(true);
+        ioregs.read_16(REG_SOUNDCNT_L - IO_BASE);
+        ioregs.write_16(REG_SOUNDCNT_L - IO_BASE, 0);
+        ioregs.write_16(REG_SOUNDCNT_L - IO_BASE, 0);
+        // handled registers don't show up
+        ioregs.write_16(REG_DISPCNT - IO_BASE, 0);
+        ioregs.read_16(REG_KEYINPUT - IO_BASE);
+        // a word access covers two registers
+        ioregs.write_32(REG_SIOMULTI0 - IO_BASE, 0);
+
+        let accesses = |reads, writes| UnhandledAccesses { reads, writes };
+        assert_eq!(
+            ioregs.unhandled_accesses(),
+            vec![
+                (REG_SOUNDCNT_L, accesses(1, 2)),
+                (REG_SIOMULTI0, accesses(0, 1)),
+                (REG_SIOMULTI1, accesses(0, 1)),
+            ]
+        );
+        assert!(ioregs
+            .unhandled_report()
+            .contains("unhandled I/O at 0x04000080 (1 reads, 2 writes)"));
+
+        ioregs.reset_unhandled();
+        assert!(ioregs.unhandled_accesses().is_empty());
+    }
+}
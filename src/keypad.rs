@@ -0,0 +1,308 @@
+use std::collections::VecDeque;
+
+use super::ioregs::consts::*;
+use super::sysbus::SysBus;
+
+use crate::bit::BitIndex;
+use crate::num::FromPrimitive;
+
+#[derive(Debug, Primitive, Copy, Clone, PartialEq)]
+pub enum Keys {
+    ButtonA = 0,
+    ButtonB = 1,
+    Select = 2,
+    Start = 3,
+    Right = 4,
+    Left = 5,
+    Up = 6,
+    Down = 7,
+    ButtonR = 8,
+    ButtonL = 9,
+}
+
+pub const KEYINPUT_ALL_RELEASED: u16 = 0b11_1111_1111;
+
+/// The set of keys currently held down
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct KeypadState {
+    pressed: u16,
+}
+
+impl KeypadState {
+    pub fn new() -> KeypadState {
+        Default::default()
+    }
+
+    pub fn with(mut self, key: Keys) -> KeypadState {
+        self.set(key, true);
+        self
+    }
+
+    pub fn set(&mut self, key: Keys, pressed: bool) {
+        self.pressed.set_bit(key as usize, pressed);
+    }
+
+    pub fn is_pressed(&self, key: Keys) -> bool {
+        self.pressed.bit(key as usize)
+    }
+
+    /// The KEYINPUT register value for this state, keys are active low
+    pub fn keyinput(&self) -> u16 {
+        !self.pressed & KEYINPUT_ALL_RELEASED
+    }
+}
+
+/// How to resolve opposing d-pad directions held at the same time (Simultaneous Opposing
+/// Cardinal Directions), which a real d-pad can't produce.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SocdMode {
+    /// Pass both directions through to the game
+    AllowBoth,
+    /// Cancel out both directions
+    Neutral,
+    /// Only the most recently pressed direction is held
+    LastWins,
+}
+
+impl Default for SocdMode {
+    fn default() -> SocdMode {
+        SocdMode::AllowBoth
+    }
+}
+
+/// Keypad states scheduled by frame number, to play a game without a frontend.
+/// Each state holds until the next one.
+#[derive(Debug, Default, Clone)]
+pub struct InputScript {
+    events: VecDeque<(usize, KeypadState)>,
+}
+
+impl InputScript {
+    pub fn new(mut events: Vec<(usize, KeypadState)>) -> InputScript {
+        events.sort_by_key(|&(frame, _)| frame);
+        InputScript {
+            events: events.into(),
+        }
+    }
+
+    /// Consume the events due by `frame`, returns the latest of them
+    pub fn advance(&mut self, frame: usize) -> Option<KeypadState> {
+        let mut state = None;
+        while let Some(&(at, next)) = self.events.front() {
+            if at > frame {
+                break;
+            }
+            state = Some(next);
+            self.events.pop_front();
+        }
+        state
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Keypad {
+    state: KeypadState,
+    socd_mode: SocdMode,
+    /// `(from, to)` pairs, the key pressed on the left reaches the game as the one on the right
+    key_map: Vec<(Keys, Keys)>,
+    last_horizontal: Option<Keys>,
+    last_vertical: Option<Keys>,
+}
+
+impl Keypad {
+    pub fn new() -> Keypad {
+        Default::default()
+    }
+
+    pub fn set_socd_mode(&mut self, mode: SocdMode) {
+        self.socd_mode = mode;
+    }
+
+    pub fn socd_mode(&self) -> SocdMode {
+        self.socd_mode
+    }
+
+    /// Deliver keys to the game as other keys, e.g. to swap A and B. Each `(from, to)` pair
+    /// turns `from` into `to`, keys that aren't listed stay as they are.
+    pub fn set_key_map(&mut self, key_map: Vec<(Keys, Keys)>) {
+        self.key_map = key_map;
+    }
+
+    pub fn key_map(&self) -> &[(Keys, Keys)] {
+        &self.key_map
+    }
+
+    fn map_state(&self, state: KeypadState) -> KeypadState {
+        let mut mapped = KeypadState::new();
+        for key in (0..10).filter_map(Keys::from_usize) {
+            if state.is_pressed(key) {
+                let to = self.key_map.iter().find(|&&(from, _)| from == key);
+                mapped.set(to.map_or(key, |&(_, to)| to), true);
+            }
+        }
+        mapped
+    }
+
+    /// The keys as they were fed by the frontend, before the key map and SOCD resolution
+    pub fn state(&self) -> KeypadState {
+        self.state
+    }
+
+    pub fn set_state(&mut self, state: KeypadState) {
+        let previous = self.map_state(self.state);
+        let mapped = self.map_state(state);
+        let newly_pressed = |key| mapped.is_pressed(key) && !previous.is_pressed(key);
+        for &key in &[Keys::Left, Keys::Right] {
+            if newly_pressed(key) {
+                self.last_horizontal = Some(key);
+            }
+        }
+        for &key in &[Keys::Up, Keys::Down] {
+            if newly_pressed(key) {
+                self.last_vertical = Some(key);
+            }
+        }
+        self.state = state;
+    }
+
+    pub fn set_key(&mut self, key: Keys, pressed: bool) {
+        let mut state = self.state;
+        state.set(key, pressed);
+        self.set_state(state);
+    }
+
+    /// The state the game gets to see, after the key map and SOCD resolution
+    pub fn resolved_state(&self) -> KeypadState {
+        let mut state = self.map_state(self.state);
+        self.resolve_axis(&mut state, Keys::Left, Keys::Right, self.last_horizontal);
+        self.resolve_axis(&mut state, Keys::Up, Keys::Down, self.last_vertical);
+        state
+    }
+
+    fn resolve_axis(&self, state: &mut KeypadState, a: Keys, b: Keys, last: Option<Keys>) {
+        if !(state.is_pressed(a) && state.is_pressed(b)) {
+            return;
+        }
+        match self.socd_mode {
+            SocdMode::AllowBoth => {}
+            SocdMode::Neutral => {
+                state.set(a, false);
+                state.set(b, false);
+            }
+            SocdMode::LastWins => {
+                let winner = last.unwrap_or(b);
+                state.set(a, winner == a);
+                state.set(b, winner == b);
+            }
+        }
+    }
+
+    pub fn keyinput(&self) -> u16 {
+        self.resolved_state().keyinput()
+    }
+
+    /// Whether the keypad interrupt condition configured in KEYCNT is met
+    pub fn irq_condition(&self, keycnt: u16) -> bool {
+        if !keycnt.bit(14) {
+            return false;
+        }
+        let selected = keycnt & KEYINPUT_ALL_RELEASED;
+        let pressed = self.resolved_state().pressed & selected;
+        if keycnt.bit(15) {
+            selected != 0 && pressed == selected
+        } else {
+            pressed != 0
+        }
+    }
+
+    pub fn update_keyinput(&self, sysbus: &mut SysBus) {
+        sysbus.ioregs.write_reg(REG_KEYINPUT, self.keyinput());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIRECTIONS: u16 = 0b1111_0000;
+
+    fn left_then_right(mode: SocdMode) -> u16 {
+        let mut keypad = Keypad::new();
+        keypad.set_socd_mode(mode);
+        keypad.set_key(Keys::Left, true);
+        keypad.set_key(Keys::Right, true);
+        keypad.keyinput() & DIRECTIONS
+    }
+
+    #[test]
+    fn socd_allow_both() {
+        assert_eq!(left_then_right(SocdMode::AllowBoth), 0b1100_0000);
+    }
+
+    #[test]
+    fn socd_neutral() {
+        assert_eq!(left_then_right(SocdMode::Neutral), DIRECTIONS);
+    }
+
+    #[test]
+    fn socd_last_wins() {
+        assert_eq!(left_then_right(SocdMode::LastWins), 0b1110_0000);
+
+        let mut keypad = Keypad::new();
+        keypad.set_socd_mode(SocdMode::LastWins);
+        keypad.set_state(KeypadState::new().with(Keys::Right));
+        keypad.set_state(KeypadState::new().with(Keys::Right).with(Keys::Left));
+        assert_eq!(keypad.keyinput() & DIRECTIONS, 0b1101_0000);
+
+        // releasing the winner gives back the other direction
+        keypad.set_key(Keys::Left, false);
+        assert_eq!(keypad.keyinput() & DIRECTIONS, 0b1110_0000);
+    }
+
+    #[test]
+    fn key_map() {
+        let mut keypad = Keypad::new();
+        keypad.set_key_map(vec![
+            (Keys::ButtonA, Keys::ButtonB),
+            (Keys::ButtonB, Keys::ButtonA),
+        ]);
+        keypad.set_key(Keys::ButtonA, true);
+        assert!(keypad.state().is_pressed(Keys::ButtonA));
+        assert_eq!(
+            keypad.keyinput(),
+            KEYINPUT_ALL_RELEASED & !(1 << Keys::ButtonB as u16)
+        );
+
+        // mapped directions take part in SOCD resolution as what they're mapped to
+        keypad.set_socd_mode(SocdMode::LastWins);
+        keypad.set_key_map(vec![(Keys::Up, Keys::Left)]);
+        keypad.set_key(Keys::Right, true);
+        keypad.set_key(Keys::Up, true);
+        assert_eq!(keypad.keyinput() & DIRECTIONS, 0b1101_0000);
+    }
+
+    #[test]
+    fn irq_condition() {
+        let mut keypad = Keypad::new();
+        let start_or_select = (1 << 14) | (1 << Keys::Start as u16) | (1 << Keys::Select as u16);
+        let start_and_select = start_or_select | (1 << 15);
+
+        keypad.set_key(Keys::Start, true);
+        assert!(keypad.irq_condition(start_or_select));
+        assert!(!keypad.irq_condition(start_and_select));
+        // irq disabled
+        assert!(!keypad.irq_condition(start_or_select & !(1 << 14)));
+
+        keypad.set_key(Keys::Select, true);
+        assert!(keypad.irq_condition(start_and_select));
+    }
+
+    #[test]
+    fn default_released() {
+        assert_eq!(Keypad::new().keyinput(), KEYINPUT_ALL_RELEASED);
+    }
+}
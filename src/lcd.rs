@@ -3,6 +3,7 @@ use std::io::{Seek, SeekFrom};
 
 use super::arm7tdmi::{Addr, Bus};
 use super::ioregs::consts::*;
+use super::ioregs::IoRegs;
 use super::palette::{Palette, PixelFormat, Rgb15};
 use super::*;
 
@@ -11,9 +12,11 @@ use crate::byteorder::{LittleEndian, ReadBytesExt};
 use crate::num::FromPrimitive;
 
 const VRAM_ADDR: Addr = 0x0600_0000;
+const OBJ_VRAM_ADDR: Addr = 0x0601_0000;
+const OAM_ADDR: Addr = 0x0700_0000;
 
-#[derive(Debug, Primitive)]
-enum BGMode {
+#[derive(Debug, Primitive, Copy, Clone, PartialEq)]
+pub enum BGMode {
     BGMode0 = 0,
     BGMode1 = 1,
     BGMode2 = 2,
@@ -24,16 +27,16 @@ enum BGMode {
 
 #[derive(Debug)]
 pub struct DisplayControl {
-    bg_mode: BGMode,
-    display_frame: usize,
-    hblank_interval_free: bool,
-    obj_character_vram_mapping: bool, // true - 1 dimentional, false - 2 dimentional
-    forced_blank: bool,
-    disp_bg: [bool; 4],
-    disp_obj: bool,
-    disp_window0: bool,
-    disp_window1: bool,
-    disp_obj_window: bool,
+    pub bg_mode: BGMode,
+    pub display_frame: usize,
+    pub hblank_interval_free: bool,
+    pub obj_character_vram_mapping: bool, // true - 1 dimentional, false - 2 dimentional
+    pub forced_blank: bool,
+    pub disp_bg: [bool; 4],
+    pub disp_obj: bool,
+    pub disp_window0: bool,
+    pub disp_window1: bool,
+    pub disp_obj_window: bool,
 }
 
 impl From<u16> for DisplayControl {
@@ -54,16 +57,45 @@ impl From<u16> for DisplayControl {
     }
 }
 
+impl DisplayControl {
+    /// Address of the tile for the 8x8 cell (`cell_x`, `cell_y`) of a sprite `width` tiles wide,
+    /// starting at OBJ tile `tile_index`. Tile numbers count 32 byte units, so 256 color tiles
+    /// take two numbers each. With 1D mapping the sprite's tiles follow one another row after row,
+    /// with 2D mapping every row of cells starts 32 tile numbers after the previous one.
+    pub fn obj_tile_addr(
+        &self,
+        tile_index: u32,
+        cell_x: u32,
+        cell_y: u32,
+        width: u32,
+        palette256: bool,
+    ) -> Addr {
+        let tile_units = if palette256 { 2 } else { 1 };
+        let tile = if self.obj_character_vram_mapping {
+            tile_index + (cell_y * width + cell_x) * tile_units
+        } else {
+            // the lowest bit of the tile number is ignored in 256 color mode
+            let tile_index = if palette256 {
+                tile_index & !1
+            } else {
+                tile_index
+            };
+            tile_index + cell_y * 32 + cell_x * tile_units
+        };
+        OBJ_VRAM_ADDR + (tile % 1024) * Lcd::TILE_SIZE
+    }
+}
+
 #[derive(Debug)]
 pub struct DisplayStatus {
-    vblank_flag: bool,
-    hblank_flag: bool,
-    vcount_flag: bool,
-    vblank_irq_enable: bool,
-    hblank_irq_enable: bool,
-    vcount_irq_enable: bool,
-    vcount_setting: u8,
-    raw_value: u16,
+    pub vblank_flag: bool,
+    pub hblank_flag: bool,
+    pub vcount_flag: bool,
+    pub vblank_irq_enable: bool,
+    pub hblank_irq_enable: bool,
+    pub vcount_irq_enable: bool,
+    pub vcount_setting: u8,
+    pub raw_value: u16,
 }
 
 impl From<u16> for DisplayStatus {
@@ -84,14 +116,16 @@ impl From<u16> for DisplayStatus {
 
 #[derive(Debug)]
 pub struct BgControl {
-    bg_priority: u8,
-    character_base_block: u8,
-    moasic: bool,
-    palette256: bool, // 0=16/16, 1=256/1)
-    screen_base_block: u8,
-    wraparound: bool,
-    screen_width: usize,
-    screen_height: usize,
+    pub bg_priority: u8,
+    pub character_base_block: u8,
+    pub mosaic: bool,
+    pub palette256: bool, // 0=16/16, 1=256/1)
+    pub screen_base_block: u8,
+    pub wraparound: bool,
+    pub screen_width: usize,
+    pub screen_height: usize,
+    /// Width and height in pixels as a rotation/scaling background, which has its own sizes
+    pub affine_size: u32,
 }
 
 impl From<u16> for BgControl {
@@ -106,12 +140,13 @@ impl From<u16> for BgControl {
         BgControl {
             bg_priority: v.bit_range(0..2) as u8,
             character_base_block: v.bit_range(2..4) as u8,
-            moasic: v.bit(6),
+            mosaic: v.bit(6),
             palette256: v.bit(7),
             screen_base_block: v.bit_range(8..13) as u8,
             wraparound: v.bit(13),
             screen_width: width,
             screen_height: height,
+            affine_size: 128 << v.bit_range(14..16),
         }
     }
 }
@@ -125,6 +160,16 @@ impl BgControl {
         VRAM_ADDR + (self.screen_base_block as u32) * 0x800
     }
 
+    /// Address of the tilemap entry for the tile at (`tile_x`, `tile_y`) in the background.
+    /// Backgrounds wider or taller than 256 pixels span several 32x32 screenblocks, laid out as
+    /// SB0 SB1 horizontally and SB0 / SB1 vertically, or SB0 SB1 / SB2 SB3 for 512x512.
+    pub fn tile_map_addr(&self, tile_x: u32, tile_y: u32) -> Addr {
+        let blocks_per_row = (self.screen_width / 256) as u32;
+        let block = (tile_y / 32) * blocks_per_row + tile_x / 32;
+        let entry = (tile_y % 32) * 32 + tile_x % 32;
+        self.screen_block() + block * 0x800 + entry * 2
+    }
+
     pub fn tile_format(&self) -> (u32, PixelFormat) {
         if self.palette256 {
             (2 * Lcd::TILE_SIZE, PixelFormat::BPP8)
@@ -134,6 +179,199 @@ impl BgControl {
     }
 }
 
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct BgAffine {
+    pub pa: i16,
+    pub pb: i16,
+    pub pc: i16,
+    pub pd: i16,
+    /// reference point, 20.8 fixed-point
+    pub x: i32,
+    pub y: i32,
+}
+
+/// Cell sizes of the mosaic effect in pixels, (width, height)
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Mosaic {
+    pub bg: (usize, usize),
+    pub obj: (usize, usize),
+}
+
+impl From<u16> for Mosaic {
+    fn from(v: u16) -> Self {
+        let size = |bits: std::ops::Range<usize>| v.bit_range(bits) as usize + 1;
+        Mosaic {
+            bg: (size(0..4), size(4..8)),
+            obj: (size(8..12), size(12..16)),
+        }
+    }
+}
+
+impl Mosaic {
+    /// Horizontal mosaic, every cell of `width` pixels takes the color of its leftmost pixel
+    fn apply_line<T: Copy>(line: &mut [T], width: usize) {
+        for cell in line.chunks_mut(width) {
+            let first = cell[0];
+            for pixel in cell.iter_mut() {
+                *pixel = first;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct WindowBounds {
+    pub left: u8,
+    pub right: u8,
+    pub top: u8,
+    pub bottom: u8,
+}
+
+impl WindowBounds {
+    fn from_regs(h: u16, v: u16) -> WindowBounds {
+        WindowBounds {
+            left: h.bit_range(8..16) as u8,
+            right: h.bit_range(0..8) as u8,
+            top: v.bit_range(8..16) as u8,
+            bottom: v.bit_range(0..8) as u8,
+        }
+    }
+
+    /// Whether the dot (`x`, `y`) is inside. The right and bottom edges are exclusive, when
+    /// they come before the left or top edge the window wraps around the screen.
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        let within = |pos: usize, start: u8, end: u8| {
+            let (start, end) = (start as usize, end as usize);
+            if start <= end {
+                pos >= start && pos < end
+            } else {
+                pos >= start || pos < end
+            }
+        };
+        within(x, self.left, self.right) && within(y, self.top, self.bottom)
+    }
+}
+
+/// Layers enabled inside a window region (6 bits of WININ/WINOUT)
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct WindowControl {
+    pub bg: [bool; 4],
+    pub obj: bool,
+    pub sfx: bool,
+}
+
+impl From<u16> for WindowControl {
+    fn from(v: u16) -> Self {
+        WindowControl {
+            bg: [v.bit(0), v.bit(1), v.bit(2), v.bit(3)],
+            obj: v.bit(4),
+            sfx: v.bit(5),
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct WindowState {
+    pub win0: WindowBounds,
+    pub win1: WindowBounds,
+    pub win0_control: WindowControl,
+    pub win1_control: WindowControl,
+    pub outside_control: WindowControl,
+    pub objwin_control: WindowControl,
+}
+
+impl WindowState {
+    fn from_regs(ioregs: &IoRegs) -> WindowState {
+        let winin = ioregs.read_reg(REG_WININ);
+        let winout = ioregs.read_reg(REG_WINOUT);
+        WindowState {
+            win0: WindowBounds::from_regs(ioregs.read_reg(REG_WIN0H), ioregs.read_reg(REG_WIN0V)),
+            win1: WindowBounds::from_regs(ioregs.read_reg(REG_WIN1H), ioregs.read_reg(REG_WIN1V)),
+            win0_control: WindowControl::from(winin.bit_range(0..6)),
+            win1_control: WindowControl::from(winin.bit_range(8..14)),
+            outside_control: WindowControl::from(winout.bit_range(0..6)),
+            objwin_control: WindowControl::from(winout.bit_range(8..14)),
+        }
+    }
+}
+
+#[derive(Debug, Primitive, Copy, Clone, PartialEq)]
+pub enum BlendMode {
+    BldNone = 0,
+    BldAlpha = 1,
+    BldWhite = 2,
+    BldBlack = 3,
+}
+
+/// Blend target layers (6 bits of BLDCNT)
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct BlendTargets {
+    pub bg: [bool; 4],
+    pub obj: bool,
+    pub backdrop: bool,
+}
+
+impl From<u16> for BlendTargets {
+    fn from(v: u16) -> Self {
+        BlendTargets {
+            bg: [v.bit(0), v.bit(1), v.bit(2), v.bit(3)],
+            obj: v.bit(4),
+            backdrop: v.bit(5),
+        }
+    }
+}
+
+impl BlendTargets {
+    pub fn contains(&self, layer: Layer) -> bool {
+        match layer {
+            Layer::Bg(bg) => self.bg[bg],
+            Layer::Obj => self.obj,
+            Layer::Backdrop => self.backdrop,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BlendState {
+    pub mode: BlendMode,
+    pub target1: BlendTargets,
+    pub target2: BlendTargets,
+    pub eva: u8,
+    pub evb: u8,
+    pub evy: u8,
+}
+
+impl BlendState {
+    fn from_regs(ioregs: &IoRegs) -> BlendState {
+        let bldcnt = ioregs.read_reg(REG_BLDCNT);
+        let bldalpha = ioregs.read_reg(REG_BLDALPHA);
+        BlendState {
+            mode: BlendMode::from_u16(bldcnt.bit_range(6..8)).unwrap(),
+            target1: BlendTargets::from(bldcnt.bit_range(0..6)),
+            target2: BlendTargets::from(bldcnt.bit_range(8..14)),
+            eva: bldalpha.bit_range(0..5) as u8,
+            evb: bldalpha.bit_range(8..13) as u8,
+            evy: ioregs.read_reg(REG_BLDY).bit_range(0..5) as u8,
+        }
+    }
+}
+
+/// Decoded snapshot of the LCD registers, for graphics debugging
+#[derive(Debug)]
+pub struct GraphicsState {
+    pub dispcnt: DisplayControl,
+    pub dispstat: DisplayStatus,
+    pub vcount: usize,
+    pub bgcnt: [BgControl; 4],
+    /// (hofs, vofs) for each background
+    pub bgofs: [(u32, u32); 4],
+    /// BG2 and BG3 rotation/scaling parameters
+    pub bg_affine: [BgAffine; 2],
+    pub window: WindowState,
+    pub blend: BlendState,
+    pub mosaic: Mosaic,
+}
+
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum LcdState {
     HDraw = 0,
@@ -147,11 +385,53 @@ impl Default for LcdState {
 }
 use LcdState::*;
 
+/// Where the color of a pixel comes from
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Layer {
+    Bg(usize),
+    Obj,
+    Backdrop,
+}
+
+/// The sprite pixel on top at a column of the current line
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct ObjPixel {
+    color: Rgb15,
+    priority: u8,
+    /// Alpha blended with what's below whatever BLDCNT says
+    semi_transparent: bool,
+}
+
 pub struct Lcd {
     cycles: usize,
     pub pixeldata: [Rgb15; 256 * 256],
+    /// The current line of each background, `None` where it's transparent
+    bg_line: [[Option<Rgb15>; Lcd::DISPLAY_WIDTH]; 4],
     pub state: LcdState,
     pub current_scanline: usize, // VCOUNT
+    forced_mode: Option<u8>,
+    /// Display the 160x128 mode 5 bitmap in the middle of the screen instead of the top-left
+    mode5_centered: bool,
+    /// Which pixels of the current line came from the backdrop
+    backdrop_line: [bool; Lcd::DISPLAY_WIDTH],
+    /// Which pixels of the current line are inside the OBJ window
+    obj_window_line: [bool; Lcd::DISPLAY_WIDTH],
+    /// Where the window of the pixel lets color special effects through
+    sfx_line: [bool; Lcd::DISPLAY_WIDTH],
+    /// The sprites of the current line, `None` where there's none or it's transparent
+    obj_line: [Option<ObjPixel>; Lcd::DISPLAY_WIDTH],
+    /// Output color for backdrop pixels, e.g. a chroma key
+    backdrop_override: Option<Rgb15>,
+    /// Whether `framebuffer_rgb24` approximates the colors of the GBA screen
+    color_correction: bool,
+    /// Keep the timing and registers going without drawing, for frame skipping
+    skip_rendering: bool,
+    /// The BG2 and BG3 reference points of the current line, (x, y) in 20.8 fixed point.
+    /// Reloaded from BGxX/BGxY at the start of a frame and when those are written, moved by
+    /// (PB, PD) after each line otherwise.
+    bg_ref: [(i32, i32); 2],
+    /// See `take_vcount_irq`
+    vcount_irq: bool,
 }
 
 impl Lcd {
@@ -164,16 +444,163 @@ impl Lcd {
     pub const CYCLES_SCANLINE: usize = 1232;
     pub const CYCLES_VDRAW: usize = 197120;
     pub const CYCLES_VBLANK: usize = 83776;
+    /// Lines in a frame including VBlank, VCOUNT goes up to one less
+    pub const SCANLINES: usize = 228;
 
     pub const TILE_SIZE: u32 = 0x20;
 
+    pub const MODE5_WIDTH: usize = 160;
+    pub const MODE5_HEIGHT: usize = 128;
+
+    /// Width to height of a dot, applied by `scaled_framebuffer` when correcting the aspect
+    pub const PIXEL_ASPECT: (usize, usize) = (10, 9);
+
     pub fn new() -> Lcd {
         Lcd {
             state: HDraw,
             current_scanline: 0,
             cycles: 0,
             pixeldata: [Rgb15::from(0); 256 * 256],
+            bg_line: [[None; Self::DISPLAY_WIDTH]; 4],
+            forced_mode: None,
+            mode5_centered: false,
+            backdrop_line: [false; Self::DISPLAY_WIDTH],
+            obj_window_line: [false; Self::DISPLAY_WIDTH],
+            sfx_line: [true; Self::DISPLAY_WIDTH],
+            obj_line: [None; Self::DISPLAY_WIDTH],
+            backdrop_override: None,
+            color_correction: false,
+            skip_rendering: false,
+            bg_ref: [(0, 0); 2],
+            vcount_irq: false,
+        }
+    }
+
+    /// Back to the power-on state, keeping the debug options
+    pub fn reset(&mut self) {
+        *self = Lcd {
+            forced_mode: self.forced_mode,
+            mode5_centered: self.mode5_centered,
+            backdrop_override: self.backdrop_override,
+            color_correction: self.color_correction,
+            ..Lcd::new()
+        };
+    }
+
+    /// Debug override for the DISPCNT mode field, used by the renderer instead of what the game wrote.
+    /// DISPCNT itself is left untouched. Pass `None`, or a mode above 5, to go back to the game's mode.
+    pub fn force_mode(&mut self, mode: Option<u8>) {
+        self.forced_mode = mode.filter(|&mode| mode <= 5);
+    }
+
+    /// Whether scanlines are drawn, the previous frame stays in `pixeldata` while they aren't
+    pub fn set_rendering(&mut self, enabled: bool) {
+        self.skip_rendering = !enabled;
+    }
+
+    /// Not what the hardware does, but makes small mode 5 games easier on the eyes
+    pub fn set_mode5_centered(&mut self, centered: bool) {
+        self.mode5_centered = centered;
+    }
+
+    /// Replace backdrop pixels in the output with `color`, for chroma keying the screen over
+    /// something else. Only `pixeldata` is affected, the palette is left untouched.
+    pub fn set_backdrop_override(&mut self, color: Option<Rgb15>) {
+        self.backdrop_override = color;
+    }
+
+    /// Convert to what the colors look like on the GBA screen in `framebuffer_rgb24`, see
+    /// `Rgb15::get_rgb24_corrected`. Off by default.
+    pub fn set_color_correction(&mut self, enabled: bool) {
+        self.color_correction = enabled;
+    }
+
+    pub fn color_correction(&self) -> bool {
+        self.color_correction
+    }
+
+    /// The visible `DISPLAY_WIDTH`x`DISPLAY_HEIGHT` pixels in 24-bit color, row by row,
+    /// color corrected if enabled
+    pub fn framebuffer_rgb24(&self) -> Vec<(u8, u8, u8)> {
+        let mut buffer = Vec::with_capacity(Self::DISPLAY_WIDTH * Self::DISPLAY_HEIGHT);
+        for y in 0..Self::DISPLAY_HEIGHT {
+            let line = &self.pixeldata[y * 256..y * 256 + Self::DISPLAY_WIDTH];
+            buffer.extend(line.iter().map(|pixel| {
+                if self.color_correction {
+                    pixel.get_rgb24_corrected()
+                } else {
+                    pixel.get_rgb24()
+                }
+            }));
+        }
+        buffer
+    }
+
+    /// The emulated colors before any conversion, rows are 256 entries apart with the visible
+    /// `DISPLAY_WIDTH` pixels at the start of each.
+    pub fn framebuffer_rgb15(&self) -> &[Rgb15] {
+        &self.pixeldata
+    }
+
+    /// The screen scaled by the largest integer factor that fits `target_width`x`target_height`,
+    /// at least 1. With `aspect_correct` each dot is also made `PIXEL_ASPECT` wide, rounded down
+    /// to whole pixels. Returns the buffer, `Lcd::DISPLAY_HEIGHT * scale` rows, and the scale.
+    pub fn scaled_framebuffer(
+        &self,
+        target_width: usize,
+        target_height: usize,
+        aspect_correct: bool,
+    ) -> (Vec<Rgb15>, usize) {
+        let dot_width = |scale: usize| {
+            if aspect_correct {
+                scale * Self::PIXEL_ASPECT.0 / Self::PIXEL_ASPECT.1
+            } else {
+                scale
+            }
+        };
+        let mut scale = target_height / Self::DISPLAY_HEIGHT;
+        while scale > 1 && Self::DISPLAY_WIDTH * dot_width(scale) > target_width {
+            scale -= 1;
+        }
+        let scale = scale.max(1);
+        let dot_width = dot_width(scale);
+
+        let width = Self::DISPLAY_WIDTH * dot_width;
+        let mut buffer = Vec::with_capacity(width * Self::DISPLAY_HEIGHT * scale);
+        for y in 0..Self::DISPLAY_HEIGHT {
+            let line = &self.pixeldata[y * 256..y * 256 + Self::DISPLAY_WIDTH];
+            for _ in 0..scale {
+                for &pixel in line {
+                    buffer.resize(buffer.len() + dot_width, pixel);
+                }
+            }
         }
+        (buffer, scale)
+    }
+
+    /// Cycles until the LCD moves on to its next state, and VCOUNT or DISPSTAT change
+    pub fn cycles_to_next_event(&self) -> usize {
+        let state_cycles = match self.state {
+            HDraw => Lcd::CYCLES_HDRAW,
+            HBlank => Lcd::CYCLES_HBLANK,
+            VBlank => Lcd::CYCLES_SCANLINE,
+        };
+        state_cycles.saturating_sub(self.cycles).max(1)
+    }
+
+    /// Compare the line just started with the VCOUNT setting of DISPSTAT, a match sets the
+    /// VCOUNT flag for the line and raises the VCOUNT interrupt if it's enabled
+    fn match_vcount(&mut self, dispstat: &mut DisplayStatus) {
+        dispstat.vcount_flag = dispstat.vcount_setting as usize == self.current_scanline;
+        if dispstat.vcount_flag && dispstat.vcount_irq_enable {
+            self.vcount_irq = true;
+        }
+    }
+
+    /// Whether the VCOUNT interrupt was raised since the last call. A line can start VBlank
+    /// and match VCOUNT at once, so it doesn't come with the interrupt `step` returns.
+    pub fn take_vcount_irq(&mut self) -> bool {
+        std::mem::replace(&mut self.vcount_irq, false)
     }
 
     fn palette(&self, sysbus: &SysBus) -> Palette {
@@ -181,6 +608,9 @@ impl Lcd {
     }
 
     fn update_regs(&self, dispstat: DisplayStatus, sysbus: &mut SysBus) {
+        sysbus
+            .ioregs
+            .write_reg(REG_VCOUNT, self.current_scanline as u16);
         let mut v = dispstat.raw_value;
         v.set_bit(0, dispstat.vblank_flag);
         v.set_bit(1, dispstat.hblank_flag);
@@ -231,6 +661,78 @@ impl Lcd {
         (hofs, vofs)
     }
 
+    fn bg_affine(&self, bg: u32, sysbus: &SysBus) -> BgAffine {
+        let base = REG_BG2PA + 0x10 * (bg - 2);
+        let read_i16 = |offset| sysbus.ioregs.read_reg(base + offset) as i16;
+        let read_ref = |offset| {
+            let lo = sysbus.ioregs.read_reg(base + offset) as u32;
+            let hi = sysbus.ioregs.read_reg(base + offset + 2) as u32;
+            // 28 bit signed
+            (((hi << 16) | lo) << 4) as i32 >> 4
+        };
+        BgAffine {
+            pa: read_i16(0),
+            pb: read_i16(2),
+            pc: read_i16(4),
+            pd: read_i16(6),
+            x: read_ref(8),
+            y: read_ref(12),
+        }
+    }
+
+    fn reload_bg_ref(&mut self, bg: usize, sysbus: &SysBus) {
+        let affine = self.bg_affine(bg as u32, sysbus);
+        self.bg_ref[bg - 2] = (affine.x, affine.y);
+    }
+
+    fn reload_bg_refs(&mut self, sysbus: &SysBus) {
+        self.reload_bg_ref(2, sysbus);
+        self.reload_bg_ref(3, sysbus);
+    }
+
+    /// Pick up reference points the CPU wrote since the last line
+    fn latch_bg_refs(&mut self, sysbus: &mut SysBus) {
+        for bg in 2..4 {
+            if sysbus.ioregs.take_bg_ref_write(bg) {
+                self.reload_bg_ref(bg, sysbus);
+            }
+        }
+    }
+
+    fn advance_bg_refs(&mut self, sysbus: &SysBus) {
+        for bg in 2..4 {
+            let affine = self.bg_affine(bg as u32, sysbus);
+            let (x, y) = self.bg_ref[bg - 2];
+            self.bg_ref[bg - 2] = (x + affine.pb as i32, y + affine.pd as i32);
+        }
+    }
+
+    pub fn graphics_state(&self, sysbus: &SysBus) -> GraphicsState {
+        let ioregs = &sysbus.ioregs;
+
+        GraphicsState {
+            dispcnt: DisplayControl::from(ioregs.read_reg(REG_DISPCNT)),
+            dispstat: DisplayStatus::from(ioregs.read_reg(REG_DISPSTAT)),
+            vcount: self.current_scanline,
+            bgcnt: [
+                self.bgcnt(0, sysbus),
+                self.bgcnt(1, sysbus),
+                self.bgcnt(2, sysbus),
+                self.bgcnt(3, sysbus),
+            ],
+            bgofs: [
+                self.bgofs(0, sysbus),
+                self.bgofs(1, sysbus),
+                self.bgofs(2, sysbus),
+                self.bgofs(3, sysbus),
+            ],
+            bg_affine: [self.bg_affine(2, sysbus), self.bg_affine(3, sysbus)],
+            window: WindowState::from_regs(ioregs),
+            blend: BlendState::from_regs(ioregs),
+            mosaic: Mosaic::from(ioregs.read_reg(REG_MOSAIC)),
+        }
+    }
+
     /// helper method that reads the palette index from a base address and x + y
     pub fn read_pixel_index(
         &self,
@@ -243,108 +745,571 @@ impl Lcd {
     ) -> usize {
         match format {
             PixelFormat::BPP4 => {
-                let byte = sysbus.read_8(addr + width * y + x / 2);
+                let byte = sysbus.peek_8(addr + width * y + x / 2);
                 if x & 1 != 0 {
                     (byte >> 4) as usize
                 } else {
                     (byte & 0xf) as usize
                 }
             }
-            PixelFormat::BPP8 => sysbus.read_8(addr + width * y + x) as usize,
+            PixelFormat::BPP8 => sysbus.peek_8(addr + width * y + x) as usize,
         }
     }
 
     pub fn get_palette_color(&self, sysbus: &SysBus, index: u32, palette_index: u32) -> Rgb15 {
         sysbus
-            .read_16(0x0500_0000 + 2 * index + 0x20 * palette_index)
+            .peek_16(0x0500_0000 + 2 * index + 0x20 * palette_index)
             .into()
     }
 
     fn scanline_mode0(&mut self, bg: u32, sysbus: &mut SysBus) {
         let bgcnt = self.bgcnt(bg, sysbus);
+        let (hofs, vofs) = self.bgofs(bg, sysbus);
         let tileset_base = bgcnt.char_block();
-        let tilemap_base = bgcnt.screen_block();
         let (tile_size, pixel_format) = bgcnt.tile_format();
+        let tile_row_width = tile_size / 8;
+        let mosaic = Mosaic::from(sysbus.ioregs.read_reg(REG_MOSAIC));
 
-        let tiles_per_row = bgcnt.screen_width / 8;
+        // every line of a mosaic cell repeats its first line
+        let mut py = self.current_scanline;
+        if bgcnt.mosaic {
+            py -= py % mosaic.bg.1;
+        }
+        let bg_y = (py as u32 + vofs) % (bgcnt.screen_height as u32);
+        let tile_y = bg_y % 8;
 
-        let mut px = 0;
-        let py = self.current_scanline;
-        let tile_y = py % 8;
+        for pixel in self.bg_line[bg as usize].iter_mut() {
+            *pixel = None;
+        }
+
+        // the last tile row looked at, and whether all of its pixels are transparent
+        let mut row_cache: Option<(Addr, bool)> = None;
 
-        for tile in 0..tiles_per_row {
-            let tile_y = py % 8;
+        let mut px = 0;
+        while px < Self::DISPLAY_WIDTH {
+            let bg_x = (px as u32 + hofs) % (bgcnt.screen_width as u32);
+            let tile_x = bg_x % 8;
 
-            let map_addr = tilemap_base + (tile as u32) * 2;
-            let entry = TileMapEntry::from(sysbus.read_16(map_addr));
+            let map_addr = bgcnt.tile_map_addr(bg_x / 8, bg_y / 8);
+            let entry = TileMapEntry::from(sysbus.peek_16(map_addr));
             let tile_addr = tileset_base + entry.tile_index * tile_size;
+            let row = if entry.y_flip { 7 - tile_y } else { tile_y };
 
-            for tile_x in 0..=7 {
-                let color = match pixel_format {
-                    PixelFormat::BPP4 => {
-                        let index = self.read_pixel_index(
-                            sysbus,
-                            tile_addr,
-                            tile_x,
-                            tile_y as u32,
-                            4,
-                            pixel_format,
-                        );
-                        self.get_palette_color(sysbus, index as u32, entry.palette_bank as u32)
-                    }
-                    PixelFormat::BPP8 => {
-                        let index = self.read_pixel_index(
-                            sysbus,
-                            tile_addr,
-                            tile_x,
-                            tile_y as u32,
-                            8,
-                            pixel_format,
-                        );
-                        self.get_palette_color(sysbus, index as u32, 0)
-                    }
+            // fast path, skip the rest of a tile row that has nothing to draw
+            let row_addr = tile_addr + tile_row_width * row;
+            let transparent_row = match row_cache {
+                Some((addr, transparent)) if addr == row_addr => transparent,
+                _ => {
+                    let transparent = Self::is_transparent_row(sysbus, row_addr, tile_row_width);
+                    row_cache = Some((row_addr, transparent));
+                    transparent
+                }
+            };
+            if transparent_row {
+                px += (8 - tile_x) as usize;
+                continue;
+            }
+
+            let column = if entry.x_flip { 7 - tile_x } else { tile_x };
+            let index =
+                self.read_pixel_index(sysbus, tile_addr, column, row, tile_row_width, pixel_format);
+            // color 0 is transparent
+            if index != 0 {
+                let palette_bank = match pixel_format {
+                    PixelFormat::BPP4 => entry.palette_bank as u32,
+                    PixelFormat::BPP8 => 0,
                 };
-                self.pixeldata[((px + tile_x) as usize) + py * 256] = color;
+                self.bg_line[bg as usize][px] =
+                    Some(self.get_palette_color(sysbus, index as u32, palette_bank));
+            }
+            px += 1;
+        }
+        if bgcnt.mosaic {
+            Mosaic::apply_line(&mut self.bg_line[bg as usize], mosaic.bg.0);
+        }
+    }
+
+    /// A rotation/scaling background, its map is a byte per tile and the tiles are 256 colors
+    fn scanline_affine(&mut self, bg: u32, sysbus: &mut SysBus) {
+        let bgcnt = self.bgcnt(bg, sysbus);
+        let affine = self.bg_affine(bg, sysbus);
+        let mosaic = Mosaic::from(sysbus.ioregs.read_reg(REG_MOSAIC));
+        let (ref_x, ref_y) = self.mosaic_bg_ref(bg, &bgcnt, &affine, &mosaic);
+        let size = bgcnt.affine_size as i32;
+        let map_width = bgcnt.affine_size / 8;
+
+        for px in 0..Self::DISPLAY_WIDTH {
+            let mut tx = (ref_x + (affine.pa as i32) * px as i32) >> 8;
+            let mut ty = (ref_y + (affine.pc as i32) * px as i32) >> 8;
+            self.bg_line[bg as usize][px] = None;
+            if bgcnt.wraparound {
+                tx = tx.rem_euclid(size);
+                ty = ty.rem_euclid(size);
+            } else if !(0..size).contains(&tx) || !(0..size).contains(&ty) {
+                continue;
             }
-            px += 8;
-            if px == bgcnt.screen_width as u32 {
-                return;
+            let (tx, ty) = (tx as u32, ty as u32);
+
+            let map_addr = bgcnt.screen_block() + (ty / 8) * map_width + tx / 8;
+            let tile_addr =
+                bgcnt.char_block() + sysbus.peek_8(map_addr) as u32 * 2 * Self::TILE_SIZE;
+            let index =
+                self.read_pixel_index(sysbus, tile_addr, tx % 8, ty % 8, 8, PixelFormat::BPP8);
+            // color 0 is transparent
+            if index != 0 {
+                self.bg_line[bg as usize][px] =
+                    Some(self.get_palette_color(sysbus, index as u32, 0));
             }
         }
+        if bgcnt.mosaic {
+            Mosaic::apply_line(&mut self.bg_line[bg as usize], mosaic.bg.0);
+        }
+    }
+
+    /// The reference point of BG2 or BG3 for the current line. With mosaic on, every line of a
+    /// cell is drawn from the reference point of the cell's first line.
+    fn mosaic_bg_ref(
+        &self,
+        bg: u32,
+        bgcnt: &BgControl,
+        affine: &BgAffine,
+        mosaic: &Mosaic,
+    ) -> (i32, i32) {
+        let (ref_x, ref_y) = self.bg_ref[bg as usize - 2];
+        if !bgcnt.mosaic {
+            return (ref_x, ref_y);
+        }
+        let lines = (self.current_scanline % mosaic.bg.1) as i32;
+        (
+            ref_x - affine.pb as i32 * lines,
+            ref_y - affine.pd as i32 * lines,
+        )
+    }
+
+    /// Whether a row of tile pixel data is all palette index 0
+    fn is_transparent_row(sysbus: &SysBus, row_addr: Addr, row_width: u32) -> bool {
+        (0..row_width)
+            .step_by(4)
+            .all(|ofs| sysbus.peek_32(row_addr + ofs) == 0)
+    }
+
+    fn obj_attrs(&self, index: u32, sysbus: &SysBus) -> ObjAttrs {
+        let addr = OAM_ADDR + 8 * index;
+        ObjAttrs::from_attrs(
+            sysbus.peek_16(addr),
+            sysbus.peek_16(addr + 2),
+            sysbus.peek_16(addr + 4),
+        )
+    }
+
+    /// Rotation/scaling parameters of an affine sprite, interleaved with the attributes in OAM
+    fn obj_affine(&self, index: u32, sysbus: &SysBus) -> BgAffine {
+        let read_i16 = |param: u32| sysbus.peek_16(OAM_ADDR + 32 * index + 8 * param + 6) as i16;
+        BgAffine {
+            pa: read_i16(0),
+            pb: read_i16(1),
+            pc: read_i16(2),
+            pd: read_i16(3),
+            ..Default::default()
+        }
     }
 
-    fn scanline_mode4(&mut self, bg: u32, dispcnt: &DisplayControl, sysbus: &mut SysBus) {
-        let page: u32 = match dispcnt.display_frame {
-            0 => 0x0600_0000,
-            1 => 0x0600_a000,
-            _ => unreachable!()
+    /// Palette index of the pixel (`x`, `y`) within the sprite's bounds, 0 where it's transparent
+    fn obj_pixel_index(
+        &self,
+        obj: &ObjAttrs,
+        dispcnt: &DisplayControl,
+        x: u32,
+        y: u32,
+        sysbus: &SysBus,
+    ) -> usize {
+        let (tex_x, tex_y) = if obj.affine {
+            // the parameters map from the center of the bounds to the center of the sprite
+            let affine = self.obj_affine(obj.affine_index, sysbus);
+            let (bounds_w, bounds_h) = obj.bounds();
+            let dx = x as i32 - bounds_w as i32 / 2;
+            let dy = y as i32 - bounds_h as i32 / 2;
+            let tex_x =
+                ((affine.pa as i32 * dx + affine.pb as i32 * dy) >> 8) + obj.width as i32 / 2;
+            let tex_y =
+                ((affine.pc as i32 * dx + affine.pd as i32 * dy) >> 8) + obj.height as i32 / 2;
+            if tex_x < 0 || tex_y < 0 || tex_x >= obj.width as i32 || tex_y >= obj.height as i32 {
+                return 0;
+            }
+            (tex_x as u32, tex_y as u32)
+        } else {
+            (
+                if obj.x_flip { obj.width - 1 - x } else { x },
+                if obj.y_flip { obj.height - 1 - y } else { y },
+            )
         };
 
+        let tile_addr = dispcnt.obj_tile_addr(
+            obj.tile_index,
+            tex_x / 8,
+            tex_y / 8,
+            obj.width / 8,
+            obj.palette256,
+        );
+        let (row_width, format) = if obj.palette256 {
+            (8, PixelFormat::BPP8)
+        } else {
+            (4, PixelFormat::BPP4)
+        };
+        self.read_pixel_index(sysbus, tile_addr, tex_x % 8, tex_y % 8, row_width, format)
+    }
+
+    /// Marks the opaque pixels of OBJ window sprites on the current line. The sprites themselves
+    /// are never drawn.
+    fn scanline_obj_window(&mut self, dispcnt: &DisplayControl, sysbus: &SysBus) {
+        self.obj_window_line = [false; Self::DISPLAY_WIDTH];
+        let mosaic = Mosaic::from(sysbus.ioregs.read_reg(REG_MOSAIC));
+        for index in 0..128 {
+            let obj = self.obj_attrs(index, sysbus);
+            if obj.mode != ObjMode::Window || obj.hidden() {
+                continue;
+            }
+            let (bounds_w, bounds_h) = obj.bounds();
+            // sprites wrap around from the bottom of the 256 line space to the top
+            let y = (self.current_scanline as u32).wrapping_sub(obj.y) % 256;
+            if y >= bounds_h {
+                continue;
+            }
+            for x in 0..bounds_w {
+                let px = obj.screen_x() + x as i32;
+                if px < 0 || px >= Self::DISPLAY_WIDTH as i32 || self.obj_window_line[px as usize] {
+                    continue;
+                }
+                let (sample_x, sample_y) = obj.mosaic_sample(x, y, &mosaic);
+                if self.obj_pixel_index(&obj, dispcnt, sample_x, sample_y, sysbus) != 0 {
+                    self.obj_window_line[px as usize] = true;
+                }
+            }
+        }
+    }
+
+    /// Draws the sprites of the current line into `obj_line`. Where sprites overlap the one with
+    /// the highest priority is kept, the lowest OAM index among equals.
+    fn scanline_objs(&mut self, dispcnt: &DisplayControl, sysbus: &SysBus) {
+        self.obj_line = [None; Self::DISPLAY_WIDTH];
+        if !dispcnt.disp_obj {
+            return;
+        }
+        // the bitmap modes take the first half of OBJ VRAM
+        let first_tile = if dispcnt.bg_mode as u8 >= 3 { 512 } else { 0 };
+        let mosaic = Mosaic::from(sysbus.ioregs.read_reg(REG_MOSAIC));
+        for index in 0..128 {
+            let obj = self.obj_attrs(index, sysbus);
+            let drawn = obj.mode == ObjMode::Normal || obj.mode == ObjMode::SemiTransparent;
+            if !drawn || obj.hidden() || obj.tile_index < first_tile {
+                continue;
+            }
+            let (bounds_w, bounds_h) = obj.bounds();
+            // sprites wrap around from the bottom of the 256 line space to the top
+            let y = (self.current_scanline as u32).wrapping_sub(obj.y) % 256;
+            if y >= bounds_h {
+                continue;
+            }
+            for x in 0..bounds_w {
+                let px = obj.screen_x() + x as i32;
+                if px < 0 || px >= Self::DISPLAY_WIDTH as i32 {
+                    continue;
+                }
+                let px = px as usize;
+                match self.obj_line[px] {
+                    Some(pixel) if pixel.priority <= obj.priority => continue,
+                    _ => {}
+                }
+                let (sample_x, sample_y) = obj.mosaic_sample(x, y, &mosaic);
+                let index = self.obj_pixel_index(&obj, dispcnt, sample_x, sample_y, sysbus) as u32;
+                if index == 0 {
+                    continue;
+                }
+                // the OBJ palette follows the 256 BG colors
+                let color = if obj.palette256 {
+                    self.get_palette_color(sysbus, 256 + index, 0)
+                } else {
+                    self.get_palette_color(sysbus, index, 16 + obj.palette_bank)
+                };
+                self.obj_line[px] = Some(ObjPixel {
+                    color,
+                    priority: obj.priority,
+                    semi_transparent: obj.mode == ObjMode::SemiTransparent,
+                });
+            }
+        }
+    }
+
+    /// Masking stage before compositing, hides the layers the window of each pixel disables.
+    /// WIN0 goes over WIN1, which goes over the OBJ window, pixels outside every window use the
+    /// WINOUT layers. Nothing is masked when no window is on.
+    fn apply_windows(&mut self, dispcnt: &DisplayControl, sysbus: &SysBus) {
+        self.sfx_line = [true; Self::DISPLAY_WIDTH];
+        let obj_window = dispcnt.disp_obj && dispcnt.disp_obj_window;
+        if !(dispcnt.disp_window0 || dispcnt.disp_window1 || obj_window) {
+            return;
+        }
+        if obj_window {
+            self.scanline_obj_window(dispcnt, sysbus);
+        } else {
+            self.obj_window_line = [false; Self::DISPLAY_WIDTH];
+        }
+        let window = WindowState::from_regs(&sysbus.ioregs);
         let y = self.current_scanline;
+        for px in 0..Self::DISPLAY_WIDTH {
+            let control = if dispcnt.disp_window0 && window.win0.contains(px, y) {
+                &window.win0_control
+            } else if dispcnt.disp_window1 && window.win1.contains(px, y) {
+                &window.win1_control
+            } else if self.obj_window_line[px] {
+                &window.objwin_control
+            } else {
+                &window.outside_control
+            };
+            for bg in 0..4 {
+                if !control.bg[bg] {
+                    self.bg_line[bg][px] = None;
+                }
+            }
+            if !control.obj {
+                self.obj_line[px] = None;
+            }
+            self.sfx_line[px] = control.sfx;
+        }
+    }
+
+    /// The two topmost opaque layers at `px`, given the backgrounds and their priorities from
+    /// the highest priority to the lowest. A sprite goes above backgrounds of the same priority.
+    /// The second one is what the blend unit mixes the top one with.
+    fn resolve_layers(&self, px: usize, bg_order: &[(usize, u8)]) -> (Layer, Layer) {
+        let mut opaque = [Layer::Backdrop; 2];
+        let mut found = 0;
+        let mut push = |layer| {
+            if found < 2 {
+                opaque[found] = layer;
+                found += 1;
+            }
+        };
+        let mut obj = self.obj_line[px];
+        for &(bg, priority) in bg_order {
+            match obj {
+                Some(pixel) if pixel.priority <= priority => {
+                    push(Layer::Obj);
+                    obj = None;
+                }
+                _ => {}
+            }
+            if self.bg_line[bg][px].is_some() {
+                push(Layer::Bg(bg));
+            }
+        }
+        if obj.is_some() {
+            push(Layer::Obj);
+        }
+        (opaque[0], opaque[1])
+    }
+
+    fn layer_color(&self, layer: Layer, px: usize, backdrop: Rgb15) -> Rgb15 {
+        match layer {
+            Layer::Bg(bg) => self.bg_line[bg][px].unwrap(),
+            Layer::Obj => self.obj_line[px].unwrap().color,
+            Layer::Backdrop => backdrop,
+        }
+    }
+
+    /// The color special effect of BLDCNT on `color`, the color of the `top` layer.
+    /// Alpha blending mixes it with the `second` layer when that is a second target, otherwise
+    /// there's nothing to blend with and it stays as it is.
+    fn apply_effect(
+        &self,
+        blend: &BlendState,
+        color: Rgb15,
+        top: Layer,
+        second: Layer,
+        px: usize,
+        backdrop: Rgb15,
+    ) -> Rgb15 {
+        let alpha = |color: Rgb15| {
+            let below = self.layer_color(second, px, backdrop);
+            color.blend(below, blend.eva, blend.evb)
+        };
+        // semi-transparent sprites are a first target of their own, even with OBJ not
+        // selected in BLDCNT or another effect chosen
+        let semi_transparent = top == Layer::Obj && self.obj_line[px].unwrap().semi_transparent;
+        if semi_transparent && blend.target2.contains(second) {
+            return alpha(color);
+        }
+        if !blend.target1.contains(top) {
+            return color;
+        }
+        match blend.mode {
+            BlendMode::BldAlpha if blend.target2.contains(second) => alpha(color),
+            BlendMode::BldWhite => color.brighten(blend.evy),
+            BlendMode::BldBlack => color.darken(blend.evy),
+            _ => color,
+        }
+    }
+
+    fn compose_scanline(&mut self, dispcnt: &DisplayControl, sysbus: &SysBus) {
+        // lower priority value is on top, ties go to the lower BG number
+        let mut bg_order: Vec<(usize, u8)> = (0..4)
+            .filter(|&bg| dispcnt.disp_bg[bg])
+            .map(|bg| (bg, self.bgcnt(bg as u32, sysbus).bg_priority))
+            .collect();
+        bg_order.sort_by_key(|&(bg, priority)| (priority, bg));
+
+        let blend = BlendState::from_regs(&sysbus.ioregs);
+        let backdrop = self.get_palette_color(sysbus, 0, 0);
+        let line = self.current_scanline * 256;
+        for px in 0..Self::DISPLAY_WIDTH {
+            let (top, second) = self.resolve_layers(px, &bg_order);
+            self.backdrop_line[px] = top == Layer::Backdrop;
+            let color = self.layer_color(top, px, backdrop);
+            self.pixeldata[line + px] = if self.sfx_line[px] {
+                self.apply_effect(&blend, color, top, second, px, backdrop)
+            } else {
+                color
+            };
+        }
+    }
+
+    /// Modes 3 to 5, BG2 is a bitmap drawn through its rotation/scaling parameters. Mode 3 is
+    /// 16 bit colors, mode 4 palette indices and mode 5 smaller 16 bit colors, 4 and 5 have two
+    /// pages to flip between. Outside the bitmap BG2 is transparent.
+    fn scanline_bitmap(&mut self, dispcnt: &DisplayControl, sysbus: &mut SysBus) {
+        let page: u32 = match (dispcnt.bg_mode, dispcnt.display_frame) {
+            (BGMode::BGMode3, _) | (_, 0) => 0x0600_0000,
+            (_, 1) => 0x0600_a000,
+            _ => unreachable!(),
+        };
+        let (width, height) = match dispcnt.bg_mode {
+            BGMode::BGMode5 => (Self::MODE5_WIDTH, Self::MODE5_HEIGHT),
+            _ => (Self::DISPLAY_WIDTH, Self::DISPLAY_HEIGHT),
+        };
+        let bgcnt = self.bgcnt(2, sysbus);
+        let affine = self.bg_affine(2, sysbus);
+        let mosaic = Mosaic::from(sysbus.ioregs.read_reg(REG_MOSAIC));
+        let (ref_x, ref_y) = self.mosaic_bg_ref(2, &bgcnt, &affine, &mosaic);
+
+        let (origin_x, origin_y) = if self.mode5_centered && dispcnt.bg_mode == BGMode::BGMode5 {
+            (
+                (Self::DISPLAY_WIDTH - Self::MODE5_WIDTH) as i32 / 2,
+                (Self::DISPLAY_HEIGHT - Self::MODE5_HEIGHT) as i32 / 2,
+            )
+        } else {
+            (0, 0)
+        };
+
+        let sy = self.current_scanline as i32 - origin_y;
+        // the reference point has moved down to this line already
+        let (ref_x, ref_y) = (
+            ref_x - (affine.pb as i32) * origin_y,
+            ref_y - (affine.pd as i32) * origin_y,
+        );
 
         for x in 0..Self::DISPLAY_WIDTH {
-            let bitmap_index = x + y * Self::DISPLAY_WIDTH;
-            let bitmap_addr = page + (bitmap_index as u32);
-            let index = sysbus.read_8(bitmap_addr as Addr) as u32;
-            self.pixeldata[x + y * 256] = self.get_palette_color(sysbus, index, 0);
+            let sample_x = if bgcnt.mosaic { x - x % mosaic.bg.0 } else { x };
+            let sx = sample_x as i32 - origin_x;
+            let tx = (ref_x + (affine.pa as i32) * sx) >> 8;
+            let ty = (ref_y + (affine.pc as i32) * sx) >> 8;
+
+            self.bg_line[2][x] = if sx >= 0
+                && sy >= 0
+                && (0..width as i32).contains(&tx)
+                && (0..height as i32).contains(&ty)
+            {
+                let pixel_index = tx as u32 + ty as u32 * width as u32;
+                match dispcnt.bg_mode {
+                    BGMode::BGMode4 => match sysbus.peek_8(page + pixel_index) {
+                        0 => None,
+                        index => Some(self.get_palette_color(sysbus, index as u32, 0)),
+                    },
+                    _ => Some(sysbus.peek_16(page + 2 * pixel_index).into()),
+                }
+            } else {
+                None
+            };
         }
     }
 
     pub fn scanline(&mut self, sysbus: &mut SysBus) {
-        let dispcnt = DisplayControl::from(sysbus.ioregs.read_reg(REG_DISPCNT));
+        self.latch_bg_refs(sysbus);
+        if !self.skip_rendering {
+            self.render_scanline(sysbus);
+        }
+        self.advance_bg_refs(sysbus);
+    }
 
-        match dispcnt.bg_mode {
-            BGMode::BGMode0 | BGMode::BGMode2 => {
-                for bg in 0..3 {
-                    if dispcnt.disp_bg[bg] {
-                        self.scanline_mode0(bg as u32, sysbus);
-                    }
-                }
+    /// Draw `line` from the registers and memory as they are now and return its
+    /// `DISPLAY_WIDTH` pixels, for debugging raster effects. The frame being drawn is left
+    /// alone. The rotation/scaling reference points are BGxX/BGxY moved down to `line`, as if
+    /// nothing changed them during the frame.
+    pub fn render_single_scanline(&mut self, line: u16, sysbus: &mut SysBus) -> Vec<Rgb15> {
+        let line = line as usize;
+        assert!(line < Self::DISPLAY_HEIGHT, "line {} isn't visible", line);
+        let row = line * 256..line * 256 + Self::DISPLAY_WIDTH;
+        let saved_row = self.pixeldata[row.clone()].to_vec();
+        let saved_scanline = self.current_scanline;
+        let saved_bg_ref = self.bg_ref;
+
+        self.current_scanline = line;
+        for bg in 2..4 {
+            let affine = self.bg_affine(bg as u32, sysbus);
+            self.bg_ref[bg - 2] = (
+                affine.x + affine.pb as i32 * line as i32,
+                affine.y + affine.pd as i32 * line as i32,
+            );
+        }
+        self.render_scanline(sysbus);
+        let pixels = self.pixeldata[row.clone()].to_vec();
+
+        self.pixeldata[row].copy_from_slice(&saved_row);
+        self.current_scanline = saved_scanline;
+        self.bg_ref = saved_bg_ref;
+        pixels
+    }
+
+    fn render_scanline(&mut self, sysbus: &mut SysBus) {
+        let mut dispcnt = DisplayControl::from(sysbus.ioregs.read_reg(REG_DISPCNT));
+        if let Some(mode) = self.forced_mode {
+            dispcnt.bg_mode = BGMode::from_u8(mode).unwrap();
+        }
+
+        self.backdrop_line = [false; Self::DISPLAY_WIDTH];
+        for bg in 0..4 {
+            // mode 1 has text BG0 and BG1 and a rotation/scaling BG2, mode 2 has only
+            // rotation/scaling BG2 and BG3, the bitmap modes only the BG2 bitmap
+            let present = match dispcnt.bg_mode {
+                BGMode::BGMode0 => true,
+                BGMode::BGMode1 => bg < 3,
+                BGMode::BGMode2 => bg >= 2,
+                _ => bg == 2,
+            };
+            dispcnt.disp_bg[bg] &= present;
+            if !dispcnt.disp_bg[bg] {
+                continue;
             }
-            BGMode::BGMode4 => {
-                self.scanline_mode4(2, &dispcnt, sysbus);
+            match dispcnt.bg_mode {
+                BGMode::BGMode0 => self.scanline_mode0(bg as u32, sysbus),
+                BGMode::BGMode1 if bg < 2 => self.scanline_mode0(bg as u32, sysbus),
+                BGMode::BGMode1 | BGMode::BGMode2 => self.scanline_affine(bg as u32, sysbus),
+                _ => self.scanline_bitmap(&dispcnt, sysbus),
+            }
+        }
+        self.scanline_objs(&dispcnt, sysbus);
+        self.apply_windows(&dispcnt, sysbus);
+        self.compose_scanline(&dispcnt, sysbus);
+        self.apply_backdrop_override();
+    }
+
+    fn apply_backdrop_override(&mut self) {
+        if let Some(color) = self.backdrop_override {
+            let line = self.current_scanline * 256;
+            for px in 0..Self::DISPLAY_WIDTH {
+                if self.backdrop_line[px] {
+                    self.pixeldata[line + px] = color;
+                }
             }
-            _ => panic!("{:?} not supported", dispcnt.bg_mode),
         }
     }
 }
@@ -360,57 +1325,56 @@ impl EmuIoDev for Lcd {
             .write_reg(REG_VCOUNT, self.current_scanline as u16);
         let mut dispstat = DisplayStatus::from(sysbus.ioregs.read_reg(REG_DISPSTAT));
 
-        dispstat.vcount_flag = dispstat.vcount_setting as usize == self.current_scanline;
-        if dispstat.vcount_irq_enable {
-            panic!("VCOUNT IRQ NOT IMPL");
-        }
-
         match self.state {
             HDraw => {
-                if self.cycles > Lcd::CYCLES_HDRAW {
-                    self.current_scanline += 1;
+                if self.cycles >= Lcd::CYCLES_HDRAW {
                     self.cycles -= Lcd::CYCLES_HDRAW;
-
-                    let (new_state, irq) = if self.current_scanline < Lcd::DISPLAY_HEIGHT {
-                        self.scanline(sysbus);
-                        // HBlank
-                        dispstat.hblank_flag = true;
-                        let irq = if dispstat.hblank_irq_enable {
-                            Some(Interrupt::LCD_HBlank)
-                        } else {
-                            None
-                        };
-                        (HBlank, irq)
+                    self.scanline(sysbus);
+                    // only visible lines have an HBlank
+                    self.state = HBlank;
+                    dispstat.hblank_flag = true;
+                    let irq = if dispstat.hblank_irq_enable {
+                        Some(Interrupt::LCD_HBlank)
                     } else {
-                        dispstat.vblank_flag = true;
-                        let irq = if dispstat.vblank_irq_enable {
-                            Some(Interrupt::LCD_VBlank)
-                        } else {
-                            None
-                        };
-                        (VBlank, irq)
+                        None
                     };
-                    self.state = new_state;
                     self.update_regs(dispstat, sysbus);
                     return (0, irq);
                 }
             }
             HBlank => {
-                if self.cycles > Lcd::CYCLES_HBLANK {
+                if self.cycles >= Lcd::CYCLES_HBLANK {
                     self.cycles -= Lcd::CYCLES_HBLANK;
-                    self.state = HDraw;
+                    self.current_scanline += 1;
+                    self.match_vcount(&mut dispstat);
                     dispstat.hblank_flag = false;
+                    let irq = if self.current_scanline < Lcd::DISPLAY_HEIGHT {
+                        self.state = HDraw;
+                        None
+                    } else {
+                        self.state = VBlank;
+                        dispstat.vblank_flag = true;
+                        if dispstat.vblank_irq_enable {
+                            Some(Interrupt::LCD_VBlank)
+                        } else {
+                            None
+                        }
+                    };
                     self.update_regs(dispstat, sysbus);
-                    return (0, None);
+                    return (0, irq);
                 }
             }
             VBlank => {
-                if self.cycles > Lcd::CYCLES_VBLANK {
-                    self.cycles -= Lcd::CYCLES_VBLANK;
-                    self.state = HDraw;
-                    dispstat.vblank_flag = false;
-                    self.current_scanline = 0;
-                    self.scanline(sysbus);
+                if self.cycles >= Lcd::CYCLES_SCANLINE {
+                    self.cycles -= Lcd::CYCLES_SCANLINE;
+                    self.current_scanline += 1;
+                    if self.current_scanline == Lcd::SCANLINES {
+                        self.state = HDraw;
+                        dispstat.vblank_flag = false;
+                        self.current_scanline = 0;
+                        self.reload_bg_refs(sysbus);
+                    }
+                    self.match_vcount(&mut dispstat);
                     self.update_regs(dispstat, sysbus);
                     return (0, None);
                 }
@@ -443,3 +1407,1098 @@ impl From<u16> for TileMapEntry {
         }
     }
 }
+
+#[derive(Debug, Primitive, Copy, Clone, PartialEq)]
+enum ObjMode {
+    Normal = 0,
+    SemiTransparent = 1,
+    /// Not drawn, the opaque pixels make up the OBJ window
+    Window = 2,
+    Forbidden = 3,
+}
+
+/// A sprite's OAM entry
+#[derive(Debug)]
+struct ObjAttrs {
+    y: u32,
+    affine: bool,
+    /// Double size for affine sprites, hides the sprite otherwise
+    double_size: bool,
+    mode: ObjMode,
+    palette256: bool,
+    x: u32,
+    affine_index: u32,
+    x_flip: bool,
+    y_flip: bool,
+    width: u32,
+    height: u32,
+    tile_index: u32,
+    /// Against the backgrounds, 0 is the highest
+    priority: u8,
+    palette_bank: u32,
+    mosaic: bool,
+}
+
+impl ObjAttrs {
+    fn from_attrs(attr0: u16, attr1: u16, attr2: u16) -> ObjAttrs {
+        let size = attr1.bit_range(14..16) as usize;
+        let (width, height) = match attr0.bit_range(14..16) {
+            0 => [(8, 8), (16, 16), (32, 32), (64, 64)][size],
+            1 => [(16, 8), (32, 8), (32, 16), (64, 32)][size],
+            2 => [(8, 16), (8, 32), (16, 32), (32, 64)][size],
+            _ => (8, 8), // prohibited
+        };
+        ObjAttrs {
+            y: attr0.bit_range(0..8) as u32,
+            affine: attr0.bit(8),
+            double_size: attr0.bit(9),
+            mode: ObjMode::from_u16(attr0.bit_range(10..12)).unwrap(),
+            palette256: attr0.bit(13),
+            x: attr1.bit_range(0..9) as u32,
+            affine_index: attr1.bit_range(9..14) as u32,
+            x_flip: attr1.bit(12),
+            y_flip: attr1.bit(13),
+            width,
+            height,
+            tile_index: attr2.bit_range(0..10) as u32,
+            priority: attr2.bit_range(10..12) as u8,
+            palette_bank: attr2.bit_range(12..16) as u32,
+            mosaic: attr0.bit(12),
+        }
+    }
+
+    /// The point within the bounds that (`x`, `y`) takes its color from, the top left of its
+    /// mosaic cell when the sprite has mosaic on
+    fn mosaic_sample(&self, x: u32, y: u32, mosaic: &Mosaic) -> (u32, u32) {
+        if self.mosaic {
+            let (width, height) = (mosaic.obj.0 as u32, mosaic.obj.1 as u32);
+            (x - x % width, y - y % height)
+        } else {
+            (x, y)
+        }
+    }
+
+    fn hidden(&self) -> bool {
+        !self.affine && self.double_size
+    }
+
+    /// Width and height of the screen area the sprite covers
+    fn bounds(&self) -> (u32, u32) {
+        if self.affine && self.double_size {
+            (2 * self.width, 2 * self.height)
+        } else {
+            (self.width, self.height)
+        }
+    }
+
+    /// X is 9 bits signed, sprites can hang off the left edge
+    fn screen_x(&self) -> i32 {
+        ((self.x << 23) as i32) >> 23
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+
+    /// Render every line of a frame the way `step` does
+    fn render_frame(lcd: &mut Lcd, sysbus: &mut SysBus) {
+        lcd.reload_bg_refs(sysbus);
+        for line in 0..Lcd::DISPLAY_HEIGHT {
+            lcd.current_scanline = line;
+            lcd.scanline(sysbus);
+        }
+    }
+
+    #[test]
+    fn screenblock_layout() {
+        let sb = |size: u16, tile_x, tile_y| {
+            BgControl::from(size << 14).tile_map_addr(tile_x, tile_y) - VRAM_ADDR
+        };
+        assert_eq!(sb(0, 31, 31), 0x7fe);
+        assert_eq!(sb(1, 32, 0), 0x800);
+        assert_eq!(sb(1, 63, 31), 0xffe);
+        assert_eq!(sb(2, 0, 32), 0x800);
+        assert_eq!(sb(2, 31, 63), 0xffe);
+        assert_eq!(sb(3, 32, 0), 0x800);
+        assert_eq!(sb(3, 0, 32), 0x1000);
+        assert_eq!(sb(3, 32, 32), 0x1800);
+        assert_eq!(sb(3, 63, 63), 0x1ffe);
+    }
+
+    #[test]
+    fn obj_tile_mapping() {
+        let map_1d = DisplayControl::from(1 << 6);
+        let map_2d = DisplayControl::from(0);
+
+        // a 32x32 sprite is 4x4 cells, tile number of each cell
+        let tiles = |dispcnt: &DisplayControl, tile_index, palette256| {
+            let mut tiles = vec![];
+            for cell_y in 0..4 {
+                for cell_x in 0..4 {
+                    let addr = dispcnt.obj_tile_addr(tile_index, cell_x, cell_y, 4, palette256);
+                    tiles.push((addr - OBJ_VRAM_ADDR) / Lcd::TILE_SIZE);
+                }
+            }
+            tiles
+        };
+
+        assert_eq!(tiles(&map_1d, 0, false), (0..16).collect::<Vec<_>>());
+        assert_eq!(tiles(&map_2d, 0, false)[..8], [0, 1, 2, 3, 32, 33, 34, 35]);
+        assert_eq!(tiles(&map_2d, 0, false)[15], 99);
+
+        assert_eq!(tiles(&map_1d, 0, true)[..5], [0, 2, 4, 6, 8]);
+        assert_eq!(tiles(&map_2d, 0, true)[..5], [0, 2, 4, 6, 32]);
+        // odd tile numbers are rounded down in 2D mapping with 256 colors
+        assert_eq!(tiles(&map_1d, 3, true)[0], 3);
+        assert_eq!(tiles(&map_2d, 3, true)[0], 2);
+
+        // tile numbers wrap around the 32K of OBJ VRAM
+        assert_eq!(tiles(&map_2d, 1023, false)[1], 0);
+    }
+
+    #[test]
+    fn scroll_512x512_across_screenblocks() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let mut lcd = Lcd::new();
+
+        // BG0: 512x512, char block 0, screen block 8
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x0100);
+        sysbus.ioregs.write_reg(REG_BG0CNT, (3 << 14) | (8 << 8));
+        // top left of the screen lands 8 pixels left of the SB2/SB3 boundary
+        sysbus.ioregs.write_reg(REG_BG0HOFS, 248);
+        sysbus.ioregs.write_reg(REG_BG0VOFS, 256);
+
+        // tile 1 is solid with color index 1
+        for i in 0..Lcd::TILE_SIZE {
+            sysbus.write_8(VRAM_ADDR + Lcd::TILE_SIZE + i, 0x11);
+        }
+        sysbus.write_16(0x0500_0002, 0x001f);
+        // first entry of SB3 uses tile 1
+        sysbus.write_16(VRAM_ADDR + 8 * 0x800 + 3 * 0x800, 1);
+
+        lcd.scanline(&mut sysbus);
+        assert_eq!(lcd.pixeldata[7], Rgb15::from(0));
+        assert_eq!(lcd.pixeldata[8], Rgb15::from(0x001f));
+        assert_eq!(lcd.pixeldata[15], Rgb15::from(0x001f));
+        assert_eq!(lcd.pixeldata[16], Rgb15::from(0));
+    }
+
+    #[test]
+    fn transparent_tiles_skipped() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let mut lcd = Lcd::new();
+
+        // BG0: 256x256 4bpp, char block 0, screen block 8
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x0100);
+        sysbus.ioregs.write_reg(REG_BG0CNT, 8 << 8);
+
+        // tile 0 is left all zero, tile 1 has transparent holes, tile 2 is solid
+        for i in 0..Lcd::TILE_SIZE {
+            sysbus.write_8(VRAM_ADDR + Lcd::TILE_SIZE + i, 0x10);
+            sysbus.write_8(VRAM_ADDR + 2 * Lcd::TILE_SIZE + i, 0x22);
+        }
+        sysbus.write_16(0x0500_0000, 0x7c00);
+        sysbus.write_16(0x0500_0002, 0x001f);
+        sysbus.write_16(0x0500_0004, 0x03e0);
+        for tx in 0..32 {
+            sysbus.write_16(VRAM_ADDR + 8 * 0x800 + 2 * tx, (tx % 3) as u16);
+        }
+        // leftovers from a previous frame
+        for pixel in lcd.pixeldata.iter_mut() {
+            *pixel = Rgb15::from(0x1234);
+        }
+
+        lcd.scanline(&mut sysbus);
+
+        // compare with looking up every pixel
+        let backdrop = Rgb15::from(0x7c00);
+        for px in 0..Lcd::DISPLAY_WIDTH {
+            let tile_addr = VRAM_ADDR + ((px / 8) % 3) as u32 * Lcd::TILE_SIZE;
+            let index =
+                lcd.read_pixel_index(&sysbus, tile_addr, px as u32 % 8, 0, 4, PixelFormat::BPP4);
+            let expected = if index == 0 {
+                backdrop
+            } else {
+                lcd.get_palette_color(&sysbus, index as u32, 0)
+            };
+            assert_eq!(lcd.pixeldata[px], expected, "pixel {}", px);
+        }
+        assert_eq!(lcd.pixeldata[0], backdrop);
+        assert_eq!(lcd.pixeldata[8], backdrop);
+        assert_eq!(lcd.pixeldata[9], Rgb15::from(0x001f));
+        assert_eq!(lcd.pixeldata[16], Rgb15::from(0x03e0));
+    }
+
+    #[test]
+    fn tile_flips() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let mut lcd = Lcd::new();
+
+        // BG0: 256x256 4bpp, char block 0, screen block 8
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x0100);
+        sysbus.ioregs.write_reg(REG_BG0CNT, 8 << 8);
+        // tile 1 has a single dot in its top-left corner
+        sysbus.write_8(VRAM_ADDR + Lcd::TILE_SIZE, 0x01);
+        sysbus.write_16(0x0500_0002, 0x001f);
+        // as is, flipped horizontally, vertically and both
+        for (tx, flips) in [0, 1, 2, 3].iter().enumerate() {
+            sysbus.write_16(VRAM_ADDR + 8 * 0x800 + 2 * tx as u32, 1 | (flips << 10));
+        }
+        let dots = |lcd: &Lcd, line: usize| {
+            (0..32)
+                .filter(|&px| lcd.pixeldata[line * 256 + px] == Rgb15::from(0x001f))
+                .collect::<Vec<_>>()
+        };
+
+        lcd.scanline(&mut sysbus);
+        assert_eq!(dots(&lcd, 0), [0, 15]);
+        lcd.current_scanline = 7;
+        lcd.scanline(&mut sysbus);
+        assert_eq!(dots(&lcd, 7), [16, 31]);
+
+        // scrolled past the right edge the map wraps around to its first column
+        sysbus.ioregs.write_reg(REG_BG0HOFS, 0x1f8);
+        lcd.current_scanline = 0;
+        lcd.scanline(&mut sysbus);
+        assert_eq!(dots(&lcd, 0), [8, 23]);
+    }
+
+    #[test]
+    fn affine_backgrounds() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let mut lcd = Lcd::new();
+
+        // mode 1, BG0 and BG2 on. BG2 is 128x128, char block 0, screen block 8.
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x0501);
+        sysbus.ioregs.write_reg(REG_BG2CNT, 8 << 8);
+        // tile 1 is solid color 1 and is the top-left tile of the map, the others are tile 0
+        for i in 0..2 * Lcd::TILE_SIZE {
+            sysbus.write_8(VRAM_ADDR + 2 * Lcd::TILE_SIZE + i, 0x01);
+        }
+        sysbus.write_8(VRAM_ADDR + 8 * 0x800, 1);
+        sysbus.write_16(0x0500_0002, 0x001f);
+        // vertically squashed by half
+        sysbus.ioregs.write_reg(REG_BG2PA, 0x100);
+        sysbus.ioregs.write_reg(REG_BG2PD, 0x200);
+
+        let red = Rgb15::from(0x001f);
+        let row = |lcd: &Lcd, line: usize| {
+            (0..Lcd::DISPLAY_WIDTH)
+                .filter(|&px| lcd.pixeldata[line * 256 + px] == red)
+                .collect::<Vec<_>>()
+        };
+        render_frame(&mut lcd, &mut sysbus);
+        // BG0 is a text background and stays transparent
+        assert_eq!(row(&lcd, 0), (0..8).collect::<Vec<_>>());
+        // the reference point moves down by PD each line
+        assert_eq!(row(&lcd, 3), (0..8).collect::<Vec<_>>());
+        assert!(row(&lcd, 4).is_empty());
+
+        // with wraparound the map repeats every 128 pixels
+        sysbus.ioregs.write_reg(REG_BG2CNT, (1 << 13) | (8 << 8));
+        render_frame(&mut lcd, &mut sysbus);
+        assert_eq!(row(&lcd, 0), (0..8).chain(128..136).collect::<Vec<_>>());
+        assert_eq!(row(&lcd, 64), (0..8).chain(128..136).collect::<Vec<_>>());
+
+        // mode 2 has BG3 instead of BG0 and BG1, also rotation/scaling
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x0902);
+        sysbus.ioregs.write_reg(REG_BG3CNT, 8 << 8);
+        sysbus.ioregs.write_reg(REG_BG3PA, 0x80);
+        sysbus.ioregs.write_reg(REG_BG3PD, 0x100);
+        render_frame(&mut lcd, &mut sysbus);
+        assert_eq!(row(&lcd, 7), (0..16).collect::<Vec<_>>());
+        assert!(row(&lcd, 8).is_empty());
+    }
+
+    #[test]
+    fn single_scanline() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let mut lcd = Lcd::new();
+
+        // BG0: 256x256 4bpp, char block 0, screen block 8, scrolled down a tile
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x0100);
+        sysbus.ioregs.write_reg(REG_BG0CNT, 8 << 8);
+        sysbus.ioregs.write_reg(REG_BG0VOFS, 8);
+        // tile 1 has stripes of colors 1 and 2
+        for i in 0..Lcd::TILE_SIZE {
+            sysbus.write_8(VRAM_ADDR + Lcd::TILE_SIZE + i, 0x21);
+        }
+        sysbus.write_16(0x0500_0000, 0x7c00);
+        sysbus.write_16(0x0500_0002, 0x001f);
+        sysbus.write_16(0x0500_0004, 0x03e0);
+        // the second row of the map starts with tile 1
+        sysbus.write_16(VRAM_ADDR + 8 * 0x800 + 2 * 32, 1);
+        lcd.current_scanline = 100;
+        lcd.pixeldata[5] = Rgb15::from(0x1234);
+
+        let pixels = lcd.render_single_scanline(0, &mut sysbus);
+        assert_eq!(pixels.len(), Lcd::DISPLAY_WIDTH);
+        let expected: Vec<Rgb15> = (0..Lcd::DISPLAY_WIDTH)
+            .map(|px| match px {
+                0...7 if px % 2 == 0 => Rgb15::from(0x001f),
+                0...7 => Rgb15::from(0x03e0),
+                _ => Rgb15::from(0x7c00),
+            })
+            .collect();
+        assert_eq!(pixels, expected);
+        // nothing else changed
+        assert_eq!(lcd.current_scanline, 100);
+        assert_eq!(lcd.pixeldata[5], Rgb15::from(0x1234));
+    }
+
+    #[test]
+    fn sprites() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let mut lcd = Lcd::new();
+
+        // mode 0 with 1D OBJ mapping, BG0 is solid green at priority 1
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x1140);
+        sysbus.ioregs.write_reg(REG_BG0CNT, (8 << 8) | 1);
+        for i in 0..Lcd::TILE_SIZE {
+            sysbus.write_8(VRAM_ADDR + i, 0x11);
+        }
+        sysbus.write_16(0x0500_0002, 0x03e0);
+        // OBJ tile 1 is solid, tile 2 only has its left column, red in OBJ palette bank 2
+        for i in 0..Lcd::TILE_SIZE {
+            sysbus.write_8(OBJ_VRAM_ADDR + Lcd::TILE_SIZE + i, 0x11);
+        }
+        for row in 0..8 {
+            sysbus.write_8(OBJ_VRAM_ADDR + 2 * Lcd::TILE_SIZE + 4 * row, 0x01);
+        }
+        sysbus.write_16(0x0500_0200 + 2 * 16 * 2 + 2, 0x001f);
+
+        let set_obj = |sysbus: &mut SysBus, index: u32, attrs: [u16; 3]| {
+            for (i, &attr) in attrs.iter().enumerate() {
+                sysbus.write_16(OAM_ADDR + 8 * index + 2 * i as u32, attr);
+            }
+        };
+        // behind BG0
+        set_obj(&mut sysbus, 0, [0, 16, 1 | (2 << 10) | (2 << 12)]);
+        // in front of BG0 and of the sprite before it, which has a lower priority
+        set_obj(&mut sysbus, 1, [0, 20, 1 | (2 << 12)]);
+        // hanging off the left edge, flipped so its column is the last one on screen
+        set_obj(&mut sysbus, 2, [0, 0x1fc | (1 << 12), 2 | (2 << 12)]);
+        // affine and double size, the 8x8 sprite is in the middle of 16x16 bounds that start
+        // above the screen
+        set_obj(
+            &mut sysbus,
+            3,
+            [252 | (1 << 8) | (1 << 9), 100, 1 | (2 << 12)],
+        );
+        sysbus.write_16(OAM_ADDR + 6, 0x100);
+        sysbus.write_16(OAM_ADDR + 30, 0x100);
+
+        let red = |lcd: &Lcd| {
+            (0..Lcd::DISPLAY_WIDTH)
+                .filter(|&px| lcd.pixeldata[px] == Rgb15::from(0x001f))
+                .collect::<Vec<_>>()
+        };
+        lcd.scanline(&mut sysbus);
+        assert_eq!(
+            red(&lcd),
+            [3].iter()
+                .cloned()
+                .chain(20..28)
+                .chain(104..112)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(lcd.pixeldata[16], Rgb15::from(0x03e0));
+
+        // the bitmap modes only have the upper half of OBJ VRAM
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x1443);
+        lcd.scanline(&mut sysbus);
+        assert!(red(&lcd).is_empty());
+        for i in 0..Lcd::TILE_SIZE {
+            sysbus.write_8(OBJ_VRAM_ADDR + 512 * Lcd::TILE_SIZE + i, 0x11);
+        }
+        set_obj(&mut sysbus, 1, [0, 20, 512 | (2 << 12)]);
+        lcd.scanline(&mut sysbus);
+        assert_eq!(red(&lcd), (20..28).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn semi_transparent_sprites() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let mut lcd = Lcd::new();
+
+        // BG0 is solid green, with a red semi-transparent sprite over its first tile
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x1140);
+        sysbus.ioregs.write_reg(REG_BG0CNT, 8 << 8);
+        for i in 0..Lcd::TILE_SIZE {
+            sysbus.write_8(VRAM_ADDR + i, 0x11);
+            sysbus.write_8(OBJ_VRAM_ADDR + Lcd::TILE_SIZE + i, 0x11);
+        }
+        sysbus.write_16(0x0500_0002, 0x03e0);
+        sysbus.write_16(0x0500_0202, 0x001f);
+        sysbus.write_16(OAM_ADDR, 1 << 10);
+        sysbus.write_16(OAM_ADDR + 4, 1);
+        sysbus.ioregs.write_reg(REG_BLDALPHA, 0x0808);
+
+        let blended = Rgb15::from(0x01ef);
+        // BG0 is the second target, OBJ isn't a first target and no effect is selected
+        sysbus.ioregs.write_reg(REG_BLDCNT, 0x0100);
+        lcd.scanline(&mut sysbus);
+        assert_eq!(lcd.pixeldata[0], blended);
+        assert_eq!(lcd.pixeldata[8], Rgb15::from(0x03e0));
+        // the same with OBJ selected and brightness increase chosen
+        sysbus.ioregs.write_reg(REG_BLDCNT, 0x0190);
+        lcd.scanline(&mut sysbus);
+        assert_eq!(lcd.pixeldata[0], blended);
+        // nothing to blend with when BG0 isn't a second target
+        sysbus.ioregs.write_reg(REG_BLDCNT, 0x0010);
+        lcd.scanline(&mut sysbus);
+        assert_eq!(lcd.pixeldata[0], Rgb15::from(0x001f));
+    }
+
+    #[test]
+    fn color_special_effects() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let mut lcd = Lcd::new();
+
+        // BG0 is half red all over, BG1 below it half blue except at its second tile
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x0300);
+        sysbus.ioregs.write_reg(REG_BG0CNT, 8 << 8);
+        sysbus.ioregs.write_reg(REG_BG1CNT, (9 << 8) | (2 << 2) | 1);
+        for i in 0..Lcd::TILE_SIZE {
+            sysbus.write_8(VRAM_ADDR + i, 0x11);
+            sysbus.write_8(VRAM_ADDR + 0x8000 + i, 0x22);
+        }
+        sysbus.write_16(VRAM_ADDR + 9 * 0x800 + 2, 1);
+        sysbus.write_16(0x0500_0002, 0x0010);
+        sysbus.write_16(0x0500_0004, 0x4000);
+        let blend = |lcd: &mut Lcd, sysbus: &mut SysBus, bldcnt: u16| {
+            sysbus.ioregs.write_reg(REG_BLDCNT, bldcnt);
+            lcd.scanline(sysbus);
+            (lcd.pixeldata[0], lcd.pixeldata[8])
+        };
+        let red = Rgb15::from(0x0010);
+
+        // alpha blending BG0 with BG1, not with the backdrop unless it's a second target too
+        sysbus.ioregs.write_reg(REG_BLDALPHA, 0x0808);
+        assert_eq!(
+            blend(&mut lcd, &mut sysbus, 0x0241),
+            (Rgb15::from(0x2008), red)
+        );
+        assert_eq!(blend(&mut lcd, &mut sysbus, 0x2241).1, Rgb15::from(0x0008));
+        // weights above 16 count as 16
+        sysbus.ioregs.write_reg(REG_BLDALPHA, 0x1f1f);
+        assert_eq!(blend(&mut lcd, &mut sysbus, 0x0241).0, Rgb15::from(0x4010));
+
+        // brightness increase and decrease, EVY saturates at 16
+        sysbus.ioregs.write_reg(REG_BLDY, 8);
+        assert_eq!(
+            blend(&mut lcd, &mut sysbus, 0x0081),
+            (Rgb15::from(0x3df7), Rgb15::from(0x3df7))
+        );
+        assert_eq!(blend(&mut lcd, &mut sysbus, 0x00c1).0, Rgb15::from(0x0008));
+        sysbus.ioregs.write_reg(REG_BLDY, 31);
+        assert_eq!(blend(&mut lcd, &mut sysbus, 0x0081).0, Rgb15::from(0x7fff));
+        assert_eq!(blend(&mut lcd, &mut sysbus, 0x00c1).0, Rgb15::from(0));
+
+        // only first targets are affected
+        assert_eq!(blend(&mut lcd, &mut sysbus, 0x00c2).0, red);
+
+        // nor where the window turns effects off
+        sysbus.ioregs.write_reg(REG_WIN0H, 240);
+        sysbus.ioregs.write_reg(REG_WIN0V, 160);
+        sysbus.ioregs.write_reg(REG_WININ, 0x0003);
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x2300);
+        assert_eq!(blend(&mut lcd, &mut sysbus, 0x00c1).0, red);
+        sysbus.ioregs.write_reg(REG_WININ, 0x0023);
+        assert_eq!(blend(&mut lcd, &mut sysbus, 0x00c1).0, Rgb15::from(0));
+    }
+
+    #[test]
+    fn bg_priority() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let mut lcd = Lcd::new();
+
+        // BG0 and BG1 on, both priority 1 and 4bpp, BG2 priority 0
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x0700);
+        sysbus.ioregs.write_reg(REG_BG0CNT, (8 << 8) | 1);
+        sysbus.ioregs.write_reg(REG_BG1CNT, (9 << 8) | 1);
+        sysbus.ioregs.write_reg(REG_BG2CNT, 10 << 8);
+
+        // tile 1 is solid color 1, tile 2 is solid color 2, tile 3 is solid color 3
+        for tile in 1..4 {
+            for i in 0..Lcd::TILE_SIZE {
+                sysbus.write_8(VRAM_ADDR + tile * Lcd::TILE_SIZE + i, (tile * 0x11) as u8);
+            }
+        }
+        let backdrop = Rgb15::from(0x7c00);
+        sysbus.write_16(0x0500_0000, 0x7c00);
+        sysbus.write_16(0x0500_0002, 0x001f);
+        sysbus.write_16(0x0500_0004, 0x03e0);
+        sysbus.write_16(0x0500_0006, 0x7fff);
+
+        // BG0 covers tiles 0-1, BG1 tiles 1-2, BG2 only tile 3, tile 4 is empty everywhere
+        sysbus.write_16(VRAM_ADDR + 8 * 0x800, 1);
+        sysbus.write_16(VRAM_ADDR + 8 * 0x800 + 2, 1);
+        sysbus.write_16(VRAM_ADDR + 9 * 0x800 + 2, 2);
+        sysbus.write_16(VRAM_ADDR + 9 * 0x800 + 4, 2);
+        sysbus.write_16(VRAM_ADDR + 9 * 0x800 + 6, 2);
+        sysbus.write_16(VRAM_ADDR + 10 * 0x800 + 6, 3);
+
+        lcd.scanline(&mut sysbus);
+        assert_eq!(lcd.pixeldata[0], Rgb15::from(0x001f));
+        // equal priority, BG0 wins over BG1
+        assert_eq!(lcd.pixeldata[8], Rgb15::from(0x001f));
+        assert_eq!(
+            lcd.resolve_layers(8, &[(2, 0), (0, 1), (1, 1)]),
+            (Layer::Bg(0), Layer::Bg(1))
+        );
+        assert_eq!(lcd.pixeldata[16], Rgb15::from(0x03e0));
+        // BG2 has a higher priority
+        assert_eq!(lcd.pixeldata[24], Rgb15::from(0x7fff));
+        // nothing opaque
+        assert_eq!(lcd.pixeldata[32], backdrop);
+        assert_eq!(
+            lcd.resolve_layers(32, &[(2, 0), (0, 1), (1, 1)]),
+            (Layer::Backdrop, Layer::Backdrop)
+        );
+    }
+
+    #[test]
+    fn mode5_backdrop() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let mut lcd = Lcd::new();
+
+        let backdrop = Rgb15::from(0x001f);
+        let bitmap = Rgb15::from(0x03e0);
+
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x0405);
+        sysbus.ioregs.write_reg(REG_BG2PA, 0x100);
+        sysbus.ioregs.write_reg(REG_BG2PD, 0x100);
+        sysbus.write_16(0x0500_0000, 0x001f);
+        for i in 0..(Lcd::MODE5_WIDTH * Lcd::MODE5_HEIGHT) as u32 {
+            sysbus.write_16(VRAM_ADDR + 2 * i, 0x03e0);
+        }
+
+        render_frame(&mut lcd, &mut sysbus);
+        assert_eq!(lcd.pixeldata[0], bitmap);
+        assert_eq!(lcd.pixeldata[159], bitmap);
+        assert_eq!(lcd.pixeldata[160], backdrop);
+        assert_eq!(lcd.pixeldata[239], backdrop);
+        assert_eq!(lcd.pixeldata[128 * 256], backdrop);
+
+        // scrolled half way to the left using the reference point
+        sysbus.ioregs.write_reg(REG_BG2X, (80 << 8) as u16);
+        render_frame(&mut lcd, &mut sysbus);
+        assert_eq!(lcd.pixeldata[79], bitmap);
+        assert_eq!(lcd.pixeldata[80], backdrop);
+        sysbus.ioregs.write_reg(REG_BG2X, 0);
+
+        lcd.set_mode5_centered(true);
+        render_frame(&mut lcd, &mut sysbus);
+        assert_eq!(lcd.pixeldata[40], backdrop);
+        assert_eq!(lcd.pixeldata[16 * 256 + 39], backdrop);
+        assert_eq!(lcd.pixeldata[16 * 256 + 40], bitmap);
+        assert_eq!(lcd.pixeldata[16 * 256 + 199], bitmap);
+        assert_eq!(lcd.pixeldata[16 * 256 + 200], backdrop);
+    }
+
+    #[test]
+    fn reference_point_written_mid_frame() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let mut lcd = Lcd::new();
+
+        // mode 5, identity transform, a green bitmap with its first column red
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x0405);
+        sysbus.ioregs.write_reg(REG_BG2PA, 0x100);
+        sysbus.ioregs.write_reg(REG_BG2PD, 0x100);
+        for i in 0..(Lcd::MODE5_WIDTH * Lcd::MODE5_HEIGHT) {
+            let color = if i % Lcd::MODE5_WIDTH == 0 {
+                0x001f
+            } else {
+                0x03e0
+            };
+            sysbus.write_16(VRAM_ADDR + 2 * i as u32, color);
+        }
+        let red = Rgb15::from(0x001f);
+
+        lcd.reload_bg_refs(&sysbus);
+        for line in 0..20 {
+            lcd.current_scanline = line;
+            lcd.scanline(&mut sysbus);
+            if line == 9 {
+                // the CPU moves the picture 8 pixels right during line 9
+                sysbus.write_32(REG_BG2X, (-8i32 << 8) as u32);
+            }
+        }
+        assert_eq!(lcd.pixeldata[9 * 256], red);
+        assert_eq!(lcd.pixeldata[10 * 256 + 8], red);
+        assert_eq!(lcd.pixeldata[19 * 256 + 8], red);
+
+        // the lines after the write still move down the bitmap
+        sysbus.write_16(VRAM_ADDR + 2 * (15 * Lcd::MODE5_WIDTH as u32 + 1), 0x7c00);
+        lcd.reload_bg_refs(&sysbus);
+        for line in 0..20 {
+            lcd.current_scanline = line;
+            lcd.scanline(&mut sysbus);
+        }
+        assert_eq!(lcd.pixeldata[15 * 256 + 9], Rgb15::from(0x7c00));
+    }
+
+    #[test]
+    fn backdrop_override() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let mut lcd = Lcd::new();
+
+        // BG0 4bpp, screen block 8, tile 1 solid color 1 in the first 8 pixels only
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x0100);
+        sysbus.ioregs.write_reg(REG_BG0CNT, 8 << 8);
+        for i in 0..Lcd::TILE_SIZE {
+            sysbus.write_8(VRAM_ADDR + Lcd::TILE_SIZE + i, 0x11);
+        }
+        sysbus.write_16(0x0500_0000, 0x7c00);
+        sysbus.write_16(0x0500_0002, 0x001f);
+        sysbus.write_16(VRAM_ADDR + 8 * 0x800, 1);
+
+        let chroma_key = Rgb15::from(0x03e0);
+        lcd.set_backdrop_override(Some(chroma_key));
+        lcd.scanline(&mut sysbus);
+        assert_eq!(lcd.pixeldata[0], Rgb15::from(0x001f));
+        assert_eq!(lcd.pixeldata[7], Rgb15::from(0x001f));
+        assert_eq!(lcd.pixeldata[8], chroma_key);
+        assert_eq!(lcd.pixeldata[239], chroma_key);
+        // the game still sees its own backdrop color
+        assert_eq!(sysbus.read_16(0x0500_0000), 0x7c00);
+
+        // the override outlives a reset, and works in the bitmap modes as well
+        lcd.reset();
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x0405);
+        sysbus.ioregs.write_reg(REG_BG2PA, 0x100);
+        sysbus.ioregs.write_reg(REG_BG2PD, 0x100);
+        sysbus.write_16(VRAM_ADDR, 0x7fff);
+        lcd.scanline(&mut sysbus);
+        assert_eq!(lcd.pixeldata[0], Rgb15::from(0x7fff));
+        assert_eq!(lcd.pixeldata[160], chroma_key);
+
+        lcd.set_backdrop_override(None);
+        lcd.scanline(&mut sysbus);
+        assert_eq!(lcd.pixeldata[160], Rgb15::from(0x7c00));
+    }
+
+    #[test]
+    fn color_correction() {
+        let mut lcd = Lcd::new();
+        lcd.pixeldata[0] = Rgb15::from(0x7fff);
+        lcd.pixeldata[1] = Rgb15::from(0x001f);
+        lcd.pixeldata[256] = Rgb15::from(0x03e0);
+        let pixels = |lcd: &Lcd| {
+            let buffer = lcd.framebuffer_rgb24();
+            assert_eq!(buffer.len(), Lcd::DISPLAY_WIDTH * Lcd::DISPLAY_HEIGHT);
+            (buffer[0], buffer[1], buffer[2], buffer[Lcd::DISPLAY_WIDTH])
+        };
+
+        assert_eq!(
+            pixels(&lcd),
+            ((248, 248, 248), (248, 0, 0), (0, 0, 0), (0, 248, 0))
+        );
+
+        // duller, with the channels bleeding into each other, black stays black
+        lcd.set_color_correction(true);
+        lcd.reset();
+        assert!(lcd.color_correction());
+        lcd.pixeldata[0] = Rgb15::from(0x7fff);
+        lcd.pixeldata[1] = Rgb15::from(0x001f);
+        lcd.pixeldata[256] = Rgb15::from(0x03e0);
+        assert_eq!(
+            pixels(&lcd),
+            ((251, 238, 242), (232, 53, 110), (0, 0, 0), (110, 221, 53))
+        );
+    }
+
+    #[test]
+    fn obj_window_masks_background() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let mut lcd = Lcd::new();
+
+        // BG0 covered in a solid tile across the first two rows
+        sysbus.ioregs.write_reg(REG_BG0CNT, 8 << 8);
+        for i in 0..Lcd::TILE_SIZE {
+            sysbus.write_8(VRAM_ADDR + Lcd::TILE_SIZE + i, 0x11);
+        }
+        for entry in 0..64 {
+            sysbus.write_16(VRAM_ADDR + 8 * 0x800 + 2 * entry, 1);
+        }
+        let backdrop = Rgb15::from(0x7c00);
+        let bg_color = Rgb15::from(0x001f);
+        sysbus.write_16(0x0500_0000, 0x7c00);
+        sysbus.write_16(0x0500_0002, 0x001f);
+        sysbus.write_16(0x0500_0202, 0x03e0);
+
+        // OBJ tile 1 has its left half opaque
+        for row in 0..8 {
+            sysbus.write_32(OBJ_VRAM_ADDR + Lcd::TILE_SIZE + 4 * row, 0x0000_1111);
+        }
+        // 8x8 OBJ window sprites at x=16 and, flipped, at x=40
+        sysbus.write_16(OAM_ADDR, 0x0800);
+        sysbus.write_16(OAM_ADDR + 2, 16);
+        sysbus.write_16(OAM_ADDR + 4, 1);
+        sysbus.write_16(OAM_ADDR + 8, 0x0800);
+        sysbus.write_16(OAM_ADDR + 10, (1 << 12) | 40);
+        sysbus.write_16(OAM_ADDR + 12, 1);
+
+        // BG0 shows outside the windows only
+        sysbus.ioregs.write_reg(REG_WINOUT, 0x0001);
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x9140);
+        lcd.scanline(&mut sysbus);
+        assert_eq!(lcd.pixeldata[15], bg_color);
+        assert_eq!(lcd.pixeldata[16], backdrop);
+        assert_eq!(lcd.pixeldata[19], backdrop);
+        assert_eq!(lcd.pixeldata[20], bg_color);
+        assert_eq!(lcd.pixeldata[43], bg_color);
+        assert_eq!(lcd.pixeldata[44], backdrop);
+        assert_eq!(lcd.pixeldata[47], backdrop);
+        assert_eq!(lcd.pixeldata[48], bg_color);
+
+        // the other way around with WINOUT
+        sysbus.ioregs.write_reg(REG_WINOUT, 0x0100);
+        lcd.scanline(&mut sysbus);
+        assert_eq!(lcd.pixeldata[15], backdrop);
+        assert_eq!(lcd.pixeldata[16], bg_color);
+
+        // no OBJ window without DISPCNT bit 15
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x1140);
+        lcd.scanline(&mut sysbus);
+        assert_eq!(lcd.pixeldata[15], bg_color);
+        assert_eq!(lcd.pixeldata[16], bg_color);
+
+        // the sprites end before line 8
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x9140);
+        sysbus.ioregs.write_reg(REG_WINOUT, 0x0001);
+        lcd.current_scanline = 7;
+        lcd.scanline(&mut sysbus);
+        assert_eq!(lcd.pixeldata[7 * 256 + 16], backdrop);
+        lcd.current_scanline = 8;
+        lcd.scanline(&mut sysbus);
+        assert_eq!(lcd.pixeldata[8 * 256 + 16], bg_color);
+    }
+
+    #[test]
+    fn mosaic() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let mut lcd = Lcd::new();
+        let pixel = |lcd: &Lcd, x: usize, y: usize| lcd.pixeldata[y * 256 + x];
+
+        // a gradient tile for BG0 everywhere and for an 8x8 sprite at (16, 8), each color
+        // number is its palette index
+        for y in 0..8 {
+            for x in (0..8).step_by(2) {
+                let index = |x: u32| ((x + y) % 15 + 1) as u8;
+                let byte = index(x) | index(x + 1) << 4;
+                sysbus.write_8(VRAM_ADDR + Lcd::TILE_SIZE + 4 * y + x / 2, byte);
+                sysbus.write_8(OBJ_VRAM_ADDR + Lcd::TILE_SIZE + 4 * y + x / 2, byte);
+            }
+        }
+        for entry in 0..32 * 32 {
+            sysbus.write_16(VRAM_ADDR + 8 * 0x800 + 2 * entry, 1);
+        }
+        for index in 1..16 {
+            sysbus.write_16(0x0500_0000 + 2 * index, index as u16);
+            sysbus.write_16(0x0500_0200 + 2 * index, 0x100 + index as u16);
+        }
+        let bg = |x: u32, y: u32| Rgb15::from(((x % 8 + y % 8) % 15 + 1) as u16);
+        let obj = |x: u32, y: u32| Rgb15::from(0x100 + ((x + y) % 15 + 1) as u16);
+        sysbus.write_16(OAM_ADDR, 8);
+        sysbus.write_16(OAM_ADDR + 2, 16);
+        sysbus.write_16(OAM_ADDR + 4, 1);
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x1140);
+        sysbus.ioregs.write_reg(REG_MOSAIC, 0x3333);
+
+        // nothing changes without the mosaic bits of BG0CNT and the sprite
+        sysbus.ioregs.write_reg(REG_BG0CNT, 8 << 8);
+        render_frame(&mut lcd, &mut sysbus);
+        assert_eq!(pixel(&lcd, 5, 6), bg(5, 6));
+        assert_eq!(pixel(&lcd, 19, 10), obj(3, 2));
+
+        // 4x4 cells repeat their top left pixel
+        sysbus.ioregs.write_reg(REG_BG0CNT, (8 << 8) | (1 << 6));
+        sysbus.write_16(OAM_ADDR, (1 << 12) | 8);
+        render_frame(&mut lcd, &mut sysbus);
+        for y in 0..8 {
+            for x in 0..16 {
+                assert_eq!(pixel(&lcd, x, y), bg(x as u32 & !3, y as u32 & !3));
+            }
+        }
+        for y in 0..8 {
+            for x in 0..8 {
+                let expected = obj(x as u32 & !3, y as u32 & !3);
+                assert_eq!(pixel(&lcd, 16 + x, 8 + y), expected);
+            }
+        }
+
+        // BG and OBJ sizes are separate, here 2x1 and 1x8
+        sysbus.ioregs.write_reg(REG_MOSAIC, 0x7001);
+        render_frame(&mut lcd, &mut sysbus);
+        assert_eq!(pixel(&lcd, 5, 6), bg(4, 6));
+        assert_eq!(pixel(&lcd, 19, 14), obj(3, 0));
+    }
+
+    #[test]
+    fn rect_windows() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let mut lcd = Lcd::new();
+        let pixel = |lcd: &Lcd, x: usize, y: usize| lcd.pixeldata[y * 256 + x];
+
+        // BG0 covered in a solid tile
+        sysbus.ioregs.write_reg(REG_BG0CNT, 8 << 8);
+        for i in 0..Lcd::TILE_SIZE {
+            sysbus.write_8(VRAM_ADDR + Lcd::TILE_SIZE + i, 0x11);
+        }
+        for entry in 0..32 * 32 {
+            sysbus.write_16(VRAM_ADDR + 8 * 0x800 + 2 * entry, 1);
+        }
+        let backdrop = Rgb15::from(0x7c00);
+        let bg_color = Rgb15::from(0x001f);
+        sysbus.write_16(0x0500_0000, 0x7c00);
+        sysbus.write_16(0x0500_0002, 0x001f);
+
+        // WIN0 over x 10..20 of lines 0..4 hides BG0, WIN1 under it over x 15..30 shows it
+        sysbus.ioregs.write_reg(REG_WIN0H, (10 << 8) | 20);
+        sysbus.ioregs.write_reg(REG_WIN0V, 4);
+        sysbus.ioregs.write_reg(REG_WIN1H, (15 << 8) | 30);
+        sysbus.ioregs.write_reg(REG_WIN1V, 160);
+        sysbus.ioregs.write_reg(REG_WININ, 0x0100);
+        sysbus.ioregs.write_reg(REG_WINOUT, 0x0000);
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x6100);
+        for line in 0..5 {
+            lcd.current_scanline = line;
+            lcd.scanline(&mut sysbus);
+        }
+        assert_eq!(pixel(&lcd, 9, 0), backdrop);
+        assert_eq!(pixel(&lcd, 10, 0), backdrop);
+        assert_eq!(pixel(&lcd, 19, 3), backdrop);
+        assert_eq!(pixel(&lcd, 20, 0), bg_color);
+        assert_eq!(pixel(&lcd, 29, 0), bg_color);
+        assert_eq!(pixel(&lcd, 30, 0), backdrop);
+        // WIN0 ends before line 4
+        assert_eq!(pixel(&lcd, 15, 4), bg_color);
+        assert_eq!(pixel(&lcd, 10, 4), backdrop);
+
+        // a right edge before the left one wraps around the screen
+        sysbus.ioregs.write_reg(REG_WIN0H, (230 << 8) | 8);
+        sysbus.ioregs.write_reg(REG_WINOUT, 0x0001);
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x2100);
+        lcd.current_scanline = 0;
+        lcd.scanline(&mut sysbus);
+        assert_eq!(pixel(&lcd, 3, 0), backdrop);
+        assert_eq!(pixel(&lcd, 8, 0), bg_color);
+        assert_eq!(pixel(&lcd, 229, 0), bg_color);
+        assert_eq!(pixel(&lcd, 235, 0), backdrop);
+
+        // no masking with the windows off
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x0100);
+        lcd.scanline(&mut sysbus);
+        assert_eq!(pixel(&lcd, 3, 0), bg_color);
+    }
+
+    #[test]
+    fn bitmap_modes() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let mut lcd = Lcd::new();
+        let pixel = |lcd: &Lcd, x: usize, y: usize| lcd.pixeldata[y * 256 + x];
+        sysbus.write_16(0x0500_0000, 0x1111);
+
+        // mode 3 is a single page of 240x160 colors
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x0403);
+        sysbus.write_16(VRAM_ADDR + 2 * (2 * 240 + 10), 0x7c00);
+        render_frame(&mut lcd, &mut sysbus);
+        assert_eq!(pixel(&lcd, 10, 2), Rgb15::from(0x7c00));
+        // drawn through the BG2 parameters, here stretched twice as wide
+        sysbus.ioregs.write_reg(REG_BG2PA, 0x80);
+        render_frame(&mut lcd, &mut sysbus);
+        assert_eq!(pixel(&lcd, 20, 2), Rgb15::from(0x7c00));
+        assert_eq!(pixel(&lcd, 21, 2), Rgb15::from(0x7c00));
+        assert_eq!(pixel(&lcd, 10, 2), Rgb15::from(0));
+        sysbus.ioregs.write_reg(REG_BG2PA, 0x100);
+
+        // mode 4 shows the second page with the frame select bit, index 0 is the backdrop
+        sysbus.write_16(0x0500_0006, 0x03e0);
+        sysbus.write_8(0x0600_a000 + 240 + 7, 3);
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x0414);
+        render_frame(&mut lcd, &mut sysbus);
+        assert_eq!(pixel(&lcd, 7, 1), Rgb15::from(0x03e0));
+        assert_eq!(pixel(&lcd, 8, 1), Rgb15::from(0x1111));
+
+        // mode 5 is 160x128, the rest of the screen is backdrop
+        sysbus.write_16(0x0600_a000 + 2 * (127 * 160 + 159), 0x001f);
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x0415);
+        render_frame(&mut lcd, &mut sysbus);
+        assert_eq!(pixel(&lcd, 159, 127), Rgb15::from(0x001f));
+        assert_eq!(pixel(&lcd, 160, 127), Rgb15::from(0x1111));
+        assert_eq!(pixel(&lcd, 159, 128), Rgb15::from(0x1111));
+
+        // nothing but backdrop with BG2 off
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x0015);
+        render_frame(&mut lcd, &mut sysbus);
+        assert_eq!(pixel(&lcd, 159, 127), Rgb15::from(0x1111));
+    }
+
+    #[test]
+    fn bitmap_windows_and_effects() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let mut lcd = Lcd::new();
+        let pixel = |lcd: &Lcd, x: usize| lcd.pixeldata[x];
+        let backdrop = Rgb15::from(0x1111);
+        let red = Rgb15::from(0x001f);
+        sysbus.write_16(0x0500_0000, 0x1111);
+        for x in 0..30 {
+            sysbus.write_16(VRAM_ADDR + 2 * x, 0x001f);
+        }
+
+        // mode 3 with WIN0 over x 10..20 hiding BG2, effects only outside of it
+        sysbus.ioregs.write_reg(REG_BG2PA, 0x100);
+        sysbus.ioregs.write_reg(REG_BG2PD, 0x100);
+        sysbus.ioregs.write_reg(REG_WIN0H, (10 << 8) | 20);
+        sysbus.ioregs.write_reg(REG_WIN0V, 160);
+        sysbus.ioregs.write_reg(REG_WININ, 0x0000);
+        sysbus.ioregs.write_reg(REG_WINOUT, 0x0024);
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x2403);
+        render_frame(&mut lcd, &mut sysbus);
+        assert_eq!(pixel(&lcd, 9), red);
+        assert_eq!(pixel(&lcd, 10), backdrop);
+        assert_eq!(pixel(&lcd, 19), backdrop);
+        assert_eq!(pixel(&lcd, 20), red);
+
+        // BG2 faded halfway to black
+        sysbus.ioregs.write_reg(REG_BLDCNT, 0x00c4);
+        sysbus.ioregs.write_reg(REG_BLDY, 8);
+        render_frame(&mut lcd, &mut sysbus);
+        assert_eq!(pixel(&lcd, 9), red.darken(8));
+        assert_eq!(pixel(&lcd, 10), backdrop);
+        assert_eq!(pixel(&lcd, 20), red.darken(8));
+
+        // BG2 blended half and half with the backdrop
+        sysbus.ioregs.write_reg(REG_BLDCNT, 0x2044);
+        sysbus.ioregs.write_reg(REG_BLDALPHA, 0x0808);
+        render_frame(&mut lcd, &mut sysbus);
+        assert_eq!(pixel(&lcd, 9), red.blend(backdrop, 8, 8));
+        assert_eq!(pixel(&lcd, 10), backdrop);
+    }
+
+    #[test]
+    fn raw_framebuffer() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let mut lcd = Lcd::new();
+
+        // mode 4, palette entry 5 at (17, 3)
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x0404);
+        sysbus.ioregs.write_reg(REG_BG2PA, 0x100);
+        sysbus.ioregs.write_reg(REG_BG2PD, 0x100);
+        sysbus.write_16(0x0500_0000 + 5 * 2, 0x2d6b);
+        sysbus.write_16(VRAM_ADDR + 3 * 240 + 16, 0x0500);
+
+        render_frame(&mut lcd, &mut sysbus);
+        let framebuffer = lcd.framebuffer_rgb15();
+        assert_eq!(framebuffer.len(), 256 * 256);
+        assert_eq!(framebuffer[3 * 256 + 17], Rgb15::from(0x2d6b));
+        assert_eq!(framebuffer[3 * 256 + 16], Rgb15::from(0));
+    }
+
+    #[test]
+    fn integer_scaling() {
+        let mut lcd = Lcd::new();
+        lcd.pixeldata[0] = Rgb15::from(0x7fff);
+        lcd.pixeldata[256 + 239] = Rgb15::from(0x001f);
+
+        let (buffer, scale) = lcd.scaled_framebuffer(1280, 720, false);
+        assert_eq!(scale, 4);
+        let width = 240 * 4;
+        assert_eq!(buffer.len(), width * 160 * 4);
+        assert_eq!(buffer[3 * width + 3], Rgb15::from(0x7fff));
+        assert_eq!(buffer[3 * width + 4], Rgb15::from(0));
+        assert_eq!(buffer[4 * width + width - 1], Rgb15::from(0x001f));
+        assert_eq!(buffer[4 * width - 1], Rgb15::from(0));
+
+        // too narrow for 4x
+        assert_eq!(lcd.scaled_framebuffer(900, 720, false).1, 3);
+        // smaller than the screen
+        assert_eq!(lcd.scaled_framebuffer(100, 100, false).0.len(), 240 * 160);
+
+        // the dots are 10 pixels wide and 9 tall
+        let (buffer, scale) = lcd.scaled_framebuffer(2400, 1440, true);
+        assert_eq!(scale, 9);
+        assert_eq!(buffer.len(), 2400 * 1440);
+        assert_eq!(lcd.scaled_framebuffer(2399, 1440, true).1, 8);
+    }
+
+    #[test]
+    fn force_mode() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let mut lcd = Lcd::new();
+
+        // mode 0 with BG2 enabled and an empty tilemap
+        sysbus.ioregs.write_reg(REG_DISPCNT, 0x0400);
+        sysbus.write_16(VRAM_ADDR + 2 * 5, 0x7fff);
+
+        lcd.force_mode(Some(3));
+        lcd.scanline(&mut sysbus);
+        assert_eq!(lcd.pixeldata[5], Rgb15::from(0x7fff));
+        assert_eq!(sysbus.ioregs.read_reg(REG_DISPCNT), 0x0400);
+
+        lcd.force_mode(None);
+        lcd.scanline(&mut sysbus);
+        assert_ne!(lcd.pixeldata[5], Rgb15::from(0x7fff));
+
+        // invalid modes clear the override instead of panicking
+        lcd.force_mode(Some(3));
+        lcd.force_mode(Some(6));
+        assert_eq!(lcd.forced_mode, None);
+        lcd.scanline(&mut sysbus);
+        assert_ne!(lcd.pixeldata[5], Rgb15::from(0x7fff));
+    }
+
+    #[test]
+    fn hblank_only_on_visible_lines() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let mut lcd = Lcd::new();
+        lcd.set_rendering(false);
+        // VBlank and HBlank IRQs
+        sysbus.ioregs.write_reg(REG_DISPSTAT, 0x18);
+        let next_event = |lcd: &mut Lcd, sysbus: &mut SysBus| {
+            let cycles = lcd.cycles_to_next_event();
+            lcd.step(cycles, sysbus).1
+        };
+        let hblank_flag = |sysbus: &SysBus| sysbus.ioregs.read_reg(REG_DISPSTAT).bit(1);
+
+        // a visible line, HBlank follows HDraw on the same line
+        assert_eq!(
+            next_event(&mut lcd, &mut sysbus),
+            Some(Interrupt::LCD_HBlank)
+        );
+        assert_eq!(lcd.state, HBlank);
+        assert_eq!(sysbus.ioregs.read_reg(REG_VCOUNT), 0);
+        assert!(hblank_flag(&sysbus));
+        assert_eq!(next_event(&mut lcd, &mut sysbus), None);
+        assert_eq!(lcd.state, HDraw);
+        assert_eq!(sysbus.ioregs.read_reg(REG_VCOUNT), 1);
+        assert!(!hblank_flag(&sysbus));
+
+        // line 159 has its HBlank too, then VBlank starts
+        let mut hblanks = 1;
+        while lcd.state != VBlank {
+            if next_event(&mut lcd, &mut sysbus) == Some(Interrupt::LCD_HBlank) {
+                hblanks += 1;
+            }
+        }
+        assert_eq!(hblanks, 160);
+        assert_eq!(sysbus.ioregs.read_reg(REG_VCOUNT), 160);
+        assert!(sysbus.ioregs.read_reg(REG_DISPSTAT).bit(0));
+
+        // VBlank lines go by a whole line at a time, without HBlank
+        for line in 161..Lcd::SCANLINES {
+            assert_eq!(next_event(&mut lcd, &mut sysbus), None);
+            assert_eq!(lcd.state, VBlank);
+            assert_eq!(sysbus.ioregs.read_reg(REG_VCOUNT), line as u16);
+            assert!(!hblank_flag(&sysbus));
+        }
+        next_event(&mut lcd, &mut sysbus);
+        assert_eq!(lcd.state, HDraw);
+        assert_eq!(sysbus.ioregs.read_reg(REG_VCOUNT), 0);
+        assert!(!sysbus.ioregs.read_reg(REG_DISPSTAT).bit(0));
+    }
+}
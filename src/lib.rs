@@ -15,19 +15,30 @@ extern crate ansi_term;
 extern crate colored; // not needed in Rust 2018
 
 pub mod arm7tdmi;
+pub mod bios;
 pub mod cartridge;
 pub mod debugger;
 pub mod disass;
+pub mod eeprom;
+pub mod flash;
+pub mod sram;
 pub mod sysbus;
 pub use sysbus::SysBus;
 pub mod interrupt;
 pub mod ioregs;
+pub mod keypad;
 pub use interrupt::Interrupt;
+pub mod frame_skip;
+pub mod game_config;
 pub mod gba;
+pub mod scheduler;
 pub use gba::GameBoyAdvance;
 pub mod dma;
 pub mod lcd;
 pub mod palette;
+pub mod savestate;
+pub mod sound;
+pub mod timer;
 pub mod util;
 
 pub trait EmuIoDev {
@@ -39,6 +50,8 @@ pub enum GBAError {
     IO(::std::io::Error),
     CpuError(arm7tdmi::CpuError),
     DebuggerError(debugger::DebuggerError),
+    SaveMismatch(cartridge::SaveMismatch),
+    SaveState(savestate::StateError),
 }
 
 pub type GBAResult<T> = Result<T, GBAError>;
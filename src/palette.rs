@@ -3,7 +3,7 @@ use std::fmt;
 use byteorder::{LittleEndian, ReadBytesExt};
 use std::io::Cursor;
 
-#[derive(Debug, Copy, Clone, Default)]
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
 pub struct Rgb15 {
     pub r: u8,
     pub g: u8,
@@ -32,6 +32,54 @@ impl Rgb15 {
     pub fn get_rgb24(&self) -> (u8, u8, u8) {
         (self.r << 3, self.g << 3, self.b << 3)
     }
+
+    /// Like `get_rgb24`, but approximating how the color looks on the GBA screen, which is
+    /// darker and less saturated than a PC monitor. The LCD response is taken as a gamma of 4,
+    /// with the channels bleeding into each other, brought back with the usual 2.2.
+    pub fn get_rgb24_corrected(&self) -> (u8, u8, u8) {
+        let lcd = |c: u8| (c as f32 / 31.0).powf(4.0);
+        let (r, g, b) = (lcd(self.r), lcd(self.g), lcd(self.b));
+        let out = |mix: f32| ((mix / 255.0).powf(1.0 / 2.2) * 255.0 * 255.0 / 280.0) as u8;
+        (
+            out(255.0 * r + 50.0 * g),
+            out(10.0 * r + 230.0 * g + 30.0 * b),
+            out(50.0 * r + 10.0 * g + 220.0 * b),
+        )
+    }
+
+    /// Alpha blending as the LCD does it, `eva` and `evb` are the weights of `self` and `other`
+    /// in 1/16ths, anything above 16 counts as 16
+    pub fn blend(self, other: Rgb15, eva: u8, evb: u8) -> Rgb15 {
+        let (eva, evb) = (eva.min(16) as u16, evb.min(16) as u16);
+        let mix = |a: u8, b: u8| ((a as u16 * eva + b as u16 * evb) / 16).min(31) as u8;
+        Rgb15 {
+            r: mix(self.r, other.r),
+            g: mix(self.g, other.g),
+            b: mix(self.b, other.b),
+        }
+    }
+
+    /// Fade towards white by `evy` 1/16ths, anything above 16 counts as 16
+    pub fn brighten(self, evy: u8) -> Rgb15 {
+        let evy = evy.min(16) as u16;
+        let fade = |c: u8| (c as u16 + (31 - c as u16) * evy / 16) as u8;
+        Rgb15 {
+            r: fade(self.r),
+            g: fade(self.g),
+            b: fade(self.b),
+        }
+    }
+
+    /// Fade towards black by `evy` 1/16ths, anything above 16 counts as 16
+    pub fn darken(self, evy: u8) -> Rgb15 {
+        let evy = evy.min(16) as u16;
+        let fade = |c: u8| (c as u16 - c as u16 * evy / 16) as u8;
+        Rgb15 {
+            r: fade(self.r),
+            g: fade(self.g),
+            b: fade(self.b),
+        }
+    }
 }
 
 #[derive(Debug, Primitive, Copy, Clone)]
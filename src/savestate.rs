@@ -0,0 +1,270 @@
+/// Importing save states written by other emulators.
+///
+/// Only mGBA's raw state is supported so far, that is the uncompressed `.ss` layout without the
+/// PNG wrapping. Timing state that has no counterpart here (the LCD position within a line,
+/// timer prescalers, DMA internals, audio) keeps its defaults and settles within a frame.
+use std::fmt;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use super::arm7tdmi::bus::Bus;
+use super::arm7tdmi::psr::RegPSR;
+use super::arm7tdmi::{CpuMode, CpuState};
+use super::gba::GameBoyAdvance;
+use super::ioregs::consts::*;
+use super::{GBAError, GBAResult};
+
+/// mGBA's version magic is this plus the version number
+const MGBA_MAGIC: u32 = 0x0100_0000;
+const MGBA_STATE_SIZE: usize = 0x6_1000;
+
+const MGBA_GPRS: usize = 0x20;
+const MGBA_CPSR: usize = 0x60;
+const MGBA_SPSR: usize = 0x64;
+const MGBA_BANKED_REGS: usize = 0x70;
+const MGBA_BANKED_SPSRS: usize = 0x118;
+const MGBA_IO: usize = 0x400;
+
+/// (address, offset in the state, size) of the memory dumps
+const MGBA_MEMORY: [(u32, usize, usize); 5] = [
+    (0x0500_0000, 0x800, 0x400),       // palette
+    (0x0700_0000, 0xc00, 0x400),       // OAM
+    (0x0600_0000, 0x1000, 0x1_8000),   // VRAM
+    (0x0300_0000, 0x1_9000, 0x8000),   // IWRAM
+    (0x0200_0000, 0x2_1000, 0x4_0000), // EWRAM
+];
+
+/// The modes in the order of mGBA's register banks, which is also `CpuMode::bank_index`
+const BANK_MODES: [CpuMode; 6] = [
+    CpuMode::User,
+    CpuMode::Fiq,
+    CpuMode::Irq,
+    CpuMode::Supervisor,
+    CpuMode::Abort,
+    CpuMode::Undefined,
+];
+
+/// A save state that couldn't be imported
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StateError {
+    /// Shorter than the format, holds the size in bytes
+    Truncated(usize),
+    /// Not the format's magic number, holds what was found instead
+    BadMagic(u32),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::Truncated(size) => write!(
+                f,
+                "the save state is {} bytes, expected {}",
+                size, MGBA_STATE_SIZE
+            ),
+            StateError::BadMagic(magic) => {
+                write!(f, "not an mGBA save state (magic {:#010x})", magic)
+            }
+        }
+    }
+}
+
+/// The parts of an mGBA save state that map onto this emulator
+#[derive(Debug, Clone)]
+pub struct MgbaState {
+    /// r0-r15 of the current mode, r15 is two instructions ahead of the next to execute
+    pub gprs: [u32; 16],
+    pub cpsr: u32,
+    /// SPSR of the current mode
+    pub spsr: u32,
+    /// r13, r14 and r8-r12 per bank, only the user and FIQ banks hold r8-r12. The bank of the
+    /// current mode is stale, its registers are in `gprs`.
+    pub banked_regs: [[u32; 7]; 6],
+    pub banked_spsrs: [u32; 6],
+    data: Vec<u8>,
+}
+
+impl MgbaState {
+    pub fn parse(data: &[u8]) -> Result<MgbaState, StateError> {
+        if data.len() < MGBA_STATE_SIZE {
+            return Err(StateError::Truncated(data.len()));
+        }
+        let magic = LittleEndian::read_u32(data);
+        if magic & 0xff00_0000 != MGBA_MAGIC {
+            return Err(StateError::BadMagic(magic));
+        }
+        let word = |offset: usize| LittleEndian::read_u32(&data[offset..]);
+
+        let mut gprs = [0; 16];
+        for (r, gpr) in gprs.iter_mut().enumerate() {
+            *gpr = word(MGBA_GPRS + 4 * r);
+        }
+        let mut banked_regs = [[0; 7]; 6];
+        for (bank, regs) in banked_regs.iter_mut().enumerate() {
+            for (r, reg) in regs.iter_mut().enumerate() {
+                *reg = word(MGBA_BANKED_REGS + 4 * (7 * bank + r));
+            }
+        }
+        let mut banked_spsrs = [0; 6];
+        for (bank, spsr) in banked_spsrs.iter_mut().enumerate() {
+            *spsr = word(MGBA_BANKED_SPSRS + 4 * bank);
+        }
+
+        Ok(MgbaState {
+            gprs,
+            cpsr: word(MGBA_CPSR),
+            spsr: word(MGBA_SPSR),
+            banked_regs,
+            banked_spsrs,
+            data: data[..MGBA_STATE_SIZE].to_vec(),
+        })
+    }
+
+    /// The I/O register at `addr` as it was saved
+    pub fn io_reg(&self, addr: u32) -> u16 {
+        LittleEndian::read_u16(&self.data[MGBA_IO + (addr - IO_BASE) as usize..])
+    }
+
+    /// Load the CPU registers, I/O registers and memory into `gba`, which should already have
+    /// the game and BIOS the state was saved with
+    pub fn apply(&self, gba: &mut GameBoyAdvance) {
+        for &(addr, offset, size) in MGBA_MEMORY.iter() {
+            gba.sysbus.get_bytes_mut(addr)[..size]
+                .copy_from_slice(&self.data[offset..offset + size]);
+        }
+
+        // through the bus so that timers and wait states pick up their settings, but IF is
+        // latched and not acknowledged
+        for addr in (IO_BASE..IO_BASE + 0x400).step_by(2) {
+            let value = self.io_reg(addr);
+            if addr == REG_IF {
+                gba.sysbus.ioregs.intc.reg_if = value;
+            } else {
+                gba.sysbus.write_16(addr, value);
+            }
+        }
+
+        self.apply_cpu(gba);
+    }
+
+    fn apply_cpu(&self, gba: &mut GameBoyAdvance) {
+        let cpu = &mut gba.cpu;
+        cpu.set_cpsr(self.cpsr);
+        let mode = cpu.cpsr.mode();
+
+        for r in 0..15 {
+            cpu.set_reg(r, self.gprs[r]);
+        }
+        for (bank, &bank_mode) in BANK_MODES.iter().enumerate() {
+            if bank != mode.bank_index() {
+                cpu.set_banked_reg(bank_mode, 13, self.banked_regs[bank][0]);
+                cpu.set_banked_reg(bank_mode, 14, self.banked_regs[bank][1]);
+            }
+            if let Some(index) = bank_mode.spsr_index() {
+                cpu.spsr[index] = RegPSR::new(if bank == mode.bank_index() {
+                    self.spsr
+                } else {
+                    self.banked_spsrs[bank]
+                });
+            }
+        }
+        // the r8-r12 of the modes not running
+        let (other_mode, other_bank) = if mode == CpuMode::Fiq {
+            (CpuMode::User, 0)
+        } else {
+            (CpuMode::Fiq, 1)
+        };
+        for r in 0..5 {
+            cpu.set_banked_reg(other_mode, 8 + r, self.banked_regs[other_bank][2 + r]);
+        }
+
+        // mGBA's r15 is past the prefetched instructions, refetch from the next to execute
+        let word_size = match cpu.cpsr.state() {
+            CpuState::ARM => 4,
+            CpuState::THUMB => 2,
+        };
+        cpu.pc = self.gprs[15].wrapping_sub(2 * word_size);
+        cpu.pipeline_arm.flush();
+        cpu.pipeline_thumb.flush();
+    }
+}
+
+impl GameBoyAdvance {
+    /// Import an uncompressed mGBA save state, see `MgbaState::apply`
+    pub fn import_mgba_state(&mut self, data: &[u8]) -> GBAResult<()> {
+        let state = MgbaState::parse(data).map_err(GBAError::SaveState)?;
+        state.apply(self);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arm7tdmi::Core;
+    use crate::cartridge::Cartridge;
+
+    fn put_word(data: &mut [u8], offset: usize, value: u32) {
+        LittleEndian::write_u32(&mut data[offset..], value);
+    }
+
+    #[test]
+    fn import_mgba() {
+        let mut gba = GameBoyAdvance::new(Core::new(), vec![0; 0x4000], Cartridge::new(vec![]));
+
+        let mut data = vec![0; MGBA_STATE_SIZE];
+        assert_eq!(
+            MgbaState::parse(&data[..0x1000]).err(),
+            Some(StateError::Truncated(0x1000))
+        );
+        assert_eq!(MgbaState::parse(&data).err(), Some(StateError::BadMagic(0)));
+
+        put_word(&mut data, 0, MGBA_MAGIC | 2);
+        for r in 0..15 {
+            put_word(&mut data, MGBA_GPRS + 4 * r, 0x100 + r as u32);
+        }
+        // thumb, in IRQ mode, about to execute 0x0800_0100
+        put_word(&mut data, MGBA_GPRS + 4 * 15, 0x0800_0104);
+        put_word(&mut data, MGBA_CPSR, 0x4000_0032);
+        put_word(&mut data, MGBA_SPSR, 0x1f);
+        // user r13 and r14, a stale user r8 as IRQ mode shares it, supervisor r13 and SPSR
+        put_word(&mut data, MGBA_BANKED_REGS, 0x0300_7f00);
+        put_word(&mut data, MGBA_BANKED_REGS + 4, 0x0800_0200);
+        put_word(&mut data, MGBA_BANKED_REGS + 8, 0x88);
+        put_word(&mut data, MGBA_BANKED_REGS + 4 * 7 * 3, 0x0300_7fe0);
+        put_word(&mut data, MGBA_BANKED_SPSRS + 4 * 3, 0x1f);
+        // a word of IWRAM and the end of EWRAM
+        put_word(&mut data, 0x1_9000 + 0x10, 0xdead_beef);
+        put_word(&mut data, 0x2_1000 + 0x3_fffc, 0x1234_5678);
+        // IE and IME
+        data[MGBA_IO + 0x200] = 0x01;
+        data[MGBA_IO + 0x208] = 0x01;
+
+        gba.import_mgba_state(&data).unwrap();
+
+        let cpu = &mut gba.cpu;
+        assert_eq!(cpu.cpsr.mode(), CpuMode::Irq);
+        assert_eq!(cpu.cpsr.state(), CpuState::THUMB);
+        assert!(cpu.cpsr.Z());
+        assert_eq!(cpu.get_next_pc(), 0x0800_0100);
+        for r in 0..15 {
+            assert_eq!(cpu.get_reg(r), 0x100 + r as u32);
+        }
+        assert_eq!(cpu.spsr[CpuMode::Irq.spsr_index().unwrap()].get(), 0x1f);
+        assert_eq!(
+            cpu.spsr[CpuMode::Supervisor.spsr_index().unwrap()].get(),
+            0x1f
+        );
+        cpu.set_cpsr(0x1f);
+        assert_eq!(cpu.get_reg(13), 0x0300_7f00);
+        assert_eq!(cpu.get_reg(14), 0x0800_0200);
+        // r8-r12 aren't banked between IRQ and system mode
+        assert_eq!(cpu.get_reg(8), 0x108);
+        cpu.set_cpsr(0x13);
+        assert_eq!(cpu.get_reg(13), 0x0300_7fe0);
+
+        assert_eq!(gba.sysbus.read_32(0x0300_0010), 0xdead_beef);
+        assert_eq!(gba.sysbus.read_32(0x0203_fffc), 0x1234_5678);
+        assert_eq!(gba.sysbus.read_16(REG_IE), 1);
+        assert_eq!(gba.sysbus.read_16(REG_IME), 1);
+    }
+}
@@ -0,0 +1,146 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// Something a device wants done at a given cycle
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EventKind {
+    /// The LCD moves on to its next state: HBlank, the next line or VBlank
+    Lcd,
+    /// The next overflow of a timer counting cycles, and of the count-up timers above it
+    TimerOverflow,
+    /// A DMA control register was written, a channel may start an immediate transfer
+    DmaImmediate,
+    /// Sound FIFO A (0) or B (1) ran low and asks its DMA channel for more samples
+    FifoRefill(usize),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Event {
+    cycle: usize,
+    /// Order of scheduling, events due at the same cycle run first come first served
+    seq: usize,
+    kind: EventKind,
+}
+
+impl Ord for Event {
+    /// Reversed, so that the max-heap pops the earliest event
+    fn cmp(&self, other: &Event) -> Ordering {
+        (other.cycle, other.seq).cmp(&(self.cycle, self.seq))
+    }
+}
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Event) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Runs device events at the CPU cycle they are due, so devices don't have to be polled after
+/// every instruction. Cycles are absolute, on the same count as `Core::cycles`.
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    /// The cycle the last popped event was due at
+    now: usize,
+    events: BinaryHeap<Event>,
+    next_seq: usize,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Default::default()
+    }
+
+    pub fn now(&self) -> usize {
+        self.now
+    }
+
+    /// Run `kind` at `cycle`, which may already be past, then it's popped by the next `pop_due`
+    pub fn schedule_at(&mut self, kind: EventKind, cycle: usize) {
+        self.events.push(Event {
+            cycle,
+            seq: self.next_seq,
+            kind,
+        });
+        self.next_seq += 1;
+    }
+
+    /// Run `kind` `in_cycles` from now
+    pub fn schedule(&mut self, kind: EventKind, in_cycles: usize) {
+        self.schedule_at(kind, self.now + in_cycles);
+    }
+
+    /// Drop every pending `kind` event
+    pub fn cancel(&mut self, kind: EventKind) {
+        self.events.retain(|event| event.kind != kind);
+    }
+
+    /// Drop every pending event
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    /// When the next `kind` event is due, if one is pending
+    pub fn event_cycle(&self, kind: EventKind) -> Option<usize> {
+        self.events
+            .iter()
+            .filter(|event| event.kind == kind)
+            .map(|event| event.cycle)
+            .min()
+    }
+
+    /// When the earliest pending event is due
+    pub fn next_event_cycle(&self) -> Option<usize> {
+        self.events.peek().map(|event| event.cycle)
+    }
+
+    /// Pop the earliest event due at or before `cycle`, returns it with the cycle it was due at
+    pub fn pop_due(&mut self, cycle: usize) -> Option<(EventKind, usize)> {
+        match self.events.peek() {
+            Some(event) if event.cycle <= cycle => {
+                let event = self.events.pop().unwrap();
+                self.now = self.now.max(event.cycle);
+                Some((event.kind, event.cycle))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dispatch_order() {
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(EventKind::Lcd, 100);
+        scheduler.schedule_at(EventKind::Lcd, 50);
+        assert_eq!(scheduler.next_event_cycle(), Some(50));
+        assert_eq!(scheduler.pop_due(49), None);
+
+        // an event scheduled on the way is popped too if it's due
+        let mut dispatched = vec![];
+        while let Some((_, due)) = scheduler.pop_due(120) {
+            dispatched.push(due);
+            if due == 50 {
+                scheduler.schedule_at(EventKind::Lcd, 60);
+            }
+        }
+        assert_eq!(dispatched, [50, 60, 100]);
+        assert_eq!(scheduler.now(), 100);
+        assert_eq!(scheduler.next_event_cycle(), None);
+
+        // relative to the last event
+        scheduler.schedule(EventKind::Lcd, 10);
+        assert_eq!(scheduler.event_cycle(EventKind::Lcd), Some(110));
+        scheduler.cancel(EventKind::Lcd);
+        assert_eq!(scheduler.event_cycle(EventKind::Lcd), None);
+
+        scheduler.schedule(EventKind::TimerOverflow, 10);
+        scheduler.schedule(EventKind::FifoRefill(1), 20);
+        scheduler.cancel(EventKind::FifoRefill(0));
+        assert_eq!(scheduler.next_event_cycle(), Some(110));
+        scheduler.clear();
+        assert_eq!(scheduler.next_event_cycle(), None);
+    }
+}
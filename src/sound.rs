@@ -0,0 +1,278 @@
+use std::collections::VecDeque;
+
+use super::arm7tdmi::Addr;
+use super::ioregs::consts::*;
+
+use crate::bit::BitIndex;
+
+/// Bytes in each wave RAM bank, 32 4-bit samples
+const BANK_SIZE: usize = 16;
+/// Samples a direct sound FIFO holds
+const FIFO_SIZE: usize = 32;
+
+/// Channel 3's two banks of wave RAM.
+///
+/// SOUND3CNT_L selects the bank that is played, the CPU reads and writes the other one through
+/// REG_WAVE_RAM. The playing bank can't be accessed at all, so games fill the idle bank and then
+/// swap. In the 64 sample mode both banks are played, starting with the selected one.
+#[derive(Debug, Default)]
+pub struct WaveRam {
+    banks: [[u8; BANK_SIZE]; 2],
+    /// SOUND3CNT_L
+    ctl: u16,
+}
+
+impl WaveRam {
+    pub fn new() -> WaveRam {
+        Default::default()
+    }
+
+    /// The bank being played, the CPU sees the other one
+    pub fn playing_bank(&self) -> usize {
+        self.ctl.bit(6) as usize
+    }
+
+    pub fn two_banks(&self) -> bool {
+        self.ctl.bit(5)
+    }
+
+    /// The 4-bit samples channel 3 plays in order, the high nibble of each byte first
+    pub fn samples(&self) -> Vec<u8> {
+        let playing = self.playing_bank();
+        let mut banks = vec![&self.banks[playing]];
+        if self.two_banks() {
+            banks.push(&self.banks[playing ^ 1]);
+        }
+        banks
+            .into_iter()
+            .flat_map(|bank| bank.iter())
+            .flat_map(|byte| vec![byte >> 4, byte & 0xf])
+            .collect()
+    }
+
+    /// Offset into the CPU's bank of a halfword of wave RAM
+    fn decode_addr(addr: Addr) -> Option<usize> {
+        match addr {
+            0x0400_0090...0x0400_009e => Some((addr - REG_WAVE_RAM) as usize),
+            _ => None,
+        }
+    }
+
+    /// Reads of wave RAM, `None` for other addresses
+    pub fn read(&self, addr: Addr) -> Option<u16> {
+        let offset = Self::decode_addr(addr)?;
+        let bank = &self.banks[self.playing_bank() ^ 1];
+        Some(bank[offset] as u16 | (bank[offset + 1] as u16) << 8)
+    }
+
+    /// Writes to wave RAM and the bank selection, other addresses are ignored
+    pub fn write(&mut self, addr: Addr, value: u16) {
+        if addr == REG_SOUND3CNT_L {
+            self.ctl = value;
+        } else if let Some(offset) = Self::decode_addr(addr) {
+            let bank = &mut self.banks[self.playing_bank() ^ 1];
+            bank[offset] = value as u8;
+            bank[offset + 1] = (value >> 8) as u8;
+        }
+    }
+
+    /// Byte writes to wave RAM, returns whether `addr` was in wave RAM
+    pub fn write_8(&mut self, addr: Addr, value: u8) -> bool {
+        match addr {
+            0x0400_0090...0x0400_009f => {
+                let offset = (addr - REG_WAVE_RAM) as usize;
+                self.banks[self.playing_bank() ^ 1][offset] = value;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The FIFO of a direct sound channel, 8-bit signed samples written through FIFO_A or FIFO_B.
+///
+/// A sample is played on every overflow of the timer SOUNDCNT_H selects, and once no more than
+/// half of it is left the FIFO asks its DMA channel for another 4 words.
+#[derive(Debug, Default)]
+pub struct SoundFifo {
+    samples: VecDeque<i8>,
+    /// The sample being played
+    current: i8,
+}
+
+impl SoundFifo {
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// The samples queued, the next to play first
+    pub fn samples(&self) -> Vec<i8> {
+        self.samples.iter().cloned().collect()
+    }
+
+    pub fn current_sample(&self) -> i8 {
+        self.current
+    }
+
+    /// Queue the two samples of a halfword write, the low byte first
+    fn push(&mut self, value: u16) {
+        self.push_sample(value as u8);
+        self.push_sample((value >> 8) as u8);
+    }
+
+    /// Queue a sample, a full FIFO drops it
+    fn push_sample(&mut self, sample: u8) {
+        if self.samples.len() < FIFO_SIZE {
+            self.samples.push_back(sample as i8);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Move on to the next sample when the timer overflows, an empty FIFO keeps the last one.
+    /// Returns whether the FIFO wants more data.
+    pub fn timer_overflow(&mut self) -> bool {
+        if let Some(sample) = self.samples.pop_front() {
+            self.current = sample;
+        }
+        self.samples.len() <= FIFO_SIZE / 2
+    }
+}
+
+/// Direct sound A and B, fed through their FIFOs and clocked by timer 0 or 1
+#[derive(Debug, Default)]
+pub struct DirectSound {
+    pub fifos: [SoundFifo; 2],
+    /// SOUNDCNT_H
+    ctl: u16,
+}
+
+impl DirectSound {
+    pub fn new() -> DirectSound {
+        Default::default()
+    }
+
+    /// The timer whose overflows play `fifo` (0 for A, 1 for B)
+    pub fn timer(&self, fifo: usize) -> usize {
+        self.ctl.bit(10 + 4 * fifo) as usize
+    }
+
+    /// Writes to the FIFOs and SOUNDCNT_H, other addresses are ignored
+    pub fn write(&mut self, addr: Addr, value: u16) {
+        match addr {
+            REG_SOUNDCNT_H => {
+                self.ctl = value;
+                for fifo in 0..2 {
+                    if value.bit(11 + 4 * fifo) {
+                        self.fifos[fifo].clear();
+                    }
+                }
+            }
+            0x0400_00a0...0x0400_00a3 => self.fifos[0].push(value),
+            0x0400_00a4...0x0400_00a7 => self.fifos[1].push(value),
+            _ => {}
+        }
+    }
+
+    /// Byte writes to the FIFOs queue a single sample, returns whether `addr` was in a FIFO
+    pub fn write_8(&mut self, addr: Addr, value: u8) -> bool {
+        match addr {
+            0x0400_00a0...0x0400_00a3 => self.fifos[0].push_sample(value),
+            0x0400_00a4...0x0400_00a7 => self.fifos[1].push_sample(value),
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arm7tdmi::Bus;
+    use crate::ioregs::IoRegs;
+
+    #[test]
+    fn write_idle_bank_then_swap() {
+        let mut ioregs = IoRegs::default();
+        let wave_offset = REG_WAVE_RAM - IO_BASE;
+        let ctl_offset = REG_SOUND3CNT_L - IO_BASE;
+
+        // bank 0 playing, the pattern lands in bank 1
+        ioregs.write_16(ctl_offset, 0);
+        for i in 0..8 {
+            ioregs.write_16(wave_offset + 2 * i, 0x0123 + 0x1111 * i as u16);
+        }
+        assert_eq!(ioregs.read_16(wave_offset), 0x0123);
+        assert_eq!(ioregs.wave_ram.samples(), vec![0; 32]);
+
+        // play bank 1, the CPU now sees the untouched bank 0
+        ioregs.write_16(ctl_offset, 1 << 6);
+        assert_eq!(ioregs.read_16(wave_offset), 0);
+        let samples = ioregs.wave_ram.samples();
+        assert_eq!(samples.len(), 32);
+        assert_eq!(samples[..8], [2, 3, 0, 1, 3, 4, 1, 2]);
+
+        // fill bank 0 through the CPU side, playing both banks starts with the selected one
+        ioregs.write_32(wave_offset, 0xffff_ffff);
+        ioregs.write_16(ctl_offset, (1 << 6) | (1 << 5));
+        let samples = ioregs.wave_ram.samples();
+        assert_eq!(samples.len(), 64);
+        assert_eq!(samples[..4], [2, 3, 0, 1]);
+        assert_eq!(samples[32..40], [0xf; 8]);
+        assert_eq!(samples[40], 0);
+
+        // a byte write leaves the neighbouring samples alone
+        ioregs.write_16(ctl_offset, 0);
+        ioregs.write_8(wave_offset + 1, 0xab);
+        assert_eq!(ioregs.read_16(wave_offset), 0xab23);
+        ioregs.write_8(wave_offset + 2, 0xcd);
+        assert_eq!(ioregs.read_16(wave_offset + 2), 0x12cd);
+    }
+
+    #[test]
+    fn direct_sound_fifo() {
+        let mut ioregs = IoRegs::default();
+        let fifo_a = REG_FIFO_A - IO_BASE;
+
+        // 4 samples per word, the low byte first
+        ioregs.write_32(fifo_a, 0x80ff_0201);
+        ioregs.write_16(REG_FIFO_B - IO_BASE, 0x0403);
+        let fifos = &mut ioregs.direct_sound.fifos;
+        assert_eq!(fifos[0].samples(), vec![1, 2, -1, -128]);
+        assert_eq!(fifos[1].samples(), vec![3, 4]);
+        // a byte is a single sample
+        ioregs.write_8(REG_FIFO_B + 3 - IO_BASE, 0xfb);
+        assert_eq!(ioregs.direct_sound.fifos[1].samples(), vec![3, 4, -5]);
+        let fifos = &mut ioregs.direct_sound.fifos;
+        assert!(fifos[0].timer_overflow());
+        assert_eq!(fifos[0].current_sample(), 1);
+        assert_eq!(fifos[0].len(), 3);
+
+        // holds 32 samples, and wants more once half of them are played
+        for _ in 0..10 {
+            ioregs.write_32(fifo_a, 0x0505_0505);
+        }
+        let fifo = &mut ioregs.direct_sound.fifos[0];
+        assert_eq!(fifo.len(), 32);
+        assert!(!fifo.timer_overflow());
+        assert_eq!(fifo.current_sample(), 2);
+        for _ in 0..14 {
+            assert!(!fifo.timer_overflow());
+        }
+        assert!(fifo.timer_overflow());
+        assert_eq!(fifo.len(), 16);
+
+        // playing from timer 1 and resetting FIFO A
+        ioregs.write_16(REG_SOUNDCNT_H - IO_BASE, 1 << 10 | 1 << 11);
+        assert!(ioregs.direct_sound.fifos[0].is_empty());
+        assert_eq!(ioregs.direct_sound.fifos[1].len(), 3);
+        assert_eq!(ioregs.direct_sound.timer(0), 1);
+        assert_eq!(ioregs.direct_sound.timer(1), 0);
+    }
+}
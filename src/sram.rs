@@ -0,0 +1,111 @@
+use super::arm7tdmi::{
+    bus::{Bus, MemoryAccess, MemoryAccessWidth},
+    Addr,
+};
+use super::sysbus::WaitState;
+
+const SIZE: usize = 32 * 1024;
+
+/// Battery backed SRAM save memory at 0x0E00_0000, 32K repeated over the 64K window.
+///
+/// The chip sits on an 8 bit bus, wider reads see the byte repeated and wider writes only store
+/// the low byte.
+#[derive(Debug)]
+pub struct Sram {
+    memory: Box<[u8]>,
+    ws: WaitState,
+}
+
+impl Sram {
+    pub fn new() -> Sram {
+        Sram {
+            memory: vec![0; SIZE].into_boxed_slice(),
+            ws: WaitState::sram(4),
+        }
+    }
+
+    pub fn save_data(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Restore a save file, which is as big as the SRAM
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        self.memory.copy_from_slice(data);
+    }
+
+    fn offset(addr: Addr) -> usize {
+        addr as usize % SIZE
+    }
+}
+
+impl Default for Sram {
+    fn default() -> Sram {
+        Sram::new()
+    }
+}
+
+impl Bus for Sram {
+    fn read_32(&self, addr: Addr) -> u32 {
+        self.read_8(addr) as u32 * 0x0101_0101
+    }
+
+    fn read_16(&self, addr: Addr) -> u16 {
+        self.read_8(addr) as u16 * 0x0101
+    }
+
+    fn read_8(&self, addr: Addr) -> u8 {
+        self.memory[Self::offset(addr)]
+    }
+
+    fn write_32(&mut self, addr: Addr, value: u32) {
+        self.write_8(addr, value as u8)
+    }
+
+    fn write_16(&mut self, addr: Addr, value: u16) {
+        self.write_8(addr, value as u8)
+    }
+
+    fn write_8(&mut self, addr: Addr, value: u8) {
+        self.memory[Self::offset(addr)] = value;
+    }
+
+    fn get_bytes(&self, addr: Addr) -> &[u8] {
+        &self.memory[Self::offset(addr)..]
+    }
+
+    fn get_bytes_mut(&mut self, addr: Addr) -> &mut [u8] {
+        &mut self.memory[Self::offset(addr)..]
+    }
+
+    fn get_cycles(&self, _addr: Addr, access: MemoryAccess) -> usize {
+        match access.1 {
+            MemoryAccessWidth::MemoryAccess8 => self.ws.access8,
+            MemoryAccessWidth::MemoryAccess16 => self.ws.access16,
+            MemoryAccessWidth::MemoryAccess32 => self.ws.access32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+    use crate::sysbus::SysBus;
+
+    #[test]
+    fn mirrored_8_bit_bus() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        sysbus.write_8(0x0e00_0010, 0xab);
+        assert_eq!(sysbus.read_8(0x0e00_8010), 0xab);
+        assert_eq!(sysbus.read_16(0x0e00_0010), 0xabab);
+        assert_eq!(sysbus.read_32(0x0e00_0010), 0xabab_abab);
+
+        sysbus.write_16(0x0e00_8020, 0x1234);
+        assert_eq!(sysbus.read_8(0x0e00_0020), 0x34);
+        assert_eq!(sysbus.read_8(0x0e00_0021), 0);
+
+        let sram = sysbus.cartridge().sram().unwrap();
+        assert_eq!(sram.save_data().len(), 32 * 1024);
+        assert_eq!(sram.save_data()[0x10], 0xab);
+    }
+}
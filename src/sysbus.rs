@@ -1,18 +1,38 @@
+use std::cell::{Cell, RefCell};
 use std::io;
+use std::mem;
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
-use super::{cartridge::Cartridge, ioregs::IoRegs};
+use crate::bit::BitIndex;
 
-use super::arm7tdmi::bus::{Bus, MemoryAccess, MemoryAccessWidth};
+use super::{
+    cartridge::Cartridge,
+    ioregs::{self, consts::*, IoRegs},
+};
+
+use super::arm7tdmi::bus::{
+    Bus, MemoryAccess, MemoryAccessType, MemoryAccessWidth, MemoryAccessWidth::*,
+};
 use super::arm7tdmi::Addr;
 
+const BIOS_SIZE: usize = 16 * 1024;
 const VIDEO_RAM_SIZE: usize = 128 * 1024;
 const WORK_RAM_SIZE: usize = 256 * 1024;
 const INTERNAL_RAM: usize = 32 * 1024;
 const PALETTE_RAM_SIZE: usize = 1 * 1024;
 const OAM_SIZE: usize = 1 * 1024;
 
+/// Wait states for each setting of the 2 bit WAITCNT fields, SRAM and gamepak nonsequential
+const WAIT_STATES: [usize; 4] = [4, 3, 2, 8];
+/// Sequential gamepak wait states of WS0, WS1 and WS2 when their WAITCNT bit is clear,
+/// setting it makes them 1
+const SEQ_WAIT_STATES: [usize; 3] = [2, 4, 8];
+
+/// Extra cycles charged for a CPU access to palette/VRAM/OAM while the LCD is drawing,
+/// when the approximate contention model is enabled.
+const VRAM_CONTENTION_PENALTY: usize = 1;
+
 #[derive(Debug)]
 pub struct BoxedMemory(Box<[u8]>, WaitState);
 
@@ -31,6 +51,9 @@ pub struct WaitState {
     pub access8: usize,
     pub access16: usize,
     pub access32: usize,
+    /// Sequential accesses, only the gamepak tells them apart
+    pub seq_access16: usize,
+    pub seq_access32: usize,
 }
 
 impl WaitState {
@@ -39,6 +62,34 @@ impl WaitState {
             access8,
             access16,
             access32,
+            seq_access16: access16,
+            seq_access32: access32,
+        }
+    }
+
+    /// The gamepak bus is 16 bits wide, a 32 bit access is followed by a sequential one
+    pub fn gamepak(nonseq_waits: usize, seq_waits: usize) -> WaitState {
+        WaitState {
+            access8: 1 + nonseq_waits,
+            access16: 1 + nonseq_waits,
+            access32: (1 + nonseq_waits) + (1 + seq_waits),
+            seq_access16: 1 + seq_waits,
+            seq_access32: 2 * (1 + seq_waits),
+        }
+    }
+
+    /// SRAM has an 8 bit bus, wider accesses take one 8 bit access per byte
+    pub fn sram(waits: usize) -> WaitState {
+        WaitState::new(1 + waits, 2 * (1 + waits), 4 * (1 + waits))
+    }
+
+    pub fn cycles(&self, access: MemoryAccess) -> usize {
+        match access {
+            MemoryAccess(_, MemoryAccess8) => self.access8,
+            MemoryAccess(MemoryAccessType::Seq, MemoryAccess16) => self.seq_access16,
+            MemoryAccess(MemoryAccessType::Seq, MemoryAccess32) => self.seq_access32,
+            MemoryAccess(MemoryAccessType::NonSeq, MemoryAccess16) => self.access16,
+            MemoryAccess(MemoryAccessType::NonSeq, MemoryAccess32) => self.access32,
         }
     }
 }
@@ -91,11 +142,7 @@ impl Bus for BoxedMemory {
     }
 
     fn get_cycles(&self, _addr: Addr, access: MemoryAccess) -> usize {
-        match access.1 {
-            MemoryAccessWidth::MemoryAccess8 => self.1.access8,
-            MemoryAccessWidth::MemoryAccess16 => self.1.access16,
-            MemoryAccessWidth::MemoryAccess32 => self.1.access32,
-        }
+        self.1.cycles(access)
     }
 }
 
@@ -134,6 +181,69 @@ impl Bus for DummyBus {
     }
 }
 
+/// The gamepak prefetch buffer, which reads the ROM ahead of the CPU while it's busy elsewhere.
+///
+/// It holds up to 8 halfwords following the last instruction fetched from the ROM, fetches that
+/// find their opcode there take a single cycle. Fetching somewhere else or reading data from the
+/// gamepak starts it over. Internal CPU cycles don't go through the bus, so only accesses to other
+/// memory give it time to fill.
+#[derive(Debug, Default, Copy, Clone)]
+struct Prefetch {
+    /// Only set after an instruction fetch from the ROM
+    active: bool,
+    /// Address of the halfword being read into the buffer
+    head: Addr,
+    /// Halfwords in the buffer, they're the ones just before `head`
+    count: usize,
+    /// Cycles spent reading `head` so far
+    progress: usize,
+    /// Cycles to read a halfword, the sequential access time of the region
+    halfword_cycles: usize,
+}
+
+impl Prefetch {
+    const CAPACITY: usize = 8;
+
+    /// Let the buffer fill for `cycles`
+    fn advance(&mut self, cycles: usize) {
+        if !self.active || self.count == Prefetch::CAPACITY {
+            return;
+        }
+        self.progress += cycles;
+        while self.progress >= self.halfword_cycles && self.count < Prefetch::CAPACITY {
+            self.progress -= self.halfword_cycles;
+            self.count += 1;
+            self.head += 2;
+        }
+        if self.count == Prefetch::CAPACITY {
+            self.progress = 0;
+        }
+    }
+
+    /// Cycles to fetch `halfwords` from `addr` out of the buffer, None if they're not in it or
+    /// being read into it
+    fn fetch(&mut self, addr: Addr, halfwords: usize) -> Option<usize> {
+        if !self.active {
+            return None;
+        }
+        let buffered = self.head - 2 * self.count as Addr;
+        if addr == buffered && self.count >= halfwords {
+            self.count -= halfwords;
+            self.advance(1);
+            Some(1)
+        } else if addr == buffered {
+            // wait for the rest to come in
+            let cycles = (halfwords - self.count) * self.halfword_cycles - self.progress;
+            self.head += 2 * (halfwords - self.count) as Addr;
+            self.count = 0;
+            self.progress = 0;
+            Some(cycles)
+        } else {
+            None
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct SysBus {
     bios: BoxedMemory,
@@ -145,12 +255,169 @@ pub struct SysBus {
     vram: BoxedMemory,
     oam: BoxedMemory,
     gamepak: Cartridge,
+    /// Access times of the WS0, WS1 and WS2 gamepak regions, set through WAITCNT
+    gamepak_waitstates: [WaitState; 3],
+    /// The WAITCNT prefetch enable bit
+    prefetch_enabled: bool,
+    prefetch: Cell<Prefetch>,
+    /// Access time of the SRAM region set through WAITCNT, whichever save memory is there
+    sram_waitstate: WaitState,
+    /// Address of the last instruction fetch, the BIOS can only be read while executing in it
+    fetch_addr: Cell<Addr>,
+    /// The last opcode fetched from the BIOS, which is what protected BIOS reads return
+    bios_latch: Cell<u32>,
+    /// The last prefetched opcode, what the CPU sees when reading unmapped memory or a write-only
+    /// register. Thumb opcodes show up in both halves.
+    open_bus: Cell<u32>,
     dummy: DummyBus,
+    /// Approximate CPU/LCD contention on video memory (slower, slightly more accurate)
+    vram_contention: bool,
+    /// Set when the CPU writes the Stop bit of HALTCNT
+    stop_requested: bool,
+    /// Set when the CPU writes HALTCNT without the Stop bit
+    halt_requested: bool,
+    /// Per region access counters, only kept when enabled
+    count_accesses: bool,
+    memory_stats: RefCell<MemoryStats>,
+}
+
+#[derive(Debug, Primitive, Copy, Clone, PartialEq)]
+pub enum MemoryRegion {
+    Bios = 0,
+    OnboardWorkRam = 1,
+    InternalWorkRam = 2,
+    IoRegs = 3,
+    PaletteRam = 4,
+    VideoRam = 5,
+    Oam = 6,
+    GamePak = 7,
+    Sram = 8,
+    Eeprom = 9,
+    Unmapped = 10,
+}
+
+impl MemoryRegion {
+    pub fn from_addr(addr: Addr) -> MemoryRegion {
+        match addr as usize {
+            0x0000_0000...0x0000_3fff => MemoryRegion::Bios,
+            0x0200_0000...0x02ff_ffff => MemoryRegion::OnboardWorkRam,
+            0x0300_0000...0x03ff_ffff => MemoryRegion::InternalWorkRam,
+            0x0400_0000...0x0400_03fe => MemoryRegion::IoRegs,
+            0x0500_0000...0x05ff_ffff => MemoryRegion::PaletteRam,
+            0x0600_0000...0x06ff_ffff => MemoryRegion::VideoRam,
+            0x0700_0000...0x07ff_ffff => MemoryRegion::Oam,
+            // WS0, with the WS1 and WS2 mirrors up to the EEPROM
+            0x0800_0000...0x0cff_ffff => MemoryRegion::GamePak,
+            0x0d00_0000...0x0dff_ffff => MemoryRegion::Eeprom,
+            0x0e00_0000...0x0e00_ffff => MemoryRegion::Sram,
+            _ => MemoryRegion::Unmapped,
+        }
+    }
+
+    /// First address of the region, offsets into it are from here
+    pub fn base(self) -> Addr {
+        match self {
+            MemoryRegion::Bios | MemoryRegion::Unmapped => 0x0000_0000,
+            MemoryRegion::OnboardWorkRam => 0x0200_0000,
+            MemoryRegion::InternalWorkRam => 0x0300_0000,
+            MemoryRegion::IoRegs => 0x0400_0000,
+            MemoryRegion::PaletteRam => 0x0500_0000,
+            MemoryRegion::VideoRam => 0x0600_0000,
+            MemoryRegion::Oam => 0x0700_0000,
+            MemoryRegion::GamePak => 0x0800_0000,
+            MemoryRegion::Eeprom => 0x0d00_0000,
+            MemoryRegion::Sram => 0x0e00_0000,
+        }
+    }
+}
+
+/// The region `addr` falls in and its offset into the region.
+///
+/// The RAMs repeat over their whole 16MB window. VRAM repeats every 128K, and the last 32K of
+/// those mirror the 32K before them, the OBJ tiles. The 32MB ROM window is repeated for the WS1
+/// and WS2 wait states.
+pub fn map_address(addr: Addr) -> (MemoryRegion, Addr) {
+    let region = MemoryRegion::from_addr(addr);
+    let offset = addr - region.base();
+    let offset = match region {
+        MemoryRegion::OnboardWorkRam => offset % WORK_RAM_SIZE as Addr,
+        MemoryRegion::InternalWorkRam => offset % INTERNAL_RAM as Addr,
+        MemoryRegion::PaletteRam => offset % PALETTE_RAM_SIZE as Addr,
+        MemoryRegion::Oam => offset % OAM_SIZE as Addr,
+        MemoryRegion::GamePak => offset % 0x0200_0000,
+        MemoryRegion::VideoRam => match offset % 0x2_0000 {
+            offset @ 0x1_8000...0x1_ffff => offset - 0x8000,
+            offset => offset,
+        },
+        _ => offset,
+    };
+    (region, offset)
+}
+
+/// Region, name, first and last address
+#[rustfmt::skip]
+const MEMORY_MAP: [(MemoryRegion, &str, Addr, Addr); 10] = [
+    (MemoryRegion::Bios,            "BIOS",          0x0000_0000, 0x0000_3fff),
+    (MemoryRegion::OnboardWorkRam,  "On-board WRAM", 0x0200_0000, 0x0203_ffff),
+    (MemoryRegion::InternalWorkRam, "On-chip WRAM",  0x0300_0000, 0x0300_7fff),
+    (MemoryRegion::IoRegs,          "I/O registers", 0x0400_0000, 0x0400_03fe),
+    (MemoryRegion::PaletteRam,      "Palette RAM",   0x0500_0000, 0x0500_03ff),
+    (MemoryRegion::VideoRam,        "VRAM",          0x0600_0000, 0x0601_7fff),
+    (MemoryRegion::Oam,             "OAM",           0x0700_0000, 0x0700_03ff),
+    (MemoryRegion::GamePak,         "Game Pak ROM",  0x0800_0000, 0x09ff_ffff),
+    (MemoryRegion::Eeprom,          "EEPROM",        0x0d00_0000, 0x0dff_ffff),
+    (MemoryRegion::Sram,            "Game Pak SRAM", 0x0e00_0000, 0x0e00_ffff),
+];
+
+/// An entry of `SysBus::memory_map`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegionInfo {
+    pub region: MemoryRegion,
+    pub name: &'static str,
+    pub start: Addr,
+    /// Last mapped address, inclusive
+    pub end: Addr,
+    pub size: usize,
+    /// Non-sequential access cycles for 8, 16 and 32 bit accesses
+    pub cycles: [usize; 3],
+}
+
+/// Number of reads and writes to each memory region, by access width
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MemoryStats {
+    reads: [[usize; 3]; 11],
+    writes: [[usize; 3]; 11],
+}
+
+impl MemoryStats {
+    pub fn reads(&self, region: MemoryRegion, width: MemoryAccessWidth) -> usize {
+        self.reads[region as usize][width as usize]
+    }
+
+    pub fn writes(&self, region: MemoryRegion, width: MemoryAccessWidth) -> usize {
+        self.writes[region as usize][width as usize]
+    }
+
+    pub fn total_reads(&self, region: MemoryRegion) -> usize {
+        self.reads[region as usize].iter().sum()
+    }
+
+    pub fn total_writes(&self, region: MemoryRegion) -> usize {
+        self.writes[region as usize].iter().sum()
+    }
 }
 
 impl SysBus {
-    pub fn new(bios_rom: Vec<u8>, gamepak: Cartridge) -> SysBus {
-        SysBus {
+    pub fn new(mut bios_rom: Vec<u8>, gamepak: Cartridge) -> SysBus {
+        // a missing BIOS is fine when it's never run (HLE, direct boot), the rest reads as 0
+        if bios_rom.len() < BIOS_SIZE {
+            eprintln!(
+                "warning: BIOS is only {} bytes, reads past its end return 0",
+                bios_rom.len()
+            );
+            bios_rom.resize(BIOS_SIZE, 0);
+        }
+        let mut sysbus = SysBus {
             bios: BoxedMemory::new(bios_rom.into_boxed_slice()),
             onboard_work_ram: BoxedMemory::new_with_waitstate(
                 vec![0; WORK_RAM_SIZE].into_boxed_slice(),
@@ -168,74 +435,797 @@ impl SysBus {
             ),
             oam: BoxedMemory::new(vec![0; OAM_SIZE].into_boxed_slice()),
             gamepak: gamepak,
+            gamepak_waitstates: [
+                WaitState::default(),
+                WaitState::default(),
+                WaitState::default(),
+            ],
+            prefetch_enabled: false,
+            prefetch: Cell::new(Default::default()),
+            sram_waitstate: WaitState::default(),
+            fetch_addr: Cell::new(0),
+            bios_latch: Cell::new(0),
+            open_bus: Cell::new(0),
             dummy: DummyBus([0; 4]),
+            vram_contention: false,
+            stop_requested: false,
+            halt_requested: false,
+            count_accesses: false,
+            memory_stats: RefCell::new(Default::default()),
+        };
+        sysbus.update_waitcnt(0);
+        sysbus
+    }
+
+    pub fn cartridge(&self) -> &Cartridge {
+        &self.gamepak
+    }
+
+    pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.gamepak
+    }
+
+    /// Insert a new cartridge and bring every memory back to its power-on state.
+    /// The BIOS and the debug settings are kept. Returns the cartridge that was removed.
+    pub fn reset_with_cartridge(&mut self, gamepak: Cartridge) -> Cartridge {
+        let bios = mem::replace(&mut self.bios, BoxedMemory::new(Box::new([])));
+        let mut sysbus = SysBus::new(bios.0.into_vec(), gamepak);
+        sysbus.vram_contention = self.vram_contention;
+        sysbus.count_accesses = self.count_accesses;
+        sysbus.ioregs.set_log_unhandled(self.ioregs.log_unhandled());
+        mem::replace(self, sysbus).gamepak
+    }
+
+    /// Zero the work RAMs, palette, VRAM and OAM, as the BIOS does before starting the game
+    pub fn clear_ram(&mut self) {
+        for memory in [
+            &mut self.onboard_work_ram,
+            &mut self.internal_work_ram,
+            &mut self.palette_ram,
+            &mut self.vram,
+            &mut self.oam,
+        ]
+        .iter_mut()
+        {
+            for byte in memory.0.iter_mut() {
+                *byte = 0;
+            }
         }
     }
 
-    fn map(&self, addr: Addr) -> &Bus {
-        match addr as usize {
-            0x0000_0000...0x0000_3fff => &self.bios,
-            0x0200_0000...0x0203_ffff => &self.onboard_work_ram,
-            0x0300_0000...0x0300_7fff => &self.internal_work_ram,
-            0x0400_0000...0x0400_03fe => &self.ioregs,
-            0x0500_0000...0x0500_03ff => &self.palette_ram,
-            0x0600_0000...0x0601_7fff => &self.vram,
-            0x0700_0000...0x0700_03ff => &self.oam,
-            0x0800_0000...0x09ff_ffff => &self.gamepak,
-            _ => &self.dummy,
+    pub fn set_vram_contention(&mut self, enabled: bool) {
+        self.vram_contention = enabled;
+    }
+
+    pub fn vram_contention(&self) -> bool {
+        self.vram_contention
+    }
+
+    /// Toggle counting memory accesses per region, see `memory_stats`
+    pub fn set_count_accesses(&mut self, enabled: bool) {
+        self.count_accesses = enabled;
+    }
+
+    pub fn memory_stats(&self) -> MemoryStats {
+        self.memory_stats.borrow().clone()
+    }
+
+    pub fn reset_memory_stats(&mut self) {
+        *self.memory_stats.borrow_mut() = Default::default();
+    }
+
+    fn count_read(&self, addr: Addr, width: MemoryAccessWidth) {
+        if self.count_accesses {
+            let region = MemoryRegion::from_addr(addr);
+            self.memory_stats.borrow_mut().reads[region as usize][width as usize] += 1;
         }
     }
 
-    /// TODO proc-macro for generating this function
-    fn map_mut(&mut self, addr: Addr) -> &mut Bus {
-        match addr as usize {
-            0x0000_0000...0x0000_3fff => &mut self.bios,
-            0x0200_0000...0x0203_ffff => &mut self.onboard_work_ram,
-            0x0300_0000...0x0300_7fff => &mut self.internal_work_ram,
-            0x0400_0000...0x0400_03fe => &mut self.ioregs,
-            0x0500_0000...0x0500_03ff => &mut self.palette_ram,
-            0x0600_0000...0x0601_7fff => &mut self.vram,
-            0x0700_0000...0x0700_03ff => &mut self.oam,
-            0x0800_0000...0x09ff_ffff => &mut self.gamepak,
-            _ => &mut self.dummy,
+    fn count_write(&self, addr: Addr, width: MemoryAccessWidth) {
+        if self.count_accesses {
+            let region = MemoryRegion::from_addr(addr);
+            self.memory_stats.borrow_mut().writes[region as usize][width as usize] += 1;
+        }
+    }
+
+    /// The regions of the address space as they are currently set up
+    pub fn memory_map(&self) -> Vec<RegionInfo> {
+        MEMORY_MAP
+            .iter()
+            .filter(|&&(region, ..)| {
+                region != MemoryRegion::Eeprom || self.gamepak.eeprom().is_some()
+            })
+            .map(|&(region, name, start, end)| {
+                let bus = self.device(region);
+                // the ROM is only mapped as far as it goes
+                let end = match region {
+                    MemoryRegion::GamePak => start + bus.get_bytes(0).len() as Addr - 1,
+                    _ => end,
+                };
+                let cycles = |width| {
+                    self.access_cycles(start, MemoryAccess(MemoryAccessType::NonSeq, width))
+                };
+                RegionInfo {
+                    region,
+                    name,
+                    start,
+                    end,
+                    size: (end + 1 - start) as usize,
+                    cycles: [
+                        cycles(MemoryAccess8),
+                        cycles(MemoryAccess16),
+                        cycles(MemoryAccess32),
+                    ],
+                }
+            })
+            .collect()
+    }
+
+    /// Returns whether the CPU requested Stop mode since the last call
+    pub fn take_stop_request(&mut self) -> bool {
+        let requested = self.stop_requested;
+        self.stop_requested = false;
+        requested
+    }
+
+    /// Returns whether the CPU requested Halt mode since the last call
+    pub fn take_halt_request(&mut self) -> bool {
+        let requested = self.halt_requested;
+        self.halt_requested = false;
+        requested
+    }
+
+    fn is_video_memory(addr: Addr) -> bool {
+        match MemoryRegion::from_addr(addr) {
+            MemoryRegion::PaletteRam | MemoryRegion::VideoRam | MemoryRegion::Oam => true,
+            _ => false,
+        }
+    }
+
+    /// Set the SRAM and gamepak access times from a WAITCNT value, called whenever it's written
+    pub fn update_waitcnt(&mut self, value: u16) {
+        self.sram_waitstate = WaitState::sram(WAIT_STATES[value.bit_range(0..2) as usize]);
+        for (region, ws) in self.gamepak_waitstates.iter_mut().enumerate() {
+            let fields = value >> (2 + 3 * region);
+            let nonseq_waits = WAIT_STATES[fields.bit_range(0..2) as usize];
+            let seq_waits = if fields.bit(2) {
+                1
+            } else {
+                SEQ_WAIT_STATES[region]
+            };
+            *ws = WaitState::gamepak(nonseq_waits, seq_waits);
+        }
+        self.prefetch_enabled = value.bit(14);
+        if !self.prefetch_enabled {
+            self.prefetch.set(Default::default());
+        }
+    }
+
+    /// Access time of `addr` as set up now, without the prefetch buffer
+    fn access_cycles(&self, addr: Addr, access: MemoryAccess) -> usize {
+        if MemoryRegion::from_addr(addr) == MemoryRegion::Sram {
+            return self.sram_waitstate.cycles(access);
+        }
+        if let Some(region) = Self::gamepak_region(addr) {
+            return self.gamepak_waitstates[region].cycles(access);
+        }
+        let (region, offset) = map_address(addr);
+        let cycles = self.device(region).get_cycles(offset, access);
+        if self.vram_contention && Self::is_video_memory(addr) && self.is_lcd_drawing() {
+            cycles + VRAM_CONTENTION_PENALTY
+        } else {
+            cycles
+        }
+    }
+
+    fn check_waitcnt_write(&mut self, addr: Addr) {
+        if addr & !3 == REG_WAITCNT {
+            self.update_waitcnt(self.ioregs.read_reg(REG_WAITCNT));
+        }
+    }
+
+    /// Request a halt or stop for a write of `width` bytes at `addr` that covers HALTCNT
+    fn check_haltcnt_write(&mut self, addr: Addr, width: Addr, value: u32) {
+        if (addr..addr + width).contains(&REG_HALTCNT) {
+            let haltcnt = (value >> (8 * (REG_HALTCNT - addr))) as u8;
+            if haltcnt.bit(7) {
+                self.stop_requested = true;
+            } else {
+                self.halt_requested = true;
+            }
+        }
+    }
+
+    /// Index of the gamepak wait state region of `addr`, the ROM and EEPROM share them
+    fn gamepak_region(addr: Addr) -> Option<usize> {
+        match addr {
+            0x0800_0000...0x0dff_ffff => Some((addr as usize >> 25) - 4),
+            _ => None,
+        }
+    }
+
+    fn in_bios(addr: Addr) -> bool {
+        addr < BIOS_SIZE as Addr
+    }
+
+    fn is_unmapped(&self, addr: Addr) -> bool {
+        match MemoryRegion::from_addr(addr) {
+            MemoryRegion::Unmapped => true,
+            MemoryRegion::Eeprom => self.gamepak.eeprom().is_none(),
+            _ => false,
+        }
+    }
+
+    /// What a read that doesn't reach any memory returns, `None` for regular reads.
+    ///
+    /// A data read of the BIOS while the CPU is executing outside of it gives the last opcode
+    /// fetched from the BIOS. The real BIOS leaves well known opcodes behind this way, like
+    /// 0xe129f000 after booting, 0xe25ef004 after an IRQ and 0xe3a02004 after a SWI. Reads of
+    /// unmapped memory give the last prefetched opcode instead.
+    fn open_bus_read(&self, addr: Addr) -> Option<u32> {
+        if Self::in_bios(addr) {
+            if Self::in_bios(self.fetch_addr.get()) {
+                None
+            } else {
+                Some(self.bios_latch.get())
+            }
+        } else if self.is_unmapped(addr) {
+            Some(self.open_bus.get())
+        } else {
+            None
+        }
+    }
+
+    fn fetch(&self, addr: Addr) {
+        self.fetch_addr.set(addr);
+        if Self::in_bios(addr) {
+            self.bios_latch.set(self.bios.read_32(addr & !3));
+        }
+    }
+
+    /// What a read of the I/O halfword at `addr` gives, the open bus for write-only registers
+    fn read_io_16(&self, addr: Addr) -> u16 {
+        if ioregs::is_write_only(addr) {
+            (self.open_bus.get() >> (8 * (addr & 2))) as u16
+        } else {
+            self.ioregs.read_16(addr & 0xff_ffff)
+        }
+    }
+
+    /// The LCD is fetching from video memory only outside of the HBlank/VBlank periods
+    fn is_lcd_drawing(&self) -> bool {
+        let dispstat = self.ioregs.read_reg(REG_DISPSTAT);
+        dispstat & 0b11 == 0
+    }
+
+    /// The memory or device behind `region`, as set up for the inserted cartridge
+    fn device(&self, region: MemoryRegion) -> &Bus {
+        match region {
+            MemoryRegion::Bios => &self.bios,
+            MemoryRegion::OnboardWorkRam => &self.onboard_work_ram,
+            MemoryRegion::InternalWorkRam => &self.internal_work_ram,
+            MemoryRegion::IoRegs => &self.ioregs,
+            MemoryRegion::PaletteRam => &self.palette_ram,
+            MemoryRegion::VideoRam => &self.vram,
+            MemoryRegion::Oam => &self.oam,
+            MemoryRegion::GamePak => &self.gamepak,
+            MemoryRegion::Eeprom => match self.gamepak.eeprom() {
+                Some(eeprom) => eeprom,
+                None => &self.dummy,
+            },
+            MemoryRegion::Sram => match (self.gamepak.flash(), self.gamepak.sram()) {
+                (Some(flash), _) => flash,
+                (None, Some(sram)) => sram,
+                (None, None) => &self.dummy,
+            },
+            MemoryRegion::Unmapped => &self.dummy,
+        }
+    }
+
+    fn device_mut(&mut self, region: MemoryRegion) -> &mut Bus {
+        match region {
+            MemoryRegion::Bios => &mut self.bios,
+            MemoryRegion::OnboardWorkRam => &mut self.onboard_work_ram,
+            MemoryRegion::InternalWorkRam => &mut self.internal_work_ram,
+            MemoryRegion::IoRegs => &mut self.ioregs,
+            MemoryRegion::PaletteRam => &mut self.palette_ram,
+            MemoryRegion::VideoRam => &mut self.vram,
+            MemoryRegion::Oam => &mut self.oam,
+            MemoryRegion::GamePak => &mut self.gamepak,
+            MemoryRegion::Eeprom if self.gamepak.eeprom().is_some() => {
+                self.gamepak.eeprom_mut().unwrap()
+            }
+            MemoryRegion::Sram if self.gamepak.flash().is_some() => {
+                self.gamepak.flash_mut().unwrap()
+            }
+            MemoryRegion::Sram if self.gamepak.sram().is_some() => self.gamepak.sram_mut().unwrap(),
+            MemoryRegion::Sram | MemoryRegion::Eeprom | MemoryRegion::Unmapped => &mut self.dummy,
         }
     }
 }
 
 impl Bus for SysBus {
     fn read_32(&self, addr: Addr) -> u32 {
-        self.map(addr).read_32(addr & 0xff_ffff)
+        // the low address bits don't reach the bus, the CPU rotates misaligned loads itself
+        let addr = addr & !3;
+        self.count_read(addr, MemoryAccess32);
+        if let Some(value) = self.open_bus_read(addr) {
+            return value;
+        }
+        if ioregs::is_write_only(addr) || ioregs::is_write_only(addr + 2) {
+            return self.read_io_16(addr) as u32 | (self.read_io_16(addr + 2) as u32) << 16;
+        }
+        let (region, offset) = map_address(addr);
+        self.device(region).read_32(offset)
     }
 
     fn read_16(&self, addr: Addr) -> u16 {
-        self.map(addr).read_16(addr & 0xff_ffff)
+        let addr = addr & !1;
+        self.count_read(addr, MemoryAccess16);
+        if let Some(value) = self.open_bus_read(addr) {
+            return (value >> (8 * (addr & 2))) as u16;
+        }
+        if ioregs::is_write_only(addr) {
+            return self.read_io_16(addr & !1);
+        }
+        let (region, offset) = map_address(addr);
+        self.device(region).read_16(offset)
     }
 
     fn read_8(&self, addr: Addr) -> u8 {
-        self.map(addr).read_8(addr & 0xff_ffff)
+        self.count_read(addr, MemoryAccess8);
+        if let Some(value) = self.open_bus_read(addr) {
+            return (value >> (8 * (addr & 3))) as u8;
+        }
+        if ioregs::is_write_only(addr) {
+            return (self.open_bus.get() >> (8 * (addr & 3))) as u8;
+        }
+        let (region, offset) = map_address(addr);
+        self.device(region).read_8(offset)
+    }
+
+    /// Reads the memory behind `addr` without counting the access, bypassing the open bus
+    fn peek_32(&self, addr: Addr) -> u32 {
+        let (region, offset) = map_address(addr & !3);
+        self.device(region).peek_32(offset)
+    }
+
+    fn peek_16(&self, addr: Addr) -> u16 {
+        let (region, offset) = map_address(addr & !1);
+        self.device(region).peek_16(offset)
+    }
+
+    fn peek_8(&self, addr: Addr) -> u8 {
+        let (region, offset) = map_address(addr);
+        self.device(region).peek_8(offset)
+    }
+
+    fn fetch_32(&self, addr: Addr) -> u32 {
+        self.fetch(addr);
+        let value = self.read_32(addr);
+        self.open_bus.set(value);
+        value
+    }
+
+    fn fetch_16(&self, addr: Addr) -> u16 {
+        self.fetch(addr);
+        let value = self.read_16(addr);
+        self.open_bus.set(value as u32 * 0x1_0001);
+        value
     }
 
     fn write_32(&mut self, addr: Addr, value: u32) {
-        self.map_mut(addr).write_32(addr & 0xff_ffff, value)
+        let addr = addr & !3;
+        self.count_write(addr, MemoryAccess32);
+        let (region, offset) = map_address(addr);
+        self.device_mut(region).write_32(offset, value);
+        self.check_waitcnt_write(addr);
+        self.check_haltcnt_write(addr, 4, value);
     }
 
     fn write_16(&mut self, addr: Addr, value: u16) {
-        self.map_mut(addr).write_16(addr & 0xff_ffff, value)
+        let addr = addr & !1;
+        self.count_write(addr, MemoryAccess16);
+        let (region, offset) = map_address(addr);
+        self.device_mut(region).write_16(offset, value);
+        self.check_waitcnt_write(addr);
+        self.check_haltcnt_write(addr, 2, value as u32);
     }
 
     fn write_8(&mut self, addr: Addr, value: u8) {
-        self.map_mut(addr).write_8(addr & 0xff_ffff, value)
+        self.count_write(addr, MemoryAccess8);
+        let (region, offset) = map_address(addr);
+        self.device_mut(region).write_8(offset, value);
+        self.check_waitcnt_write(addr);
+        self.check_haltcnt_write(addr, 1, value as u32);
     }
 
     fn get_bytes(&self, addr: Addr) -> &[u8] {
-        self.map(addr).get_bytes(addr & 0xff_ffff)
+        let (region, offset) = map_address(addr);
+        self.device(region).get_bytes(offset)
     }
 
     fn get_bytes_mut(&mut self, addr: Addr) -> &mut [u8] {
-        self.map_mut(addr).get_bytes_mut(addr & 0xff_ffff)
+        let (region, offset) = map_address(addr);
+        self.device_mut(region).get_bytes_mut(offset)
     }
 
     fn get_cycles(&self, addr: Addr, access: MemoryAccess) -> usize {
-        self.map(addr).get_cycles(addr & 0xff_ffff, access)
+        let cycles = self.access_cycles(addr, access);
+        let mut prefetch = self.prefetch.get();
+        if Self::gamepak_region(addr).is_some() {
+            // the gamepak bus is taken from the prefetcher
+            prefetch.active = false;
+        } else {
+            prefetch.advance(cycles);
+        }
+        self.prefetch.set(prefetch);
+        cycles
+    }
+
+    fn fetch_cycles(&self, addr: Addr, access: MemoryAccess) -> usize {
+        let region = match Self::gamepak_region(addr) {
+            Some(region) if self.prefetch_enabled => region,
+            _ => return self.get_cycles(addr, access),
+        };
+        let halfwords = match access.1 {
+            MemoryAccess32 => 2,
+            _ => 1,
+        };
+        let mut prefetch = self.prefetch.get();
+        let cycles = match prefetch.fetch(addr, halfwords) {
+            Some(cycles) => cycles,
+            None => {
+                let ws = &self.gamepak_waitstates[region];
+                prefetch = Prefetch {
+                    active: true,
+                    head: addr + 2 * halfwords as Addr,
+                    count: 0,
+                    progress: 0,
+                    halfword_cycles: ws.cycles(MemoryAccess(MemoryAccessType::Seq, MemoryAccess16)),
+                };
+                ws.cycles(access)
+            }
+        };
+        self.prefetch.set(prefetch);
+        cycles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arm7tdmi::bus::MemoryAccessType::*;
+
+    #[test]
+    fn memory_map() {
+        let sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let map = sysbus.memory_map();
+        assert_eq!(map.len(), 9);
+
+        for entry in map.iter() {
+            assert_eq!(MemoryRegion::from_addr(entry.start), entry.region);
+            assert_eq!(MemoryRegion::from_addr(entry.end), entry.region);
+            assert_ne!(
+                MemoryRegion::from_addr(entry.start.wrapping_sub(1)),
+                entry.region
+            );
+        }
+
+        assert_eq!(map[0].name, "BIOS");
+        assert_eq!((map[0].start, map[0].end, map[0].size), (0, 0x3fff, 0x4000));
+
+        let ewram = &map[1];
+        assert_eq!(ewram.region, MemoryRegion::OnboardWorkRam);
+        assert_eq!((ewram.start, ewram.end), (0x0200_0000, 0x0203_ffff));
+        assert_eq!(ewram.size, 256 * 1024);
+        assert_eq!(ewram.cycles, [3, 3, 6]);
+
+        // ROMs are padded to 4MB
+        let rom = &map[7];
+        assert_eq!(rom.region, MemoryRegion::GamePak);
+        assert_eq!((rom.start, rom.end), (0x0800_0000, 0x083f_ffff));
+        assert_eq!(rom.size, 4 * 1024 * 1024);
+        assert_eq!(rom.cycles, [5, 5, 8]);
+
+        let sram = &map[8];
+        assert_eq!((sram.start, sram.end), (0x0e00_0000, 0x0e00_ffff));
+        assert_eq!(sram.cycles, [5, 10, 20]);
+    }
+
+    #[test]
+    fn haltcnt_write_widths() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        sysbus.write_8(REG_POSTFLG, 1);
+        assert!(!sysbus.take_halt_request() && !sysbus.take_stop_request());
+
+        sysbus.write_8(REG_HALTCNT, 0);
+        assert!(sysbus.take_halt_request() && !sysbus.take_stop_request());
+        sysbus.write_16(REG_POSTFLG, 0x8001);
+        assert!(sysbus.take_stop_request() && !sysbus.take_halt_request());
+        sysbus.write_32(REG_POSTFLG, 0x0000_0001);
+        assert!(sysbus.take_halt_request() && !sysbus.take_stop_request());
+        // the neighbouring halfword doesn't cover it
+        sysbus.write_16(REG_POSTFLG + 2, 0);
+        assert!(!sysbus.take_halt_request() && !sysbus.take_stop_request());
+    }
+
+    #[test]
+    fn sram_waitstates() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let cycles = |sysbus: &SysBus, width| sysbus.get_cycles(0x0e00_0010, NonSeq + width);
+
+        // 4 wait states at power on
+        assert_eq!(cycles(&sysbus, MemoryAccess8), 5);
+        assert_eq!(cycles(&sysbus, MemoryAccess32), 20);
+
+        for &(field, access8) in &[(1, 4), (2, 3), (3, 9)] {
+            sysbus.write_16(REG_WAITCNT, field);
+            assert_eq!(cycles(&sysbus, MemoryAccess8), access8);
+            assert_eq!(cycles(&sysbus, MemoryAccess16), 2 * access8);
+            assert_eq!(cycles(&sysbus, MemoryAccess32), 4 * access8);
+        }
+
+        sysbus.write_8(0x0e00_0010, 0xab);
+        assert_eq!(sysbus.read_8(0x0e00_0010), 0xab);
+    }
+
+    #[test]
+    fn address_mapping() {
+        assert_eq!(map_address(0x0000_0100), (MemoryRegion::Bios, 0x100));
+        assert_eq!(
+            map_address(0x0203_fffc),
+            (MemoryRegion::OnboardWorkRam, 0x3_fffc)
+        );
+        assert_eq!(map_address(0x0400_0208), (MemoryRegion::IoRegs, 0x208));
+        // the second half of a 32MB ROM
+        assert_eq!(
+            map_address(0x0900_0010),
+            (MemoryRegion::GamePak, 0x100_0010)
+        );
+        assert_eq!(map_address(0x0e00_8000), (MemoryRegion::Sram, 0x8000));
+        assert_eq!(
+            map_address(0x1000_0000),
+            (MemoryRegion::Unmapped, 0x1000_0000)
+        );
+
+        for &(region, _, start, end) in MEMORY_MAP.iter() {
+            assert_eq!(region.base(), start);
+            assert_eq!(map_address(end), (region, end - start));
+        }
+    }
+
+    #[test]
+    fn mirroring() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let mirrors: &[(Addr, Addr)] = &[
+            (0x0200_0010, 0x0204_0010),
+            (0x0200_0010, 0x02fc_0010),
+            (0x0300_7ffc, 0x03ff_fffc),
+            (0x0500_0020, 0x0500_0420),
+            (0x0600_0000, 0x0602_0000),
+            // the OBJ tiles twice in every 128K
+            (0x0601_0000, 0x0601_8000),
+            (0x0601_7ffe, 0x0603_fffe),
+            (0x0700_0008, 0x07ff_fc08),
+        ];
+        for (i, &(base, mirror)) in mirrors.iter().enumerate() {
+            let value = 0x1234_0000 + i as u32;
+            sysbus.write_32(mirror & !3, value);
+            assert_eq!(sysbus.read_32(base & !3), value, "{:#x}", mirror);
+            assert_eq!(sysbus.read_16(mirror), sysbus.read_16(base));
+        }
+    }
+
+    #[test]
+    fn misaligned_accesses() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        sysbus.write_32(0x0300_0102, 0x4433_2211);
+        assert_eq!(sysbus.read_32(0x0300_0100), 0x4433_2211);
+        assert_eq!(sysbus.read_32(0x0300_0103), 0x4433_2211);
+        sysbus.write_16(0x0300_0201, 0xbbaa);
+        assert_eq!(sysbus.read_16(0x0300_0200), 0xbbaa);
+        assert_eq!(sysbus.read_16(0x0300_0201), 0xbbaa);
+        // right at the end of IWRAM
+        sysbus.write_32(0x0300_7fff, 0x1234_5678);
+        assert_eq!(sysbus.read_32(0x0300_7ffd), 0x1234_5678);
+    }
+
+    #[test]
+    fn gamepak_mirrors() {
+        let mut rom = vec![0; 0x1000];
+        rom[0x10..0x14].copy_from_slice(&[0x78, 0x56, 0x34, 0x12]);
+        let sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(rom));
+        for &base in &[0x0800_0000, 0x0a00_0000, 0x0c00_0000] {
+            assert_eq!(sysbus.read_32(base + 0x10), 0x1234_5678);
+        }
+        assert_eq!(map_address(0x0c00_0010), (MemoryRegion::GamePak, 0x10));
+        // past the ROM it's the ROM's own open bus, not the CPU's
+        assert_eq!(sysbus.read_16(0x0a40_0000), 0);
+        assert_eq!(sysbus.read_16(0x0a40_0002), 1);
+    }
+
+    #[test]
+    fn gamepak_waitstates() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        let cycles = |sysbus: &SysBus, addr, access| sysbus.get_cycles(addr, access);
+
+        // WS0 at power on, 4 nonsequential and 2 sequential wait states
+        assert_eq!(cycles(&sysbus, 0x0800_0000, NonSeq + MemoryAccess16), 5);
+        assert_eq!(cycles(&sysbus, 0x0800_0000, Seq + MemoryAccess16), 3);
+        assert_eq!(cycles(&sysbus, 0x0800_0000, NonSeq + MemoryAccess32), 8);
+        assert_eq!(cycles(&sysbus, 0x0800_0000, Seq + MemoryAccess32), 6);
+        // WS1 and WS2 default to 4 and 8 sequential wait states
+        assert_eq!(cycles(&sysbus, 0x0a00_0000, Seq + MemoryAccess16), 5);
+        assert_eq!(cycles(&sysbus, 0x0c00_0000, Seq + MemoryAccess16), 9);
+
+        // the usual setting, WS0 3/1 and prefetch on
+        sysbus.write_16(REG_WAITCNT, 0x4317);
+        assert_eq!(sysbus.read_16(REG_WAITCNT), 0x4317);
+        assert_eq!(cycles(&sysbus, 0x0800_0000, NonSeq + MemoryAccess16), 4);
+        assert_eq!(cycles(&sysbus, 0x0800_0000, Seq + MemoryAccess16), 2);
+        assert_eq!(cycles(&sysbus, 0x0900_0000, NonSeq + MemoryAccess32), 6);
+        // SRAM with 8 wait states
+        assert_eq!(cycles(&sysbus, 0x0e00_0000, NonSeq + MemoryAccess8), 9);
+        // WS2 with 2 nonsequential and 1 sequential wait states, through a byte write
+        sysbus.write_8(REG_WAITCNT + 1, 0x06);
+        assert_eq!(cycles(&sysbus, 0x0d00_0000, NonSeq + MemoryAccess16), 3);
+        assert_eq!(cycles(&sysbus, 0x0d00_0000, Seq + MemoryAccess16), 2);
+        // the low byte is untouched
+        assert_eq!(cycles(&sysbus, 0x0800_0000, NonSeq + MemoryAccess16), 4);
+    }
+
+    #[test]
+    fn prefetch_buffer() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        // WS0 3/1 with prefetch, 4 cycles nonsequential and 2 sequential
+        sysbus.write_16(REG_WAITCNT, 0x4317);
+        let fetch = |sysbus: &SysBus, addr, access| sysbus.fetch_cycles(addr, access);
+
+        assert_eq!(fetch(&sysbus, 0x0800_0000, NonSeq + MemoryAccess16), 4);
+        // 6 cycles in EWRAM let 3 halfwords in
+        assert_eq!(sysbus.get_cycles(0x0200_0000, NonSeq + MemoryAccess32), 6);
+        assert_eq!(fetch(&sysbus, 0x0800_0002, Seq + MemoryAccess16), 1);
+        assert_eq!(fetch(&sysbus, 0x0800_0004, Seq + MemoryAccess16), 1);
+        assert_eq!(fetch(&sysbus, 0x0800_0006, Seq + MemoryAccess32), 1);
+        // the buffer ran dry, what's left of the halfword being read
+        assert_eq!(fetch(&sysbus, 0x0800_000a, Seq + MemoryAccess16), 1);
+        assert_eq!(fetch(&sysbus, 0x0800_000c, Seq + MemoryAccess16), 2);
+
+        // a data read from the ROM flushes it
+        assert_eq!(sysbus.get_cycles(0x0800_1000, NonSeq + MemoryAccess16), 4);
+        assert_eq!(sysbus.get_cycles(0x0200_0000, NonSeq + MemoryAccess32), 6);
+        assert_eq!(fetch(&sysbus, 0x0800_000e, Seq + MemoryAccess16), 2);
+        // and so does a branch
+        assert_eq!(sysbus.get_cycles(0x0200_0000, NonSeq + MemoryAccess32), 6);
+        assert_eq!(fetch(&sysbus, 0x0800_0100, NonSeq + MemoryAccess16), 4);
+
+        // every fetch pays the wait states without it
+        sysbus.write_16(REG_WAITCNT, 0x0317);
+        assert_eq!(fetch(&sysbus, 0x0800_0000, NonSeq + MemoryAccess16), 4);
+        assert_eq!(sysbus.get_cycles(0x0200_0000, NonSeq + MemoryAccess32), 6);
+        assert_eq!(fetch(&sysbus, 0x0800_0002, Seq + MemoryAccess16), 2);
+        assert_eq!(fetch(&sysbus, 0x0800_0004, Seq + MemoryAccess32), 4);
+    }
+
+    #[test]
+    fn empty_bios() {
+        let sysbus = SysBus::new(vec![], Cartridge::empty());
+        assert_eq!(sysbus.read_32(0x0000_0000), 0);
+        assert_eq!(sysbus.read_32(0x0000_3ffc), 0);
+
+        let sysbus = SysBus::new(vec![0x02, 0xf3, 0xa0, 0xe3], Cartridge::empty());
+        assert_eq!(sysbus.read_32(0x0000_0000), 0xe3a0f302);
+        assert_eq!(sysbus.get_bytes(0).len(), 0x4000);
+    }
+
+    #[test]
+    fn write_only_registers() {
+        let rom = vec![0x01, 0x00, 0xa0, 0xe3, 0x01, 0x20, 0x00, 0x00];
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(rom));
+
+        sysbus.write_16(REG_BG0HOFS, 0x0123);
+        // mov r0, #1
+        sysbus.fetch_32(0x0800_0000);
+        assert_eq!(sysbus.read_16(REG_BG0HOFS), 0x0001);
+        assert_eq!(sysbus.read_16(REG_BG0VOFS), 0xe3a0);
+        assert_eq!(sysbus.read_8(REG_BG0HOFS + 1), 0x00);
+        // the written value is still there for the LCD
+        assert_eq!(sysbus.ioregs.read_reg(REG_BG0HOFS), 0x0123);
+
+        // thumb opcodes are seen twice, movs r0, #1
+        sysbus.fetch_16(0x0800_0004);
+        assert_eq!(sysbus.read_32(REG_BG0HOFS), 0x2001_2001);
+
+        // DMA3CNT_H can be read, DMA3CNT_L can't
+        sysbus.write_32(REG_DMA3CNT_L, 0x8000_0010);
+        assert_eq!(sysbus.read_32(REG_DMA3CNT_L), 0x8000_2001);
+        // so can BG0CNT
+        sysbus.write_16(REG_BG0CNT, 0x1f80);
+        assert_eq!(sysbus.read_16(REG_BG0CNT), 0x1f80);
+    }
+
+    #[test]
+    fn unmapped_reads() {
+        let rom = vec![0x01, 0x00, 0xa0, 0xe3, 0x01, 0x20, 0x00, 0x00];
+        let sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(rom));
+
+        // mov r0, #1
+        sysbus.fetch_32(0x0800_0000);
+        assert_eq!(sysbus.read_32(0x0100_0000), 0xe3a0_0001);
+        assert_eq!(sysbus.read_16(0x1000_0002), 0xe3a0);
+        assert_eq!(sysbus.read_8(0x0400_0401), 0x00);
+        // no EEPROM on this cartridge
+        assert_eq!(sysbus.read_32(0x0d00_0000), 0xe3a0_0001);
+
+        // movs r0, #1
+        sysbus.fetch_16(0x0800_0004);
+        assert_eq!(sysbus.read_32(0x0100_0000), 0x2001_2001);
+        // the BIOS still gives what was last fetched from it
+        assert_eq!(sysbus.read_32(0x0000_0000), 0);
+    }
+
+    #[test]
+    fn vram_contention() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+        sysbus.set_vram_contention(true);
+
+        // drawing
+        sysbus.ioregs.write_reg(REG_DISPSTAT, 0);
+        let drawing_cycles = sysbus.get_cycles(0x0600_0000, NonSeq + MemoryAccess16);
+
+        // vblank
+        sysbus.ioregs.write_reg(REG_DISPSTAT, 1);
+        let vblank_cycles = sysbus.get_cycles(0x0600_0000, NonSeq + MemoryAccess16);
+        assert!(drawing_cycles > vblank_cycles);
+
+        // without the contention model the beam position doesn't matter
+        sysbus.set_vram_contention(false);
+        sysbus.ioregs.write_reg(REG_DISPSTAT, 0);
+        assert_eq!(
+            sysbus.get_cycles(0x0600_0000, NonSeq + MemoryAccess16),
+            vblank_cycles
+        );
+
+        // work ram is never affected
+        sysbus.set_vram_contention(true);
+        assert_eq!(sysbus.get_cycles(0x0200_0000, NonSeq + MemoryAccess16), 3);
+    }
+
+    #[test]
+    fn memory_stats() {
+        let mut sysbus = SysBus::new(vec![0; 0x4000], Cartridge::new(vec![]));
+
+        // disabled by default
+        sysbus.read_32(0x0200_0000);
+        assert_eq!(sysbus.memory_stats(), Default::default());
+
+        sysbus.set_count_accesses(true);
+        sysbus.read_32(0x0200_0000);
+        sysbus.read_32(0x0200_0004);
+        sysbus.write_16(0x0600_0000, 0);
+        sysbus.write_16(0x0600_0002, 0);
+        sysbus.write_16(0x0600_0004, 0);
+        sysbus.read_8(0x0800_0000);
+        sysbus.write_8(0x0300_0000, 0);
+        sysbus.read_16(0x0400_0004);
+        // the emulator's own reads, e.g. by the renderer, aren't counted
+        sysbus.peek_16(0x0600_0000);
+        sysbus.peek_32(0x0200_0000);
+
+        let stats = sysbus.memory_stats();
+        assert_eq!(stats.reads(MemoryRegion::OnboardWorkRam, MemoryAccess32), 2);
+        assert_eq!(stats.writes(MemoryRegion::VideoRam, MemoryAccess16), 3);
+        assert_eq!(stats.total_reads(MemoryRegion::VideoRam), 0);
+        assert_eq!(stats.reads(MemoryRegion::GamePak, MemoryAccess8), 1);
+        assert_eq!(
+            stats.writes(MemoryRegion::InternalWorkRam, MemoryAccess8),
+            1
+        );
+        assert_eq!(stats.reads(MemoryRegion::IoRegs, MemoryAccess16), 1);
+        assert_eq!(stats.total_reads(MemoryRegion::Bios), 0);
+
+        sysbus.reset_memory_stats();
+        assert_eq!(sysbus.memory_stats(), Default::default());
     }
 }
@@ -0,0 +1,382 @@
+use std::mem;
+
+use super::arm7tdmi::Addr;
+use super::ioregs::consts::*;
+
+use crate::bit::BitIndex;
+
+/// Cycles per tick for each TMxCNT_H prescaler selection
+const PRESCALERS: [usize; 4] = [1, 64, 256, 1024];
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Timer {
+    /// Loaded into the counter when the timer starts and when it overflows, written through TMxCNT_L
+    pub reload: u16,
+    /// TMxCNT_H
+    pub ctl: u16,
+    counter: u16,
+    /// Cycles that don't add up to a whole prescaler tick yet
+    leftover_cycles: usize,
+}
+
+impl Timer {
+    pub fn enabled(&self) -> bool {
+        self.ctl.bit(7)
+    }
+
+    pub fn irq_enabled(&self) -> bool {
+        self.ctl.bit(6)
+    }
+
+    /// Count overflows of the previous timer instead of cycles, meaningless for timer 0
+    pub fn count_up(&self) -> bool {
+        self.ctl.bit(2)
+    }
+
+    pub fn prescaler(&self) -> usize {
+        PRESCALERS[self.ctl.bit_range(0..2) as usize]
+    }
+
+    /// The live counter value, which is what reading TMxCNT_L returns
+    pub fn counter(&self) -> u16 {
+        self.counter
+    }
+
+    fn write_ctl(&mut self, value: u16) {
+        if !self.enabled() && value.bit(7) {
+            self.counter = self.reload;
+            self.leftover_cycles = 0;
+        }
+        self.ctl = value;
+    }
+
+    /// Advance the counter by `ticks`, returns how many times it overflowed
+    fn add_ticks(&mut self, ticks: usize) -> usize {
+        let counter = self.counter as usize + ticks;
+        if counter <= 0xffff {
+            self.counter = counter as u16;
+            return 0;
+        }
+        let period = 0x1_0000 - self.reload as usize;
+        let excess = counter - 0x1_0000;
+        self.counter = self.reload + (excess % period) as u16;
+        1 + excess / period
+    }
+}
+
+/// The timers only run when one of them overflows, the GBA schedules that with
+/// `next_overflow_cycle`. In between, register reads catch the counters up to the cycle set with
+/// `set_now` on the fly, and writes bring them up to it first.
+#[derive(Debug, Default, Clone)]
+pub struct Timers {
+    timers: [Timer; 4],
+    /// How many times each timer overflowed in the last `step`
+    overflows: [usize; 4],
+    /// The CPU cycle the counters were run up to with `run_to`
+    synced_cycle: usize,
+    /// The CPU cycle register accesses happen at
+    now: usize,
+    /// Set when a timer register is written, see `take_write`
+    written: bool,
+}
+
+impl Timers {
+    pub fn new() -> Timers {
+        Default::default()
+    }
+
+    pub fn timer(&self, index: usize) -> &Timer {
+        &self.timers[index]
+    }
+
+    /// The CPU cycle register accesses happen at, the GBA sets it as it runs the devices.
+    /// There's no overflow up to it that `run_to` hasn't run through.
+    pub fn set_now(&mut self, cycle: usize) {
+        self.now = cycle;
+    }
+
+    /// Run the timers from the cycle they were last run up to until `cycle`, see `step`
+    pub fn run_to(&mut self, cycle: usize) -> u8 {
+        let cycles = cycle.saturating_sub(self.synced_cycle);
+        self.synced_cycle = self.synced_cycle.max(cycle);
+        self.step(cycles)
+    }
+
+    /// Whether a timer register was written since the last call, the next overflow may have moved
+    pub fn take_write(&mut self) -> bool {
+        mem::replace(&mut self.written, false)
+    }
+
+    /// The CPU cycle at which a timer counting cycles overflows next, see `cycles_to_overflow`
+    pub fn next_overflow_cycle(&self) -> Option<usize> {
+        self.cycles_to_overflow()
+            .map(|cycles| self.synced_cycle + cycles)
+    }
+
+    /// The CPU cycle at which timer `index` overflows next, see `cycles_to_timer_overflow`
+    pub fn timer_overflow_cycle(&self, index: usize) -> Option<usize> {
+        self.cycles_to_timer_overflow(index)
+            .map(|cycles| self.synced_cycle + cycles)
+    }
+
+    /// Cycles until the next overflow of a timer counting cycles, `None` when none is running.
+    /// Count-up timers only overflow along with the timer below them.
+    pub fn cycles_to_overflow(&self) -> Option<usize> {
+        (0..4)
+            .filter(|&index| index == 0 || !self.timers[index].count_up())
+            .filter_map(|index| self.cycles_to_timer_overflow(index))
+            .min()
+    }
+
+    /// Cycles until timer `index` overflows, count-up timers included. `None` when it's stopped,
+    /// or counts up on a stopped timer.
+    pub fn cycles_to_timer_overflow(&self, index: usize) -> Option<usize> {
+        self.overflow_schedule(index).map(|(first, _)| first)
+    }
+
+    /// Cycles until the first overflow of timer `index` and between the ones after it
+    fn overflow_schedule(&self, index: usize) -> Option<(usize, usize)> {
+        let timer = &self.timers[index];
+        if !timer.enabled() {
+            return None;
+        }
+        let ticks = 0x1_0000 - timer.counter as usize;
+        let period = 0x1_0000 - timer.reload as usize;
+        if index != 0 && timer.count_up() {
+            // a tick for every overflow of the timer below
+            let (first, tick) = self.overflow_schedule(index - 1)?;
+            let first = first.saturating_add((ticks - 1).saturating_mul(tick));
+            Some((first, period.saturating_mul(tick)))
+        } else {
+            let first = ticks * timer.prescaler() - timer.leftover_cycles;
+            Some((first, period * timer.prescaler()))
+        }
+    }
+
+    /// Index of the timer and whether `addr` is its control register
+    fn decode_addr(addr: Addr) -> Option<(usize, bool)> {
+        match addr {
+            REG_TM0CNT_L...REG_TM3CNT_H if addr & 1 == 0 => {
+                let offset = addr - REG_TM0CNT_L;
+                Some(((offset / 4) as usize, offset & 2 != 0))
+            }
+            _ => None,
+        }
+    }
+
+    /// Reads of the timer registers, `None` for any other address
+    pub fn read(&self, addr: Addr) -> Option<u16> {
+        let (index, is_ctl) = Self::decode_addr(addr)?;
+        let timer = &self.timers[index];
+        if is_ctl {
+            Some(timer.ctl)
+        } else if self.now > self.synced_cycle {
+            let mut timers = self.clone();
+            timers.run_to(self.now);
+            Some(timers.timers[index].counter)
+        } else {
+            Some(timer.counter)
+        }
+    }
+
+    /// Writes to the timer registers, other addresses are ignored
+    pub fn write(&mut self, addr: Addr, value: u16) {
+        if let Some((index, is_ctl)) = Self::decode_addr(addr) {
+            let now = self.now;
+            self.run_to(now);
+            self.written = true;
+            let timer = &mut self.timers[index];
+            if is_ctl {
+                timer.write_ctl(value);
+            } else {
+                timer.reload = value;
+            }
+        }
+    }
+
+    /// How many times timer `index` overflowed in the last `step`
+    pub fn overflows(&self, index: usize) -> usize {
+        self.overflows[index]
+    }
+
+    /// Run the timers for `cycles`. Returns a bitmask of the timers that overflowed with their
+    /// IRQ enabled.
+    ///
+    /// Timers are stepped in order so the overflows of one feed a count-up timer above it in the
+    /// same call, a single tick of timer 0 can ripple through the whole chain.
+    pub fn step(&mut self, cycles: usize) -> u8 {
+        let mut irqs = 0;
+        let mut prev_overflows = 0;
+        self.overflows = [0; 4];
+        for (index, timer) in self.timers.iter_mut().enumerate() {
+            if !timer.enabled() {
+                prev_overflows = 0;
+                continue;
+            }
+            let ticks = if index != 0 && timer.count_up() {
+                prev_overflows
+            } else {
+                let prescaler = timer.prescaler();
+                timer.leftover_cycles += cycles;
+                let ticks = timer.leftover_cycles / prescaler;
+                timer.leftover_cycles %= prescaler;
+                ticks
+            };
+            prev_overflows = timer.add_ticks(ticks);
+            self.overflows[index] = prev_overflows;
+            if prev_overflows != 0 && timer.irq_enabled() {
+                irqs.set_bit(index, true);
+            }
+        }
+        irqs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arm7tdmi::Bus;
+    use crate::ioregs::IoRegs;
+
+    #[test]
+    fn live_counter_readback() {
+        let mut ioregs = IoRegs::default();
+
+        // timer 0, prescaler 64, irq on overflow
+        ioregs.write_16(REG_TM0CNT_L - IO_BASE, 0xff00);
+        assert_eq!(ioregs.read_16(REG_TM0CNT_L - IO_BASE), 0);
+        ioregs.write_16(REG_TM0CNT_H - IO_BASE, 0x00c1);
+        assert_eq!(ioregs.read_16(REG_TM0CNT_L - IO_BASE), 0xff00);
+
+        assert_eq!(ioregs.timers.step(10 * 64 + 63), 0);
+        assert_eq!(ioregs.read_16(REG_TM0CNT_L - IO_BASE), 0xff0a);
+        assert_eq!(ioregs.read_16(REG_TM0CNT_H - IO_BASE), 0x00c1);
+        // the leftover cycle completes a tick
+        assert_eq!(ioregs.timers.step(1), 0);
+        assert_eq!(ioregs.read_32(REG_TM0CNT_L - IO_BASE), 0x00c1_ff0b);
+        assert_eq!(ioregs.read_8(REG_TM0CNT_L - IO_BASE), 0x0b);
+
+        // 0xf5 ticks to overflow, then 5 more from the reload value
+        assert_eq!(ioregs.timers.step((0xf5 + 5) * 64), 0b1);
+        assert_eq!(ioregs.read_16(REG_TM0CNT_L - IO_BASE), 0xff05);
+
+        // stopped timers keep their count
+        ioregs.write_16(REG_TM0CNT_H - IO_BASE, 0);
+        ioregs.timers.step(1024);
+        assert_eq!(ioregs.read_16(REG_TM0CNT_L - IO_BASE), 0xff05);
+
+        // a byte read of the high half sees the count carried past the reload value
+        ioregs.write_16(REG_TM1CNT_L - IO_BASE, 0x00f0);
+        ioregs.write_16(REG_TM1CNT_H - IO_BASE, 0x0080);
+        ioregs.timers.step(0x20);
+        assert_eq!(ioregs.read_8(REG_TM1CNT_L + 1 - IO_BASE), 0x01);
+        assert_eq!(ioregs.read_8(REG_TM1CNT_L - IO_BASE), 0x10);
+    }
+
+    #[test]
+    fn count_up() {
+        let mut timers = Timers::new();
+        timers.write(REG_TM0CNT_L, 0xfffe);
+        timers.write(REG_TM0CNT_H, 0x0080);
+        timers.write(REG_TM1CNT_L, 0xfffe);
+        timers.write(REG_TM1CNT_H, 0x00c4);
+
+        // timer 0 overflows every 2 cycles
+        assert_eq!(timers.step(2), 0);
+        assert_eq!(timers.timer(1).counter(), 0xffff);
+        assert_eq!(timers.step(2), 0b10);
+        assert_eq!(timers.timer(1).counter(), 0xfffe);
+    }
+
+    #[test]
+    fn cascade_chain() {
+        let mut timers = Timers::new();
+        // timer 0 one tick from overflow, 1 and 2 count up and sit at 0xffff
+        timers.write(REG_TM0CNT_L, 0xffff);
+        timers.write(REG_TM0CNT_H, 0x00c0);
+        timers.write(REG_TM1CNT_L, 0xffff);
+        timers.write(REG_TM1CNT_H, 0x00c4);
+        timers.write(REG_TM2CNT_L, 0xfff0);
+        timers.write(REG_TM2CNT_H, 0x00c4);
+        timers.write(REG_TM2CNT_L, 0xffff);
+        // timer 3 counts up from timer 2 but stays just below overflow
+        timers.write(REG_TM3CNT_L, 0xfffe);
+        timers.write(REG_TM3CNT_H, 0x00c4);
+
+        // timer 2 was started with the earlier reload value
+        assert_eq!(timers.timer(2).counter(), 0xfff0);
+        for _ in 0..0xf {
+            timers.step(1);
+        }
+        assert_eq!(timers.timer(2).counter(), 0xffff);
+        assert_eq!(timers.timer(3).counter(), 0xfffe);
+
+        // one cycle overflows timers 0, 1 and 2 in turn, timer 3 takes a single tick
+        assert_eq!(timers.step(1), 0b0111);
+        assert_eq!(timers.timer(0).counter(), 0xffff);
+        assert_eq!(timers.timer(1).counter(), 0xffff);
+        assert_eq!(timers.timer(2).counter(), 0xffff);
+        assert_eq!(timers.timer(3).counter(), 0xffff);
+
+        // and the next ripples all the way through
+        assert_eq!(timers.step(1), 0b1111);
+        assert_eq!(timers.timer(3).counter(), 0xfffe);
+
+        // a disabled timer breaks the chain
+        timers.write(REG_TM1CNT_H, 0x0044);
+        assert_eq!(timers.step(1), 0b0001);
+        assert_eq!(timers.timer(2).counter(), 0xffff);
+    }
+
+    #[test]
+    fn catch_up_on_access() {
+        let mut timers = Timers::new();
+        timers.write(REG_TM0CNT_L, 0xff00);
+        timers.write(REG_TM0CNT_H, 0x0080);
+        assert!(timers.take_write());
+        assert!(!timers.take_write());
+        assert_eq!(timers.next_overflow_cycle(), Some(0x100));
+
+        // reads see the counter at the current cycle without running the timers
+        timers.set_now(0x10);
+        assert_eq!(timers.read(REG_TM0CNT_L), Some(0xff10));
+        assert_eq!(timers.timer(0).counter(), 0xff00);
+
+        // writes run them up to it first
+        timers.write(REG_TM0CNT_H, 0x00c1);
+        assert!(timers.take_write());
+        assert_eq!(timers.timer(0).counter(), 0xff10);
+        let overflow = 0x10 + 0xf0 * 64;
+        assert_eq!(timers.next_overflow_cycle(), Some(overflow));
+        assert_eq!(timers.timer_overflow_cycle(0), Some(overflow));
+        assert_eq!(timers.run_to(overflow - 1), 0);
+        assert_eq!(timers.run_to(overflow), 0b1);
+        assert_eq!(timers.overflows(0), 1);
+
+        // running to a cycle already passed does nothing
+        assert_eq!(timers.run_to(0x10), 0);
+        assert_eq!(timers.timer(0).counter(), 0xff00);
+    }
+
+    #[test]
+    fn overflow_cycles() {
+        let mut timers = Timers::new();
+        // 256 ticks of 64 cycles, and timer 1 overflows on every other one of those
+        timers.write(REG_TM0CNT_L, 0xff00);
+        timers.write(REG_TM0CNT_H, 0x00c1);
+        timers.write(REG_TM1CNT_L, 0xfffe);
+        timers.write(REG_TM1CNT_H, 0x00c4);
+        timers.step(100);
+        assert_eq!(timers.cycles_to_timer_overflow(0), Some(0x4000 - 100));
+        assert_eq!(timers.cycles_to_timer_overflow(1), Some(0x8000 - 100));
+        assert_eq!(timers.cycles_to_overflow(), Some(0x4000 - 100));
+        assert_eq!(timers.cycles_to_timer_overflow(2), None);
+
+        assert_eq!(timers.step(0x8000 - 101), 0b01);
+        assert_eq!(timers.step(1), 0b11);
+
+        // counting up on a stopped timer never overflows
+        timers.write(REG_TM0CNT_H, 0x0001);
+        assert_eq!(timers.cycles_to_timer_overflow(1), None);
+    }
+}
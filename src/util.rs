@@ -8,3 +8,8 @@ pub fn read_bin_file(filename: &str) -> io::Result<Vec<u8>> {
     file.read_to_end(&mut buf)?;
     Ok(buf)
 }
+
+pub fn write_bin_file(filename: &str, data: &[u8]) -> io::Result<()> {
+    let mut file = File::create(filename)?;
+    file.write_all(data)
+}